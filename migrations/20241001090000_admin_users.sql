@@ -0,0 +1,14 @@
+-- Add migration script here
+-- Roles and account state used by the admin user management API
+CREATE TYPE user_role AS ENUM ('admin', 'user');
+
+ALTER TABLE users
+    ADD COLUMN role user_role NOT NULL DEFAULT 'user',
+    ADD COLUMN disabled BOOLEAN NOT NULL DEFAULT FALSE,        -- Disabled accounts are rejected by the auth middleware
+    ADD COLUMN password_reset_required BOOLEAN NOT NULL DEFAULT FALSE,
+    ADD COLUMN tokens_valid_after TIMESTAMP WITH TIME ZONE;    -- Tokens issued before this are revoked
+
+-- Files of a deleted sender may be kept until their shares expire
+ALTER TABLE files DROP CONSTRAINT files_user_id_fkey;
+ALTER TABLE files
+    ADD CONSTRAINT files_user_id_fkey FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE SET NULL;
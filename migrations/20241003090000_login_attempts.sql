@@ -0,0 +1,9 @@
+-- Add migration script here
+-- Failed login tracking, keyed by the submitted email so unknown accounts
+-- are throttled exactly like existing ones
+CREATE TABLE login_attempts (
+    email VARCHAR(255) PRIMARY KEY,
+    failed_attempts INTEGER NOT NULL DEFAULT 0,
+    last_failed_at TIMESTAMP WITH TIME ZONE,
+    locked_until TIMESTAMP WITH TIME ZONE
+);
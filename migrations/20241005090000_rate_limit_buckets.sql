@@ -0,0 +1,7 @@
+-- Add migration script here
+-- Token buckets for the shared rate limit store (RATE_LIMIT_STORE=postgres)
+CREATE TABLE rate_limit_buckets (
+    key VARCHAR(255) PRIMARY KEY,
+    tokens DOUBLE PRECISION NOT NULL,
+    updated_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+);
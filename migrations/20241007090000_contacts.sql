@@ -0,0 +1,10 @@
+-- Add migration script here
+-- Per-user address book
+CREATE TABLE contacts (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,          -- Owner of the address book
+    contact_user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,  -- The saved user
+    nickname VARCHAR(100),
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT NOW(),
+    UNIQUE (user_id, contact_user_id)
+);
@@ -0,0 +1,17 @@
+-- Add migration script here
+-- Block lists and consent for incoming shares
+CREATE TYPE share_status AS ENUM ('pending', 'accepted');
+
+ALTER TABLE shared_links
+    ADD COLUMN status share_status NOT NULL DEFAULT 'accepted';  -- Pending shares are hidden until the recipient accepts
+
+ALTER TABLE users
+    ADD COLUMN require_share_consent BOOLEAN NOT NULL DEFAULT FALSE;  -- Shares from non-contacts start pending
+
+CREATE TABLE blocked_users (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,          -- User doing the blocking
+    blocked_user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,  -- Sender that is blocked
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT NOW(),
+    UNIQUE (user_id, blocked_user_id)
+);
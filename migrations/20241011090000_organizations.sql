@@ -0,0 +1,27 @@
+-- Add migration script here
+-- Organizations (teams) with members, roles and sharing policies
+CREATE TYPE organization_role AS ENUM ('owner', 'admin', 'member');
+
+CREATE TABLE organizations (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    name VARCHAR(100) NOT NULL,
+    max_expiry_days INTEGER,                                   -- Longest expiration members may give a share
+    allowed_recipient_domains TEXT[] NOT NULL DEFAULT '{}',    -- Empty means any domain
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT NOW(),
+    updated_at TIMESTAMP WITH TIME ZONE DEFAULT NOW()
+);
+
+CREATE TABLE organization_members (
+    organization_id UUID NOT NULL REFERENCES organizations(id) ON DELETE CASCADE,
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    role organization_role NOT NULL DEFAULT 'member',
+    accepted_at TIMESTAMP WITH TIME ZONE,                      -- NULL until the invitation is accepted
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT NOW(),
+    PRIMARY KEY (organization_id, user_id)
+);
+
+CREATE INDEX idx_organization_members_pending ON organization_members(user_id) WHERE accepted_at IS NULL;
+
+-- Shares fanned out to a whole team remember which team they were sent to
+ALTER TABLE shared_links
+    ADD COLUMN organization_id UUID REFERENCES organizations(id) ON DELETE SET NULL;
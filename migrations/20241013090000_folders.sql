@@ -0,0 +1,33 @@
+-- Add migration script here
+-- Folders for organizing a sender's files, and folder-wide shares
+CREATE TABLE folders (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    parent_id UUID REFERENCES folders(id) ON DELETE CASCADE,  -- NULL for top-level folders
+    name VARCHAR(255) NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT NOW(),
+    updated_at TIMESTAMP WITH TIME ZONE DEFAULT NOW()
+);
+
+-- Files of a deleted folder fall back to the root. Copies made for folder
+-- shares keep the id of the file they were copied from, without a foreign key
+-- so the link outlives the original and a share never gets a second copy
+ALTER TABLE files
+    ADD COLUMN folder_id UUID REFERENCES folders(id) ON DELETE SET NULL,
+    ADD COLUMN source_file_id UUID;
+
+CREATE INDEX idx_files_source_file_id ON files(source_file_id) WHERE source_file_id IS NOT NULL;
+
+-- A folder share gives the recipient a copy of every file in the folder tree,
+-- including files added after the share was created
+CREATE TABLE folder_shares (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    folder_id UUID NOT NULL REFERENCES folders(id) ON DELETE CASCADE,
+    recipient_user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    password VARCHAR(255) NOT NULL,
+    expiration_date TIMESTAMP WITH TIME ZONE NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT NOW()
+);
+
+ALTER TABLE shared_links
+    ADD COLUMN folder_share_id UUID REFERENCES folder_shares(id) ON DELETE CASCADE;
@@ -0,0 +1,12 @@
+-- Add migration script here
+-- A transfer groups the files of one batch upload sent to one recipient
+CREATE TABLE transfers (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    user_id UUID REFERENCES users(id) ON DELETE SET NULL,
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT NOW()
+);
+
+ALTER TABLE shared_links
+    ADD COLUMN transfer_id UUID REFERENCES transfers(id) ON DELETE SET NULL;
+
+CREATE INDEX shared_links_transfer_id_idx ON shared_links (transfer_id);
@@ -0,0 +1,19 @@
+-- Add migration script here
+-- Resumable (tus) uploads in progress. The bytes received so far live in
+-- assets/uploads/<id>.part, the share settings wait here until completion.
+CREATE TABLE uploads (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    upload_length BIGINT NOT NULL,
+    upload_offset BIGINT NOT NULL DEFAULT 0,
+    file_name VARCHAR(255) NOT NULL,
+    recipient_email VARCHAR(255),
+    organization_id UUID,
+    folder_id UUID,
+    password VARCHAR(255) NOT NULL,
+    expiration_date TIMESTAMP WITH TIME ZONE NOT NULL,
+    expires_at TIMESTAMP WITH TIME ZONE NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT NOW()
+);
+
+CREATE INDEX uploads_expires_at_idx ON uploads (expires_at);
@@ -0,0 +1,4 @@
+-- Add migration script here
+-- Keep ciphertext uncompressed out of line so byte ranges can be read with
+-- substring() without loading the whole value
+ALTER TABLE files ALTER COLUMN encrypted_file SET STORAGE EXTERNAL;
@@ -0,0 +1,3 @@
+-- Add migration script here
+-- SHA-256 of the plaintext, sealed with the file's AES key as IV || AES-256-CBC
+ALTER TABLE files ADD COLUMN encrypted_digest BYTEA;
@@ -0,0 +1,11 @@
+-- Add migration script here
+CREATE TABLE signing_keys (
+    user_id UUID PRIMARY KEY REFERENCES users(id) ON DELETE CASCADE,
+    public_key TEXT NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT NOW()
+);
+
+-- Ed25519 signature by the sender, and the key it was made with so it can
+-- still be checked if the sender's key changes
+ALTER TABLE files ADD COLUMN signature BYTEA;
+ALTER TABLE files ADD COLUMN signer_public_key TEXT;
@@ -0,0 +1,30 @@
+-- Add migration script here
+CREATE TYPE key_status AS ENUM ('active', 'rotated', 'retired');
+
+-- Every RSA key pair a user has had. The private half lives in
+-- assets/private_keys/<id>.pem; keys created before rotation existed reuse
+-- the user's id so their existing key file keeps working.
+CREATE TABLE user_keys (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    public_key TEXT NOT NULL,
+    status key_status NOT NULL DEFAULT 'active',
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT NOW(),
+    retired_at TIMESTAMP WITH TIME ZONE
+);
+
+CREATE UNIQUE INDEX user_keys_active_idx ON user_keys (user_id) WHERE status = 'active';
+
+INSERT INTO user_keys (id, user_id, public_key)
+SELECT id, id, public_key FROM users WHERE public_key IS NOT NULL;
+
+-- The recipient key that wraps encrypted_aes_key
+ALTER TABLE files ADD COLUMN key_id UUID REFERENCES user_keys(id) ON DELETE SET NULL;
+
+UPDATE files f
+SET key_id = sl.recipient_user_id
+FROM shared_links sl
+WHERE sl.file_id = f.id
+AND sl.recipient_user_id IN (SELECT id FROM user_keys);
+
+CREATE INDEX files_key_id_idx ON files (key_id);
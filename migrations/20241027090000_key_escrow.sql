@@ -0,0 +1,10 @@
+-- Add migration script here
+-- Private keys wrapped for the disaster recovery escrow key. The escrow
+-- private key is kept offline and only brought in to restore lost key files.
+CREATE TABLE key_escrow (
+    key_id UUID PRIMARY KEY REFERENCES user_keys(id) ON DELETE CASCADE,
+    encrypted_key BYTEA NOT NULL,
+    encrypted_private_key BYTEA NOT NULL,
+    iv BYTEA NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT NOW()
+);
@@ -0,0 +1,9 @@
+-- Add migration script here
+-- Server-held secrets for KEY_STORE=postgres, sealed under a master key
+-- that is never stored in the database.
+CREATE TABLE secrets (
+    name VARCHAR(255) PRIMARY KEY,
+    sealed BYTEA NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT NOW(),
+    updated_at TIMESTAMP WITH TIME ZONE DEFAULT NOW()
+);
@@ -0,0 +1,5 @@
+-- Add migration script here
+CREATE TYPE key_type AS ENUM ('rsa', 'x25519');
+
+-- Existing keys are all RSA-2048
+ALTER TABLE user_keys ADD COLUMN key_type key_type NOT NULL DEFAULT 'rsa';
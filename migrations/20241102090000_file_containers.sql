@@ -0,0 +1,6 @@
+-- Add migration script here
+-- New files are stored as self-describing SSEF containers, which carry
+-- their own nonces. A NULL iv marks a container, files with an iv are
+-- plain AES-256-CBC from before containers.
+ALTER TABLE files ALTER COLUMN iv DROP NOT NULL;
+ALTER TABLE key_escrow ALTER COLUMN iv DROP NOT NULL;
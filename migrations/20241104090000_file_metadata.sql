@@ -0,0 +1,9 @@
+-- Add migration script here
+-- The name, MIME type and exact size of new files are sealed with the file
+-- key in encrypted_metadata. Their file_name is NULL and file_size is the
+-- padded stored size. The sender keeps a copy of the file key, wrapped with
+-- their own key, so they can still list what they sent.
+ALTER TABLE files ALTER COLUMN file_name DROP NOT NULL;
+ALTER TABLE files ADD COLUMN encrypted_metadata BYTEA;
+ALTER TABLE files ADD COLUMN sender_encrypted_aes_key BYTEA;
+ALTER TABLE files ADD COLUMN sender_key_id UUID REFERENCES user_keys(id) ON DELETE SET NULL;
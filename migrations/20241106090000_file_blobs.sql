@@ -0,0 +1,42 @@
+-- Add migration script here
+-- Ciphertext shared by identical uploads from one sender. dedup_tag is an
+-- HMAC of the plaintext digest under a key the server holds for that sender,
+-- so equal files from different senders are never linked.
+CREATE TABLE file_blobs (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    user_id UUID REFERENCES users(id) ON DELETE SET NULL,
+    dedup_tag BYTEA NOT NULL,
+    encrypted_file BYTEA NOT NULL,
+    ref_count INTEGER NOT NULL DEFAULT 0,
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT NOW(),
+    UNIQUE (user_id, dedup_tag)
+);
+
+-- Files keep their ciphertext inline unless they point at a blob
+ALTER TABLE files ALTER COLUMN encrypted_file DROP NOT NULL;
+ALTER TABLE files ADD COLUMN blob_id UUID REFERENCES file_blobs(id);
+
+CREATE INDEX files_blob_id_idx ON files (blob_id);
+
+-- Every file row pointing at a blob holds one reference, however it is deleted
+CREATE FUNCTION count_file_blob_refs() RETURNS TRIGGER AS $$
+BEGIN
+    IF TG_OP = 'INSERT' THEN
+        UPDATE file_blobs SET ref_count = ref_count + 1 WHERE id = NEW.blob_id;
+    ELSE
+        UPDATE file_blobs SET ref_count = ref_count - 1 WHERE id = OLD.blob_id;
+    END IF;
+
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER files_blob_ref_added
+    AFTER INSERT ON files
+    FOR EACH ROW WHEN (NEW.blob_id IS NOT NULL)
+    EXECUTE FUNCTION count_file_blob_refs();
+
+CREATE TRIGGER files_blob_ref_removed
+    AFTER DELETE ON files
+    FOR EACH ROW WHEN (OLD.blob_id IS NOT NULL)
+    EXECUTE FUNCTION count_file_blob_refs();
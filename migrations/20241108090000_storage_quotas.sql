@@ -0,0 +1,17 @@
+-- Add migration script here
+-- Storage quotas set by admins for single users and organizations. Anyone
+-- without a row gets the default quota from the configuration.
+CREATE TABLE user_quotas (
+    user_id UUID PRIMARY KEY REFERENCES users(id) ON DELETE CASCADE,
+    quota_bytes BIGINT NOT NULL CHECK (quota_bytes >= 0),              -- 0 means unlimited
+    updated_at TIMESTAMP WITH TIME ZONE DEFAULT NOW()
+);
+
+CREATE TABLE organization_quotas (
+    organization_id UUID PRIMARY KEY REFERENCES organizations(id) ON DELETE CASCADE,
+    quota_bytes BIGINT NOT NULL CHECK (quota_bytes >= 0),              -- 0 means unlimited
+    updated_at TIMESTAMP WITH TIME ZONE DEFAULT NOW()
+);
+
+-- Usage is the sum of file_size over the files a user sent
+CREATE INDEX idx_files_user_id ON files(user_id);
@@ -0,0 +1,9 @@
+-- Add migration script here
+-- Result of scanning a file's plaintext on upload, NULL when it was not scanned
+CREATE TYPE scan_status AS ENUM ('clean', 'quarantined');
+
+ALTER TABLE files
+    ADD COLUMN scan_status scan_status,
+    ADD COLUMN scan_signature TEXT;        -- What the scanner found in a quarantined file
+
+CREATE INDEX files_quarantined_idx ON files(created_at) WHERE scan_status = 'quarantined';
@@ -1,16 +1,54 @@
+use std::str::FromStr;
+
+use crate::{models::KeyType, utils::{compression::CompressionMode, container::SizePadding, crypto_pool}};
+
 #[derive(Debug, Clone)]
 pub struct Config {
     pub database_url: String,
     pub jwt_secret: String,
     pub jwt_maxage: i64,
     pub port: u16,
+    pub login_max_attempts: i32,
+    pub login_delay_after: i32,
+    pub login_lockout_minutes: i64,
+    pub login_ip_burst: u32,
+    pub login_ip_per_minute: u32,
+    pub rate_limit_store: String,
+    pub rate_limit_burst: u32,
+    pub rate_limit_per_minute: u32,
+    pub search_min_query_length: usize,
+    pub search_max_results: i64,
+    pub tus_max_size: i64,
+    pub upload_expiry_hours: i64,
+    pub escrow_public_key: Option<String>,
+    pub key_store: String,
+    pub key_store_dir: String,
+    pub key_store_master_key: Option<String>,
+    pub kms_url: Option<String>,
+    pub kms_token: Option<String>,
+    pub default_key_type: KeyType,
+    pub crypto_workers: usize,
+    pub crypto_max_queue: usize,
+    pub key_pool_size: usize,
+    pub size_padding: SizePadding,
+    pub dedup_uploads: bool,
+    pub compression: CompressionMode,
+    pub compression_level: i32,
+    pub max_file_size: i64,
+    pub max_request_size: usize,
+    pub user_storage_quota: i64,
+    pub organization_storage_quota: i64,
+    pub scanner: String,
+    pub clamd_address: String,
+    pub scanner_timeout_secs: u64,
 }
 
 impl Config {
 
     pub fn init() -> Config {
         let database_url = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set");
-        let jwt_secret = std::env::var("JWT_SECRET_KEY").expect("JWT_SECRET_KEY must be set");
+        // Left empty to use the secret kept in the key store
+        let jwt_secret = std::env::var("JWT_SECRET_KEY").unwrap_or_default();
         let jwt_maxage = std::env::var("JWT_MAXAGE").expect("JWT_MAXAGE must be set");
 
         Config {
@@ -18,7 +56,49 @@ impl Config {
             jwt_secret,
             jwt_maxage: jwt_maxage.parse::<i64>().unwrap(),
             port: 8000,
+            login_max_attempts: env_or("LOGIN_MAX_ATTEMPTS", 10),
+            login_delay_after: env_or("LOGIN_DELAY_AFTER", 3),
+            login_lockout_minutes: env_or("LOGIN_LOCKOUT_MINUTES", 15),
+            login_ip_burst: env_or("LOGIN_IP_BURST", 10),
+            login_ip_per_minute: env_or("LOGIN_IP_PER_MINUTE", 10),
+            rate_limit_store: env_or("RATE_LIMIT_STORE", "memory".to_string()),
+            rate_limit_burst: env_or("RATE_LIMIT_BURST", 60),
+            rate_limit_per_minute: env_or("RATE_LIMIT_PER_MINUTE", 120),
+            search_min_query_length: env_or("SEARCH_MIN_QUERY_LENGTH", 3),
+            search_max_results: env_or("SEARCH_MAX_RESULTS", 10),
+            tus_max_size: env_or("TUS_MAX_SIZE", 512 * 1024 * 1024),
+            upload_expiry_hours: env_or("UPLOAD_EXPIRY_HOURS", 24),
+            escrow_public_key: std::env::var("ESCROW_PUBLIC_KEY").ok(),
+            key_store: env_or("KEY_STORE", "filesystem".to_string()),
+            key_store_dir: env_or("KEY_STORE_DIR", "assets".to_string()),
+            key_store_master_key: std::env::var("KEY_STORE_MASTER_KEY").ok(),
+            kms_url: std::env::var("KMS_URL").ok(),
+            kms_token: std::env::var("KMS_TOKEN").ok(),
+            default_key_type: env_or("DEFAULT_KEY_TYPE", KeyType::Rsa),
+            crypto_workers: env_or("CRYPTO_WORKERS", crypto_pool::default_workers()),
+            crypto_max_queue: env_or("CRYPTO_MAX_QUEUE", 256),
+            key_pool_size: env_or("KEY_POOL_SIZE", 8),
+            size_padding: env_or("SIZE_PADDING", SizePadding::None),
+            dedup_uploads: env_or("DEDUP_UPLOADS", true),
+            compression: env_or("COMPRESSION", CompressionMode::None),
+            compression_level: env_or("COMPRESSION_LEVEL", 3),
+            max_file_size: env_or("MAX_FILE_SIZE", 512 * 1024 * 1024),
+            max_request_size: env_or("MAX_REQUEST_SIZE", 1024 * 1024 * 1024),
+            user_storage_quota: env_or("USER_STORAGE_QUOTA", 0),
+            organization_storage_quota: env_or("ORGANIZATION_STORAGE_QUOTA", 0),
+            scanner: env_or("SCANNER", "none".to_string()),
+            clamd_address: env_or("CLAMD_ADDRESS", "/var/run/clamav/clamd.ctl".to_string()),
+            scanner_timeout_secs: env_or("SCANNER_TIMEOUT_SECS", 30),
         }
     }
-    
-}
\ No newline at end of file
+
+}
+
+fn env_or<T: FromStr>(name: &str, default: T) -> T {
+    match std::env::var(name) {
+        Ok(value) => value
+            .parse::<T>()
+            .unwrap_or_else(|_| panic!("{} has an invalid value", name)),
+        Err(_) => default,
+    }
+}
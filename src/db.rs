@@ -3,7 +3,7 @@ use chrono::{DateTime, Utc};
 use sqlx::{Pool, Postgres};
 use uuid::Uuid;
 
-use crate::models::{File, ReceiveFileDetails, SentFileDetails, SharedLink, User};
+use crate::models::{BlockedUser, BlockedUserDetails, Contact, ContactDetails, ContactSuggestion, File, FileBlob, FileContent, FileInfo, Folder, FolderFileDetails, NewFile, FolderShare, FolderShareDetails, KeyStatus, LoginAttempt, Organization, OrganizationDetails, OrganizationMemberDetails, OrganizationMembership, OrganizationRole, ReceiveFileDetails, SentFileDetails, KeyEscrow, KeyInventory, KeyType, RewrapFile, ShareStatus, ShareableFile, SharedLink, OrganizationStorageUsage, QuarantinedFile, ScanStatus, StorageUsage, Upload, User, UserKey, UserKeyDetails, UserRole};
 
 #[derive(Debug, Clone)]
 pub struct DBClient {
@@ -14,6 +14,21 @@ impl DBClient {
     pub fn new(pool: Pool<Postgres>) -> Self {
         DBClient { pool }
     }
+
+    /// Deletes blobs once no file points at them any more, however the last
+    /// one was deleted. Reference counts are kept by a trigger on `files`.
+    async fn delete_unused_blobs(&self) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            r#"
+            DELETE FROM file_blobs
+            WHERE ref_count <= 0
+            "#
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
 }
 
 #[async_trait]
@@ -44,23 +59,36 @@ pub trait UserExt {
         password: String,
     ) -> Result<User, sqlx::Error>;
 
-    async fn save_user_key(&self, user_id: Uuid, public_key: String) -> Result<(), sqlx::Error>;
+    async fn save_signing_key(&self, user_id: Uuid, public_key: String) -> Result<(), sqlx::Error>;
+
+    async fn get_signing_key(&self, user_id: Uuid) -> Result<Option<String>, sqlx::Error>;
+
+    async fn update_share_consent(
+        &self,
+        user_id: Uuid,
+        require_share_consent: bool,
+    ) -> Result<User, sqlx::Error>;
 
-    async fn search_by_email(&self, user_id: Uuid, query: String)
+    async fn search_by_email(&self, user_id: Uuid, query: String, limit: i64)
         -> Result<Vec<User>, sqlx::Error>;
 
+    async fn create_transfer(
+        &self,
+        user_id: Uuid,
+    ) -> Result<Uuid, sqlx::Error>;
+
+    /// Returns `None` when the file would take the sender or the
+    /// organization over `file.limits`.
     async fn save_encrypted_file(
+        &self,
+        file: NewFile,
+    ) -> Result<Option<Uuid>, sqlx::Error>;
+
+    async fn get_file_blob(
         &self,
         user_id: Uuid,
-        file_name: String,
-        file_size: i64,
-        recipient_user_id: Uuid,
-        password: String,
-        expiration_date: DateTime<Utc>,
-        encrypted_aes_key: Vec<u8>,
-        encrypted_file: Vec<u8>,
-        iv: Vec<u8>,
-    ) -> Result<(), sqlx::Error>;
+        dedup_tag: &[u8],
+    ) -> Result<Option<FileBlob>, sqlx::Error>;
 
     async fn get_shared(
         &self,
@@ -73,18 +101,32 @@ pub trait UserExt {
         file_id: Uuid,
     ) -> Result<Option<File>, sqlx::Error>;
 
+    async fn get_file_info(
+        &self,
+        file_id: Uuid,
+    ) -> Result<Option<FileInfo>, sqlx::Error>;
+
+    async fn get_file_chunk(
+        &self,
+        file_id: Uuid,
+        offset: i64,
+        length: i64,
+    ) -> Result<Option<Vec<u8>>, sqlx::Error>;
+
     async fn get_sent_files(
         &self,
         user_id: Uuid,
         page: u32,
-        limit: usize
+        limit: usize,
+        organization_id: Option<Uuid>,
     ) -> Result<(Vec<SentFileDetails>, i64), sqlx::Error>;
 
     async fn get_receive_files(
         &self,
         user_id: Uuid,
         page: u32,
-        limit: usize
+        limit: usize,
+        organization_id: Option<Uuid>,
     ) -> Result<(Vec<ReceiveFileDetails>, i64), sqlx::Error>;
 
     async fn delete_expired_files(
@@ -105,19 +147,19 @@ impl UserExt for DBClient {
         if let Some(user_id) = user_id {
             user = sqlx::query_as!(
                 User,
-                r#"SELECT id, name, email, password, public_key, created_at, updated_at FROM users WHERE id = $1"#,
+                r#"SELECT id, name, email, password, public_key, role as "role: UserRole", disabled, password_reset_required, tokens_valid_after, require_share_consent, created_at, updated_at FROM users WHERE id = $1"#,
                 user_id
             ).fetch_optional(&self.pool).await?;
         } else if let Some(name) = name {
             user = sqlx::query_as!(
                 User,
-                r#"SELECT id, name, email, password, public_key, created_at, updated_at FROM users WHERE name = $1"#,
+                r#"SELECT id, name, email, password, public_key, role as "role: UserRole", disabled, password_reset_required, tokens_valid_after, require_share_consent, created_at, updated_at FROM users WHERE name = $1"#,
                 name
             ).fetch_optional(&self.pool).await?;
         } else if let Some(email) = email {
             user = sqlx::query_as!(
                 User,
-                r#"SELECT id, name, email, password, public_key, created_at, updated_at FROM users WHERE email = $1"#,
+                r#"SELECT id, name, email, password, public_key, role as "role: UserRole", disabled, password_reset_required, tokens_valid_after, require_share_consent, created_at, updated_at FROM users WHERE email = $1"#,
                 email
             ).fetch_optional(&self.pool).await?;
         }
@@ -136,7 +178,7 @@ impl UserExt for DBClient {
             r#"
             INSERT INTO users (name, email, password) 
             VALUES ($1, $2, $3) 
-            RETURNING id, name, email, password, public_key, created_at, updated_at
+            RETURNING id, name, email, password, public_key, role as "role: UserRole", disabled, password_reset_required, tokens_valid_after, require_share_consent, created_at, updated_at
             "#,
             name.into(),
             email.into(),
@@ -158,7 +200,7 @@ impl UserExt for DBClient {
             UPDATE users
             SET name = $1, updated_at = Now()
             WHERE id = $2
-            RETURNING id, name, email, password, public_key, created_at, updated_at
+            RETURNING id, name, email, password, public_key, role as "role: UserRole", disabled, password_reset_required, tokens_valid_after, require_share_consent, created_at, updated_at
             "#,
             new_name.into(),
             user_id
@@ -178,9 +220,9 @@ impl UserExt for DBClient {
             User,
             r#"
             UPDATE users
-            SET password = $1, updated_at = Now()
+            SET password = $1, password_reset_required = FALSE, updated_at = Now()
             WHERE id = $2
-            RETURNING id, name, email, password, public_key, created_at, updated_at
+            RETURNING id, name, email, password, public_key, role as "role: UserRole", disabled, password_reset_required, tokens_valid_after, require_share_consent, created_at, updated_at
             "#,
             new_password,
             user_id
@@ -191,89 +233,299 @@ impl UserExt for DBClient {
         Ok(user)
     }
 
-    async fn save_user_key(&self, user_id: Uuid, public_key: String) -> Result<(), sqlx::Error> {
-        sqlx::query_as!(
+    async fn save_signing_key(&self, user_id: Uuid, public_key: String) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            r#"
+            INSERT INTO signing_keys (user_id, public_key)
+            VALUES ($1, $2)
+            ON CONFLICT (user_id) DO UPDATE SET public_key = EXCLUDED.public_key, created_at = NOW()
+            "#,
+            user_id,
+            public_key
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn get_signing_key(&self, user_id: Uuid) -> Result<Option<String>, sqlx::Error> {
+        let public_key = sqlx::query_scalar!(
+            r#"
+            SELECT public_key FROM signing_keys WHERE user_id = $1
+            "#,
+            user_id
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(public_key)
+    }
+    async fn update_share_consent(
+        &self,
+        user_id: Uuid,
+        require_share_consent: bool,
+    ) -> Result<User, sqlx::Error> {
+        let user = sqlx::query_as!(
             User,
             r#"
             UPDATE users
-            SET public_key = $1, updated_at = Now()
+            SET require_share_consent = $1, updated_at = Now()
             WHERE id = $2
-            RETURNING id, name, email, password, public_key, created_at, updated_at
+            RETURNING id, name, email, password, public_key, role as "role: UserRole", disabled, password_reset_required, tokens_valid_after, require_share_consent, created_at, updated_at
             "#,
-            public_key,
+            require_share_consent,
             user_id
         )
         .fetch_one(&self.pool)
         .await?;
 
-        Ok(())
+        Ok(user)
     }
+
     async fn search_by_email(
         &self,
         user_id: Uuid,
         query: String,
+        limit: i64,
     ) -> Result<Vec<User>, sqlx::Error> {
+        // Only contacts, organization co-members and users this account has
+        // already exchanged files with are searchable, contacts first
         let user = sqlx::query_as!(
             User,
             r#"
-            SELECT id, name, email, password, public_key, created_at, updated_at
-            FROM users
-            WHERE email LIKE $1
-            AND public_key IS NOT NULL
-            AND id != $2
+            SELECT u.id, u.name, u.email, u.password, u.public_key, u.role as "role: UserRole", u.disabled, u.password_reset_required, u.tokens_valid_after, u.require_share_consent, u.created_at, u.updated_at
+            FROM users u
+            LEFT JOIN contacts c ON c.contact_user_id = u.id AND c.user_id = $2
+            WHERE (u.email LIKE $1 ESCAPE '\' OR c.nickname ILIKE $1 ESCAPE '\')
+            AND u.public_key IS NOT NULL
+            AND u.id != $2
+            AND (
+                c.id IS NOT NULL
+                OR u.id IN (
+                    SELECT sl.recipient_user_id
+                    FROM shared_links sl
+                    JOIN files f ON sl.file_id = f.id
+                    WHERE f.user_id = $2
+                    UNION
+                    SELECT f.user_id
+                    FROM shared_links sl
+                    JOIN files f ON sl.file_id = f.id
+                    WHERE sl.recipient_user_id = $2
+                    UNION
+                    SELECT other.user_id
+                    FROM organization_members mine
+                    JOIN organization_members other ON mine.organization_id = other.organization_id
+                    WHERE mine.user_id = $2
+                    AND mine.accepted_at IS NOT NULL
+                    AND other.accepted_at IS NOT NULL
+                )
+            )
+            ORDER BY c.id IS NULL, u.email
+            LIMIT $3
             "#,
             query,
-            user_id
+            user_id,
+            limit
         )
         .fetch_all(&self.pool)
         .await?;
 
         Ok(user)
     }
-    async fn save_encrypted_file(
+    async fn create_transfer(
         &self,
         user_id: Uuid,
-        file_name: String,
-        file_size: i64,
-        recipient_user_ud: Uuid,
-        password: String,
-        expiration_date: DateTime<Utc>,
-        encrypted_aes_key: Vec<u8>,
-        encrypted_file: Vec<u8>,
-        iv: Vec<u8>,
-    ) -> Result<(), sqlx::Error> {
-        // Insert into the files table and get the file_id
+    ) -> Result<Uuid, sqlx::Error> {
+        let transfer_id = sqlx::query_scalar!(
+            r#"
+            INSERT INTO transfers (user_id)
+            VALUES ($1)
+            RETURNING id
+            "#,
+            user_id
+        )
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(transfer_id)
+    }
+
+    async fn save_encrypted_file(
+        &self,
+        file: NewFile,
+    ) -> Result<Option<Uuid>, sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+
+        // Concurrent uploads of the sender, or through the same organization,
+        // wait for each other here so none of them is missed in the usage
+        if let Some(limit) = file.limits.user {
+            sqlx::query!(
+                r#"
+                SELECT id FROM users WHERE id = $1 FOR UPDATE
+                "#,
+                file.user_id
+            )
+            .fetch_optional(&mut *tx)
+            .await?;
+
+            let used = sqlx::query_scalar!(
+                r#"
+                SELECT COALESCE(SUM(file_size), 0)::BIGINT AS "used!"
+                FROM files
+                WHERE user_id = $1
+                AND scan_status IS DISTINCT FROM 'quarantined'
+                "#,
+                file.user_id
+            )
+            .fetch_one(&mut *tx)
+            .await?;
+
+            if used.saturating_add(file.file_size) > limit {
+                return Ok(None);
+            }
+        }
+
+        if let (Some(limit), Some(organization_id)) = (file.limits.organization, file.organization_id) {
+            sqlx::query!(
+                r#"
+                SELECT id FROM organizations WHERE id = $1 FOR UPDATE
+                "#,
+                organization_id
+            )
+            .fetch_optional(&mut *tx)
+            .await?;
+
+            let used = sqlx::query_scalar!(
+                r#"
+                SELECT COALESCE(SUM(f.file_size), 0)::BIGINT AS "used!"
+                FROM files f
+                WHERE EXISTS (
+                    SELECT 1 FROM shared_links sl
+                    WHERE sl.file_id = f.id
+                    AND sl.organization_id = $1
+                )
+                AND f.scan_status IS DISTINCT FROM 'quarantined'
+                "#,
+                organization_id
+            )
+            .fetch_one(&mut *tx)
+            .await?;
+
+            if used.saturating_add(file.file_size) > limit {
+                return Ok(None);
+            }
+        }
+
+        let (encrypted_file, blob_id) = match file.content {
+            FileContent::Inline(encrypted_file) => (Some(encrypted_file), None),
+            FileContent::Blob(blob_id) => (None, Some(blob_id)),
+            FileContent::NewBlob { dedup_tag, encrypted_file } => {
+                let blob_id = sqlx::query_scalar!(
+                    r#"
+                    INSERT INTO file_blobs (user_id, dedup_tag, encrypted_file)
+                    VALUES ($1, $2, $3)
+                    ON CONFLICT (user_id, dedup_tag) DO NOTHING
+                    RETURNING id
+                    "#,
+                    file.user_id,
+                    dedup_tag,
+                    encrypted_file
+                )
+                .fetch_optional(&mut *tx)
+                .await?;
+
+                // A concurrent upload of the same content got there first
+                match blob_id {
+                    Some(blob_id) => (None, Some(blob_id)),
+                    None => (Some(encrypted_file), None),
+                }
+            },
+        };
+
+        // Insert into the files table and get the file_id; the blob's
+        // reference count is kept by a trigger
         let file_id: Uuid = sqlx::query_scalar!(
             r#"
-            INSERT INTO files (user_id, file_name, file_size, encrypted_aes_key, encrypted_file, iv, created_at)
-            VALUES ($1, $2, $3, $4, $5, $6, NOW())
+            INSERT INTO files (user_id, file_name, file_size, encrypted_aes_key, encrypted_file, blob_id, iv, encrypted_digest, encrypted_metadata, signature, signer_public_key, key_id, sender_encrypted_aes_key, sender_key_id, folder_id, scan_status, scan_signature, source_file_id, created_at)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17, $18, NOW())
             RETURNING id
             "#,
-            user_id,
-            file_name,
-            file_size,
-            encrypted_aes_key,
+            file.user_id,
+            file.file_name,
+            file.file_size,
+            file.encrypted_aes_key,
             encrypted_file,
-            iv
+            blob_id,
+            file.iv,
+            file.encrypted_digest,
+            file.encrypted_metadata,
+            file.signature,
+            file.signer_public_key,
+            file.key_id,
+            file.sender_encrypted_aes_key,
+            file.sender_key_id,
+            file.folder_id,
+            file.scan_status as Option<ScanStatus>,
+            file.scan_signature,
+            file.source_file_id
         )
-        .fetch_one(&self.pool)
+        .fetch_one(&mut *tx)
         .await?;
 
         // Insert into the shared_links table using the returned file_id
         sqlx::query!(
             r#"
-            INSERT INTO shared_links (file_id, recipient_user_id, password, expiration_date, created_at)
-            VALUES ($1, $2, $3, $4, NOW())
+            INSERT INTO shared_links (file_id, recipient_user_id, password, expiration_date, status, organization_id, folder_share_id, transfer_id, created_at)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, NOW())
             "#,
             file_id,
-            recipient_user_ud,
-            password,
-            expiration_date
+            file.recipient_user_id,
+            file.password,
+            file.expiration_date,
+            file.status as ShareStatus,
+            file.organization_id,
+            file.folder_share_id,
+            file.transfer_id
         )
-        .execute(&self.pool)
+        .execute(&mut *tx)
         .await?;
 
-        Ok(())
+        tx.commit().await?;
+
+        Ok(Some(file_id))
+    }
+
+    async fn get_file_blob(
+        &self,
+        user_id: Uuid,
+        dedup_tag: &[u8],
+    ) -> Result<Option<FileBlob>, sqlx::Error> {
+        // Any file on the blob with a usable sender copy of the key will do
+        let blob = sqlx::query_as!(
+            FileBlob,
+            r#"
+            SELECT
+                b.id,
+                f.sender_key_id AS "sender_key_id!",
+                f.sender_encrypted_aes_key AS "sender_encrypted_aes_key!",
+                f.encrypted_metadata AS "encrypted_metadata!"
+            FROM file_blobs b
+            JOIN files f ON f.blob_id = b.id
+            JOIN user_keys k ON k.id = f.sender_key_id AND k.status <> 'retired'
+            WHERE b.user_id = $1
+            AND b.dedup_tag = $2
+            AND f.sender_encrypted_aes_key IS NOT NULL
+            AND f.encrypted_metadata IS NOT NULL
+            LIMIT 1
+            "#,
+            user_id,
+            dedup_tag
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(blob)
     }
 
     async fn get_shared(
@@ -284,11 +536,18 @@ impl UserExt for DBClient {
         let shared_link = sqlx::query_as!(
             SharedLink,
             r#"
-            SELECT id, file_id, recipient_user_id, password, expiration_date, created_at
+            SELECT id, file_id, recipient_user_id, password, expiration_date, status as "status: ShareStatus", organization_id, folder_share_id, transfer_id, created_at
             FROM shared_links
             WHERE id = $1
             AND recipient_user_id = $2
+            AND status = 'accepted'
             AND expiration_date > NOW()
+            -- Quarantined files are never handed out
+            AND NOT EXISTS (
+                SELECT 1 FROM files f
+                WHERE f.id = shared_links.file_id
+                AND f.scan_status = 'quarantined'
+            )
             "#,
             shared_id,
             user_id,
@@ -306,7 +565,40 @@ impl UserExt for DBClient {
         let file = sqlx::query_as!(
             File,
             r#"
-            SELECT id, user_id, file_name, file_size, encrypted_aes_key, encrypted_file, iv, created_at
+            SELECT
+                f.id,
+                f.user_id,
+                f.file_name,
+                f.file_size,
+                f.encrypted_aes_key,
+                COALESCE(f.encrypted_file, b.encrypted_file) AS "encrypted_file!",
+                f.blob_id,
+                f.iv,
+                f.encrypted_digest,
+                f.encrypted_metadata,
+                f.key_id,
+                f.folder_id,
+                f.created_at
+            FROM files f
+            LEFT JOIN file_blobs b ON b.id = f.blob_id
+            WHERE f.id = $1
+            "#,
+            file_id
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(file)
+    }
+
+    async fn get_file_info(
+        &self,
+        file_id: Uuid,
+    ) -> Result<Option<FileInfo>, sqlx::Error> {
+        let file = sqlx::query_as!(
+            FileInfo,
+            r#"
+            SELECT id, user_id, file_name, file_size, encrypted_aes_key, iv, encrypted_digest, encrypted_metadata, signature, signer_public_key, key_id, created_at
             FROM files
             WHERE id = $1
             "#,
@@ -317,51 +609,102 @@ impl UserExt for DBClient {
 
         Ok(file)
     }
+
+    async fn get_file_chunk(
+        &self,
+        file_id: Uuid,
+        offset: i64,
+        length: i64,
+    ) -> Result<Option<Vec<u8>>, sqlx::Error> {
+        // substring() is 1-based and only reads the needed part of the value
+        let chunk = sqlx::query_scalar!(
+            r#"
+            SELECT substring(COALESCE(f.encrypted_file, b.encrypted_file) FROM ($2::BIGINT + 1)::INT FOR $3::BIGINT::INT) AS "chunk!"
+            FROM files f
+            LEFT JOIN file_blobs b ON b.id = f.blob_id
+            WHERE f.id = $1
+            "#,
+            file_id,
+            offset,
+            length
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(chunk)
+    }
     async fn get_sent_files(
         &self,
         user_id: Uuid,
         page: u32,
-        limit: usize
+        limit: usize,
+        organization_id: Option<Uuid>,
     ) -> Result<(Vec<SentFileDetails>, i64), sqlx::Error> {
         let offset = (page - 1) * limit as u32;
 
+        // Pages are counted in units, where a transfer counts once
         let files = sqlx::query_as!(
             SentFileDetails,
             r#"
+                WITH units AS (
+                    SELECT
+                        COALESCE(sl.transfer_id, sl.id) AS unit_id,
+                        MAX(sl.created_at) AS created_at
+                    FROM shared_links sl
+                    JOIN files f ON sl.file_id = f.id
+                    WHERE f.user_id = $1
+                    AND f.scan_status IS DISTINCT FROM 'quarantined'
+                    AND ($4::UUID IS NULL OR sl.organization_id = $4)
+                    GROUP BY 1
+                    ORDER BY 2 DESC
+                    LIMIT $2
+                    OFFSET $3
+                )
                 SELECT
                     f.id AS file_id,
-                    f.file_name,
+                    COALESCE(f.file_name, '') AS "file_name!",
+                    f.sender_key_id AS key_id,
+                    f.sender_encrypted_aes_key AS encrypted_aes_key,
+                    f.encrypted_metadata,
                     u.email AS recipient_email,
+                    sl.status as "status: ShareStatus",
+                    sl.transfer_id,
                     sl.expiration_date,
                     sl.created_at
                 FROM 
-                    shared_links sl
+                    units
+                JOIN 
+                    shared_links sl ON COALESCE(sl.transfer_id, sl.id) = units.unit_id
                 JOIN 
                     files f ON sl.file_id = f.id
                 JOIN 
                     users u ON sl.recipient_user_id = u.id
                 WHERE 
                     f.user_id = $1
+                    AND f.scan_status IS DISTINCT FROM 'quarantined'
+                    AND ($4::UUID IS NULL OR sl.organization_id = $4)
                 ORDER BY 
-                    sl.created_at DESC 
-                LIMIT $2 
-                OFFSET $3
+                    units.created_at DESC, units.unit_id, sl.created_at
             "#,
             user_id,
             limit as i64,
             offset as i64,
+            organization_id,
         )
         .fetch_all(&self.pool)
         .await?;
 
         let count_row = sqlx::query_scalar!(
             r#"
-                SELECT COUNT(*)
+                SELECT COUNT(DISTINCT COALESCE(sl.transfer_id, sl.id))
                 FROM shared_links sl
                 JOIN files f ON sl.file_id = f.id
                 WHERE f.user_id = $1
+                AND f.scan_status IS DISTINCT FROM 'quarantined'
+                AND ($2::UUID IS NULL OR sl.organization_id = $2)
             "#,
             user_id,
+            organization_id,
         )
         .fetch_one(&self.pool)
         .await?;
@@ -375,47 +718,75 @@ impl UserExt for DBClient {
         &self,
         user_id: Uuid,
         page: u32,
-        limit: usize
+        limit: usize,
+        organization_id: Option<Uuid>,
     ) -> Result<(Vec<ReceiveFileDetails>, i64), sqlx::Error> {
         let offset = (page - 1) * limit as u32;
 
         let files = sqlx::query_as!(
             ReceiveFileDetails,
             r#"
+                WITH units AS (
+                    SELECT
+                        COALESCE(sl.transfer_id, sl.id) AS unit_id,
+                        MAX(sl.created_at) AS created_at
+                    FROM shared_links sl
+                    JOIN files f ON sl.file_id = f.id
+                    WHERE sl.recipient_user_id = $1
+                    AND sl.status = 'accepted'
+                    AND f.scan_status IS DISTINCT FROM 'quarantined'
+                    AND ($4::UUID IS NULL OR sl.organization_id = $4)
+                    GROUP BY 1
+                    ORDER BY 2 DESC
+                    LIMIT $2
+                    OFFSET $3
+                )
                 SELECT
                     sl.id AS file_id,
-                    f.file_name,
-                    u.email AS sender_email,
+                    COALESCE(f.file_name, '') AS "file_name!",
+                    COALESCE(f.key_id, sl.recipient_user_id) AS key_id,
+                    f.encrypted_aes_key AS "encrypted_aes_key?",
+                    f.encrypted_metadata,
+                    COALESCE(u.email, 'deleted user') AS "sender_email!",
+                    sl.transfer_id,
                     sl.expiration_date,
                     sl.created_at
                 FROM 
-                    shared_links sl
+                    units
                 JOIN 
-                    files f ON sl.file_id = f.id
+                    shared_links sl ON COALESCE(sl.transfer_id, sl.id) = units.unit_id
                 JOIN 
+                    files f ON sl.file_id = f.id
+                LEFT JOIN 
                     users u ON f.user_id = u.id
                 WHERE 
                     sl.recipient_user_id = $1
+                    AND sl.status = 'accepted'
+                    AND f.scan_status IS DISTINCT FROM 'quarantined'
+                    AND ($4::UUID IS NULL OR sl.organization_id = $4)
                 ORDER BY 
-                    sl.created_at DESC 
-                LIMIT $2 
-                OFFSET $3
+                    units.created_at DESC, units.unit_id, sl.created_at
             "#,
             user_id,
             limit as i64,
             offset as i64,
+            organization_id,
         )
         .fetch_all(&self.pool)
         .await?;
 
         let count_row = sqlx::query_scalar!(
             r#"
-                SELECT COUNT(*)
+                SELECT COUNT(DISTINCT COALESCE(sl.transfer_id, sl.id))
                 FROM shared_links sl
                 JOIN files f ON sl.file_id = f.id
                 WHERE sl.recipient_user_id = $1
+                AND sl.status = 'accepted'
+                AND f.scan_status IS DISTINCT FROM 'quarantined'
+                AND ($2::UUID IS NULL OR sl.organization_id = $2)
             "#,
             user_id,
+            organization_id,
         )
         .fetch_one(&self.pool)
         .await?;
@@ -428,7 +799,21 @@ impl UserExt for DBClient {
     async fn delete_expired_files(
         &self
     ) -> Result<(), sqlx::Error> {
-        
+        // Files are always saved with a link, so one without any has lost
+        // its last recipient, e.g. to a deleted account
+        sqlx::query!(
+            r#"
+            DELETE FROM files f
+            WHERE NOT EXISTS (
+                SELECT 1
+                FROM shared_links sl
+                WHERE sl.file_id = f.id
+            )
+            "#,
+        )
+        .execute(&self.pool)
+        .await?;
+
         let expired_shared_links: Vec<Uuid> = sqlx::query_scalar!(
             r#"
             SELECT sl.id
@@ -440,6 +825,9 @@ impl UserExt for DBClient {
         .await?;
 
         if expired_shared_links.is_empty() {
+            // Files can also go when shares are declined or senders blocked
+            self.delete_unused_blobs().await?;
+
             println!("No expired files or shared links to delete.");
             return Ok(());
         }
@@ -479,9 +867,2838 @@ impl UserExt for DBClient {
         .execute(&self.pool)
         .await?;
 
+        self.delete_unused_blobs().await?;
+
+        // Transfers whose files are all gone
+        sqlx::query!(
+            r#"
+            DELETE FROM transfers t
+            WHERE t.created_at < NOW() - INTERVAL '1 day'
+            AND NOT EXISTS (
+                SELECT 1 FROM shared_links sl
+                WHERE sl.transfer_id = t.id
+            )
+            "#
+        )
+        .execute(&self.pool)
+        .await?;
+
         println!("Successfully deleted expired files and their shared links.");
 
         Ok(())
 
     }
 }
+
+#[async_trait]
+pub trait AdminExt {
+    async fn get_users(
+        &self,
+        page: u32,
+        limit: usize,
+        search: Option<String>,
+    ) -> Result<(Vec<User>, i64), sqlx::Error>;
+
+    async fn set_user_disabled(
+        &self,
+        user_id: Uuid,
+        disabled: bool,
+    ) -> Result<Option<User>, sqlx::Error>;
+
+    async fn require_password_reset(
+        &self,
+        user_id: Uuid,
+    ) -> Result<Option<User>, sqlx::Error>;
+
+    async fn revoke_user_tokens(
+        &self,
+        user_id: Uuid,
+    ) -> Result<Option<User>, sqlx::Error>;
+
+    async fn delete_user(
+        &self,
+        user_id: Uuid,
+        keep_files: bool,
+    ) -> Result<bool, sqlx::Error>;
+}
+
+#[async_trait]
+impl AdminExt for DBClient {
+    async fn get_users(
+        &self,
+        page: u32,
+        limit: usize,
+        search: Option<String>,
+    ) -> Result<(Vec<User>, i64), sqlx::Error> {
+        let offset = (page - 1) * limit as u32;
+        let search_pattern = search.map(|query| format!("%{}%", query));
+
+        let users = sqlx::query_as!(
+            User,
+            r#"
+            SELECT id, name, email, password, public_key, role as "role: UserRole", disabled, password_reset_required, tokens_valid_after, require_share_consent, created_at, updated_at
+            FROM users
+            WHERE $1::TEXT IS NULL OR name ILIKE $1 OR email ILIKE $1
+            ORDER BY created_at DESC
+            LIMIT $2
+            OFFSET $3
+            "#,
+            search_pattern,
+            limit as i64,
+            offset as i64,
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        let count_row = sqlx::query_scalar!(
+            r#"
+            SELECT COUNT(*)
+            FROM users
+            WHERE $1::TEXT IS NULL OR name ILIKE $1 OR email ILIKE $1
+            "#,
+            search_pattern,
+        )
+        .fetch_one(&self.pool)
+        .await?;
+
+        let total_count = count_row.unwrap_or(0);
+
+        Ok((users, total_count))
+    }
+
+    async fn set_user_disabled(
+        &self,
+        user_id: Uuid,
+        disabled: bool,
+    ) -> Result<Option<User>, sqlx::Error> {
+        let user = sqlx::query_as!(
+            User,
+            r#"
+            UPDATE users
+            SET disabled = $1, updated_at = Now()
+            WHERE id = $2
+            RETURNING id, name, email, password, public_key, role as "role: UserRole", disabled, password_reset_required, tokens_valid_after, require_share_consent, created_at, updated_at
+            "#,
+            disabled,
+            user_id
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(user)
+    }
+
+    async fn require_password_reset(
+        &self,
+        user_id: Uuid,
+    ) -> Result<Option<User>, sqlx::Error> {
+        let user = sqlx::query_as!(
+            User,
+            r#"
+            UPDATE users
+            SET password_reset_required = TRUE, updated_at = Now()
+            WHERE id = $1
+            RETURNING id, name, email, password, public_key, role as "role: UserRole", disabled, password_reset_required, tokens_valid_after, require_share_consent, created_at, updated_at
+            "#,
+            user_id
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(user)
+    }
+
+    async fn revoke_user_tokens(
+        &self,
+        user_id: Uuid,
+    ) -> Result<Option<User>, sqlx::Error> {
+        let user = sqlx::query_as!(
+            User,
+            r#"
+            UPDATE users
+            SET tokens_valid_after = Now(), updated_at = Now()
+            WHERE id = $1
+            RETURNING id, name, email, password, public_key, role as "role: UserRole", disabled, password_reset_required, tokens_valid_after, require_share_consent, created_at, updated_at
+            "#,
+            user_id
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(user)
+    }
+
+    async fn delete_user(
+        &self,
+        user_id: Uuid,
+        keep_files: bool,
+    ) -> Result<bool, sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+
+        // Sent files are detached from the user by the foreign key when kept,
+        // so only their removal needs to be explicit. Copies made for the
+        // user's folder shares are kept as plain shares, as the folders go
+        if keep_files {
+            sqlx::query!(
+                r#"
+                UPDATE shared_links
+                SET folder_share_id = NULL
+                WHERE folder_share_id IN (
+                    SELECT fs.id
+                    FROM folder_shares fs
+                    JOIN folders f ON f.id = fs.folder_id
+                    WHERE f.user_id = $1
+                )
+                "#,
+                user_id
+            )
+            .execute(&mut *tx)
+            .await?;
+        } else {
+            sqlx::query!(
+                r#"
+                DELETE FROM files
+                WHERE user_id = $1
+                "#,
+                user_id
+            )
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        let result = sqlx::query!(
+            r#"
+            DELETE FROM users
+            WHERE id = $1
+            "#,
+            user_id
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+}
+
+#[async_trait]
+pub trait LoginAttemptExt {
+    async fn get_login_attempt(
+        &self,
+        email: &str,
+    ) -> Result<Option<LoginAttempt>, sqlx::Error>;
+
+    async fn start_login_attempt(
+        &self,
+        email: &str,
+        delay_after: i32,
+        max_attempts: i32,
+        lockout_minutes: i64,
+    ) -> Result<Option<LoginAttempt>, sqlx::Error>;
+
+    async fn reset_login_attempts(
+        &self,
+        email: &str,
+    ) -> Result<(), sqlx::Error>;
+
+    async fn delete_stale_login_attempts(
+        &self,
+        lockout_minutes: i64,
+    ) -> Result<(), sqlx::Error>;
+}
+
+#[async_trait]
+impl LoginAttemptExt for DBClient {
+    async fn get_login_attempt(
+        &self,
+        email: &str,
+    ) -> Result<Option<LoginAttempt>, sqlx::Error> {
+        let attempt = sqlx::query_as!(
+            LoginAttempt,
+            r#"
+            SELECT email, failed_attempts, last_failed_at, locked_until
+            FROM login_attempts
+            WHERE email = $1
+            "#,
+            email
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(attempt)
+    }
+
+    async fn start_login_attempt(
+        &self,
+        email: &str,
+        delay_after: i32,
+        max_attempts: i32,
+        lockout_minutes: i64,
+    ) -> Result<Option<LoginAttempt>, sqlx::Error> {
+        // Every attempt counts as failed until it succeeds, and is checked
+        // and counted in one statement so concurrent attempts cannot all slip
+        // past the delay. Nothing is returned while the email is locked or
+        // delayed. Reaching the limit locks the account and starts a fresh
+        // count for when the lock expires
+        let attempt = sqlx::query_as!(
+            LoginAttempt,
+            r#"
+            INSERT INTO login_attempts (email, failed_attempts, last_failed_at)
+            VALUES ($1, 1, NOW())
+            ON CONFLICT (email) DO UPDATE
+            SET failed_attempts = CASE
+                    WHEN login_attempts.failed_attempts + 1 >= $3 THEN 0
+                    ELSE login_attempts.failed_attempts + 1
+                END,
+                last_failed_at = NOW(),
+                locked_until = CASE
+                    WHEN login_attempts.failed_attempts + 1 >= $3 THEN NOW() + make_interval(mins => $4::INTEGER)
+                    ELSE login_attempts.locked_until
+                END
+            WHERE (login_attempts.locked_until IS NULL OR login_attempts.locked_until <= NOW())
+            AND (
+                login_attempts.failed_attempts < $2
+                OR login_attempts.last_failed_at IS NULL
+                OR login_attempts.last_failed_at
+                    + make_interval(secs => power(2, LEAST(login_attempts.failed_attempts - $2, 6))) <= NOW()
+            )
+            RETURNING email, failed_attempts, last_failed_at, locked_until
+            "#,
+            email,
+            delay_after,
+            max_attempts,
+            lockout_minutes as i32
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(attempt)
+    }
+
+    async fn reset_login_attempts(
+        &self,
+        email: &str,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            r#"
+            DELETE FROM login_attempts
+            WHERE email = $1
+            "#,
+            email
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn delete_stale_login_attempts(
+        &self,
+        lockout_minutes: i64,
+    ) -> Result<(), sqlx::Error> {
+        // Any email can be submitted, so counts are forgotten once they no
+        // longer lock or delay anything
+        sqlx::query!(
+            r#"
+            DELETE FROM login_attempts
+            WHERE (locked_until IS NULL OR locked_until < NOW())
+            AND (last_failed_at IS NULL OR last_failed_at < NOW() - make_interval(mins => $1::INTEGER))
+            "#,
+            lockout_minutes as i32
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+pub trait RateLimitExt {
+    async fn take_rate_limit_token(
+        &self,
+        key: &str,
+        capacity: f64,
+        refill_per_second: f64,
+    ) -> Result<(bool, f64), sqlx::Error>;
+
+    async fn delete_stale_rate_limits(
+        &self
+    ) -> Result<(), sqlx::Error>;
+}
+
+#[async_trait]
+impl RateLimitExt for DBClient {
+    async fn take_rate_limit_token(
+        &self,
+        key: &str,
+        capacity: f64,
+        refill_per_second: f64,
+    ) -> Result<(bool, f64), sqlx::Error> {
+        let now = Utc::now();
+        let mut tx = self.pool.begin().await?;
+
+        sqlx::query!(
+            r#"
+            INSERT INTO rate_limit_buckets (key, tokens, updated_at)
+            VALUES ($1, $2, $3)
+            ON CONFLICT (key) DO NOTHING
+            "#,
+            key,
+            capacity,
+            now
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        // Row lock keeps concurrent instances from spending the same token
+        let bucket = sqlx::query!(
+            r#"
+            SELECT tokens, updated_at
+            FROM rate_limit_buckets
+            WHERE key = $1
+            FOR UPDATE
+            "#,
+            key
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+
+        let elapsed = (now - bucket.updated_at).num_milliseconds().max(0) as f64 / 1000.0;
+        let refilled = (bucket.tokens + elapsed * refill_per_second).min(capacity);
+        let allowed = refilled >= 1.0;
+        let tokens = if allowed { refilled - 1.0 } else { refilled };
+
+        sqlx::query!(
+            r#"
+            UPDATE rate_limit_buckets
+            SET tokens = $1, updated_at = $2
+            WHERE key = $3
+            "#,
+            tokens,
+            now,
+            key
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+
+        Ok((allowed, tokens))
+    }
+
+    async fn delete_stale_rate_limits(
+        &self
+    ) -> Result<(), sqlx::Error> {
+        // Any bucket idle for a day has long been refilled
+        sqlx::query!(
+            r#"
+            DELETE FROM rate_limit_buckets
+            WHERE updated_at < NOW() - INTERVAL '1 day'
+            "#,
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+pub trait ContactExt {
+    async fn save_contact(
+        &self,
+        user_id: Uuid,
+        contact_user_id: Uuid,
+        nickname: Option<String>,
+    ) -> Result<Contact, sqlx::Error>;
+
+    async fn update_contact_nickname(
+        &self,
+        user_id: Uuid,
+        contact_id: Uuid,
+        nickname: Option<String>,
+    ) -> Result<Option<Contact>, sqlx::Error>;
+
+    async fn delete_contact(
+        &self,
+        user_id: Uuid,
+        contact_id: Uuid,
+    ) -> Result<bool, sqlx::Error>;
+
+    async fn get_contacts(
+        &self,
+        user_id: Uuid,
+        page: u32,
+        limit: usize,
+    ) -> Result<(Vec<ContactDetails>, i64), sqlx::Error>;
+
+    async fn get_contact_suggestions(
+        &self,
+        user_id: Uuid,
+        limit: i64,
+    ) -> Result<Vec<ContactSuggestion>, sqlx::Error>;
+
+    async fn is_contact(
+        &self,
+        user_id: Uuid,
+        contact_user_id: Uuid,
+    ) -> Result<bool, sqlx::Error>;
+}
+
+#[async_trait]
+impl ContactExt for DBClient {
+    async fn save_contact(
+        &self,
+        user_id: Uuid,
+        contact_user_id: Uuid,
+        nickname: Option<String>,
+    ) -> Result<Contact, sqlx::Error> {
+        let contact = sqlx::query_as!(
+            Contact,
+            r#"
+            INSERT INTO contacts (user_id, contact_user_id, nickname)
+            VALUES ($1, $2, $3)
+            RETURNING id, user_id, contact_user_id, nickname, created_at
+            "#,
+            user_id,
+            contact_user_id,
+            nickname
+        )
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(contact)
+    }
+
+    async fn update_contact_nickname(
+        &self,
+        user_id: Uuid,
+        contact_id: Uuid,
+        nickname: Option<String>,
+    ) -> Result<Option<Contact>, sqlx::Error> {
+        let contact = sqlx::query_as!(
+            Contact,
+            r#"
+            UPDATE contacts
+            SET nickname = $1
+            WHERE id = $2
+            AND user_id = $3
+            RETURNING id, user_id, contact_user_id, nickname, created_at
+            "#,
+            nickname,
+            contact_id,
+            user_id
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(contact)
+    }
+
+    async fn delete_contact(
+        &self,
+        user_id: Uuid,
+        contact_id: Uuid,
+    ) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query!(
+            r#"
+            DELETE FROM contacts
+            WHERE id = $1
+            AND user_id = $2
+            "#,
+            contact_id,
+            user_id
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn get_contacts(
+        &self,
+        user_id: Uuid,
+        page: u32,
+        limit: usize,
+    ) -> Result<(Vec<ContactDetails>, i64), sqlx::Error> {
+        let offset = (page - 1) * limit as u32;
+
+        let contacts = sqlx::query_as!(
+            ContactDetails,
+            r#"
+            SELECT
+                c.id,
+                c.contact_user_id,
+                u.name,
+                u.email,
+                c.nickname,
+                c.created_at
+            FROM contacts c
+            JOIN users u ON c.contact_user_id = u.id
+            WHERE c.user_id = $1
+            ORDER BY COALESCE(c.nickname, u.name)
+            LIMIT $2
+            OFFSET $3
+            "#,
+            user_id,
+            limit as i64,
+            offset as i64,
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        let count_row = sqlx::query_scalar!(
+            r#"
+            SELECT COUNT(*)
+            FROM contacts
+            WHERE user_id = $1
+            "#,
+            user_id,
+        )
+        .fetch_one(&self.pool)
+        .await?;
+
+        let total_count = count_row.unwrap_or(0);
+
+        Ok((contacts, total_count))
+    }
+
+    async fn get_contact_suggestions(
+        &self,
+        user_id: Uuid,
+        limit: i64,
+    ) -> Result<Vec<ContactSuggestion>, sqlx::Error> {
+        // Counterparts of past shares in either direction, most frequent first
+        let suggestions = sqlx::query_as!(
+            ContactSuggestion,
+            r#"
+            SELECT
+                u.id AS user_id,
+                u.name,
+                u.email,
+                COUNT(*) AS "shared_count!",
+                MAX(counterparts.created_at) AS last_shared_at
+            FROM (
+                SELECT sl.recipient_user_id AS counterpart_id, sl.created_at
+                FROM shared_links sl
+                JOIN files f ON sl.file_id = f.id
+                WHERE f.user_id = $1
+                UNION ALL
+                SELECT f.user_id AS counterpart_id, sl.created_at
+                FROM shared_links sl
+                JOIN files f ON sl.file_id = f.id
+                WHERE sl.recipient_user_id = $1
+            ) counterparts
+            JOIN users u ON counterparts.counterpart_id = u.id
+            WHERE u.id != $1
+            AND NOT EXISTS (
+                SELECT 1 FROM contacts c
+                WHERE c.user_id = $1
+                AND c.contact_user_id = u.id
+            )
+            GROUP BY u.id, u.name, u.email
+            ORDER BY COUNT(*) DESC, MAX(counterparts.created_at) DESC
+            LIMIT $2
+            "#,
+            user_id,
+            limit
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(suggestions)
+    }
+
+    async fn is_contact(
+        &self,
+        user_id: Uuid,
+        contact_user_id: Uuid,
+    ) -> Result<bool, sqlx::Error> {
+        let exists = sqlx::query_scalar!(
+            r#"
+            SELECT EXISTS (
+                SELECT 1 FROM contacts
+                WHERE user_id = $1
+                AND contact_user_id = $2
+            ) AS "exists!"
+            "#,
+            user_id,
+            contact_user_id
+        )
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(exists)
+    }
+}
+
+#[async_trait]
+pub trait BlockExt {
+    async fn block_user(
+        &self,
+        user_id: Uuid,
+        blocked_user_id: Uuid,
+    ) -> Result<BlockedUser, sqlx::Error>;
+
+    async fn unblock_user(
+        &self,
+        user_id: Uuid,
+        blocked_user_id: Uuid,
+    ) -> Result<bool, sqlx::Error>;
+
+    async fn get_blocked_users(
+        &self,
+        user_id: Uuid,
+    ) -> Result<Vec<BlockedUserDetails>, sqlx::Error>;
+
+    async fn is_blocked(
+        &self,
+        user_id: Uuid,
+        sender_id: Uuid,
+    ) -> Result<bool, sqlx::Error>;
+}
+
+#[async_trait]
+impl BlockExt for DBClient {
+    async fn block_user(
+        &self,
+        user_id: Uuid,
+        blocked_user_id: Uuid,
+    ) -> Result<BlockedUser, sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+
+        let blocked = sqlx::query_as!(
+            BlockedUser,
+            r#"
+            INSERT INTO blocked_users (user_id, blocked_user_id)
+            VALUES ($1, $2)
+            RETURNING id, user_id, blocked_user_id, created_at
+            "#,
+            user_id,
+            blocked_user_id
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+
+        // Shares still waiting for consent from the blocked sender are dropped
+        sqlx::query!(
+            r#"
+            DELETE FROM files
+            WHERE user_id = $2
+            AND id IN (
+                SELECT file_id FROM shared_links
+                WHERE recipient_user_id = $1
+                AND status = 'pending'
+            )
+            "#,
+            user_id,
+            blocked_user_id
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(blocked)
+    }
+
+    async fn unblock_user(
+        &self,
+        user_id: Uuid,
+        blocked_user_id: Uuid,
+    ) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query!(
+            r#"
+            DELETE FROM blocked_users
+            WHERE user_id = $1
+            AND blocked_user_id = $2
+            "#,
+            user_id,
+            blocked_user_id
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn get_blocked_users(
+        &self,
+        user_id: Uuid,
+    ) -> Result<Vec<BlockedUserDetails>, sqlx::Error> {
+        let blocked = sqlx::query_as!(
+            BlockedUserDetails,
+            r#"
+            SELECT b.blocked_user_id, u.name, u.email, b.created_at
+            FROM blocked_users b
+            JOIN users u ON b.blocked_user_id = u.id
+            WHERE b.user_id = $1
+            ORDER BY b.created_at DESC
+            "#,
+            user_id
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(blocked)
+    }
+
+    async fn is_blocked(
+        &self,
+        user_id: Uuid,
+        sender_id: Uuid,
+    ) -> Result<bool, sqlx::Error> {
+        let exists = sqlx::query_scalar!(
+            r#"
+            SELECT EXISTS (
+                SELECT 1 FROM blocked_users
+                WHERE user_id = $1
+                AND blocked_user_id = $2
+            ) AS "exists!"
+            "#,
+            user_id,
+            sender_id
+        )
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(exists)
+    }
+}
+
+#[async_trait]
+pub trait ShareExt {
+    async fn get_pending_shares(
+        &self,
+        user_id: Uuid,
+        page: u32,
+        limit: usize,
+    ) -> Result<(Vec<ReceiveFileDetails>, i64), sqlx::Error>;
+
+    async fn accept_share(
+        &self,
+        user_id: Uuid,
+        shared_id: Uuid,
+    ) -> Result<bool, sqlx::Error>;
+
+    async fn decline_share(
+        &self,
+        user_id: Uuid,
+        shared_id: Uuid,
+    ) -> Result<bool, sqlx::Error>;
+}
+
+#[async_trait]
+impl ShareExt for DBClient {
+    async fn get_pending_shares(
+        &self,
+        user_id: Uuid,
+        page: u32,
+        limit: usize,
+    ) -> Result<(Vec<ReceiveFileDetails>, i64), sqlx::Error> {
+        let offset = (page - 1) * limit as u32;
+
+        let shares = sqlx::query_as!(
+            ReceiveFileDetails,
+            r#"
+            SELECT
+                sl.id AS file_id,
+                COALESCE(f.file_name, '') AS "file_name!",
+                COALESCE(f.key_id, sl.recipient_user_id) AS key_id,
+                f.encrypted_aes_key AS "encrypted_aes_key?",
+                f.encrypted_metadata,
+                COALESCE(u.email, 'deleted user') AS "sender_email!",
+                sl.transfer_id,
+                sl.expiration_date,
+                sl.created_at
+            FROM shared_links sl
+            JOIN files f ON sl.file_id = f.id
+            LEFT JOIN users u ON f.user_id = u.id
+            WHERE sl.recipient_user_id = $1
+            AND sl.status = 'pending'
+            AND sl.expiration_date > NOW()
+            ORDER BY sl.created_at DESC
+            LIMIT $2
+            OFFSET $3
+            "#,
+            user_id,
+            limit as i64,
+            offset as i64,
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        let count_row = sqlx::query_scalar!(
+            r#"
+            SELECT COUNT(*)
+            FROM shared_links
+            WHERE recipient_user_id = $1
+            AND status = 'pending'
+            AND expiration_date > NOW()
+            "#,
+            user_id,
+        )
+        .fetch_one(&self.pool)
+        .await?;
+
+        let total_count = count_row.unwrap_or(0);
+
+        Ok((shares, total_count))
+    }
+
+    async fn accept_share(
+        &self,
+        user_id: Uuid,
+        shared_id: Uuid,
+    ) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query!(
+            r#"
+            UPDATE shared_links
+            SET status = 'accepted'
+            WHERE id = $1
+            AND recipient_user_id = $2
+            AND status = 'pending'
+            "#,
+            shared_id,
+            user_id
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn decline_share(
+        &self,
+        user_id: Uuid,
+        shared_id: Uuid,
+    ) -> Result<bool, sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+
+        let file_id = sqlx::query_scalar!(
+            r#"
+            DELETE FROM shared_links
+            WHERE id = $1
+            AND recipient_user_id = $2
+            AND status = 'pending'
+            RETURNING file_id
+            "#,
+            shared_id,
+            user_id
+        )
+        .fetch_optional(&mut *tx)
+        .await?;
+
+        let file_id = match file_id {
+            Some(file_id) => file_id,
+            None => return Ok(false),
+        };
+
+        // The declined copy is useless once no share points at it
+        sqlx::query!(
+            r#"
+            DELETE FROM files
+            WHERE id = $1
+            AND NOT EXISTS (
+                SELECT 1 FROM shared_links WHERE file_id = $1
+            )
+            "#,
+            file_id
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(true)
+    }
+}
+
+#[async_trait]
+pub trait OrganizationExt {
+    async fn create_organization(
+        &self,
+        owner_id: Uuid,
+        name: String,
+    ) -> Result<Organization, sqlx::Error>;
+
+    async fn get_organization(
+        &self,
+        organization_id: Uuid,
+    ) -> Result<Option<Organization>, sqlx::Error>;
+
+    async fn get_user_organizations(
+        &self,
+        user_id: Uuid,
+    ) -> Result<Vec<OrganizationDetails>, sqlx::Error>;
+
+    async fn get_organization_invitations(
+        &self,
+        user_id: Uuid,
+    ) -> Result<Vec<OrganizationDetails>, sqlx::Error>;
+
+    async fn get_organization_role(
+        &self,
+        organization_id: Uuid,
+        user_id: Uuid,
+    ) -> Result<Option<OrganizationRole>, sqlx::Error>;
+
+    async fn get_organization_membership(
+        &self,
+        organization_id: Uuid,
+        user_id: Uuid,
+    ) -> Result<Option<OrganizationMembership>, sqlx::Error>;
+
+    async fn accept_organization_invitation(
+        &self,
+        organization_id: Uuid,
+        user_id: Uuid,
+    ) -> Result<bool, sqlx::Error>;
+
+    async fn update_organization_settings(
+        &self,
+        organization_id: Uuid,
+        name: String,
+        max_expiry_days: Option<i32>,
+        allowed_recipient_domains: Vec<String>,
+    ) -> Result<Organization, sqlx::Error>;
+
+    async fn delete_organization(
+        &self,
+        organization_id: Uuid,
+    ) -> Result<(), sqlx::Error>;
+
+    async fn get_organization_members(
+        &self,
+        organization_id: Uuid,
+    ) -> Result<Vec<OrganizationMemberDetails>, sqlx::Error>;
+
+    async fn get_organization_recipients(
+        &self,
+        organization_id: Uuid,
+        sender_id: Uuid,
+    ) -> Result<Vec<User>, sqlx::Error>;
+
+    async fn add_organization_member(
+        &self,
+        organization_id: Uuid,
+        user_id: Uuid,
+        role: OrganizationRole,
+    ) -> Result<bool, sqlx::Error>;
+
+    async fn update_organization_member_role(
+        &self,
+        organization_id: Uuid,
+        user_id: Uuid,
+        role: OrganizationRole,
+    ) -> Result<bool, sqlx::Error>;
+
+    async fn remove_organization_member(
+        &self,
+        organization_id: Uuid,
+        user_id: Uuid,
+    ) -> Result<bool, sqlx::Error>;
+}
+
+#[async_trait]
+impl OrganizationExt for DBClient {
+    async fn create_organization(
+        &self,
+        owner_id: Uuid,
+        name: String,
+    ) -> Result<Organization, sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+
+        let organization = sqlx::query_as!(
+            Organization,
+            r#"
+            INSERT INTO organizations (name)
+            VALUES ($1)
+            RETURNING id, name, max_expiry_days, allowed_recipient_domains, created_at, updated_at
+            "#,
+            name
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+
+        sqlx::query!(
+            r#"
+            INSERT INTO organization_members (organization_id, user_id, role, accepted_at)
+            VALUES ($1, $2, 'owner', NOW())
+            "#,
+            organization.id,
+            owner_id
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(organization)
+    }
+
+    async fn get_organization(
+        &self,
+        organization_id: Uuid,
+    ) -> Result<Option<Organization>, sqlx::Error> {
+        let organization = sqlx::query_as!(
+            Organization,
+            r#"
+            SELECT id, name, max_expiry_days, allowed_recipient_domains, created_at, updated_at
+            FROM organizations
+            WHERE id = $1
+            "#,
+            organization_id
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(organization)
+    }
+
+    async fn get_user_organizations(
+        &self,
+        user_id: Uuid,
+    ) -> Result<Vec<OrganizationDetails>, sqlx::Error> {
+        let organizations = sqlx::query_as!(
+            OrganizationDetails,
+            r#"
+            SELECT
+                o.id,
+                o.name,
+                o.max_expiry_days,
+                o.allowed_recipient_domains,
+                m.role as "role: OrganizationRole",
+                o.created_at
+            FROM organizations o
+            JOIN organization_members m ON m.organization_id = o.id
+            WHERE m.user_id = $1
+            AND m.accepted_at IS NOT NULL
+            ORDER BY o.name
+            "#,
+            user_id
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(organizations)
+    }
+
+    async fn get_organization_invitations(
+        &self,
+        user_id: Uuid,
+    ) -> Result<Vec<OrganizationDetails>, sqlx::Error> {
+        let organizations = sqlx::query_as!(
+            OrganizationDetails,
+            r#"
+            SELECT
+                o.id,
+                o.name,
+                o.max_expiry_days,
+                o.allowed_recipient_domains,
+                m.role as "role: OrganizationRole",
+                o.created_at
+            FROM organizations o
+            JOIN organization_members m ON m.organization_id = o.id
+            WHERE m.user_id = $1
+            AND m.accepted_at IS NULL
+            ORDER BY m.created_at DESC
+            "#,
+            user_id
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(organizations)
+    }
+
+    async fn get_organization_role(
+        &self,
+        organization_id: Uuid,
+        user_id: Uuid,
+    ) -> Result<Option<OrganizationRole>, sqlx::Error> {
+        let role = sqlx::query_scalar!(
+            r#"
+            SELECT role as "role: OrganizationRole"
+            FROM organization_members
+            WHERE organization_id = $1
+            AND user_id = $2
+            AND accepted_at IS NOT NULL
+            "#,
+            organization_id,
+            user_id
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(role)
+    }
+
+    async fn get_organization_membership(
+        &self,
+        organization_id: Uuid,
+        user_id: Uuid,
+    ) -> Result<Option<OrganizationMembership>, sqlx::Error> {
+        let membership = sqlx::query_as!(
+            OrganizationMembership,
+            r#"
+            SELECT
+                role as "role: OrganizationRole",
+                accepted_at IS NOT NULL AS "accepted!"
+            FROM organization_members
+            WHERE organization_id = $1
+            AND user_id = $2
+            "#,
+            organization_id,
+            user_id
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(membership)
+    }
+
+    async fn accept_organization_invitation(
+        &self,
+        organization_id: Uuid,
+        user_id: Uuid,
+    ) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query!(
+            r#"
+            UPDATE organization_members
+            SET accepted_at = NOW()
+            WHERE organization_id = $1
+            AND user_id = $2
+            AND accepted_at IS NULL
+            "#,
+            organization_id,
+            user_id
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn update_organization_settings(
+        &self,
+        organization_id: Uuid,
+        name: String,
+        max_expiry_days: Option<i32>,
+        allowed_recipient_domains: Vec<String>,
+    ) -> Result<Organization, sqlx::Error> {
+        let organization = sqlx::query_as!(
+            Organization,
+            r#"
+            UPDATE organizations
+            SET name = $1, max_expiry_days = $2, allowed_recipient_domains = $3, updated_at = Now()
+            WHERE id = $4
+            RETURNING id, name, max_expiry_days, allowed_recipient_domains, created_at, updated_at
+            "#,
+            name,
+            max_expiry_days,
+            &allowed_recipient_domains[..],
+            organization_id
+        )
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(organization)
+    }
+
+    async fn delete_organization(
+        &self,
+        organization_id: Uuid,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            r#"
+            DELETE FROM organizations
+            WHERE id = $1
+            "#,
+            organization_id
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn get_organization_members(
+        &self,
+        organization_id: Uuid,
+    ) -> Result<Vec<OrganizationMemberDetails>, sqlx::Error> {
+        let members = sqlx::query_as!(
+            OrganizationMemberDetails,
+            r#"
+            SELECT
+                u.id AS user_id,
+                u.name,
+                u.email,
+                m.role as "role: OrganizationRole",
+                m.accepted_at IS NOT NULL AS "accepted!",
+                m.created_at
+            FROM organization_members m
+            JOIN users u ON m.user_id = u.id
+            WHERE m.organization_id = $1
+            ORDER BY m.accepted_at IS NULL, m.role, u.name
+            "#,
+            organization_id
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(members)
+    }
+
+    async fn get_organization_recipients(
+        &self,
+        organization_id: Uuid,
+        sender_id: Uuid,
+    ) -> Result<Vec<User>, sqlx::Error> {
+        let users = sqlx::query_as!(
+            User,
+            r#"
+            SELECT u.id, u.name, u.email, u.password, u.public_key, u.role as "role: UserRole", u.disabled, u.password_reset_required, u.tokens_valid_after, u.require_share_consent, u.created_at, u.updated_at
+            FROM organization_members m
+            JOIN users u ON m.user_id = u.id
+            WHERE m.organization_id = $1
+            AND m.accepted_at IS NOT NULL
+            AND u.id != $2
+            AND u.public_key IS NOT NULL
+            AND u.disabled = FALSE
+            "#,
+            organization_id,
+            sender_id
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(users)
+    }
+
+    async fn add_organization_member(
+        &self,
+        organization_id: Uuid,
+        user_id: Uuid,
+        role: OrganizationRole,
+    ) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query!(
+            r#"
+            INSERT INTO organization_members (organization_id, user_id, role)
+            VALUES ($1, $2, $3)
+            ON CONFLICT (organization_id, user_id) DO NOTHING
+            "#,
+            organization_id,
+            user_id,
+            role as OrganizationRole
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn update_organization_member_role(
+        &self,
+        organization_id: Uuid,
+        user_id: Uuid,
+        role: OrganizationRole,
+    ) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query!(
+            r#"
+            UPDATE organization_members
+            SET role = $3
+            WHERE organization_id = $1
+            AND user_id = $2
+            "#,
+            organization_id,
+            user_id,
+            role as OrganizationRole
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn remove_organization_member(
+        &self,
+        organization_id: Uuid,
+        user_id: Uuid,
+    ) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query!(
+            r#"
+            DELETE FROM organization_members
+            WHERE organization_id = $1
+            AND user_id = $2
+            "#,
+            organization_id,
+            user_id
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+}
+
+#[async_trait]
+pub trait FolderExt {
+    async fn create_folder(
+        &self,
+        user_id: Uuid,
+        parent_id: Option<Uuid>,
+        name: String,
+    ) -> Result<Folder, sqlx::Error>;
+
+    async fn get_folder(
+        &self,
+        user_id: Uuid,
+        folder_id: Uuid,
+    ) -> Result<Option<Folder>, sqlx::Error>;
+
+    async fn update_folder(
+        &self,
+        user_id: Uuid,
+        folder_id: Uuid,
+        name: String,
+        parent_id: Option<Uuid>,
+    ) -> Result<Option<Folder>, sqlx::Error>;
+
+    async fn delete_folder(
+        &self,
+        user_id: Uuid,
+        folder_id: Uuid,
+    ) -> Result<bool, sqlx::Error>;
+
+    async fn get_subfolders(
+        &self,
+        user_id: Uuid,
+        parent_id: Option<Uuid>,
+    ) -> Result<Vec<Folder>, sqlx::Error>;
+
+    async fn get_folder_files(
+        &self,
+        user_id: Uuid,
+        folder_id: Option<Uuid>,
+        page: u32,
+        limit: usize,
+    ) -> Result<(Vec<FolderFileDetails>, i64), sqlx::Error>;
+
+    async fn is_folder_descendant(
+        &self,
+        folder_id: Uuid,
+        candidate_id: Uuid,
+    ) -> Result<bool, sqlx::Error>;
+
+    async fn move_file(
+        &self,
+        user_id: Uuid,
+        file_id: Uuid,
+        folder_id: Option<Uuid>,
+    ) -> Result<bool, sqlx::Error>;
+
+    async fn get_shareable_file(
+        &self,
+        file_id: Uuid,
+    ) -> Result<Option<ShareableFile>, sqlx::Error>;
+
+    async fn get_folder_tree_files(
+        &self,
+        folder_id: Uuid,
+    ) -> Result<Vec<ShareableFile>, sqlx::Error>;
+
+    async fn has_folder_share_copy(
+        &self,
+        folder_share_id: Uuid,
+        source_file_id: Uuid,
+    ) -> Result<bool, sqlx::Error>;
+
+    async fn save_folder_share(
+        &self,
+        folder_id: Uuid,
+        recipient_user_id: Uuid,
+        password: String,
+        expiration_date: DateTime<Utc>,
+    ) -> Result<FolderShare, sqlx::Error>;
+
+    async fn get_folder_shares(
+        &self,
+        folder_id: Uuid,
+    ) -> Result<Vec<FolderShareDetails>, sqlx::Error>;
+
+    async fn get_active_folder_shares(
+        &self,
+        folder_id: Uuid,
+    ) -> Result<Vec<FolderShare>, sqlx::Error>;
+
+    async fn delete_folder_share(
+        &self,
+        user_id: Uuid,
+        folder_share_id: Uuid,
+    ) -> Result<bool, sqlx::Error>;
+}
+
+#[async_trait]
+impl FolderExt for DBClient {
+    async fn create_folder(
+        &self,
+        user_id: Uuid,
+        parent_id: Option<Uuid>,
+        name: String,
+    ) -> Result<Folder, sqlx::Error> {
+        let folder = sqlx::query_as!(
+            Folder,
+            r#"
+            INSERT INTO folders (user_id, parent_id, name)
+            VALUES ($1, $2, $3)
+            RETURNING id, user_id, parent_id, name, created_at, updated_at
+            "#,
+            user_id,
+            parent_id,
+            name
+        )
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(folder)
+    }
+
+    async fn get_folder(
+        &self,
+        user_id: Uuid,
+        folder_id: Uuid,
+    ) -> Result<Option<Folder>, sqlx::Error> {
+        let folder = sqlx::query_as!(
+            Folder,
+            r#"
+            SELECT id, user_id, parent_id, name, created_at, updated_at
+            FROM folders
+            WHERE id = $1
+            AND user_id = $2
+            "#,
+            folder_id,
+            user_id
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(folder)
+    }
+
+    async fn update_folder(
+        &self,
+        user_id: Uuid,
+        folder_id: Uuid,
+        name: String,
+        parent_id: Option<Uuid>,
+    ) -> Result<Option<Folder>, sqlx::Error> {
+        let folder = sqlx::query_as!(
+            Folder,
+            r#"
+            UPDATE folders
+            SET name = $3, parent_id = $4, updated_at = NOW()
+            WHERE id = $1
+            AND user_id = $2
+            RETURNING id, user_id, parent_id, name, created_at, updated_at
+            "#,
+            folder_id,
+            user_id,
+            name,
+            parent_id
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(folder)
+    }
+
+    async fn delete_folder(
+        &self,
+        user_id: Uuid,
+        folder_id: Uuid,
+    ) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query!(
+            r#"
+            DELETE FROM folders
+            WHERE id = $1
+            AND user_id = $2
+            "#,
+            folder_id,
+            user_id
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn get_subfolders(
+        &self,
+        user_id: Uuid,
+        parent_id: Option<Uuid>,
+    ) -> Result<Vec<Folder>, sqlx::Error> {
+        let folders = sqlx::query_as!(
+            Folder,
+            r#"
+            SELECT id, user_id, parent_id, name, created_at, updated_at
+            FROM folders
+            WHERE user_id = $1
+            AND parent_id IS NOT DISTINCT FROM $2
+            ORDER BY name
+            "#,
+            user_id,
+            parent_id
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(folders)
+    }
+
+    async fn get_folder_files(
+        &self,
+        user_id: Uuid,
+        folder_id: Option<Uuid>,
+        page: u32,
+        limit: usize,
+    ) -> Result<(Vec<FolderFileDetails>, i64), sqlx::Error> {
+        let offset = (page - 1) * limit as u32;
+
+        // Copies made for folder shares are not part of the sender's own tree
+        let files = sqlx::query_as!(
+            FolderFileDetails,
+            r#"
+            SELECT
+                f.id AS file_id,
+                COALESCE(f.file_name, '') AS "file_name!",
+                f.file_size,
+                f.sender_key_id AS key_id,
+                f.sender_encrypted_aes_key AS encrypted_aes_key,
+                f.encrypted_metadata,
+                u.email AS "recipient_email?",
+                sl.expiration_date AS "expiration_date?",
+                f.created_at
+            FROM files f
+            LEFT JOIN shared_links sl ON sl.file_id = f.id
+            LEFT JOIN users u ON sl.recipient_user_id = u.id
+            WHERE f.user_id = $1
+            AND f.folder_id IS NOT DISTINCT FROM $2
+            AND sl.folder_share_id IS NULL
+            AND f.scan_status IS DISTINCT FROM 'quarantined'
+            ORDER BY f.created_at DESC
+            LIMIT $3
+            OFFSET $4
+            "#,
+            user_id,
+            folder_id,
+            limit as i64,
+            offset as i64
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        let count_row = sqlx::query_scalar!(
+            r#"
+            SELECT COUNT(*)
+            FROM files f
+            LEFT JOIN shared_links sl ON sl.file_id = f.id
+            WHERE f.user_id = $1
+            AND f.folder_id IS NOT DISTINCT FROM $2
+            AND sl.folder_share_id IS NULL
+            AND f.scan_status IS DISTINCT FROM 'quarantined'
+            "#,
+            user_id,
+            folder_id
+        )
+        .fetch_one(&self.pool)
+        .await?;
+
+        let total_count = count_row.unwrap_or(0);
+
+        Ok((files, total_count))
+    }
+
+    async fn is_folder_descendant(
+        &self,
+        folder_id: Uuid,
+        candidate_id: Uuid,
+    ) -> Result<bool, sqlx::Error> {
+        let is_descendant = sqlx::query_scalar!(
+            r#"
+            WITH RECURSIVE tree AS (
+                SELECT id FROM folders WHERE id = $1
+                UNION ALL
+                SELECT f.id FROM folders f JOIN tree t ON f.parent_id = t.id
+            )
+            SELECT EXISTS (SELECT 1 FROM tree WHERE id = $2) AS "exists!"
+            "#,
+            folder_id,
+            candidate_id
+        )
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(is_descendant)
+    }
+
+    async fn move_file(
+        &self,
+        user_id: Uuid,
+        file_id: Uuid,
+        folder_id: Option<Uuid>,
+    ) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query!(
+            r#"
+            UPDATE files
+            SET folder_id = $3
+            WHERE id = $1
+            AND user_id = $2
+            AND scan_status IS DISTINCT FROM 'quarantined'
+            AND NOT EXISTS (
+                SELECT 1 FROM shared_links
+                WHERE file_id = $1
+                AND folder_share_id IS NOT NULL
+            )
+            "#,
+            file_id,
+            user_id,
+            folder_id
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn get_shareable_file(
+        &self,
+        file_id: Uuid,
+    ) -> Result<Option<ShareableFile>, sqlx::Error> {
+        let file = sqlx::query_as!(
+            ShareableFile,
+            r#"
+            SELECT
+                f.id,
+                f.file_name,
+                f.file_size,
+                f.encrypted_aes_key,
+                f.encrypted_file,
+                f.blob_id,
+                f.iv,
+                f.encrypted_digest,
+                f.encrypted_metadata,
+                f.sender_encrypted_aes_key,
+                f.sender_key_id,
+                f.signature,
+                f.signer_public_key,
+                f.key_id,
+                f.scan_status AS "scan_status: ScanStatus",
+                f.source_file_id,
+                sl.recipient_user_id AS "recipient_user_id!"
+            FROM files f
+            JOIN shared_links sl ON sl.file_id = f.id
+            WHERE f.id = $1
+            AND sl.recipient_user_id IS NOT NULL
+            AND f.scan_status IS DISTINCT FROM 'quarantined'
+            LIMIT 1
+            "#,
+            file_id
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(file)
+    }
+
+    async fn get_folder_tree_files(
+        &self,
+        folder_id: Uuid,
+    ) -> Result<Vec<ShareableFile>, sqlx::Error> {
+        let files = sqlx::query_as!(
+            ShareableFile,
+            r#"
+            WITH RECURSIVE tree AS (
+                SELECT id FROM folders WHERE id = $1
+                UNION ALL
+                SELECT f.id FROM folders f JOIN tree t ON f.parent_id = t.id
+            )
+            SELECT DISTINCT ON (f.id)
+                f.id,
+                f.file_name,
+                f.file_size,
+                f.encrypted_aes_key,
+                f.encrypted_file,
+                f.blob_id,
+                f.iv,
+                f.encrypted_digest,
+                f.encrypted_metadata,
+                f.sender_encrypted_aes_key,
+                f.sender_key_id,
+                f.signature,
+                f.signer_public_key,
+                f.key_id,
+                f.scan_status AS "scan_status: ScanStatus",
+                f.source_file_id,
+                sl.recipient_user_id AS "recipient_user_id!"
+            FROM files f
+            JOIN shared_links sl ON sl.file_id = f.id
+            WHERE f.folder_id IN (SELECT id FROM tree)
+            AND sl.recipient_user_id IS NOT NULL
+            AND f.scan_status IS DISTINCT FROM 'quarantined'
+            "#,
+            folder_id
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(files)
+    }
+
+    async fn has_folder_share_copy(
+        &self,
+        folder_share_id: Uuid,
+        source_file_id: Uuid,
+    ) -> Result<bool, sqlx::Error> {
+        let exists = sqlx::query_scalar!(
+            r#"
+            SELECT EXISTS(
+                SELECT 1
+                FROM shared_links sl
+                JOIN files f ON f.id = sl.file_id
+                WHERE sl.folder_share_id = $1
+                AND (f.id = $2 OR f.source_file_id = $2)
+            ) AS "exists!"
+            "#,
+            folder_share_id,
+            source_file_id
+        )
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(exists)
+    }
+
+    async fn save_folder_share(
+        &self,
+        folder_id: Uuid,
+        recipient_user_id: Uuid,
+        password: String,
+        expiration_date: DateTime<Utc>,
+    ) -> Result<FolderShare, sqlx::Error> {
+        let folder_share = sqlx::query_as!(
+            FolderShare,
+            r#"
+            INSERT INTO folder_shares (folder_id, recipient_user_id, password, expiration_date)
+            VALUES ($1, $2, $3, $4)
+            RETURNING id, folder_id, recipient_user_id, password, expiration_date, created_at
+            "#,
+            folder_id,
+            recipient_user_id,
+            password,
+            expiration_date
+        )
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(folder_share)
+    }
+
+    async fn get_folder_shares(
+        &self,
+        folder_id: Uuid,
+    ) -> Result<Vec<FolderShareDetails>, sqlx::Error> {
+        let shares = sqlx::query_as!(
+            FolderShareDetails,
+            r#"
+            SELECT fs.id, fs.folder_id, u.email AS recipient_email, fs.expiration_date, fs.created_at
+            FROM folder_shares fs
+            JOIN users u ON fs.recipient_user_id = u.id
+            WHERE fs.folder_id = $1
+            ORDER BY fs.created_at DESC
+            "#,
+            folder_id
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(shares)
+    }
+
+    async fn get_active_folder_shares(
+        &self,
+        folder_id: Uuid,
+    ) -> Result<Vec<FolderShare>, sqlx::Error> {
+        // A share on any ancestor covers the folder as well
+        let shares = sqlx::query_as!(
+            FolderShare,
+            r#"
+            WITH RECURSIVE ancestors AS (
+                SELECT id, parent_id FROM folders WHERE id = $1
+                UNION ALL
+                SELECT f.id, f.parent_id FROM folders f JOIN ancestors a ON f.id = a.parent_id
+            )
+            SELECT id, folder_id, recipient_user_id, password, expiration_date, created_at
+            FROM folder_shares
+            WHERE folder_id IN (SELECT id FROM ancestors)
+            AND expiration_date > NOW()
+            "#,
+            folder_id
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(shares)
+    }
+
+    async fn delete_folder_share(
+        &self,
+        user_id: Uuid,
+        folder_share_id: Uuid,
+    ) -> Result<bool, sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+
+        let file_ids = sqlx::query_scalar!(
+            r#"
+            DELETE FROM shared_links
+            WHERE folder_share_id = $1
+            RETURNING file_id
+            "#,
+            folder_share_id
+        )
+        .fetch_all(&mut *tx)
+        .await?;
+
+        let result = sqlx::query!(
+            r#"
+            DELETE FROM folder_shares fs
+            USING folders f
+            WHERE fs.id = $1
+            AND fs.folder_id = f.id
+            AND f.user_id = $2
+            "#,
+            folder_share_id,
+            user_id
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        if result.rows_affected() == 0 {
+            tx.rollback().await?;
+            return Ok(false);
+        }
+
+        // The copies only existed for this share
+        let file_ids: Vec<Uuid> = file_ids.into_iter().flatten().collect();
+        sqlx::query!(
+            r#"
+            DELETE FROM files
+            WHERE id = ANY($1)
+            "#,
+            &file_ids
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(true)
+    }
+}
+
+#[async_trait]
+pub trait UploadExt {
+    #[allow(clippy::too_many_arguments)]
+    async fn create_upload(
+        &self,
+        user_id: Uuid,
+        upload_length: i64,
+        file_name: String,
+        recipient_email: Option<String>,
+        organization_id: Option<Uuid>,
+        folder_id: Option<Uuid>,
+        password: String,
+        expiration_date: DateTime<Utc>,
+        expires_at: DateTime<Utc>,
+    ) -> Result<Upload, sqlx::Error>;
+
+    async fn get_upload(
+        &self,
+        user_id: Uuid,
+        upload_id: Uuid,
+    ) -> Result<Option<Upload>, sqlx::Error>;
+
+    async fn set_upload_offset(
+        &self,
+        upload_id: Uuid,
+        upload_offset: i64,
+    ) -> Result<(), sqlx::Error>;
+
+    async fn delete_upload(
+        &self,
+        user_id: Uuid,
+        upload_id: Uuid,
+    ) -> Result<bool, sqlx::Error>;
+
+    async fn delete_expired_uploads(
+        &self,
+    ) -> Result<Vec<Uuid>, sqlx::Error>;
+}
+
+#[async_trait]
+impl UploadExt for DBClient {
+    async fn create_upload(
+        &self,
+        user_id: Uuid,
+        upload_length: i64,
+        file_name: String,
+        recipient_email: Option<String>,
+        organization_id: Option<Uuid>,
+        folder_id: Option<Uuid>,
+        password: String,
+        expiration_date: DateTime<Utc>,
+        expires_at: DateTime<Utc>,
+    ) -> Result<Upload, sqlx::Error> {
+        let upload = sqlx::query_as!(
+            Upload,
+            r#"
+            INSERT INTO uploads (user_id, upload_length, file_name, recipient_email, organization_id, folder_id, password, expiration_date, expires_at)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+            RETURNING id, user_id, upload_length, upload_offset, file_name, recipient_email, organization_id, folder_id, password, expiration_date, expires_at, created_at
+            "#,
+            user_id,
+            upload_length,
+            file_name,
+            recipient_email,
+            organization_id,
+            folder_id,
+            password,
+            expiration_date,
+            expires_at
+        )
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(upload)
+    }
+
+    async fn get_upload(
+        &self,
+        user_id: Uuid,
+        upload_id: Uuid,
+    ) -> Result<Option<Upload>, sqlx::Error> {
+        let upload = sqlx::query_as!(
+            Upload,
+            r#"
+            SELECT id, user_id, upload_length, upload_offset, file_name, recipient_email, organization_id, folder_id, password, expiration_date, expires_at, created_at
+            FROM uploads
+            WHERE id = $1
+            AND user_id = $2
+            AND expires_at > NOW()
+            "#,
+            upload_id,
+            user_id
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(upload)
+    }
+
+    async fn set_upload_offset(
+        &self,
+        upload_id: Uuid,
+        upload_offset: i64,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            r#"
+            UPDATE uploads
+            SET upload_offset = $2
+            WHERE id = $1
+            "#,
+            upload_id,
+            upload_offset
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn delete_upload(
+        &self,
+        user_id: Uuid,
+        upload_id: Uuid,
+    ) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query!(
+            r#"
+            DELETE FROM uploads
+            WHERE id = $1
+            AND user_id = $2
+            "#,
+            upload_id,
+            user_id
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn delete_expired_uploads(
+        &self,
+    ) -> Result<Vec<Uuid>, sqlx::Error> {
+        let upload_ids = sqlx::query_scalar!(
+            r#"
+            DELETE FROM uploads
+            WHERE expires_at < NOW()
+            RETURNING id
+            "#
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(upload_ids)
+    }
+}
+
+#[async_trait]
+pub trait KeyExt {
+    async fn create_user_key(
+        &self,
+        user_id: Uuid,
+        key_id: Uuid,
+        public_key: String,
+        key_type: KeyType,
+    ) -> Result<UserKey, sqlx::Error>;
+
+    async fn get_active_key(
+        &self,
+        user_id: Uuid,
+    ) -> Result<Option<UserKey>, sqlx::Error>;
+
+    async fn get_user_keys(
+        &self,
+        user_id: Uuid,
+    ) -> Result<Vec<UserKeyDetails>, sqlx::Error>;
+
+    async fn get_files_to_rewrap(
+        &self,
+        limit: i64,
+    ) -> Result<Vec<RewrapFile>, sqlx::Error>;
+
+    async fn update_file_key(
+        &self,
+        file_id: Uuid,
+        sender: bool,
+        old_key_id: Uuid,
+        new_key_id: Uuid,
+        encrypted_aes_key: Vec<u8>,
+    ) -> Result<bool, sqlx::Error>;
+
+    async fn retire_unused_keys(
+        &self,
+    ) -> Result<Vec<Uuid>, sqlx::Error>;
+
+    async fn get_usable_keys(
+        &self,
+        user_id: Uuid,
+    ) -> Result<Vec<UserKey>, sqlx::Error>;
+
+    async fn save_key_escrow(
+        &self,
+        key_id: Uuid,
+        encrypted_key: Vec<u8>,
+        encrypted_private_key: Vec<u8>,
+        iv: Option<Vec<u8>>,
+    ) -> Result<(), sqlx::Error>;
+
+    async fn get_unescrowed_keys(
+        &self,
+    ) -> Result<Vec<Uuid>, sqlx::Error>;
+
+    async fn get_key_escrows(
+        &self,
+    ) -> Result<Vec<KeyEscrow>, sqlx::Error>;
+
+    async fn get_key_inventory(
+        &self,
+    ) -> Result<Vec<KeyInventory>, sqlx::Error>;
+}
+
+#[async_trait]
+impl KeyExt for DBClient {
+    async fn create_user_key(
+        &self,
+        user_id: Uuid,
+        key_id: Uuid,
+        public_key: String,
+        key_type: KeyType,
+    ) -> Result<UserKey, sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+
+        sqlx::query!(
+            r#"
+            UPDATE user_keys
+            SET status = 'rotated'
+            WHERE user_id = $1 AND status = 'active'
+            "#,
+            user_id
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        let key = sqlx::query_as!(
+            UserKey,
+            r#"
+            INSERT INTO user_keys (id, user_id, public_key, key_type)
+            VALUES ($1, $2, $3, $4)
+            RETURNING id, user_id, public_key, key_type as "key_type: KeyType", status as "status: KeyStatus", created_at, retired_at
+            "#,
+            key_id,
+            user_id,
+            public_key,
+            key_type as KeyType
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+
+        // users.public_key always holds the active key
+        sqlx::query!(
+            r#"
+            UPDATE users
+            SET public_key = $1, updated_at = NOW()
+            WHERE id = $2
+            "#,
+            key.public_key,
+            user_id
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(key)
+    }
+
+    async fn get_active_key(
+        &self,
+        user_id: Uuid,
+    ) -> Result<Option<UserKey>, sqlx::Error> {
+        let key = sqlx::query_as!(
+            UserKey,
+            r#"
+            SELECT id, user_id, public_key, key_type as "key_type: KeyType", status as "status: KeyStatus", created_at, retired_at
+            FROM user_keys
+            WHERE user_id = $1 AND status = 'active'
+            "#,
+            user_id
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(key)
+    }
+
+    async fn get_user_keys(
+        &self,
+        user_id: Uuid,
+    ) -> Result<Vec<UserKeyDetails>, sqlx::Error> {
+        let keys = sqlx::query_as!(
+            UserKeyDetails,
+            r#"
+            SELECT
+                k.id,
+                k.key_type as "key_type: KeyType",
+                k.status as "status: KeyStatus",
+                (SELECT COUNT(*) FROM files f WHERE f.key_id = k.id) AS "file_count!",
+                k.created_at,
+                k.retired_at
+            FROM user_keys k
+            WHERE k.user_id = $1
+            ORDER BY k.created_at DESC
+            "#,
+            user_id
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(keys)
+    }
+
+    async fn get_files_to_rewrap(
+        &self,
+        limit: i64,
+    ) -> Result<Vec<RewrapFile>, sqlx::Error> {
+        // Expired shares are left alone, they are about to be deleted
+        let files = sqlx::query_as!(
+            RewrapFile,
+            r#"
+            SELECT * FROM (
+                SELECT
+                    f.id AS "id!",
+                    FALSE AS "sender!",
+                    old.id AS "key_id!",
+                    f.encrypted_aes_key AS "encrypted_aes_key!",
+                    new.id AS "new_key_id!",
+                    new.public_key AS "new_public_key!"
+                FROM files f
+                JOIN user_keys old ON old.id = f.key_id AND old.status = 'rotated'
+                JOIN user_keys new ON new.user_id = old.user_id AND new.status = 'active'
+                WHERE EXISTS (
+                    SELECT 1 FROM shared_links sl
+                    WHERE sl.file_id = f.id AND sl.expiration_date > NOW()
+                )
+                UNION ALL
+                -- The sender's copy lists the file for as long as it is stored
+                SELECT
+                    f.id,
+                    TRUE,
+                    old.id,
+                    f.sender_encrypted_aes_key,
+                    new.id,
+                    new.public_key
+                FROM files f
+                JOIN user_keys old ON old.id = f.sender_key_id AND old.status = 'rotated'
+                JOIN user_keys new ON new.user_id = old.user_id AND new.status = 'active'
+                WHERE f.sender_encrypted_aes_key IS NOT NULL
+            ) files
+            LIMIT $1
+            "#,
+            limit
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(files)
+    }
+
+    async fn update_file_key(
+        &self,
+        file_id: Uuid,
+        sender: bool,
+        old_key_id: Uuid,
+        new_key_id: Uuid,
+        encrypted_aes_key: Vec<u8>,
+    ) -> Result<bool, sqlx::Error> {
+        if sender {
+            let result = sqlx::query!(
+                r#"
+                UPDATE files
+                SET sender_encrypted_aes_key = $3, sender_key_id = $4
+                WHERE id = $1 AND sender_key_id = $2
+                "#,
+                file_id,
+                old_key_id,
+                encrypted_aes_key,
+                new_key_id
+            )
+            .execute(&self.pool)
+            .await?;
+
+            return Ok(result.rows_affected() > 0);
+        }
+
+        let result = sqlx::query!(
+            r#"
+            UPDATE files
+            SET encrypted_aes_key = $3, key_id = $4
+            WHERE id = $1 AND key_id = $2
+            "#,
+            file_id,
+            old_key_id,
+            encrypted_aes_key,
+            new_key_id
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn retire_unused_keys(
+        &self,
+    ) -> Result<Vec<Uuid>, sqlx::Error> {
+        // Escrowed copies go too, a retired key should not be recoverable
+        let key_ids = sqlx::query_scalar!(
+            r#"
+            WITH retired AS (
+                UPDATE user_keys k
+                SET status = 'retired', retired_at = NOW()
+                WHERE k.status = 'rotated'
+                AND NOT EXISTS (SELECT 1 FROM files f WHERE f.key_id = k.id OR f.sender_key_id = k.id)
+                RETURNING k.id
+            ), escrow AS (
+                DELETE FROM key_escrow WHERE key_id IN (SELECT id FROM retired)
+            )
+            SELECT id AS "id!" FROM retired
+            "#
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(key_ids)
+    }
+
+    async fn get_usable_keys(
+        &self,
+        user_id: Uuid,
+    ) -> Result<Vec<UserKey>, sqlx::Error> {
+        let keys = sqlx::query_as!(
+            UserKey,
+            r#"
+            SELECT id, user_id, public_key, key_type as "key_type: KeyType", status as "status: KeyStatus", created_at, retired_at
+            FROM user_keys
+            WHERE user_id = $1 AND status != 'retired'
+            ORDER BY created_at DESC
+            "#,
+            user_id
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(keys)
+    }
+
+    async fn save_key_escrow(
+        &self,
+        key_id: Uuid,
+        encrypted_key: Vec<u8>,
+        encrypted_private_key: Vec<u8>,
+        iv: Option<Vec<u8>>,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            r#"
+            INSERT INTO key_escrow (key_id, encrypted_key, encrypted_private_key, iv)
+            VALUES ($1, $2, $3, $4)
+            ON CONFLICT (key_id) DO UPDATE
+            SET encrypted_key = EXCLUDED.encrypted_key,
+                encrypted_private_key = EXCLUDED.encrypted_private_key,
+                iv = EXCLUDED.iv,
+                created_at = NOW()
+            "#,
+            key_id,
+            encrypted_key,
+            encrypted_private_key,
+            iv
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn get_unescrowed_keys(
+        &self,
+    ) -> Result<Vec<Uuid>, sqlx::Error> {
+        let key_ids = sqlx::query_scalar!(
+            r#"
+            SELECT k.id
+            FROM user_keys k
+            LEFT JOIN key_escrow e ON e.key_id = k.id
+            WHERE k.status != 'retired' AND e.key_id IS NULL
+            "#
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(key_ids)
+    }
+
+    async fn get_key_escrows(
+        &self,
+    ) -> Result<Vec<KeyEscrow>, sqlx::Error> {
+        let escrows = sqlx::query_as!(
+            KeyEscrow,
+            r#"
+            SELECT e.key_id, e.encrypted_key, e.encrypted_private_key, e.iv
+            FROM key_escrow e
+            JOIN user_keys k ON k.id = e.key_id
+            WHERE k.status != 'retired'
+            "#
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(escrows)
+    }
+
+    async fn get_key_inventory(
+        &self,
+    ) -> Result<Vec<KeyInventory>, sqlx::Error> {
+        let keys = sqlx::query_as!(
+            KeyInventory,
+            r#"
+            SELECT k.id, e.key_id IS NOT NULL AS "escrowed!"
+            FROM user_keys k
+            LEFT JOIN key_escrow e ON e.key_id = k.id
+            WHERE k.status != 'retired'
+            "#
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(keys)
+    }
+}
+
+#[async_trait]
+pub trait SecretExt {
+    async fn save_secret(
+        &self,
+        name: &str,
+        sealed: Vec<u8>,
+    ) -> Result<(), sqlx::Error>;
+
+    async fn get_secret(
+        &self,
+        name: &str,
+    ) -> Result<Option<Vec<u8>>, sqlx::Error>;
+
+    async fn delete_secret(
+        &self,
+        name: &str,
+    ) -> Result<(), sqlx::Error>;
+}
+
+#[async_trait]
+impl SecretExt for DBClient {
+    async fn save_secret(
+        &self,
+        name: &str,
+        sealed: Vec<u8>,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            r#"
+            INSERT INTO secrets (name, sealed)
+            VALUES ($1, $2)
+            ON CONFLICT (name) DO UPDATE
+            SET sealed = EXCLUDED.sealed,
+                updated_at = NOW()
+            "#,
+            name,
+            sealed
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn get_secret(
+        &self,
+        name: &str,
+    ) -> Result<Option<Vec<u8>>, sqlx::Error> {
+        let sealed = sqlx::query_scalar!(
+            r#"
+            SELECT sealed
+            FROM secrets
+            WHERE name = $1
+            "#,
+            name
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(sealed)
+    }
+
+    async fn delete_secret(
+        &self,
+        name: &str,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            r#"
+            DELETE FROM secrets
+            WHERE name = $1
+            "#,
+            name
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+pub trait QuotaExt {
+    async fn get_storage_usage(
+        &self,
+        user_id: Uuid,
+    ) -> Result<StorageUsage, sqlx::Error>;
+
+    async fn get_organization_storage_usage(
+        &self,
+        user_id: Uuid,
+    ) -> Result<Vec<OrganizationStorageUsage>, sqlx::Error>;
+
+    async fn set_user_quota(
+        &self,
+        user_id: Uuid,
+        quota_bytes: Option<i64>,
+    ) -> Result<bool, sqlx::Error>;
+
+    async fn set_organization_quota(
+        &self,
+        organization_id: Uuid,
+        quota_bytes: Option<i64>,
+    ) -> Result<bool, sqlx::Error>;
+}
+
+#[async_trait]
+impl QuotaExt for DBClient {
+    async fn get_storage_usage(
+        &self,
+        user_id: Uuid,
+    ) -> Result<StorageUsage, sqlx::Error> {
+        let usage = sqlx::query_as!(
+            StorageUsage,
+            r#"
+            SELECT
+                (
+                    SELECT COALESCE(SUM(file_size), 0)
+                    FROM files
+                    WHERE user_id = $1
+                    AND scan_status IS DISTINCT FROM 'quarantined'
+                )::BIGINT AS "used!",
+                (SELECT quota_bytes FROM user_quotas WHERE user_id = $1) AS "quota_bytes?"
+            "#,
+            user_id
+        )
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(usage)
+    }
+
+    async fn get_organization_storage_usage(
+        &self,
+        user_id: Uuid,
+    ) -> Result<Vec<OrganizationStorageUsage>, sqlx::Error> {
+        let usage = sqlx::query_as!(
+            OrganizationStorageUsage,
+            r#"
+            SELECT
+                o.id AS organization_id,
+                o.name,
+                (
+                    SELECT COALESCE(SUM(f.file_size), 0)
+                    FROM files f
+                    WHERE EXISTS (
+                        SELECT 1 FROM shared_links sl
+                        WHERE sl.file_id = f.id
+                        AND sl.organization_id = o.id
+                    )
+                    AND f.scan_status IS DISTINCT FROM 'quarantined'
+                )::BIGINT AS "used!",
+                q.quota_bytes AS "quota_bytes?"
+            FROM organization_members om
+            JOIN organizations o ON o.id = om.organization_id
+            LEFT JOIN organization_quotas q ON q.organization_id = o.id
+            WHERE om.user_id = $1
+            AND om.accepted_at IS NOT NULL
+            ORDER BY o.name
+            "#,
+            user_id
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(usage)
+    }
+
+    async fn set_user_quota(
+        &self,
+        user_id: Uuid,
+        quota_bytes: Option<i64>,
+    ) -> Result<bool, sqlx::Error> {
+        let exists = sqlx::query_scalar!(
+            r#"
+            SELECT EXISTS(SELECT 1 FROM users WHERE id = $1) AS "exists!"
+            "#,
+            user_id
+        )
+        .fetch_one(&self.pool)
+        .await?;
+
+        if !exists {
+            return Ok(false);
+        }
+
+        match quota_bytes {
+            Some(quota_bytes) => {
+                sqlx::query!(
+                    r#"
+                    INSERT INTO user_quotas (user_id, quota_bytes)
+                    VALUES ($1, $2)
+                    ON CONFLICT (user_id) DO UPDATE
+                    SET quota_bytes = EXCLUDED.quota_bytes,
+                        updated_at = NOW()
+                    "#,
+                    user_id,
+                    quota_bytes
+                )
+                .execute(&self.pool)
+                .await?;
+            },
+            None => {
+                sqlx::query!(
+                    r#"
+                    DELETE FROM user_quotas
+                    WHERE user_id = $1
+                    "#,
+                    user_id
+                )
+                .execute(&self.pool)
+                .await?;
+            },
+        }
+
+        Ok(true)
+    }
+
+    async fn set_organization_quota(
+        &self,
+        organization_id: Uuid,
+        quota_bytes: Option<i64>,
+    ) -> Result<bool, sqlx::Error> {
+        let exists = sqlx::query_scalar!(
+            r#"
+            SELECT EXISTS(SELECT 1 FROM organizations WHERE id = $1) AS "exists!"
+            "#,
+            organization_id
+        )
+        .fetch_one(&self.pool)
+        .await?;
+
+        if !exists {
+            return Ok(false);
+        }
+
+        match quota_bytes {
+            Some(quota_bytes) => {
+                sqlx::query!(
+                    r#"
+                    INSERT INTO organization_quotas (organization_id, quota_bytes)
+                    VALUES ($1, $2)
+                    ON CONFLICT (organization_id) DO UPDATE
+                    SET quota_bytes = EXCLUDED.quota_bytes,
+                        updated_at = NOW()
+                    "#,
+                    organization_id,
+                    quota_bytes
+                )
+                .execute(&self.pool)
+                .await?;
+            },
+            None => {
+                sqlx::query!(
+                    r#"
+                    DELETE FROM organization_quotas
+                    WHERE organization_id = $1
+                    "#,
+                    organization_id
+                )
+                .execute(&self.pool)
+                .await?;
+            },
+        }
+
+        Ok(true)
+    }
+}
+
+#[async_trait]
+pub trait QuarantineExt {
+    async fn get_quarantined_files(
+        &self,
+        user_id: Option<Uuid>,
+        page: u32,
+        limit: usize,
+    ) -> Result<(Vec<QuarantinedFile>, i64), sqlx::Error>;
+
+    async fn delete_quarantined_file(
+        &self,
+        file_id: Uuid,
+    ) -> Result<bool, sqlx::Error>;
+}
+
+#[async_trait]
+impl QuarantineExt for DBClient {
+    async fn get_quarantined_files(
+        &self,
+        user_id: Option<Uuid>,
+        page: u32,
+        limit: usize,
+    ) -> Result<(Vec<QuarantinedFile>, i64), sqlx::Error> {
+        let offset = (page - 1) * limit as u32;
+
+        let files = sqlx::query_as!(
+            QuarantinedFile,
+            r#"
+            SELECT
+                f.id AS file_id,
+                f.user_id AS sender_id,
+                u.email AS "sender_email?",
+                COALESCE(f.file_name, '') AS "file_name!",
+                f.file_size,
+                f.sender_key_id AS key_id,
+                f.sender_encrypted_aes_key AS encrypted_aes_key,
+                f.encrypted_metadata,
+                f.scan_signature,
+                sl.expiration_date AS "expiration_date?",
+                f.created_at
+            FROM files f
+            LEFT JOIN users u ON u.id = f.user_id
+            LEFT JOIN shared_links sl ON sl.file_id = f.id
+            WHERE f.scan_status = 'quarantined'
+            AND ($1::UUID IS NULL OR f.user_id = $1)
+            ORDER BY f.created_at DESC
+            LIMIT $2
+            OFFSET $3
+            "#,
+            user_id,
+            limit as i64,
+            offset as i64
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        let count_row = sqlx::query_scalar!(
+            r#"
+            SELECT COUNT(*)
+            FROM files
+            WHERE scan_status = 'quarantined'
+            AND ($1::UUID IS NULL OR user_id = $1)
+            "#,
+            user_id
+        )
+        .fetch_one(&self.pool)
+        .await?;
+
+        let total_count = count_row.unwrap_or(0);
+
+        Ok((files, total_count))
+    }
+
+    async fn delete_quarantined_file(
+        &self,
+        file_id: Uuid,
+    ) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query!(
+            r#"
+            DELETE FROM files
+            WHERE id = $1
+            AND scan_status = 'quarantined'
+            "#,
+            file_id
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+}
@@ -3,7 +3,7 @@ use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use validator::{Validate, ValidationError};
 
-use crate::models::{ReceiveFileDetails, SentFileDetails, User};
+use crate::{models::{BlockedUserDetails, ContactDetails, ContactSuggestion, Folder, FolderFileDetails, FolderShareDetails, KeyStatus, KeyType, Organization, OrganizationDetails, OrganizationMemberDetails, OrganizationRole, QuarantinedFile, ReceiveFileDetails, SentFileDetails, ShareStatus, User, UserKeyDetails}, utils::quota::{OrganizationUsage, Usage}};
 
 #[derive(Validate, Debug, Default, Clone, Serialize, Deserialize)]
 pub struct RegisterUserDto {
@@ -26,6 +26,10 @@ pub struct RegisterUserDto {
     )]
     #[serde(rename = "passwordConfirm")]
     pub password_confirm: String,
+
+    /// Falls back to `DEFAULT_KEY_TYPE`
+    #[serde(rename = "keyType")]
+    pub key_type: Option<KeyType>,
 }
 
 #[derive(Validate, Debug, Default, Clone, Serialize, Deserialize)]
@@ -47,12 +51,23 @@ pub struct RequestQueryDto {
     pub limit: Option<usize>,
 }
 
+#[derive(Serialize, Deserialize, Validate)]
+pub struct FileListQueryDto {
+    #[validate(range(min = 1))]
+    pub page: Option<usize>,
+    #[validate(range(min = 1, max = 50))]
+    pub limit: Option<usize>,
+    pub organization_id: Option<uuid::Uuid>,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct FilterUserDto {
     pub id: String,
     pub name: String,
     pub email: String,
     pub public_key: Option<String>,
+    pub role: String,
+    pub require_share_consent: bool,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
 }
@@ -64,6 +79,8 @@ impl FilterUserDto {
             name: user.name.to_owned(),
             email: user.email.to_owned(),
             public_key: user.public_key.to_owned(),
+            role: user.role.to_str().to_string(),
+            require_share_consent: user.require_share_consent,
             created_at: user.created_at.unwrap(),
             updated_at: user.updated_at.unwrap(),
         }
@@ -73,6 +90,42 @@ impl FilterUserDto {
 #[derive(Debug, Serialize, Deserialize)]
 pub struct UserData {
     pub user: FilterUserDto,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub storage: Option<StorageUsageDto>,
+}
+
+/// Bytes stored and the quota, `null` when unlimited, for the user and each
+/// of their organizations.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct StorageUsageDto {
+    pub used: i64,
+    pub limit: Option<i64>,
+    pub organizations: Vec<OrganizationStorageUsageDto>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct OrganizationStorageUsageDto {
+    pub organization_id: String,
+    pub name: String,
+    pub used: i64,
+    pub limit: Option<i64>,
+}
+
+impl StorageUsageDto {
+    pub fn filter_usage(usage: &Usage, organizations: &[OrganizationUsage]) -> Self {
+        StorageUsageDto {
+            used: usage.used,
+            limit: usage.limit,
+            organizations: organizations.iter()
+                .map(|organization| OrganizationStorageUsageDto {
+                    organization_id: organization.id.to_string(),
+                    name: organization.name.to_owned(),
+                    used: organization.usage.used,
+                    limit: organization.usage.limit,
+                })
+                .collect(),
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -86,6 +139,7 @@ pub struct UserSendFileDto {
     pub file_id: String,
     pub file_name: String,
     pub recipient_email: String,
+    pub status: String,
     pub expiration_date: DateTime<Utc>,
     pub created_at: DateTime<Utc>,
 }
@@ -96,21 +150,87 @@ impl UserSendFileDto {
             file_id: file_data.file_id.to_string(),
             file_name: file_data.file_name.to_owned(),
             recipient_email: file_data.recipient_email.to_owned(),
+            status: file_data.status.to_str().to_string(),
             expiration_date: file_data.expiration_date.unwrap(),
             created_at: file_data.created_at.unwrap(),
         }
     }
+}
 
-    pub fn filter_send_user_files(user: &[SentFileDetails]) -> Vec<UserSendFileDto> {
-        user.iter().map(UserSendFileDto::filter_send_user_file).collect()
-    }
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TransferFileDto {
+    pub file_id: String,
+    pub file_name: String,
 }
 
+#[derive(Debug, Serialize, Deserialize)]
+pub struct UserSendTransferDto {
+    pub transfer_id: String,
+    pub recipient_email: String,
+    pub status: String,
+    pub files: Vec<TransferFileDto>,
+    pub expiration_date: DateTime<Utc>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// One entry of the sent list: a single file, or a transfer listed as a unit.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum UserSendItemDto {
+    File(UserSendFileDto),
+    Transfer(UserSendTransferDto),
+}
+
+impl UserSendItemDto {
+    /// Expects the rows of a transfer to be adjacent, as the list query returns them.
+    pub fn group_send_files(files: &[SentFileDetails]) -> Vec<UserSendItemDto> {
+        let mut items: Vec<UserSendItemDto> = Vec::new();
+
+        for file_data in files {
+            let transfer_id = match file_data.transfer_id {
+                Some(transfer_id) => transfer_id.to_string(),
+                None => {
+                    items.push(UserSendItemDto::File(UserSendFileDto::filter_send_user_file(file_data)));
+                    continue;
+                }
+            };
+
+            let file = TransferFileDto {
+                file_id: file_data.file_id.to_string(),
+                file_name: file_data.file_name.to_owned(),
+            };
+
+            if let Some(UserSendItemDto::Transfer(transfer)) = items.last_mut() {
+                if transfer.transfer_id == transfer_id {
+                    if file_data.status == ShareStatus::Pending {
+                        transfer.status = ShareStatus::Pending.to_str().to_string();
+                    }
+                    transfer.expiration_date = transfer.expiration_date.min(file_data.expiration_date.unwrap());
+                    transfer.created_at = transfer.created_at.max(file_data.created_at.unwrap());
+                    transfer.files.push(file);
+                    continue;
+                }
+            }
+
+            items.push(UserSendItemDto::Transfer(UserSendTransferDto {
+                transfer_id,
+                recipient_email: file_data.recipient_email.to_owned(),
+                status: file_data.status.to_str().to_string(),
+                files: vec![file],
+                expiration_date: file_data.expiration_date.unwrap(),
+                created_at: file_data.created_at.unwrap(),
+            }));
+        }
+
+        items
+    }
+}
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct UserSendFileListResponseDto {
     pub status: String,
-    pub files: Vec<UserSendFileDto>,
+    pub files: Vec<UserSendItemDto>,
     pub results: i64,
 }
 
@@ -141,12 +261,131 @@ impl UserReceiveFileDto {
 
 
 #[derive(Debug, Serialize, Deserialize)]
-pub struct UserReceiveFileListResponseDto {
+pub struct PendingShareListResponseDto {
     pub status: String,
     pub files: Vec<UserReceiveFileDto>,
     pub results: i64,
 }
 
+#[derive(Debug, Serialize, Deserialize)]
+pub struct UserReceiveTransferDto {
+    pub transfer_id: String,
+    pub sender_email: String,
+    pub files: Vec<TransferFileDto>,
+    pub expiration_date: DateTime<Utc>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// One entry of the received list: a single file, or a transfer listed as a unit.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum UserReceiveItemDto {
+    File(UserReceiveFileDto),
+    Transfer(UserReceiveTransferDto),
+}
+
+impl UserReceiveItemDto {
+    /// Expects the rows of a transfer to be adjacent, as the list query returns them.
+    pub fn group_receive_files(files: &[ReceiveFileDetails]) -> Vec<UserReceiveItemDto> {
+        let mut items: Vec<UserReceiveItemDto> = Vec::new();
+
+        for file_data in files {
+            let transfer_id = match file_data.transfer_id {
+                Some(transfer_id) => transfer_id.to_string(),
+                None => {
+                    items.push(UserReceiveItemDto::File(UserReceiveFileDto::filter_receive_user_file(file_data)));
+                    continue;
+                }
+            };
+
+            let file = TransferFileDto {
+                file_id: file_data.file_id.to_string(),
+                file_name: file_data.file_name.to_owned(),
+            };
+
+            if let Some(UserReceiveItemDto::Transfer(transfer)) = items.last_mut() {
+                if transfer.transfer_id == transfer_id {
+                    transfer.expiration_date = transfer.expiration_date.min(file_data.expiration_date.unwrap());
+                    transfer.created_at = transfer.created_at.max(file_data.created_at.unwrap());
+                    transfer.files.push(file);
+                    continue;
+                }
+            }
+
+            items.push(UserReceiveItemDto::Transfer(UserReceiveTransferDto {
+                transfer_id,
+                sender_email: file_data.sender_email.to_owned(),
+                files: vec![file],
+                expiration_date: file_data.expiration_date.unwrap(),
+                created_at: file_data.created_at.unwrap(),
+            }));
+        }
+
+        items
+    }
+}
+
+/// An upload rejected because malware was found in it. Admins see the
+/// sender but not the name, which only the sender's key opens.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct QuarantinedFileDto {
+    pub file_id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sender_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sender_email: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub file_name: Option<String>,
+    pub file_size: i64,
+    pub signature: Option<String>,
+    pub expiration_date: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl QuarantinedFileDto {
+    pub fn filter_file(file: &QuarantinedFile) -> Self {
+        QuarantinedFileDto {
+            file_id: file.file_id.to_string(),
+            sender_id: None,
+            sender_email: None,
+            file_name: Some(file.file_name.to_owned()),
+            file_size: file.file_size,
+            signature: file.scan_signature.to_owned(),
+            expiration_date: file.expiration_date,
+            created_at: file.created_at.unwrap(),
+        }
+    }
+
+    pub fn filter_files(files: &[QuarantinedFile]) -> Vec<QuarantinedFileDto> {
+        files.iter().map(QuarantinedFileDto::filter_file).collect()
+    }
+
+    pub fn filter_admin_files(files: &[QuarantinedFile]) -> Vec<QuarantinedFileDto> {
+        files.iter()
+            .map(|file| QuarantinedFileDto {
+                sender_id: file.sender_id.map(|id| id.to_string()),
+                sender_email: Some(file.sender_email.clone().unwrap_or_else(|| "deleted user".to_string())),
+                file_name: None,
+                ..QuarantinedFileDto::filter_file(file)
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct QuarantinedFileListResponseDto {
+    pub status: String,
+    pub files: Vec<QuarantinedFileDto>,
+    pub results: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct UserReceiveFileListResponseDto {
+    pub status: String,
+    pub files: Vec<UserReceiveItemDto>,
+    pub results: i64,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct UserLoginResponseDto {
     pub status: String,
@@ -187,10 +426,21 @@ pub struct UserPasswordUpdateDto {
     pub old_password: String,
 }
 
+#[derive(Debug, Default, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum SearchMode {
+    /// Full email address, matched against every user
+    Exact,
+    /// Email prefix, matched only against users already exchanged files with
+    #[default]
+    Prefix,
+}
+
 #[derive(Validate, Debug, Default, Clone, Serialize, Deserialize)]
 pub struct SearchQueryByEmailDTO {
     #[validate(length(min = 1, message = "Query is requireed"))]
     pub query: String,
+    pub mode: Option<SearchMode>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -219,7 +469,11 @@ pub struct EmailListResponseDto {
 #[derive(Validate, Debug, Default, Clone, Serialize, Deserialize)]
 pub struct FileUploadDtos {
     #[validate(email(message = "Invalid email format"))]
-    pub recipient_email: String,
+    pub recipient_email: Option<String>,
+
+    pub organization_id: Option<uuid::Uuid>,
+
+    pub folder_id: Option<uuid::Uuid>,
 
     #[validate(
         length(min = 1, message = "New password is required."),
@@ -267,3 +521,608 @@ pub struct RetrieveFileDto {
     )]
     pub password: String,
 }
+
+#[derive(Validate, Debug, Default, Clone, Serialize, Deserialize)]
+pub struct VerifyFileDto {
+    #[validate(length(min = 1, message = "Shared id is required"))]
+    pub shared_id: String,
+
+    #[validate(
+        length(min = 1, message = "Password is required."),
+        length(min = 6, message = "Password must be at least 6 characters")
+    )]
+    pub password: String,
+
+    /// Hex SHA-256 of the downloaded content, checked instead of the stored digest
+    #[validate(length(equal = 64, message = "Digest must be a hex SHA-256"))]
+    pub digest: Option<String>,
+}
+
+
+#[derive(Validate, Debug, Default, Clone, Serialize, Deserialize)]
+pub struct BundleFileDto {
+    #[validate(length(min = 1, message = "Shared id is required"))]
+    pub shared_id: String,
+
+    pub password: Option<String>,
+}
+
+#[derive(Validate, Debug, Default, Clone, Serialize, Deserialize)]
+pub struct BundleDownloadDto {
+    #[validate(length(min = 1, max = 50, message = "Select between 1 and 50 files"))]
+    #[validate]
+    pub files: Vec<BundleFileDto>,
+
+    /// Used for every file that does not carry its own password
+    pub password: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Validate)]
+pub struct AdminUserQueryDto {
+    #[validate(range(min = 1))]
+    pub page: Option<usize>,
+    #[validate(range(min = 1, max = 50))]
+    pub limit: Option<usize>,
+    #[validate(length(min = 1, message = "Search must not be empty"))]
+    pub search: Option<String>,
+}
+
+#[derive(Debug, Default, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum DeletedUserFiles {
+    #[default]
+    Delete,
+    Keep,
+}
+
+/// A quota in bytes, 0 for unlimited, or `null` to go back to the default.
+#[derive(Validate, Debug, Clone, Serialize, Deserialize)]
+pub struct QuotaUpdateDto {
+    #[validate(range(min = 0, message = "Quota cannot be negative"))]
+    pub quota_bytes: Option<i64>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AdminDeleteUserQueryDto {
+    pub files: Option<DeletedUserFiles>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AdminUserDto {
+    pub id: String,
+    pub name: String,
+    pub email: String,
+    pub role: String,
+    pub disabled: bool,
+    pub password_reset_required: bool,
+    pub has_key: bool,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl AdminUserDto {
+    pub fn filter_user(user: &User) -> Self {
+        AdminUserDto {
+            id: user.id.to_string(),
+            name: user.name.to_owned(),
+            email: user.email.to_owned(),
+            role: user.role.to_str().to_string(),
+            disabled: user.disabled,
+            password_reset_required: user.password_reset_required,
+            has_key: user.public_key.is_some(),
+            created_at: user.created_at.unwrap(),
+            updated_at: user.updated_at.unwrap(),
+        }
+    }
+
+    pub fn filter_users(user: &[User]) -> Vec<AdminUserDto> {
+        user.iter().map(AdminUserDto::filter_user).collect()
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AdminUserResponseDto {
+    pub status: String,
+    pub user: AdminUserDto,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AdminUserListResponseDto {
+    pub status: String,
+    pub users: Vec<AdminUserDto>,
+    pub results: i64,
+}
+
+#[derive(Validate, Debug, Default, Clone, Serialize, Deserialize)]
+pub struct AddContactDto {
+    #[validate(length(min = 1, message = "Email is required"), email(message = "Email is invalid"))]
+    pub email: String,
+    #[validate(length(min = 1, max = 100, message = "Nickname must be between 1 and 100 characters"))]
+    pub nickname: Option<String>,
+}
+
+#[derive(Validate, Debug, Default, Clone, Serialize, Deserialize)]
+pub struct ContactNicknameDto {
+    #[validate(length(min = 1, max = 100, message = "Nickname must be between 1 and 100 characters"))]
+    pub nickname: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ContactDto {
+    pub id: String,
+    pub user_id: String,
+    pub name: String,
+    pub email: String,
+    pub nickname: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl ContactDto {
+    pub fn filter_contact(contact: &ContactDetails) -> Self {
+        ContactDto {
+            id: contact.id.to_string(),
+            user_id: contact.contact_user_id.to_string(),
+            name: contact.name.to_owned(),
+            email: contact.email.to_owned(),
+            nickname: contact.nickname.to_owned(),
+            created_at: contact.created_at.unwrap(),
+        }
+    }
+
+    pub fn filter_contacts(contacts: &[ContactDetails]) -> Vec<ContactDto> {
+        contacts.iter().map(ContactDto::filter_contact).collect()
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ContactResponseDto {
+    pub status: String,
+    pub contact: ContactDto,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ContactListResponseDto {
+    pub status: String,
+    pub contacts: Vec<ContactDto>,
+    pub results: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ContactSuggestionDto {
+    pub user_id: String,
+    pub name: String,
+    pub email: String,
+    pub shared_count: i64,
+    pub last_shared_at: Option<DateTime<Utc>>,
+}
+
+impl ContactSuggestionDto {
+    pub fn filter_suggestion(suggestion: &ContactSuggestion) -> Self {
+        ContactSuggestionDto {
+            user_id: suggestion.user_id.to_string(),
+            name: suggestion.name.to_owned(),
+            email: suggestion.email.to_owned(),
+            shared_count: suggestion.shared_count,
+            last_shared_at: suggestion.last_shared_at,
+        }
+    }
+
+    pub fn filter_suggestions(suggestions: &[ContactSuggestion]) -> Vec<ContactSuggestionDto> {
+        suggestions.iter().map(ContactSuggestionDto::filter_suggestion).collect()
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ContactSuggestionListResponseDto {
+    pub status: String,
+    pub suggestions: Vec<ContactSuggestionDto>,
+}
+
+#[derive(Validate, Debug, Default, Clone, Serialize, Deserialize)]
+pub struct BlockUserDto {
+    #[validate(length(min = 1, message = "Email is required"), email(message = "Email is invalid"))]
+    pub email: String,
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct ShareConsentDto {
+    pub require_share_consent: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BlockedUserDto {
+    pub user_id: String,
+    pub name: String,
+    pub email: String,
+    pub created_at: DateTime<Utc>,
+}
+
+impl BlockedUserDto {
+    pub fn filter_blocked_user(blocked: &BlockedUserDetails) -> Self {
+        BlockedUserDto {
+            user_id: blocked.blocked_user_id.to_string(),
+            name: blocked.name.to_owned(),
+            email: blocked.email.to_owned(),
+            created_at: blocked.created_at.unwrap(),
+        }
+    }
+
+    pub fn filter_blocked_users(blocked: &[BlockedUserDetails]) -> Vec<BlockedUserDto> {
+        blocked.iter().map(BlockedUserDto::filter_blocked_user).collect()
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BlockedUserListResponseDto {
+    pub status: String,
+    pub users: Vec<BlockedUserDto>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct UserKeyDto {
+    pub id: String,
+    pub key_type: KeyType,
+    pub status: KeyStatus,
+    pub file_count: i64,
+    pub created_at: DateTime<Utc>,
+    pub retired_at: Option<DateTime<Utc>>,
+}
+
+impl UserKeyDto {
+    pub fn filter_key(key: &UserKeyDetails) -> Self {
+        UserKeyDto {
+            id: key.id.to_string(),
+            key_type: key.key_type,
+            status: key.status,
+            file_count: key.file_count,
+            created_at: key.created_at.unwrap(),
+            retired_at: key.retired_at,
+        }
+    }
+
+    pub fn filter_keys(keys: &[UserKeyDetails]) -> Vec<UserKeyDto> {
+        keys.iter().map(UserKeyDto::filter_key).collect()
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct UserKeyListResponseDto {
+    pub status: String,
+    pub keys: Vec<UserKeyDto>,
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct RotateKeysDto {
+    /// Keeps the type of the current key when not given
+    pub key_type: Option<KeyType>,
+}
+
+#[derive(Validate, Debug, Default, Clone, Serialize, Deserialize)]
+pub struct ExportKeysDto {
+    #[validate(
+        length(min = 1, message = "Password is required."),
+        length(min = 6, message = "Password must be at least 6 characters")
+    )]
+    pub password: String,
+
+    /// A recovery code is generated when no passphrase is given
+    #[validate(length(min = 12, message = "Passphrase must be at least 12 characters"))]
+    pub passphrase: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ExportedKeyDto {
+    pub id: String,
+    pub key_type: KeyType,
+    pub status: KeyStatus,
+    pub created_at: DateTime<Utc>,
+    pub private_key: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct KeyExportResponseDto {
+    pub status: String,
+    pub recovery_code: Option<String>,
+    pub keys: Vec<ExportedKeyDto>,
+}
+
+#[derive(Validate, Debug, Default, Clone, Serialize, Deserialize)]
+pub struct ImportedKeyDto {
+    #[validate(length(min = 1, message = "Key id is required"))]
+    pub id: String,
+
+    #[validate(length(min = 1, message = "Private key is required"))]
+    pub private_key: String,
+}
+
+#[derive(Validate, Debug, Default, Clone, Serialize, Deserialize)]
+pub struct ImportKeysDto {
+    #[validate(length(min = 1, message = "Passphrase is required"))]
+    pub passphrase: String,
+
+    #[validate(length(min = 1, max = 50, message = "Import between 1 and 50 keys"))]
+    #[validate]
+    pub keys: Vec<ImportedKeyDto>,
+}
+
+#[derive(Validate, Debug, Default, Clone, Serialize, Deserialize)]
+pub struct EscrowRestoreDto {
+    #[validate(length(min = 1, message = "The escrow private key is required"))]
+    pub private_key: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct EscrowStatusResponseDto {
+    pub status: String,
+    pub escrow_enabled: bool,
+    pub keys: usize,
+    pub escrowed: usize,
+    pub missing_private_keys: usize,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct EscrowRestoreResponseDto {
+    pub status: String,
+    pub restored: usize,
+    pub present: usize,
+}
+
+#[derive(Validate, Debug, Default, Clone, Serialize, Deserialize)]
+pub struct CreateOrganizationDto {
+    #[validate(length(min = 1, max = 100, message = "Name must be between 1 and 100 characters"))]
+    pub name: String,
+}
+
+#[derive(Validate, Debug, Default, Clone, Serialize, Deserialize)]
+pub struct OrganizationSettingsDto {
+    #[validate(length(min = 1, max = 100, message = "Name must be between 1 and 100 characters"))]
+    pub name: String,
+    #[validate(range(min = 1, message = "Max expiry must be at least one day"))]
+    pub max_expiry_days: Option<i32>,
+    #[serde(default)]
+    pub allowed_recipient_domains: Vec<String>,
+}
+
+#[derive(Validate, Debug, Clone, Serialize, Deserialize)]
+pub struct AddOrganizationMemberDto {
+    #[validate(length(min = 1, message = "Email is required"), email(message = "Email is invalid"))]
+    pub email: String,
+    pub role: Option<OrganizationRole>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UpdateOrganizationMemberDto {
+    pub role: OrganizationRole,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct OrganizationDto {
+    pub id: String,
+    pub name: String,
+    pub max_expiry_days: Option<i32>,
+    pub allowed_recipient_domains: Vec<String>,
+    pub role: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl OrganizationDto {
+    pub fn filter_organization(organization: &Organization, role: Option<OrganizationRole>) -> Self {
+        OrganizationDto {
+            id: organization.id.to_string(),
+            name: organization.name.to_owned(),
+            max_expiry_days: organization.max_expiry_days,
+            allowed_recipient_domains: organization.allowed_recipient_domains.to_owned(),
+            role: role.map(|role| role.to_str().to_string()),
+            created_at: organization.created_at.unwrap(),
+        }
+    }
+
+    pub fn filter_organization_details(organization: &OrganizationDetails) -> Self {
+        OrganizationDto {
+            id: organization.id.to_string(),
+            name: organization.name.to_owned(),
+            max_expiry_days: organization.max_expiry_days,
+            allowed_recipient_domains: organization.allowed_recipient_domains.to_owned(),
+            role: Some(organization.role.to_str().to_string()),
+            created_at: organization.created_at.unwrap(),
+        }
+    }
+
+    pub fn filter_organizations(organizations: &[OrganizationDetails]) -> Vec<OrganizationDto> {
+        organizations.iter().map(OrganizationDto::filter_organization_details).collect()
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct OrganizationMemberDto {
+    pub user_id: String,
+    pub name: String,
+    pub email: String,
+    pub role: String,
+    /// `pending` until an invited member accepts
+    pub status: String,
+    pub joined_at: DateTime<Utc>,
+}
+
+impl OrganizationMemberDto {
+    pub fn filter_member(member: &OrganizationMemberDetails) -> Self {
+        OrganizationMemberDto {
+            user_id: member.user_id.to_string(),
+            name: member.name.to_owned(),
+            email: member.email.to_owned(),
+            role: member.role.to_str().to_string(),
+            status: if member.accepted { "accepted" } else { "pending" }.to_string(),
+            joined_at: member.created_at.unwrap(),
+        }
+    }
+
+    pub fn filter_members(members: &[OrganizationMemberDetails]) -> Vec<OrganizationMemberDto> {
+        members.iter().map(OrganizationMemberDto::filter_member).collect()
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct OrganizationResponseDto {
+    pub status: String,
+    pub organization: OrganizationDto,
+    pub members: Vec<OrganizationMemberDto>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct OrganizationListResponseDto {
+    pub status: String,
+    pub organizations: Vec<OrganizationDto>,
+}
+
+#[derive(Validate, Debug, Default, Clone, Serialize, Deserialize)]
+pub struct CreateFolderDto {
+    #[validate(length(min = 1, max = 255, message = "Name must be between 1 and 255 characters"))]
+    pub name: String,
+    pub parent_id: Option<uuid::Uuid>,
+}
+
+#[derive(Validate, Debug, Default, Clone, Serialize, Deserialize)]
+pub struct UpdateFolderDto {
+    #[validate(length(min = 1, max = 255, message = "Name must be between 1 and 255 characters"))]
+    pub name: String,
+    pub parent_id: Option<uuid::Uuid>,
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct MoveFileDto {
+    pub folder_id: Option<uuid::Uuid>,
+}
+
+#[derive(Validate, Debug, Default, Clone, Serialize, Deserialize)]
+pub struct ShareFolderDto {
+    #[validate(length(min = 1, message = "Email is required"), email(message = "Email is invalid"))]
+    pub recipient_email: String,
+
+    #[validate(
+        length(min = 1, message = "Password is required."),
+        length(min = 6, message = "Password must be at least 6 characters")
+    )]
+    pub password: String,
+
+    #[validate(custom = "validate_expiration_date")]
+    pub expiration_date: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FolderDto {
+    pub id: String,
+    pub parent_id: Option<String>,
+    pub name: String,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl FolderDto {
+    pub fn filter_folder(folder: &Folder) -> Self {
+        FolderDto {
+            id: folder.id.to_string(),
+            parent_id: folder.parent_id.map(|id| id.to_string()),
+            name: folder.name.to_owned(),
+            created_at: folder.created_at.unwrap(),
+            updated_at: folder.updated_at.unwrap(),
+        }
+    }
+
+    pub fn filter_folders(folders: &[Folder]) -> Vec<FolderDto> {
+        folders.iter().map(FolderDto::filter_folder).collect()
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FolderFileDto {
+    pub file_id: String,
+    pub file_name: String,
+    pub file_size: i64,
+    pub recipient_email: Option<String>,
+    pub expiration_date: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl FolderFileDto {
+    pub fn filter_file(file: &FolderFileDetails) -> Self {
+        FolderFileDto {
+            file_id: file.file_id.to_string(),
+            file_name: file.file_name.to_owned(),
+            file_size: file.file_size,
+            recipient_email: file.recipient_email.to_owned(),
+            expiration_date: file.expiration_date,
+            created_at: file.created_at.unwrap(),
+        }
+    }
+
+    pub fn filter_files(files: &[FolderFileDetails]) -> Vec<FolderFileDto> {
+        files.iter().map(FolderFileDto::filter_file).collect()
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FolderResponseDto {
+    pub status: String,
+    pub folder: FolderDto,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FolderContentsResponseDto {
+    pub status: String,
+    pub folder: Option<FolderDto>,
+    pub folders: Vec<FolderDto>,
+    pub files: Vec<FolderFileDto>,
+    pub results: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FolderShareDto {
+    pub id: String,
+    pub folder_id: String,
+    pub recipient_email: String,
+    pub expiration_date: DateTime<Utc>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl FolderShareDto {
+    pub fn filter_share(share: &FolderShareDetails) -> Self {
+        FolderShareDto {
+            id: share.id.to_string(),
+            folder_id: share.folder_id.to_string(),
+            recipient_email: share.recipient_email.to_owned(),
+            expiration_date: share.expiration_date,
+            created_at: share.created_at.unwrap(),
+        }
+    }
+
+    pub fn filter_shares(shares: &[FolderShareDetails]) -> Vec<FolderShareDto> {
+        shares.iter().map(FolderShareDto::filter_share).collect()
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FolderShareListResponseDto {
+    pub status: String,
+    pub shares: Vec<FolderShareDto>,
+}
+
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FileSignatureDto {
+    pub file_id: String,
+    pub sender_id: Option<String>,
+    pub file_name: String,
+    pub file_size: i64,
+    pub digest: String,
+    pub signature: Option<String>,
+    pub signer_public_key: Option<String>,
+    pub valid: bool,
+    /// Whether the file was signed with the key the sender publishes today
+    pub sender_key_current: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct VerifyFileResponseDto {
+    pub status: String,
+    pub verification: FileSignatureDto,
+}
\ No newline at end of file
@@ -1,6 +1,6 @@
 use std::fmt;
 
-use axum::{http::StatusCode, response::{IntoResponse, Response}, Json};
+use axum::{http::{header, HeaderValue, StatusCode}, response::{IntoResponse, Response}, Json};
 use serde::{Deserialize, Serialize};
 
 
@@ -28,11 +28,23 @@ pub enum ErrorMessage {
     EmailExist,
     UserNoLongerExist,
     TokenNotProvided,
+    PermissionDenied,
+    UserDisabled,
+    PasswordResetRequired,
+    TooManyLoginAttempts(u64),
+    RateLimited,
+    ServerBusy,
+    FileTooLarge(i64),
+    QuotaExceeded,
+    OrganizationQuotaExceeded(String),
+    MalwareDetected(String),
+    ScannerUnavailable,
+    TooLargeToScan,
 }
 
-impl ToString for ErrorMessage {
-    fn to_string(&self) -> String {
-        self.to_str().to_owned()
+impl fmt::Display for ErrorMessage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.to_str())
     }
 }
 
@@ -48,6 +60,18 @@ impl ErrorMessage {
             ErrorMessage::ExceededMaxPasswordLength(max_length) => format!("Password must not be more than {} characters", max_length),
             ErrorMessage::InvalidToken => "Authentication token is invalid or expired".to_string(),
             ErrorMessage::TokenNotProvided => "You are not logged in, please provide a token".to_string(),
+            ErrorMessage::PermissionDenied => "You are not allowed to perform this action".to_string(),
+            ErrorMessage::UserDisabled => "This account has been disabled".to_string(),
+            ErrorMessage::PasswordResetRequired => "You must change your password before continuing".to_string(),
+            ErrorMessage::RateLimited => "Too many requests, please slow down".to_string(),
+            ErrorMessage::ServerBusy => "The server is busy, please try again shortly".to_string(),
+            ErrorMessage::FileTooLarge(max_size) => format!("Files are limited to {} bytes", max_size),
+            ErrorMessage::QuotaExceeded => "This upload would exceed your storage quota".to_string(),
+            ErrorMessage::OrganizationQuotaExceeded(name) => format!("This upload would exceed the storage quota of {}", name),
+            ErrorMessage::MalwareDetected(files) => format!("Upload rejected, malware was found in {}", files),
+            ErrorMessage::ScannerUnavailable => "Uploads cannot be scanned right now, please try again shortly".to_string(),
+            ErrorMessage::TooLargeToScan => "This file is larger than the malware scanner accepts".to_string(),
+            ErrorMessage::TooManyLoginAttempts(retry_after) => format!("Too many login attempts, please try again in {} seconds", retry_after),
         }
     }
 }
@@ -56,6 +80,7 @@ impl ErrorMessage {
 pub struct HttpError {
     pub message: String,
     pub status: StatusCode,
+    pub retry_after: Option<u64>,
 }
 
 impl HttpError {
@@ -63,6 +88,7 @@ impl HttpError {
         HttpError {
             message: message.into(),
             status,
+            retry_after: None,
         }
     }
 
@@ -70,6 +96,7 @@ impl HttpError {
         HttpError {
             message: message.into(),
             status: StatusCode::INTERNAL_SERVER_ERROR,
+            retry_after: None,
         }
     }
 
@@ -77,6 +104,7 @@ impl HttpError {
         HttpError {
             message: message.into(),
             status: StatusCode::BAD_REQUEST,
+            retry_after: None,
         }
     }
 
@@ -84,6 +112,7 @@ impl HttpError {
         HttpError {
             message: message.into(),
             status: StatusCode::CONFLICT,
+            retry_after: None,
         }
     }
 
@@ -91,16 +120,69 @@ impl HttpError {
         HttpError {
             message: message.into(),
             status: StatusCode::UNAUTHORIZED,
+            retry_after: None,
+        }
+    }
+
+    pub fn forbidden(message: impl Into<String>) -> Self {
+        HttpError {
+            message: message.into(),
+            status: StatusCode::FORBIDDEN,
+            retry_after: None,
+        }
+    }
+
+    pub fn too_many_requests(message: impl Into<String>) -> Self {
+        HttpError {
+            message: message.into(),
+            status: StatusCode::TOO_MANY_REQUESTS,
+            retry_after: None,
         }
     }
 
+    pub fn payload_too_large(message: impl Into<String>) -> Self {
+        HttpError {
+            message: message.into(),
+            status: StatusCode::PAYLOAD_TOO_LARGE,
+            retry_after: None,
+        }
+    }
+
+    pub fn unprocessable_entity(message: impl Into<String>) -> Self {
+        HttpError {
+            message: message.into(),
+            status: StatusCode::UNPROCESSABLE_ENTITY,
+            retry_after: None,
+        }
+    }
+
+    pub fn service_unavailable(message: impl Into<String>) -> Self {
+        HttpError {
+            message: message.into(),
+            status: StatusCode::SERVICE_UNAVAILABLE,
+            retry_after: None,
+        }
+    }
+
+    /// Tells the client how many seconds to wait before trying again.
+    pub fn with_retry_after(mut self, seconds: u64) -> Self {
+        self.retry_after = Some(seconds);
+        self
+    }
+
     pub fn into_http_response(self) -> Response {
         let json_response = Json(ErrorResponse {
             status: "fail".to_string(),
             message: self.message.clone(),
         });
 
-        (self.status, json_response).into_response()
+        let mut response = (self.status, json_response).into_response();
+
+        if let Some(retry_after) = self.retry_after {
+            response.headers_mut().insert(header::RETRY_AFTER, HeaderValue::from(retry_after));
+        }
+
+        response
     }
 }
 
@@ -116,6 +198,22 @@ impl fmt::Display for HttpError {
 
 impl std::error::Error for HttpError {}
 
+/// Password errors are server errors, except when the crypto pool is full.
+/// Size and quota errors are 413s, malware found in an upload a 422.
+impl From<ErrorMessage> for HttpError {
+    fn from(error: ErrorMessage) -> Self {
+        match error {
+            ErrorMessage::ServerBusy | ErrorMessage::ScannerUnavailable => HttpError::service_unavailable(error.to_string()),
+            ErrorMessage::MalwareDetected(_) => HttpError::unprocessable_entity(error.to_string()),
+            ErrorMessage::FileTooLarge(_)
+            | ErrorMessage::QuotaExceeded
+            | ErrorMessage::OrganizationQuotaExceeded(_)
+            | ErrorMessage::TooLargeToScan => HttpError::payload_too_large(error.to_string()),
+            _ => HttpError::server_error(error.to_string()),
+        }
+    }
+}
+
 impl IntoResponse for HttpError {
     fn into_response(self) -> Response {
         self.into_http_response()
@@ -0,0 +1,309 @@
+use std::sync::Arc;
+
+use axum::{extract::{Path, Query}, http::StatusCode, response::IntoResponse, routing::{delete, get, post, put}, Extension, Json, Router};
+use uuid::Uuid;
+use validator::Validate;
+
+use crate::{db::{AdminExt, KeyExt, QuarantineExt, QuotaExt}, dtos::{AdminDeleteUserQueryDto, AdminUserDto, AdminUserListResponseDto, AdminUserQueryDto, AdminUserResponseDto, DeletedUserFiles, EscrowRestoreDto, EscrowRestoreResponseDto, EscrowStatusResponseDto, QuarantinedFileDto, QuarantinedFileListResponseDto, QuotaUpdateDto, RequestQueryDto, Response}, error::HttpError, middleware::JWTAuthMiddeware, models::User, utils::{dedup::delete_dedup_key, escrow::{parse_escrow_private_key, restore_from_escrow}, keys::{delete_private_key, private_key_exists}, signing::delete_signing_key}, AppState};
+
+pub fn admin_handler() -> Router {
+    Router::new()
+        .route("/users", get(get_users))
+        .route("/users/:user_id", delete(delete_user))
+        .route("/users/:user_id/disable", put(disable_user))
+        .route("/users/:user_id/enable", put(enable_user))
+        .route("/users/:user_id/force-password-reset", put(force_password_reset))
+        .route("/users/:user_id/force-logout", put(force_logout))
+        .route("/users/:user_id/quota", put(set_user_quota))
+        .route("/organizations/:organization_id/quota", put(set_organization_quota))
+        .route("/quarantine", get(get_quarantined_files))
+        .route("/quarantine/:file_id", delete(delete_quarantined_file))
+        .route("/escrow", get(get_escrow_status))
+        .route("/escrow/restore", post(restore_escrow))
+}
+
+pub async fn get_users(
+    Query(query_params): Query<AdminUserQueryDto>,
+    Extension(app_state): Extension<Arc<AppState>>,
+) -> Result<impl IntoResponse, HttpError> {
+    query_params.validate()
+        .map_err(|e| HttpError::bad_request(e.to_string()))?;
+
+    let page = query_params.page.unwrap_or(1);
+    let limit = query_params.limit.unwrap_or(10);
+
+    let (users, total_count) = app_state.db_client
+        .get_users(page as u32, limit, query_params.search)
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+    let response = AdminUserListResponseDto {
+        status: "success".to_string(),
+        users: AdminUserDto::filter_users(&users),
+        results: total_count,
+    };
+
+    Ok(Json(response))
+}
+
+pub async fn disable_user(
+    Path(user_id): Path<Uuid>,
+    Extension(app_state): Extension<Arc<AppState>>,
+    Extension(admin): Extension<JWTAuthMiddeware>,
+) -> Result<impl IntoResponse, HttpError> {
+    if admin.user.id == user_id {
+        return Err(HttpError::bad_request("You cannot disable your own account"));
+    }
+
+    let user = app_state.db_client
+        .set_user_disabled(user_id, true)
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+    user_response(user)
+}
+
+pub async fn enable_user(
+    Path(user_id): Path<Uuid>,
+    Extension(app_state): Extension<Arc<AppState>>,
+) -> Result<impl IntoResponse, HttpError> {
+    let user = app_state.db_client
+        .set_user_disabled(user_id, false)
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+    user_response(user)
+}
+
+pub async fn force_password_reset(
+    Path(user_id): Path<Uuid>,
+    Extension(app_state): Extension<Arc<AppState>>,
+) -> Result<impl IntoResponse, HttpError> {
+    let user = app_state.db_client
+        .require_password_reset(user_id)
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+    user_response(user)
+}
+
+pub async fn force_logout(
+    Path(user_id): Path<Uuid>,
+    Extension(app_state): Extension<Arc<AppState>>,
+) -> Result<impl IntoResponse, HttpError> {
+    let user = app_state.db_client
+        .revoke_user_tokens(user_id)
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+    user_response(user)
+}
+
+pub async fn delete_user(
+    Path(user_id): Path<Uuid>,
+    Query(query_params): Query<AdminDeleteUserQueryDto>,
+    Extension(app_state): Extension<Arc<AppState>>,
+    Extension(admin): Extension<JWTAuthMiddeware>,
+) -> Result<impl IntoResponse, HttpError> {
+    if admin.user.id == user_id {
+        return Err(HttpError::bad_request("You cannot delete your own account"));
+    }
+
+    let keep_files = query_params.files.unwrap_or_default() == DeletedUserFiles::Keep;
+
+    // Read before the rows go, the key store is cleaned up afterwards
+    let keys = app_state.db_client
+        .get_user_keys(user_id)
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+    let deleted = app_state.db_client
+        .delete_user(user_id, keep_files)
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+    if !deleted {
+        return Err(HttpError::new("User not found", StatusCode::NOT_FOUND));
+    }
+
+    // Kept files are readable with the recipients' keys, so none of the
+    // user's own keys are needed any more
+    for key in keys {
+        if let Err(err) = delete_private_key(&*app_state.key_store, key.id).await {
+            eprintln!("Error removing private key {} of deleted user {}: {}", key.id, user_id, err.message);
+        }
+    }
+
+    if let Err(err) = delete_signing_key(&*app_state.key_store, user_id).await {
+        eprintln!("Error removing signing key of deleted user {}: {}", user_id, err.message);
+    }
+
+    if let Err(err) = delete_dedup_key(&*app_state.key_store, user_id).await {
+        eprintln!("Error removing dedup key of deleted user {}: {}", user_id, err.message);
+    }
+
+    let response = Response {
+        message: "User deleted successfully".to_string(),
+        status: "success",
+    };
+
+    Ok(Json(response))
+}
+
+/// Sets a user's storage quota, or with `null` puts them back on the default.
+pub async fn set_user_quota(
+    Path(user_id): Path<Uuid>,
+    Extension(app_state): Extension<Arc<AppState>>,
+    Json(body): Json<QuotaUpdateDto>,
+) -> Result<impl IntoResponse, HttpError> {
+    body.validate()
+        .map_err(|e| HttpError::bad_request(e.to_string()))?;
+
+    let updated = app_state.db_client
+        .set_user_quota(user_id, body.quota_bytes)
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+    if !updated {
+        return Err(HttpError::new("User not found", StatusCode::NOT_FOUND));
+    }
+
+    let response = Response {
+        message: "Quota updated successfully".to_string(),
+        status: "success",
+    };
+
+    Ok(Json(response))
+}
+
+/// Sets the quota on what is shared through an organization.
+pub async fn set_organization_quota(
+    Path(organization_id): Path<Uuid>,
+    Extension(app_state): Extension<Arc<AppState>>,
+    Json(body): Json<QuotaUpdateDto>,
+) -> Result<impl IntoResponse, HttpError> {
+    body.validate()
+        .map_err(|e| HttpError::bad_request(e.to_string()))?;
+
+    let updated = app_state.db_client
+        .set_organization_quota(organization_id, body.quota_bytes)
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+    if !updated {
+        return Err(HttpError::new("Organization not found", StatusCode::NOT_FOUND));
+    }
+
+    let response = Response {
+        message: "Quota updated successfully".to_string(),
+        status: "success",
+    };
+
+    Ok(Json(response))
+}
+
+/// Lists every upload rejected because malware was found in it, newest first.
+pub async fn get_quarantined_files(
+    Query(query_params): Query<RequestQueryDto>,
+    Extension(app_state): Extension<Arc<AppState>>,
+) -> Result<impl IntoResponse, HttpError> {
+    query_params.validate()
+        .map_err(|e| HttpError::bad_request(e.to_string()))?;
+
+    let page = query_params.page.unwrap_or(1);
+    let limit = query_params.limit.unwrap_or(10);
+
+    let (files, total_count) = app_state.db_client
+        .get_quarantined_files(None, page as u32, limit)
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+    let response = QuarantinedFileListResponseDto {
+        status: "success".to_string(),
+        files: QuarantinedFileDto::filter_admin_files(&files),
+        results: total_count,
+    };
+
+    Ok(Json(response))
+}
+
+pub async fn delete_quarantined_file(
+    Path(file_id): Path<Uuid>,
+    Extension(app_state): Extension<Arc<AppState>>,
+) -> Result<impl IntoResponse, HttpError> {
+    let deleted = app_state.db_client
+        .delete_quarantined_file(file_id)
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+    if !deleted {
+        return Err(HttpError::new("Quarantined file not found", StatusCode::NOT_FOUND));
+    }
+
+    let response = Response {
+        message: "Quarantined file deleted successfully".to_string(),
+        status: "success",
+    };
+
+    Ok(Json(response))
+}
+
+fn user_response(user: Option<User>) -> Result<Json<AdminUserResponseDto>, HttpError> {
+    let user = user.ok_or_else(|| HttpError::new("User not found", StatusCode::NOT_FOUND))?;
+
+    Ok(Json(AdminUserResponseDto {
+        status: "success".to_string(),
+        user: AdminUserDto::filter_user(&user),
+    }))
+}
+
+/// How many usable keys are escrowed and how many have lost their private
+/// key, which is what a restore would bring back.
+pub async fn get_escrow_status(
+    Extension(app_state): Extension<Arc<AppState>>,
+) -> Result<impl IntoResponse, HttpError> {
+    let keys = app_state.db_client
+        .get_key_inventory()
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+    let mut missing_private_keys = 0;
+
+    for key in &keys {
+        if !private_key_exists(&*app_state.key_store, key.id).await? {
+            missing_private_keys += 1;
+        }
+    }
+
+    let response = EscrowStatusResponseDto {
+        status: "success".to_string(),
+        escrow_enabled: app_state.escrow_public_key.is_some(),
+        keys: keys.len(),
+        escrowed: keys.iter().filter(|key| key.escrowed).count(),
+        missing_private_keys,
+    };
+
+    Ok(Json(response))
+}
+
+/// Disaster recovery: rebuilds lost private keys from escrow using the
+/// offline escrow private key. The key is only used for this request.
+pub async fn restore_escrow(
+    Extension(app_state): Extension<Arc<AppState>>,
+    Json(body): Json<EscrowRestoreDto>,
+) -> Result<impl IntoResponse, HttpError> {
+    body.validate()
+        .map_err(|e| HttpError::bad_request(e.to_string()))?;
+
+    let escrow_private_key = parse_escrow_private_key(&body.private_key)?;
+
+    let summary = restore_from_escrow(&app_state.db_client, &*app_state.key_store, &escrow_private_key).await?;
+
+    let response = EscrowRestoreResponseDto {
+        status: "success".to_string(),
+        restored: summary.restored,
+        present: summary.present,
+    };
+
+    Ok(Json(response))
+}
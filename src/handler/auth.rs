@@ -1,10 +1,11 @@
-use std::sync::Arc;
+use std::{net::SocketAddr, sync::Arc};
 
-use axum::{http::{header, HeaderMap, StatusCode}, response::IntoResponse, routing::post, Extension, Json, Router};
+use axum::{extract::ConnectInfo, http::{header, HeaderMap, StatusCode}, response::IntoResponse, routing::post, Extension, Json, Router};
+use chrono::{Duration, Utc};
 use axum_extra::extract::cookie::Cookie;
 use validator::Validate;
 
-use crate::{db::UserExt, dtos::{LoginUserDto, RegisterUserDto, Response, UserLoginResponseDto}, error::{ErrorMessage, HttpError}, utils::{keys::generate_key, password, token}, AppState};
+use crate::{db::{LoginAttemptExt, UserExt}, dtos::{LoginUserDto, RegisterUserDto, Response, UserLoginResponseDto}, error::{ErrorMessage, HttpError}, models::LoginAttempt, utils::{keys::generate_key, password, signing::generate_signing_key, token}, AppState};
 
 pub fn auth_handler() -> Router {
     Router::new()
@@ -21,7 +22,8 @@ pub async fn register(
      .map_err(|e| HttpError::bad_request(e.to_string()))?;
 
     let hash_password = password::hash(&body.password)
-        .map_err(|e| HttpError::server_error(e.to_string()))?;
+        .await
+        .map_err(HttpError::from)?;
 
     let result = app_state.db_client
         .save_user(&body.name, &body.email, &hash_password)
@@ -29,7 +31,9 @@ pub async fn register(
 
     match result {
         Ok(user) => {
-            let _key_result = generate_key(app_state, user).await?;
+            generate_signing_key(&app_state, user.id).await?;
+            let key_type = body.key_type.unwrap_or(app_state.env.default_key_type);
+            let _key_result = generate_key(app_state, user, key_type).await?;
 
             Ok((StatusCode::CREATED, Json(Response {
                 message: "Registrations successful!".to_string(),
@@ -51,54 +55,132 @@ pub async fn register(
 
 pub async fn login(
     Extension(app_state): Extension<Arc<AppState>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
     Json(body): Json<LoginUserDto>
 ) -> Result<impl IntoResponse, HttpError> {
     body.validate()
         .map_err(|e| HttpError::bad_request(e.to_string()))?;
 
+    app_state.login_limiter
+        .check(&addr.ip().to_string())
+        .map_err(|retry_after| too_many_login_attempts(retry_after.as_secs()))?;
+
+    // Accounts are looked up by the exact email, so attempts are counted by it too
+    let attempt_key = body.email.as_str();
+    start_login_attempt(&app_state, attempt_key).await?;
+
     let result = app_state.db_client
         .get_user(None, None, Some(&body.email))
         .await
         .map_err(|e| HttpError::server_error(e.to_string()))?;
 
-    let user = result.ok_or(HttpError::bad_request(ErrorMessage::WrongCredentials.to_string()))?;
+    // Unknown emails still pay for a full hash comparison
+    let password_matched = match &result {
+        Some(user) => password::compare(&body.password, &user.password).await,
+        None => password::compare_dummy(&body.password).await,
+    }
+    .map_err(HttpError::from)?;
+
+    let user = match result {
+        Some(user) if password_matched => user,
+        _ => return Err(HttpError::bad_request(ErrorMessage::WrongCredentials.to_string())),
+    };
 
-    let password_matched = password::compare(&body.password, &user.password)
+    app_state.db_client
+        .reset_login_attempts(attempt_key)
+        .await
         .map_err(|e| HttpError::server_error(e.to_string()))?;
 
-    if password_matched {
-        let token = token::create_token(
-            &user.id.to_string(), 
-            &app_state.env.jwt_secret.as_bytes(), 
-            app_state.env.jwt_maxage
+    if user.disabled {
+        return Err(HttpError::forbidden(ErrorMessage::UserDisabled.to_string()));
+    }
+
+    let token = token::create_token(
+        &user.id.to_string(), 
+        app_state.env.jwt_secret.as_bytes(), 
+        app_state.env.jwt_maxage
+    )
+    .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+    let cookie_duration = time::Duration::minutes(app_state.env.jwt_maxage * 60);
+    let cookie = Cookie::build(("token", token.clone()))
+        .path("/")
+        .max_age(cookie_duration)
+        .http_only(true)
+        .build();
+
+    let response = Json(UserLoginResponseDto {
+        status: "success".to_string(),
+        token,
+    });
+
+    let mut headers = HeaderMap::new();
+
+    headers.append(
+        header::SET_COOKIE,
+        cookie.to_string().parse().unwrap() 
+    );
+
+    let mut response = response.into_response();
+    response.headers_mut().extend(headers);
+
+    Ok(response)
+}
+
+/// Counts a login attempt against `attempt_key` until it succeeds, or
+/// refuses it while the email is locked or its delay has not passed.
+async fn start_login_attempt(app_state: &AppState, attempt_key: &str) -> Result<(), HttpError> {
+    let started = app_state.db_client
+        .start_login_attempt(
+            attempt_key,
+            app_state.env.login_delay_after,
+            app_state.env.login_max_attempts,
+            app_state.env.login_lockout_minutes
         )
+        .await
         .map_err(|e| HttpError::server_error(e.to_string()))?;
 
-        let cookie_duration = time::Duration::minutes(app_state.env.jwt_maxage * 60);
-        let cookie = Cookie::build(("token", token.clone()))
-            .path("/")
-            .max_age(cookie_duration)
-            .http_only(true)
-            .build();
+    if started.is_some() {
+        return Ok(());
+    }
+
+    let attempt = app_state.db_client
+        .get_login_attempt(attempt_key)
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?;
 
-        let response = Json(UserLoginResponseDto {
-            status: "success".to_string(),
-            token,
-        });
+    let retry_after = attempt
+        .map(|attempt| login_retry_after(app_state, &attempt))
+        .unwrap_or_default();
 
-        let mut headers = HeaderMap::new();
+    Err(too_many_login_attempts(retry_after))
+}
 
-        headers.append(
-            header::SET_COOKIE,
-            cookie.to_string().parse().unwrap() 
-        );
+/// Seconds until a refused email may try again.
+fn login_retry_after(app_state: &AppState, attempt: &LoginAttempt) -> u64 {
+    let now = Utc::now();
 
-        let mut response = response.into_response();
-        response.headers_mut().extend(headers);
+    if let Some(locked_until) = attempt.locked_until {
+        if locked_until > now {
+            return (locked_until - now).num_seconds() as u64;
+        }
+    }
 
-        Ok(response)
-    } else {
-        Err(HttpError::bad_request(ErrorMessage::WrongCredentials.to_string()))
+    // Each failure past the free attempts doubles the wait, up to about a minute
+    let delayed_attempts = attempt.failed_attempts - app_state.env.login_delay_after;
+    if let (true, Some(last_failed_at)) = (delayed_attempts >= 0, attempt.last_failed_at) {
+        let allowed_at = last_failed_at + Duration::seconds(1 << delayed_attempts.min(6));
+        if allowed_at > now {
+            return (allowed_at - now).num_seconds() as u64;
+        }
     }
 
+    0
+}
+
+fn too_many_login_attempts(retry_after: u64) -> HttpError {
+    let retry_after = retry_after.max(1);
+
+    HttpError::too_many_requests(ErrorMessage::TooManyLoginAttempts(retry_after).to_string())
+        .with_retry_after(retry_after)
 }
\ No newline at end of file
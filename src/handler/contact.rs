@@ -0,0 +1,148 @@
+use std::sync::Arc;
+
+use axum::{extract::{Path, Query}, http::StatusCode, response::IntoResponse, routing::{get, put}, Extension, Json, Router};
+use uuid::Uuid;
+use validator::Validate;
+
+use crate::{db::{ContactExt, UserExt}, dtos::{AddContactDto, ContactDto, ContactListResponseDto, ContactNicknameDto, ContactResponseDto, ContactSuggestionDto, ContactSuggestionListResponseDto, RequestQueryDto, Response}, error::HttpError, middleware::JWTAuthMiddeware, models::ContactDetails, AppState};
+
+const MAX_SUGGESTIONS: i64 = 20;
+
+pub fn contacts_handler() -> Router {
+    Router::new()
+        .route("/", get(get_contacts).post(add_contact))
+        .route("/suggestions", get(get_contact_suggestions))
+        .route("/:contact_id", put(update_contact).delete(delete_contact))
+}
+
+pub async fn get_contacts(
+    Query(query_params): Query<RequestQueryDto>,
+    Extension(app_state): Extension<Arc<AppState>>,
+    Extension(user): Extension<JWTAuthMiddeware>,
+) -> Result<impl IntoResponse, HttpError> {
+    query_params.validate()
+        .map_err(|e| HttpError::bad_request(e.to_string()))?;
+
+    let page = query_params.page.unwrap_or(1);
+    let limit = query_params.limit.unwrap_or(10);
+
+    let (contacts, total_count) = app_state.db_client
+        .get_contacts(user.user.id, page as u32, limit)
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+    let response = ContactListResponseDto {
+        status: "success".to_string(),
+        contacts: ContactDto::filter_contacts(&contacts),
+        results: total_count,
+    };
+
+    Ok(Json(response))
+}
+
+pub async fn add_contact(
+    Extension(app_state): Extension<Arc<AppState>>,
+    Extension(user): Extension<JWTAuthMiddeware>,
+    Json(body): Json<AddContactDto>,
+) -> Result<impl IntoResponse, HttpError> {
+    body.validate()
+        .map_err(|e| HttpError::bad_request(e.to_string()))?;
+
+    let contact_user = app_state.db_client
+        .get_user(None, None, Some(&body.email))
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?
+        .ok_or(HttpError::bad_request("User not found"))?;
+
+    if contact_user.id == user.user.id {
+        return Err(HttpError::bad_request("You cannot add yourself as a contact"));
+    }
+
+    let result = app_state.db_client
+        .save_contact(user.user.id, contact_user.id, body.nickname)
+        .await;
+
+    let contact = match result {
+        Ok(contact) => contact,
+        Err(sqlx::Error::Database(db_err)) if db_err.is_unique_violation() => {
+            return Err(HttpError::unique_constraint_violation("This user is already in your contacts"));
+        }
+        Err(e) => return Err(HttpError::server_error(e.to_string())),
+    };
+
+    let details = ContactDetails {
+        id: contact.id,
+        contact_user_id: contact.contact_user_id,
+        name: contact_user.name,
+        email: contact_user.email,
+        nickname: contact.nickname,
+        created_at: contact.created_at,
+    };
+
+    Ok((StatusCode::CREATED, Json(ContactResponseDto {
+        status: "success".to_string(),
+        contact: ContactDto::filter_contact(&details),
+    })))
+}
+
+pub async fn update_contact(
+    Path(contact_id): Path<Uuid>,
+    Extension(app_state): Extension<Arc<AppState>>,
+    Extension(user): Extension<JWTAuthMiddeware>,
+    Json(body): Json<ContactNicknameDto>,
+) -> Result<impl IntoResponse, HttpError> {
+    body.validate()
+        .map_err(|e| HttpError::bad_request(e.to_string()))?;
+
+    app_state.db_client
+        .update_contact_nickname(user.user.id, contact_id, body.nickname)
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?
+        .ok_or_else(|| HttpError::new("Contact not found", StatusCode::NOT_FOUND))?;
+
+    let response = Response {
+        message: "Contact updated successfully".to_string(),
+        status: "success",
+    };
+
+    Ok(Json(response))
+}
+
+pub async fn delete_contact(
+    Path(contact_id): Path<Uuid>,
+    Extension(app_state): Extension<Arc<AppState>>,
+    Extension(user): Extension<JWTAuthMiddeware>,
+) -> Result<impl IntoResponse, HttpError> {
+    let deleted = app_state.db_client
+        .delete_contact(user.user.id, contact_id)
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+    if !deleted {
+        return Err(HttpError::new("Contact not found", StatusCode::NOT_FOUND));
+    }
+
+    let response = Response {
+        message: "Contact removed successfully".to_string(),
+        status: "success",
+    };
+
+    Ok(Json(response))
+}
+
+pub async fn get_contact_suggestions(
+    Extension(app_state): Extension<Arc<AppState>>,
+    Extension(user): Extension<JWTAuthMiddeware>,
+) -> Result<impl IntoResponse, HttpError> {
+    let suggestions = app_state.db_client
+        .get_contact_suggestions(user.user.id, MAX_SUGGESTIONS)
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+    let response = ContactSuggestionListResponseDto {
+        status: "success".to_string(),
+        suggestions: ContactSuggestionDto::filter_suggestions(&suggestions),
+    };
+
+    Ok(Json(response))
+}
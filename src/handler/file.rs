@@ -1,17 +1,29 @@
-use std::{fs, path::PathBuf, sync::Arc};
+use std::{collections::{HashSet, VecDeque}, io, sync::Arc};
 
-use axum::{body::Body, extract::Multipart, http::{Response, StatusCode}, response::IntoResponse, routing::post, Extension, Json, Router};
-use chrono::{DateTime, Utc};
-use rsa::{pkcs1::{DecodeRsaPrivateKey, DecodeRsaPublicKey}, RsaPrivateKey, RsaPublicKey};
+use axum::{body::Body, extract::{multipart::{Field, MultipartError}, DefaultBodyLimit, Multipart}, http::{header, HeaderMap, Response, StatusCode}, response::IntoResponse, routing::post, Extension, Json, Router};
+use chrono::{DateTime, Duration, Utc};
+use futures_util::stream;
 use validator::Validate;
 use base64::{engine::general_purpose::STANDARD, Engine};
+use uuid::Uuid;
 
-use crate::{db::UserExt, dtos::{FileUploadDtos, Response as ResponseDto, RetrieveFileDto}, error::HttpError, middleware::JWTAuthMiddeware, utils::{decrypt::decrypt_file, encrypt::encrypt_file, password}, AppState};
+use crate::{db::{BlockExt, ContactExt, FolderExt, KeyExt, OrganizationExt, UserExt}, dtos::{BundleDownloadDto, FileSignatureDto, FileUploadDtos, Response as ResponseDto, RetrieveFileDto, VerifyFileDto, VerifyFileResponseDto}, error::{ErrorMessage, HttpError}, handler::folder::share_added_file, middleware::JWTAuthMiddeware, models::{FileContent, FileInfo, KeyStatus, NewFile, ScanStatus, ShareStatus, User}, utils::{decrypt::{decrypt_blocks, decrypt_file, open_digest, unwrap_aes_key, BLOCK_SIZE}, digest::{digest_header, repr_digest_header, sha256}, compression::{is_compressible, CompressionMode}, crypto_pool, signing, encrypt::{encrypt_file, reuse_file_key, seal_digest}, dedup, container::{self, Header, Metadata, SizePadding, Stanza, PREFIX_SIZE, TAG_SIZE}, file_metadata::open_metadata, key_pair::{PrivateKey, PublicKey}, keys::{load_private_key, parse_public_key}, password, quota::{check_file_size, check_quota}, range::{parse_range, ByteRange}, scanner::{scan_file, ScanVerdict}, zip::ZipWriter}, AppState};
+
+const MAX_BATCH_FILES: usize = 50;
+const MAX_TEXT_FIELD_SIZE: usize = 1024;
+
+pub fn file_handle(app_state: &AppState) -> Router {
+    // Files are read in chunks and checked against MAX_FILE_SIZE, and the
+    // request as a whole against MAX_REQUEST_SIZE
+    let body_limit = DefaultBodyLimit::max(app_state.env.max_request_size);
 
-pub fn file_handle() -> Router {
     Router::new()
-    .route("/upload", post(upload_file))
+    .route("/upload", post(upload_file).layer(body_limit))
     .route("/retrieve", post(retrieve_file))
+    .route("/bundle", post(download_bundle))
+    .route("/verify", post(verify_file))
+    .route("/export", post(export_file))
+    .route("/import", post(import_file).layer(body_limit))
 }
 
 pub async fn upload_file(
@@ -20,32 +32,52 @@ pub async fn upload_file(
     mut multipart: Multipart
 ) -> Result<impl IntoResponse, HttpError> {
 
-    let mut file_data = Vec::new();
-    let mut file_name = String::new();
-    let mut file_size: i64 = 0;
+    // Every `fileUpload` part is kept; more than one makes the upload a transfer
+    let mut files: Vec<(String, Option<String>, Vec<u8>)> = Vec::new();
     let mut form_data = FileUploadDtos {
-        recipient_email: String::new(),
+        recipient_email: None,
+        organization_id: None,
+        folder_id: None,
         password: String::new(),
         expiration_date: String::new(),
     };
 
-    while let Some(field) = multipart.next_field().await.unwrap() {
-        let name = field.name().unwrap().to_string();
+    while let Some(field) = multipart.next_field().await.map_err(multipart_error)? {
+        let name = field.name().unwrap_or_default().to_string();
 
         match name.as_str() {
             "fileUpload" => {
-                file_name = field.file_name().unwrap_or("unknow_file").to_string();
-                file_data = field.bytes().await.unwrap().to_vec();
-                file_size = file_data.len() as i64;
+                if files.len() == MAX_BATCH_FILES {
+                    return Err(HttpError::bad_request(format!("A batch can contain at most {} files", MAX_BATCH_FILES)));
+                }
+
+                let file_name = field.file_name().unwrap_or("unknow_file").to_string();
+                let content_type = field.content_type().map(str::to_string);
+                let file_data = read_file_field(&app_state, field).await?;
+                files.push((file_name, content_type, file_data));
             },
             "recipient_email" => {
-                form_data.recipient_email = field.text().await.unwrap();
+                form_data.recipient_email = Some(read_text_field(field).await?);
+            },
+            "organization_id" => {
+                let organization_id = read_text_field(field).await?;
+                form_data.organization_id = Some(
+                    uuid::Uuid::parse_str(&organization_id)
+                        .map_err(|_| HttpError::bad_request("Invalid organization id"))?
+                );
+            },
+            "folder_id" => {
+                let folder_id = read_text_field(field).await?;
+                form_data.folder_id = Some(
+                    uuid::Uuid::parse_str(&folder_id)
+                        .map_err(|_| HttpError::bad_request("Invalid folder id"))?
+                );
             },
             "password" => {
-                form_data.password = field.text().await.unwrap();
+                form_data.password = read_text_field(field).await?;
             },
             "expiration_date" => {
-                form_data.expiration_date = field.text().await.unwrap();
+                form_data.expiration_date = read_text_field(field).await?;
             },
             _ => {}
         }
@@ -54,68 +86,766 @@ pub async fn upload_file(
     form_data.validate()
         .map_err(|e| HttpError::bad_request(e.to_string()))?;
 
-    let recipient_result = app_state.db_client
-        .get_user(None, None, Some(&form_data.recipient_email))
+    if files.is_empty() {
+        return Err(HttpError::bad_request("No file was uploaded"));
+    }
+
+    let user_id = user.user.id;
+
+    let expiration_date = DateTime::parse_from_rfc3339(&form_data.expiration_date)
+        .map_err(|e| HttpError::bad_request(e.to_string()))?
+        .with_timezone(&Utc);
+
+    let hash_password = password::hash(&form_data.password)
         .await
-        .map_err(|e| HttpError::server_error(e.to_string()))?;
+        .map_err(HttpError::from)?;
 
-    let recipient_user = recipient_result.ok_or(HttpError::bad_request("Recipient user not found"))?;
+    let message = share_files(
+        &app_state,
+        user_id,
+        form_data.recipient_email,
+        form_data.organization_id,
+        form_data.folder_id,
+        hash_password,
+        expiration_date,
+        files
+    ).await?;
 
-    let public_key_str = match &recipient_user.public_key {
-        Some(key) => key,
-        None => return Err(HttpError::bad_request("Recipient user has no public key")),
+    let response = ResponseDto {
+        message,
+        status: "success"
     };
 
-    let public_key_bytes = STANDARD.decode(public_key_str)
-        .map_err(|e| HttpError::server_error(e.to_string()))?;
+    Ok(Json(response))
+}
 
-    let public_key = String::from_utf8(public_key_bytes)
-        .map_err(|e| HttpError::server_error(e.to_string()))?;
+/// Keeps the infected files of an upload for review, wrapped for the sender's
+/// own key and shared with nobody. They expire with the upload and cannot be
+/// downloaded, not even by the sender. A sender without a key only gets a
+/// record of the size and signature.
+#[allow(clippy::too_many_arguments)]
+async fn quarantine_files(
+    app_state: &AppState,
+    user_id: Uuid,
+    files: &[(String, Option<String>, Vec<u8>)],
+    signed_files: &[(Vec<u8>, Vec<u8>)],
+    infected: &[(usize, String)],
+    sender_key: Option<(Uuid, &PublicKey)>,
+    signer_public_key: &str,
+    hash_password: String,
+    expiration_date: DateTime<Utc>,
+) -> Result<(), HttpError> {
+    let padding = app_state.env.size_padding;
+
+    for (index, scan_signature) in infected {
+        let (file_name, content_type, file_data) = &files[*index];
+        let (digest, signature) = &signed_files[*index];
+
+        let file = NewFile {
+            user_id,
+            file_size: padding.padded_size(file_data.len() as u64) as i64,
+            recipient_user_id: user_id,
+            password: hash_password.clone(),
+            expiration_date,
+            scan_status: Some(ScanStatus::Quarantined),
+            scan_signature: Some(scan_signature.clone()),
+            ..Default::default()
+        };
+
+        let file = match sender_key {
+            Some((sender_key_id, public_key)) => {
+                let metadata = Metadata::new(file_name.clone(), content_type.clone(), file_data.len(), digest);
+
+                let encrypted = encrypt_file(file_data.clone(), metadata, digest, (sender_key_id, public_key), None, padding, None).await?;
+
+                NewFile {
+                    encrypted_aes_key: encrypted.keys.encrypted_aes_key.clone(),
+                    content: FileContent::Inline(encrypted.encrypted_file),
+                    encrypted_digest: Some(encrypted.keys.encrypted_digest),
+                    encrypted_metadata: Some(encrypted.keys.encrypted_metadata),
+                    signature: Some(signature.clone()),
+                    signer_public_key: Some(signer_public_key.to_string()),
+                    key_id: Some(sender_key_id),
+                    sender_encrypted_aes_key: Some(encrypted.keys.encrypted_aes_key),
+                    sender_key_id: Some(sender_key_id),
+                    ..file
+                }
+            },
+            // Nothing can be stored that only the sender could read
+            None => file,
+        };
+
+        app_state.db_client
+            .save_encrypted_file(file)
+            .await
+            .map_err(|e| HttpError::server_error(e.to_string()))?;
+    }
+
+    Ok(())
+}
+
+/// Reads an uploaded file part, failing as soon as it goes past
+/// `MAX_FILE_SIZE` rather than after buffering all of it.
+async fn read_file_field(app_state: &AppState, mut field: Field<'_>) -> Result<Vec<u8>, HttpError> {
+    let mut file_data = Vec::new();
+
+    while let Some(chunk) = field.chunk().await.map_err(multipart_error)? {
+        check_file_size(app_state, (file_data.len() + chunk.len()) as i64)?;
+        file_data.extend_from_slice(&chunk);
+    }
+
+    Ok(file_data)
+}
+
+/// Reads a text part of an upload form, refusing one over `MAX_TEXT_FIELD_SIZE`.
+async fn read_text_field(mut field: Field<'_>) -> Result<String, HttpError> {
+    let mut text = Vec::new();
+
+    while let Some(chunk) = field.chunk().await.map_err(multipart_error)? {
+        if text.len() + chunk.len() > MAX_TEXT_FIELD_SIZE {
+            return Err(HttpError::bad_request(format!("Form fields are limited to {} bytes", MAX_TEXT_FIELD_SIZE)));
+        }
+
+        text.extend_from_slice(&chunk);
+    }
+
+    String::from_utf8(text).map_err(|_| HttpError::bad_request("Form fields must be valid UTF-8"))
+}
+
+/// Keeps the status of a multipart error, 413 when the request is over
+/// `MAX_REQUEST_SIZE`.
+fn multipart_error(error: MultipartError) -> HttpError {
+    match error.status() {
+        StatusCode::PAYLOAD_TOO_LARGE => HttpError::payload_too_large("The request is over the upload size limit"),
+        status => HttpError::new(error.body_text(), status),
+    }
+}
+
+/// Encrypts `files` (name, MIME type, content) for every recipient of an upload
+/// and stores them, returning the message for the client. Completed resumable
+/// uploads go through here too.
+#[allow(clippy::too_many_arguments)]
+pub async fn share_files(
+    app_state: &AppState,
+    user_id: Uuid,
+    recipient_email: Option<String>,
+    organization_id: Option<Uuid>,
+    folder_id: Option<Uuid>,
+    hash_password: String,
+    expiration_date: DateTime<Utc>,
+    files: Vec<(String, Option<String>, Vec<u8>)>,
+) -> Result<String, HttpError> {
+    let (recipients, organization_id) = match (recipient_email.as_deref(), organization_id) {
+        (Some(recipient_email), None) => {
+            let recipient_result = app_state.db_client
+                .get_user(None, None, Some(recipient_email))
+                .await
+                .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+            let recipient_user = recipient_result.ok_or(HttpError::bad_request("Recipient user not found"))?;
+
+            if recipient_user.public_key.is_none() {
+                return Err(HttpError::bad_request("Recipient user has no public key"));
+            }
+
+            (vec![recipient_user], None)
+        }
+        (None, Some(organization_id)) => {
+            let role = app_state.db_client
+                .get_organization_role(organization_id, user_id)
+                .await
+                .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+            if role.is_none() {
+                return Err(HttpError::forbidden("You are not a member of this organization"));
+            }
+
+            let members = app_state.db_client
+                .get_organization_recipients(organization_id, user_id)
+                .await
+                .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+            if members.is_empty() {
+                return Err(HttpError::bad_request("This organization has no other members to share with"));
+            }
+
+            (members, Some(organization_id))
+        }
+        _ => return Err(HttpError::bad_request("Provide either recipient_email or organization_id")),
+    };
+
+    check_organization_policies(app_state, organization_id, &recipients, expiration_date).await?;
+
+    if let Some(folder_id) = folder_id {
+        app_state.db_client
+            .get_folder(user_id, folder_id)
+            .await
+            .map_err(|e| HttpError::server_error(e.to_string()))?
+            .ok_or(HttpError::bad_request("Folder not found"))?;
+    }
+
+    // Members of a team who blocked the sender or have no key are skipped
+    let mut shares = Vec::with_capacity(recipients.len());
+    for recipient_user in recipients {
+        let status = match share_status(app_state, &recipient_user, user_id).await? {
+            Some(status) => status,
+            None if organization_id.is_some() => continue,
+            None => return Err(HttpError::forbidden("The recipient is not accepting files from you")),
+        };
+
+        let key = app_state.db_client
+            .get_active_key(recipient_user.id)
+            .await
+            .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+        let key = match key {
+            Some(key) => key,
+            None if organization_id.is_some() => continue,
+            None => return Err(HttpError::bad_request("Recipient user has no public key")),
+        };
+
+        shares.push((recipient_user, status, key.id, parse_public_key(&key.public_key)?));
+    }
+
+    // Folder shares get a copy of whatever the first recipient holds
+    let folder_copies = match (folder_id, shares.first()) {
+        (Some(folder_id), Some((first_recipient, ..))) => app_state.db_client
+            .get_active_folder_shares(folder_id)
+            .await
+            .map_err(|e| HttpError::server_error(e.to_string()))?
+            .iter()
+            .filter(|folder_share| folder_share.recipient_user_id != first_recipient.id)
+            .count(),
+        _ => 0,
+    };
+
+    let padding = app_state.env.size_padding;
+
+    // Every copy that is written counts against the sender's quota
+    let stored_size: i64 = files.iter()
+        .map(|(_, _, file_data)| padding.padded_size(file_data.len() as u64) as i64)
+        .sum();
+
+    let copies = (shares.len() + folder_copies) as i64;
+    let limits = check_quota(app_state, user_id, organization_id, stored_size.saturating_mul(copies)).await?;
+
+    // The plaintext is only ever seen here, before it is encrypted
+    let mut infected = Vec::new();
+    for (index, (_, _, file_data)) in files.iter().enumerate() {
+        if let Some(ScanVerdict::Infected(signature)) = scan_file(app_state, file_data).await? {
+            infected.push((index, signature));
+        }
+    }
+
+    let scan_status = app_state.scanner.is_some().then_some(ScanStatus::Clean);
+
+    let mut shared_count = 0;
+    let mut pending_count = 0;
+    let mut first_file_ids = vec![None; files.len()];
+
+    // Each file is hashed and signed once, the signature is the same for every recipient
+    let signing_key = signing::signing_key(app_state, user_id).await?;
+    let signer_public_key = signing::encode_public_key(&signing_key.verifying_key());
+
+    let files = Arc::new(files);
+    let signed_files: Vec<(Vec<u8>, Vec<u8>)> = crypto_pool::run({
+        let files = files.clone();
+        move || files.iter()
+            .map(|(file_name, _, file_data)| {
+                let digest = sha256(file_data);
+                let message = signing::signed_message(user_id, file_name, file_data.len() as i64, &digest);
+                let signature = signing::sign(&signing_key, &message);
+                (digest, signature)
+            })
+            .collect()
+    })
+    .await?;
+
+    // The sender keeps the file key too, so they can still list what they sent
+    let sender_key = match app_state.db_client
+        .get_active_key(user_id)
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))? {
+        Some(key) => Some((key.id, parse_public_key(&key.public_key)?)),
+        None => None,
+    };
+
+    // Nothing of an upload with malware in it is shared
+    if !infected.is_empty() {
+        quarantine_files(
+            app_state,
+            user_id,
+            &files,
+            &signed_files,
+            &infected,
+            sender_key.as_ref().map(|(sender_key_id, public_key)| (*sender_key_id, public_key)),
+            &signer_public_key,
+            hash_password,
+            expiration_date
+        ).await?;
+
+        let found = infected.iter()
+            .map(|(index, signature)| format!("{} ({})", files[*index].0, signature))
+            .collect::<Vec<_>>()
+            .join(", ");
+
+        return Err(ErrorMessage::MalwareDetected(found).into());
+    }
+
+    // Identical files from this sender share one stored ciphertext. New copies
+    // get the key through the sender's own wrapped copy, so it needs one.
+    let dedup_tags: Vec<Option<Vec<u8>>> = if app_state.env.dedup_uploads && sender_key.is_some() {
+        let dedup_key = dedup::dedup_key(&*app_state.key_store, user_id).await?;
+
+        signed_files.iter()
+            .map(|(digest, _)| dedup::dedup_tag(&dedup_key, digest).map(Some))
+            .collect::<Result<_, _>>()?
+    } else {
+        vec![None; files.len()]
+    };
+
+    // Team shares are fanned out to one copy per member, wrapped with their own key
+    for (recipient_user, status, key_id, public_key_pem) in shares {
+        // Each recipient gets their own transfer so it can be listed on both sides
+        let transfer_id = if files.len() > 1 {
+            let transfer_id = app_state.db_client
+                .create_transfer(user_id)
+                .await
+                .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+            Some(transfer_id)
+        } else {
+            None
+        };
+
+        for ((((file_name, content_type, file_data), (digest, signature)), dedup_tag), first_file_id) in files.iter()
+            .zip(&signed_files)
+            .zip(&dedup_tags)
+            .zip(first_file_ids.iter_mut()) {
+            let metadata = Metadata::new(file_name.clone(), content_type.clone(), file_data.len(), digest);
 
-    let public_key_pem = RsaPublicKey::from_pkcs1_pem(&public_key)
+            let compression_level = (app_state.env.compression == CompressionMode::Zstd
+                && is_compressible(content_type.as_deref()))
+                .then_some(app_state.env.compression_level);
+
+            let blob = match dedup_tag {
+                Some(dedup_tag) => app_state.db_client
+                    .get_file_blob(user_id, dedup_tag)
+                    .await
+                    .map_err(|e| HttpError::server_error(e.to_string()))?,
+                None => None,
+            };
+
+            let (keys, content) = match blob {
+                Some(blob) => {
+                    let holder_private_key = load_private_key(&*app_state.key_store, blob.sender_key_id).await?;
+
+                    let keys = reuse_file_key(
+                        &blob.sender_encrypted_aes_key,
+                        &holder_private_key,
+                        &blob.encrypted_metadata,
+                        metadata,
+                        digest,
+                        &public_key_pem,
+                        sender_key.as_ref().map(|(_, public_key)| public_key)
+                    ).await?;
+
+                    (keys, FileContent::Blob(blob.id))
+                },
+                None => {
+                    let encrypted = encrypt_file(
+                        file_data.clone(),
+                        metadata,
+                        digest,
+                        (key_id, &public_key_pem),
+                        sender_key.as_ref().map(|(sender_key_id, public_key)| (*sender_key_id, public_key)),
+                        padding,
+                        compression_level
+                    ).await?;
+
+                    let content = match dedup_tag {
+                        Some(dedup_tag) => FileContent::NewBlob {
+                            dedup_tag: dedup_tag.clone(),
+                            encrypted_file: encrypted.encrypted_file,
+                        },
+                        None => FileContent::Inline(encrypted.encrypted_file),
+                    };
+
+                    (encrypted.keys, content)
+                },
+            };
+
+            // Only the padded size is stored in the clear, the name not at all
+            let file_id = app_state.db_client
+                .save_encrypted_file(NewFile {
+                    user_id,
+                    file_size: padding.padded_size(file_data.len() as u64) as i64,
+                    recipient_user_id: recipient_user.id,
+                    password: hash_password.clone(),
+                    expiration_date,
+                    encrypted_aes_key: keys.encrypted_aes_key,
+                    content,
+                    encrypted_digest: Some(keys.encrypted_digest),
+                    encrypted_metadata: Some(keys.encrypted_metadata),
+                    signature: Some(signature.clone()),
+                    signer_public_key: Some(signer_public_key.clone()),
+                    key_id: Some(key_id),
+                    sender_encrypted_aes_key: keys.sender_encrypted_aes_key,
+                    sender_key_id: sender_key.as_ref().map(|(sender_key_id, _)| *sender_key_id),
+                    status,
+                    organization_id,
+                    folder_id,
+                    transfer_id,
+                    scan_status,
+                    limits,
+                    ..Default::default()
+                })
+                .await
+                .map_err(|e| HttpError::server_error(e.to_string()))?
+                // Another upload used up the quota since it was checked
+                .ok_or(ErrorMessage::QuotaExceeded)?;
+
+            first_file_id.get_or_insert(file_id);
+        }
+
+        shared_count += 1;
+        if status == ShareStatus::Pending {
+            pending_count += 1;
+        }
+    }
+
+    // Folder shares only need one copy of the upload, whichever recipient holds it
+    if let Some(folder_id) = folder_id {
+        for file_id in first_file_ids.into_iter().flatten() {
+            share_added_file(app_state, user_id, file_id, folder_id).await?;
+        }
+    }
+
+    let uploaded = match files.len() {
+        1 => "File".to_string(),
+        count => format!("{} files", count),
+    };
+
+    let message = match (organization_id, pending_count) {
+        (None, 0) => format!("{} uploaded and encrypted successfully", uploaded),
+        (None, _) => format!("{} uploaded and encrypted, waiting for the recipient to accept it", uploaded),
+        (Some(_), 0) => format!("{} uploaded and shared with {} organization members", uploaded, shared_count),
+        (Some(_), _) => format!(
+            "{} uploaded and shared with {} organization members, {} of them must accept it first",
+            uploaded,
+            shared_count,
+            pending_count
+        ),
+    };
+
+    Ok(message)
+}
+
+/// Returns how a share to `recipient` starts out, or `None` when the
+/// recipient has blocked the sender.
+pub async fn share_status(
+    app_state: &AppState,
+    recipient: &User,
+    sender_id: Uuid,
+) -> Result<Option<ShareStatus>, HttpError> {
+    let blocked = app_state.db_client
+        .is_blocked(recipient.id, sender_id)
+        .await
         .map_err(|e| HttpError::server_error(e.to_string()))?;
 
-    let (
-        encrypted_aes_key,
-        encrypted_data,
-        iv
-    ) = encrypt_file(file_data, &public_key_pem).await?;
+    if blocked {
+        return Ok(None);
+    }
 
-    let user_id = uuid::Uuid::parse_str(&user.user.id.to_string()).unwrap();
+    // Recipients who require consent only get shares from contacts directly
+    if !recipient.require_share_consent {
+        return Ok(Some(ShareStatus::Accepted));
+    }
 
-    let hash_password = password::hash(&form_data.password)
+    let is_contact = app_state.db_client
+        .is_contact(recipient.id, sender_id)
+        .await
         .map_err(|e| HttpError::server_error(e.to_string()))?;
 
-    let expiration_date = DateTime::parse_from_rfc3339(&form_data.expiration_date)
+    Ok(Some(if is_contact { ShareStatus::Accepted } else { ShareStatus::Pending }))
+}
+
+/// Enforces the expiry and recipient domain rules of the organization a
+/// share is made through. Shares to single users are not bound by them.
+async fn check_organization_policies(
+    app_state: &AppState,
+    organization_id: Option<Uuid>,
+    recipients: &[User],
+    expiration_date: DateTime<Utc>,
+) -> Result<(), HttpError> {
+    let organization_id = match organization_id {
+        Some(organization_id) => organization_id,
+        None => return Ok(()),
+    };
+
+    let organization = app_state.db_client
+        .get_organization(organization_id)
+        .await
         .map_err(|e| HttpError::server_error(e.to_string()))?
-        .with_timezone(&Utc);
+        .ok_or(HttpError::bad_request("Organization not found"))?;
 
-    let recipient_user_id = uuid::Uuid::parse_str(&recipient_user.id.to_string()).unwrap();
+    if let Some(max_expiry_days) = organization.max_expiry_days {
+        if expiration_date > Utc::now() + Duration::days(max_expiry_days as i64) {
+            return Err(HttpError::bad_request(format!(
+                "{} only allows shares that expire within {} days",
+                organization.name,
+                max_expiry_days
+            )));
+        }
+    }
 
-    app_state.db_client
-        .save_encrypted_file(
-            user_id.clone(),
-            file_name, 
-            file_size, 
-            recipient_user_id, 
-            hash_password, 
-            expiration_date, 
-            encrypted_aes_key, 
-            encrypted_data, 
-            iv
-        )
+    if organization.allowed_recipient_domains.is_empty() {
+        return Ok(());
+    }
+
+    for recipient in recipients {
+        let domain = recipient.email.rsplit('@').next().unwrap_or_default().to_lowercase();
+
+        if !organization.allowed_recipient_domains.contains(&domain) {
+            return Err(HttpError::forbidden(format!(
+                "{} does not allow sharing with {}",
+                organization.name,
+                domain
+            )));
+        }
+    }
+
+    Ok(())
+}
+
+pub fn recipient_public_key(recipient: &User) -> Result<PublicKey, HttpError> {
+    let public_key_str = match &recipient.public_key {
+        Some(key) => key,
+        None => return Err(HttpError::bad_request("Recipient user has no public key")),
+    };
+
+    parse_public_key(public_key_str)
+}
+
+/// The recipient's active key, which new files are wrapped with, and its id.
+pub async fn recipient_key(app_state: &AppState, recipient: &User) -> Result<(Uuid, PublicKey), HttpError> {
+    let key = app_state.db_client
+        .get_active_key(recipient.id)
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?
+        .ok_or_else(|| HttpError::bad_request("Recipient user has no public key"))?;
+
+    Ok((key.id, parse_public_key(&key.public_key)?))
+}
+
+pub async fn retrieve_file(
+    Extension(app_state): Extension<Arc<AppState>>,
+    Extension(user): Extension<JWTAuthMiddeware>,
+    headers: HeaderMap,
+    Json(body): Json<RetrieveFileDto>
+) -> Result<impl IntoResponse, HttpError> {
+    body.validate()
+        .map_err(|e| HttpError::bad_request(e.to_string()))?;
+
+    let user_id = user.user.id;
+    let file_id = shared_file_id(&app_state, user_id, &body.shared_id, &body.password).await?;
+
+    let file_result = app_state.db_client
+        .get_file_info(file_id)
         .await
         .map_err(|e| HttpError::server_error(e.to_string()))?;
 
-    let response = ResponseDto {
-        message: "File uploaded and encrypted successfully".to_string(),
-        status: "success"
+    let file_info = file_result.ok_or_else(|| {
+        HttpError::bad_request("The requested file either does not exist or has expired.".to_string())
+    })?;
+
+    // Files from before key rotation carry no key id and use the user's first key
+    let private_key_pem = load_private_key(&*app_state.key_store, file_info.key_id.unwrap_or(user_id)).await?;
+
+    let metadata = open_metadata(
+        &private_key_pem,
+        &file_info.encrypted_aes_key,
+        file_info.encrypted_metadata.as_deref(),
+        file_info.file_name.as_deref(),
+        file_info.file_size
+    ).await?;
+
+    let file_size = metadata.size as u64;
+
+    // Stored files never change, so the file id is a strong validator
+    let etag = format!("\"{}\"", file_id);
+
+    let range_header = headers.get(header::RANGE).and_then(|value| value.to_str().ok());
+    let if_range = headers.get(header::IF_RANGE).and_then(|value| value.to_str().ok());
+
+    let range = match if_range {
+        Some(validator) if validator != etag => ByteRange::Full,
+        _ => parse_range(range_header, file_size),
+    };
+
+    let response = Response::builder()
+        .header("Content-Disposition", format!("attachment; filename=\"{}\"", metadata.name))
+        .header("Content-Type", metadata.content_type.as_deref().unwrap_or("application/octet-stream"))
+        .header(header::ACCEPT_RANGES, "bytes")
+        .header(header::ETAG, &etag);
+
+    let response = match (&file_info.signature, &file_info.signer_public_key, file_info.user_id) {
+        (Some(signature), Some(signer_public_key), Some(sender_id)) => response
+            .header("X-Sender-Id", sender_id.to_string())
+            .header("X-Sender-Signature", STANDARD.encode(signature))
+            .header("X-Sender-Public-Key", signer_public_key),
+        _ => response,
+    };
+
+    let response = match range {
+        ByteRange::Full => {
+            let file_data = app_state.db_client
+                .get_file(file_id)
+                .await
+                .map_err(|e| HttpError::server_error(e.to_string()))?
+                .ok_or_else(|| {
+                    HttpError::bad_request("The requested file either does not exist or has expired.".to_string())
+                })?;
+
+            let (decrypted_file, digest) = decrypt_file(
+                file_data.encrypted_aes_key, 
+                file_data.encrypted_file, 
+                file_data.iv, 
+                file_data.encrypted_digest,
+                &private_key_pem
+            ).await?;
+
+            // decrypt_file has already checked the content against it
+            let response = match digest {
+                Some(digest) => response
+                    .header("Repr-Digest", repr_digest_header(&digest))
+                    .header("Digest", digest_header(&digest)),
+                None => response,
+            };
+
+            response
+                .status(StatusCode::OK)
+                .header(header::CONTENT_LENGTH, decrypted_file.len())
+                .body(Body::from(decrypted_file))
+        },
+        ByteRange::Unsatisfiable => {
+            response
+                .status(StatusCode::RANGE_NOT_SATISFIABLE)
+                .header(header::CONTENT_RANGE, format!("bytes */{}", file_size))
+                .body(Body::empty())
+        },
+        ByteRange::Partial { start, end } => {
+            let (content, digest) = match metadata.compressed {
+                true => decrypt_compressed_range(&app_state, file_id, &private_key_pem, start, end).await?,
+                false => decrypt_file_range(&app_state, &file_info, &private_key_pem, start, end).await?,
+            };
+
+            // Repr-Digest covers the whole file, so it is valid on a partial response too
+            let response = match digest {
+                Some(digest) => response.header("Repr-Digest", repr_digest_header(&digest)),
+                None => response,
+            };
+
+            response
+                .status(StatusCode::PARTIAL_CONTENT)
+                .header(header::CONTENT_RANGE, format!("bytes {}-{}/{}", start, end, file_size))
+                .header(header::CONTENT_LENGTH, content.len())
+                .body(Body::from(content))
+        },
+    };
+
+    response.map_err(|e| HttpError::server_error(e.to_string()))
+}
+
+/// Checks a sender's signature over a received file. The stored digest is
+/// used unless the client sends the digest of what it downloaded.
+pub async fn verify_file(
+    Extension(app_state): Extension<Arc<AppState>>,
+    Extension(user): Extension<JWTAuthMiddeware>,
+    Json(body): Json<VerifyFileDto>
+) -> Result<impl IntoResponse, HttpError> {
+    body.validate()
+        .map_err(|e| HttpError::bad_request(e.to_string()))?;
+
+    let user_id = user.user.id;
+    let file_id = shared_file_id(&app_state, user_id, &body.shared_id, &body.password).await?;
+
+    let file_info = app_state.db_client
+        .get_file_info(file_id)
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?
+        .ok_or_else(|| {
+            HttpError::bad_request("The requested file either does not exist or has expired.".to_string())
+        })?;
+
+    let private_key = load_private_key(&*app_state.key_store, file_info.key_id.unwrap_or(user_id)).await?;
+
+    // The signature covers the real name and size, not what is stored in the clear
+    let metadata = open_metadata(
+        &private_key,
+        &file_info.encrypted_aes_key,
+        file_info.encrypted_metadata.as_deref(),
+        file_info.file_name.as_deref(),
+        file_info.file_size
+    ).await?;
+
+    let digest = match body.digest {
+        Some(digest) => parse_digest(&digest)
+            .ok_or_else(|| HttpError::bad_request("Digest must be a hex SHA-256"))?,
+        None => {
+            let encrypted_digest = file_info.encrypted_digest
+                .as_deref()
+                .ok_or_else(|| HttpError::bad_request("This file was uploaded without a digest"))?;
+
+            let encrypted_aes_key = file_info.encrypted_aes_key.clone();
+            let encrypted_digest = encrypted_digest.to_vec();
+
+            crypto_pool::run(move || {
+                let aes_key = unwrap_aes_key(&encrypted_aes_key, &private_key)?;
+                open_digest(&aes_key, &encrypted_digest)
+            })
+            .await??
+        },
+    };
+
+    let valid = match (&file_info.signature, &file_info.signer_public_key, file_info.user_id) {
+        (Some(signature), Some(signer_public_key), Some(sender_id)) => {
+            let message = signing::signed_message(sender_id, &metadata.name, metadata.size, &digest);
+            signing::verify(signer_public_key, &message, signature)
+        },
+        _ => false,
+    };
+
+    let sender_key = match file_info.user_id {
+        Some(sender_id) => app_state.db_client
+            .get_signing_key(sender_id)
+            .await
+            .map_err(|e| HttpError::server_error(e.to_string()))?,
+        None => None,
+    };
+
+    let response = VerifyFileResponseDto {
+        status: "success".to_string(),
+        verification: FileSignatureDto {
+            file_id: file_info.id.to_string(),
+            sender_id: file_info.user_id.map(|id| id.to_string()),
+            file_name: metadata.name,
+            file_size: metadata.size,
+            digest: digest.iter().map(|byte| format!("{:02x}", byte)).collect(),
+            signature: file_info.signature.as_ref().map(|signature| STANDARD.encode(signature)),
+            sender_key_current: sender_key.is_some() && sender_key == file_info.signer_public_key,
+            signer_public_key: file_info.signer_public_key,
+            valid,
+        },
     };
 
     Ok(Json(response))
 }
 
-pub async fn retrieve_file(
+/// Downloads a received file as a standalone SSEF container, wrapped for the
+/// key that holds it now. Files from before containers, and files sharing a
+/// deduplicated blob, are sealed again.
+pub async fn export_file(
     Extension(app_state): Extension<Arc<AppState>>,
     Extension(user): Extension<JWTAuthMiddeware>,
     Json(body): Json<RetrieveFileDto>
@@ -123,11 +853,242 @@ pub async fn retrieve_file(
     body.validate()
         .map_err(|e| HttpError::bad_request(e.to_string()))?;
 
-    let user_id = uuid::Uuid::parse_str(&user.user.id.to_string()).unwrap();
-    let shared_id = uuid::Uuid::parse_str(&body.shared_id.to_string()).unwrap();
+    let user_id = user.user.id;
+    let file_id = shared_file_id(&app_state, user_id, &body.shared_id, &body.password).await?;
+
+    let file_data = app_state.db_client
+        .get_file(file_id)
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?
+        .ok_or_else(|| {
+            HttpError::bad_request("The requested file either does not exist or has expired.".to_string())
+        })?;
+
+    let key_id = file_data.key_id.unwrap_or(user_id);
+    let private_key = load_private_key(&*app_state.key_store, key_id).await?;
+    let public_key = private_key.public_key();
+
+    let metadata = open_metadata(
+        &private_key,
+        &file_data.encrypted_aes_key,
+        file_data.encrypted_metadata.as_deref(),
+        file_data.file_name.as_deref(),
+        file_data.file_size
+    ).await?;
+
+    let exported = match file_data.iv {
+        // The stored stanza is from upload time, the key column follows rotations
+        None if file_data.blob_id.is_none() => {
+            let (mut header, data_offset) = Header::parse(&file_data.encrypted_file)?;
+            header.stanzas = vec![Stanza {
+                key_type: public_key.key_type(),
+                key_id,
+                wrapped_key: file_data.encrypted_aes_key,
+            }];
+
+            let mut exported = header.to_bytes()?;
+            exported.extend_from_slice(&file_data.encrypted_file[data_offset..]);
+            exported
+        },
+        // A shared blob's header names the file as first uploaded, which may
+        // not be this one, so it is sealed again like a file from before containers
+        iv => {
+            let (decrypted_file, digest) = decrypt_file(
+                file_data.encrypted_aes_key,
+                file_data.encrypted_file,
+                iv,
+                file_data.encrypted_digest,
+                &private_key
+            ).await?;
+
+            let (decrypted_file, digest) = match digest {
+                Some(digest) => (decrypted_file, digest),
+                None => crypto_pool::run(move || {
+                    let digest = sha256(&decrypted_file);
+                    (decrypted_file, digest)
+                })
+                .await?,
+            };
+
+            let converted = Metadata::new(metadata.name.clone(), metadata.content_type, decrypted_file.len(), &digest);
+
+            encrypt_file(decrypted_file, converted, &digest, (key_id, &public_key), None, SizePadding::None, None)
+                .await?
+                .encrypted_file
+        },
+    };
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .header("Content-Disposition", format!("attachment; filename=\"{}.ssef\"", metadata.name))
+        .header("Content-Type", "application/octet-stream")
+        .header(header::CONTENT_LENGTH, exported.len())
+        .body(Body::from(exported))
+        .map_err(|e| HttpError::server_error(e.to_string()))
+}
+
+/// Stores an SSEF container the user can open, for example one they exported
+/// earlier, as a file shared with themselves. The whole container is checked
+/// before anything is saved.
+pub async fn import_file(
+    Extension(app_state): Extension<Arc<AppState>>,
+    Extension(user): Extension<JWTAuthMiddeware>,
+    mut multipart: Multipart
+) -> Result<impl IntoResponse, HttpError> {
+    let mut container = None;
+    let mut form_data = FileUploadDtos::default();
+
+    while let Some(field) = multipart.next_field().await.map_err(multipart_error)? {
+        let name = field.name().unwrap_or_default().to_string();
+
+        match name.as_str() {
+            "container" => {
+                container = Some(read_file_field(&app_state, field).await?);
+            },
+            "password" => {
+                form_data.password = read_text_field(field).await?;
+            },
+            "expiration_date" => {
+                form_data.expiration_date = read_text_field(field).await?;
+            },
+            _ => {}
+        }
+    }
+
+    form_data.validate()
+        .map_err(|e| HttpError::bad_request(e.to_string()))?;
+
+    let container = container.ok_or_else(|| HttpError::bad_request("No container was uploaded"))?;
+
+    let user_id = user.user.id;
+
+    let expiration_date = DateTime::parse_from_rfc3339(&form_data.expiration_date)
+        .map_err(|e| HttpError::bad_request(e.to_string()))?
+        .with_timezone(&Utc);
+
+    let (mut header, data_offset) = Header::parse(&container)?;
+
+    let keys = app_state.db_client
+        .get_user_keys(user_id)
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+    let stanza = header.stanzas.iter()
+        .find(|stanza| keys.iter().any(|key| {
+            key.id == stanza.key_id && key.key_type == stanza.key_type && key.status != KeyStatus::Retired
+        }))
+        .cloned()
+        .ok_or_else(|| HttpError::bad_request("This file is not encrypted for any of your keys"))?;
+
+    let private_key = load_private_key(&*app_state.key_store, stanza.key_id).await?;
+    let wrapped_key = stanza.wrapped_key.clone();
+    let pool_state = app_state.clone();
+    let pool_header = header.clone();
+
+    let (container, file_name, file_size, plaintext, encrypted_digest, encrypted_metadata) = crypto_pool::run(move || {
+        let aes_key = unwrap_aes_key(&wrapped_key, &private_key)
+            .map_err(|_| HttpError::bad_request("The file key could not be unwrapped"))?;
+
+        // The claimed size bounds decompression, so it is checked before opening
+        let claimed = pool_header.opener(&aes_key)
+            .and_then(|opener| opener.metadata(&pool_header))
+            .map_err(|e| HttpError::bad_request(e.message))?;
+
+        check_file_size(&pool_state, i64::try_from(claimed.size).unwrap_or(i64::MAX))?;
+
+        let (metadata, plaintext, digest) = container::open(&container, &aes_key)
+            .map_err(|e| HttpError::bad_request(e.message))?;
+
+        let encrypted_digest = seal_digest(&aes_key, &digest)?;
+        let encrypted_metadata = container::seal_detached_metadata(&aes_key, &metadata)?;
+
+        Ok::<_, HttpError>((container, metadata.name, metadata.size, plaintext, encrypted_digest, encrypted_metadata))
+    })
+    .await??;
+
+    // Imports are only shared with the user, so an infected one is just refused
+    let scan_status = match scan_file(&app_state, &plaintext).await? {
+        Some(ScanVerdict::Infected(signature)) => {
+            return Err(ErrorMessage::MalwareDetected(format!("{} ({})", file_name, signature)).into());
+        },
+        Some(ScanVerdict::Clean) => Some(ScanStatus::Clean),
+        None => None,
+    };
+
+    drop(plaintext);
+
+    // Other recipients' stanzas are of no use here
+    let key_id = stanza.key_id;
+    let encrypted_aes_key = stanza.wrapped_key.clone();
+    header.stanzas = vec![stanza];
+
+    // Charged like an upload, whatever padding the container was sealed with
+    let stored_size = app_state.env.size_padding.padded_size(file_size) as i64;
+
+    let limits = check_quota(&app_state, user_id, None, stored_size).await?;
+
+    let mut stored = header.to_bytes()?;
+    stored.extend_from_slice(&container[data_offset..]);
+
+    let hash_password = password::hash(&form_data.password)
+        .await
+        .map_err(HttpError::from)?;
+
+    app_state.db_client
+        .save_encrypted_file(NewFile {
+            user_id,
+            file_size: stored_size,
+            recipient_user_id: user_id,
+            password: hash_password,
+            expiration_date,
+            encrypted_aes_key: encrypted_aes_key.clone(),
+            content: FileContent::Inline(stored),
+            encrypted_digest: Some(encrypted_digest),
+            encrypted_metadata: Some(encrypted_metadata),
+            key_id: Some(key_id),
+            sender_encrypted_aes_key: Some(encrypted_aes_key),
+            sender_key_id: Some(key_id),
+            scan_status,
+            limits,
+            ..Default::default()
+        })
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?
+        .ok_or(ErrorMessage::QuotaExceeded)?;
+
+    let response = ResponseDto {
+        message: "File imported successfully".to_string(),
+        status: "success"
+    };
+
+    Ok(Json(response))
+}
+
+/// Resolves a shared link the user received to its file, checking the
+/// link password.
+/// Decodes a hex SHA-256 digest, anything but 64 hex characters is rejected.
+fn parse_digest(digest: &str) -> Option<Vec<u8>> {
+    if digest.len() != 64 || !digest.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+        return None;
+    }
+
+    digest.as_bytes()
+        .chunks_exact(2)
+        .map(|pair| std::str::from_utf8(pair).ok().and_then(|pair| u8::from_str_radix(pair, 16).ok()))
+        .collect()
+}
+
+async fn shared_file_id(
+    app_state: &AppState,
+    user_id: Uuid,
+    shared_id: &str,
+    link_password: &str,
+) -> Result<Uuid, HttpError> {
+    let shared_id = Uuid::parse_str(shared_id)
+        .map_err(|_| HttpError::bad_request("Invalid shared id"))?;
 
     let shared_result = app_state.db_client
-        .get_shared(shared_id.clone(), user_id.clone())
+        .get_shared(shared_id, user_id)
         .await
         .map_err(|e| HttpError::server_error(e.to_string()))?;
 
@@ -135,52 +1096,309 @@ pub async fn retrieve_file(
         HttpError::bad_request("The requested shared link either does not exist or has expired.".to_string())
     })?;
 
-    let match_password = password::compare(&body.password, &shared_data.password)
-    .map_err(|e| HttpError::server_error(e.to_string()))?;
+    let match_password = password::compare(link_password, &shared_data.password)
+    .await
+    .map_err(HttpError::from)?;
 
     if !match_password {
         return Err(HttpError::bad_request("The provided password is incorrect.".to_string()));
     }
 
-    let file_id = match shared_data.file_id {
-        Some(id) => id,
+    match shared_data.file_id {
+        Some(id) => Ok(id),
+        None => Err(HttpError::bad_request("File ID is missing".to_string())),
+    }
+}
+
+/// Decrypts plaintext bytes `start..=end` of a stored file, along with its
+/// sealed digest if it has one. Only the chunks (or, for files from before
+/// containers, the cipher blocks) covering the range are read.
+async fn decrypt_file_range(
+    app_state: &AppState,
+    file_info: &FileInfo,
+    private_key: &PrivateKey,
+    start: u64,
+    end: u64,
+) -> Result<(Vec<u8>, Option<Vec<u8>>), HttpError> {
+    let (first_unit, unit_size, previous_block, sealed, header) = match &file_info.iv {
+        Some(iv) => {
+            let block_size = BLOCK_SIZE as u64;
+            let first_block = start / block_size;
+            let last_block = end / block_size;
+
+            // Block 0 is chained to the IV, every other block to the one before it
+            let offset = first_block.saturating_sub(1) * block_size;
+            let length = (last_block + 1) * block_size - offset;
+
+            let mut chunk = read_stored_bytes(app_state, file_info.id, offset, length).await?;
+
+            let previous_block = if first_block == 0 {
+                iv.clone()
+            } else {
+                chunk.drain(..BLOCK_SIZE).collect()
+            };
+
+            (first_block, block_size, previous_block, chunk, None)
+        },
         None => {
-            // Handle the case when file_id is None
-            return Err(HttpError::bad_request("File ID is missing".to_string()));
-        }
+            let prefix = read_stored_bytes(app_state, file_info.id, 0, PREFIX_SIZE as u64).await?;
+            let header_len = Header::header_len(&prefix)?;
+
+            let header = read_stored_bytes(app_state, file_info.id, 0, (PREFIX_SIZE + header_len) as u64).await?;
+            let (header, data_offset) = Header::parse(&header)?;
+
+            let chunk_size = header.chunk_size as u64;
+            let sealed_chunk_size = chunk_size + TAG_SIZE as u64;
+            let first_chunk = start / chunk_size;
+            let last_chunk = end / chunk_size;
+
+            let offset = data_offset as u64 + first_chunk * sealed_chunk_size;
+            let length = (last_chunk - first_chunk + 1) * sealed_chunk_size;
+
+            let chunks = read_stored_bytes(app_state, file_info.id, offset, length).await?;
+
+            (first_chunk, chunk_size, Vec::new(), chunks, Some(header))
+        },
     };
 
-    let file_result = app_state.db_client
-        .get_file(file_id.clone())
-        .await
-        .map_err(|e| HttpError::server_error(e.to_string()))?;
+    let skip = (start - first_unit * unit_size) as usize;
+    let take = (end - start + 1) as usize;
 
-    let file_data = file_result.ok_or_else(|| {
-        HttpError::bad_request("The requested file either does not exist or has expired.".to_string())
-    })?;
+    let encrypted_aes_key = file_info.encrypted_aes_key.clone();
+    let encrypted_digest = file_info.encrypted_digest.clone();
+    let private_key = private_key.clone();
 
-    let mut path = PathBuf::from("assets/private_keys");
-    path.push(format!("{}.pem", user_id.clone()));
+    crypto_pool::run(move || {
+        let aes_key = unwrap_aes_key(&encrypted_aes_key, &private_key)?;
 
-    let private_key = fs::read_to_string(&path)
-        .map_err(|e| HttpError::server_error(e.to_string()))?;
+        let plaintext = match header {
+            Some(header) => header.opener(&aes_key)?.open_chunks(&header, first_unit, &sealed)?,
+            None => decrypt_blocks(&aes_key, &previous_block, &sealed)?,
+        };
 
-    let private_key_pem = RsaPrivateKey::from_pkcs1_pem(&private_key)
-        .map_err(|e| HttpError::server_error(e.to_string()))?;
+        let content = plaintext
+            .get(skip..skip + take)
+            .map(|bytes| bytes.to_vec())
+            .ok_or_else(|| HttpError::server_error("Stored file is shorter than its recorded size"))?;
+
+        let digest = encrypted_digest
+            .as_deref()
+            .map(|encrypted_digest| open_digest(&aes_key, encrypted_digest))
+            .transpose()?;
 
-    let decrypted_file = decrypt_file(
-        file_data.encrypted_aes_key, 
-        file_data.encrypted_file, 
-        file_data.iv, 
-        &private_key_pem
+        Ok((content, digest))
+    })
+    .await?
+}
+
+/// Like `decrypt_file_range` for a compressed file, whose plaintext offsets
+/// do not map onto chunks: the whole file is decrypted and the range cut out.
+async fn decrypt_compressed_range(
+    app_state: &AppState,
+    file_id: Uuid,
+    private_key: &PrivateKey,
+    start: u64,
+    end: u64,
+) -> Result<(Vec<u8>, Option<Vec<u8>>), HttpError> {
+    let file_data = app_state.db_client
+        .get_file(file_id)
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?
+        .ok_or_else(|| {
+            HttpError::bad_request("The requested file either does not exist or has expired.".to_string())
+        })?;
+
+    let (decrypted_file, digest) = decrypt_file(
+        file_data.encrypted_aes_key,
+        file_data.encrypted_file,
+        file_data.iv,
+        file_data.encrypted_digest,
+        private_key
     ).await?;
 
+    let content = decrypted_file
+        .get(start as usize..=end as usize)
+        .map(|bytes| bytes.to_vec())
+        .ok_or_else(|| HttpError::server_error("Stored file is shorter than its recorded size"))?;
+
+    Ok((content, digest))
+}
+
+async fn read_stored_bytes(app_state: &AppState, file_id: Uuid, offset: u64, length: u64) -> Result<Vec<u8>, HttpError> {
+    app_state.db_client
+        .get_file_chunk(file_id, offset as i64, length as i64)
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?
+        .ok_or_else(|| {
+            HttpError::bad_request("The requested file either does not exist or has expired.".to_string())
+        })
+}
+
+/// Streams several received files as one ZIP archive. Every link and password
+/// is checked before the response starts, then files are decrypted and sent
+/// one at a time.
+pub async fn download_bundle(
+    Extension(app_state): Extension<Arc<AppState>>,
+    Extension(user): Extension<JWTAuthMiddeware>,
+    Json(body): Json<BundleDownloadDto>
+) -> Result<impl IntoResponse, HttpError> {
+    body.validate()
+        .map_err(|e| HttpError::bad_request(e.to_string()))?;
+
+    let user_id = user.user.id;
+    let mut file_ids = VecDeque::new();
+
+    for item in &body.files {
+        let shared_id = Uuid::parse_str(&item.shared_id)
+            .map_err(|_| HttpError::bad_request(format!("Invalid shared id {}", item.shared_id)))?;
+
+        let password = item.password.as_ref()
+            .or(body.password.as_ref())
+            .ok_or_else(|| HttpError::bad_request(format!("A password is required for {}", shared_id)))?;
+
+        let shared_data = app_state.db_client
+            .get_shared(shared_id, user_id)
+            .await
+            .map_err(|e| HttpError::server_error(e.to_string()))?
+            .ok_or_else(|| HttpError::bad_request(format!(
+                "The shared link {} either does not exist or has expired.",
+                shared_id
+            )))?;
+
+        let match_password = password::compare(password, &shared_data.password)
+            .await
+            .map_err(HttpError::from)?;
+
+        if !match_password {
+            return Err(HttpError::bad_request(format!("The provided password is incorrect for {}.", shared_id)));
+        }
+
+        let file_id = shared_data.file_id
+            .ok_or_else(|| HttpError::bad_request("File ID is missing".to_string()))?;
+
+        if !file_ids.contains(&file_id) {
+            file_ids.push_back(file_id);
+        }
+    }
+
+    let bundle = BundleStream {
+        app_state,
+        user_id,
+        file_ids,
+        names: HashSet::new(),
+        writer: ZipWriter::new(),
+    };
+
+    let body = Body::from_stream(stream::unfold(Some(bundle), |bundle| async move {
+        let mut bundle = bundle?;
+
+        match bundle.file_ids.pop_front() {
+            Some(file_id) => match bundle.next_entry(file_id).await {
+                Ok(chunk) => Some((Ok(chunk), Some(bundle))),
+                Err(e) => Some((Err(io::Error::other(e)), None)),
+            },
+            None => Some((bundle.writer.finish().map_err(io::Error::other), None)),
+        }
+    }));
+
     let response = Response::builder()
         .status(StatusCode::OK)
-        .header("Content-Disposition", format!("attachment; filename=\"{}\"", file_data.file_name))
-        .header("Content-Type", "application/octet-stream")
-        .body(Body::from(decrypted_file))
+        .header("Content-Disposition", "attachment; filename=\"files.zip\"")
+        .header("Content-Type", "application/zip")
+        .body(body)
         .map_err(|e| HttpError::server_error(e.to_string()))?;
 
     Ok(response)
-}
\ No newline at end of file
+}
+
+struct BundleStream {
+    app_state: Arc<AppState>,
+    user_id: Uuid,
+    file_ids: VecDeque<Uuid>,
+    names: HashSet<String>,
+    writer: ZipWriter,
+}
+
+impl BundleStream {
+    async fn next_entry(&mut self, file_id: Uuid) -> Result<Vec<u8>, String> {
+        let file_data = self.app_state.db_client
+            .get_file(file_id)
+            .await
+            .map_err(|e| e.to_string())?
+            .ok_or_else(|| format!("File {} no longer exists", file_id))?;
+
+        let private_key = load_private_key(&*self.app_state.key_store, file_data.key_id.unwrap_or(self.user_id))
+            .await
+            .map_err(|e| e.message)?;
+
+        let metadata = open_metadata(
+            &private_key,
+            &file_data.encrypted_aes_key,
+            file_data.encrypted_metadata.as_deref(),
+            file_data.file_name.as_deref(),
+            file_data.file_size
+        ).await.map_err(|e| e.message)?;
+
+        let (decrypted_file, _) = decrypt_file(
+            file_data.encrypted_aes_key,
+            file_data.encrypted_file,
+            file_data.iv,
+            file_data.encrypted_digest,
+            &private_key
+        ).await.map_err(|e| e.message)?;
+
+        let name = self.entry_name(&metadata.name);
+
+        self.writer.entry(&name, &decrypted_file, file_data.created_at.unwrap_or_else(Utc::now))
+    }
+
+    /// Strips directories from the stored name and numbers duplicates.
+    fn entry_name(&mut self, file_name: &str) -> String {
+        let base = file_name.rsplit(['/', '\\']).next().unwrap_or_default();
+        let base = if base.is_empty() || base == ".." { "file" } else { base };
+
+        let (stem, extension) = match base.rfind('.') {
+            Some(index) if index > 0 => base.split_at(index),
+            _ => (base, ""),
+        };
+
+        let mut name = base.to_string();
+        let mut counter = 1;
+        while !self.names.insert(name.clone()) {
+            counter += 1;
+            name = format!("{} ({}){}", stem, counter, extension);
+        }
+
+        name
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_hex_digests() {
+        let digest = "00ff".repeat(16);
+
+        assert_eq!(parse_digest(&digest), Some([0x00, 0xff].repeat(16)));
+        assert_eq!(parse_digest(&digest.to_uppercase()), Some([0x00, 0xff].repeat(16)));
+    }
+
+    #[test]
+    fn rejects_malformed_digests() {
+        // Odd length, too short, too long
+        assert_eq!(parse_digest(&"a".repeat(63)), None);
+        assert_eq!(parse_digest(&"a".repeat(65)), None);
+        assert_eq!(parse_digest("abcd"), None);
+        assert_eq!(parse_digest(""), None);
+        assert_eq!(parse_digest(&"aa".repeat(33)), None);
+
+        // Not hex, or a sign that from_str_radix would accept on its own
+        assert_eq!(parse_digest(&"zz".repeat(32)), None);
+        assert_eq!(parse_digest(&"+a".repeat(32)), None);
+
+        // Multi-byte characters counted as 64 bytes
+        assert_eq!(parse_digest(&format!("{}é", "a".repeat(62))), None);
+    }
+}
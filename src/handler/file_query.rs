@@ -3,17 +3,18 @@ use std::sync::Arc;
 use axum::{extract::Query, response::IntoResponse, routing::get, Extension, Json, Router};
 use validator::Validate;
 
-use crate::{db::UserExt, dtos::{RequestQueryDto, UserReceiveFileDto, UserReceiveFileListResponseDto, UserSendFileDto, UserSendFileListResponseDto}, error::HttpError, middleware::JWTAuthMiddeware, AppState};
+use crate::{db::{QuarantineExt, UserExt}, dtos::{FileListQueryDto, QuarantinedFileDto, QuarantinedFileListResponseDto, RequestQueryDto, UserReceiveFileListResponseDto, UserReceiveItemDto, UserSendFileListResponseDto, UserSendItemDto}, error::HttpError, middleware::JWTAuthMiddeware, utils::file_metadata::MetadataReader, AppState};
 
 pub fn get_file_list_handler() -> Router {
     Router::new()
        .route("/send", get(get_user_shared_files))
        .route("/receive", get(get_receive_shared_files))
+       .route("/quarantine", get(get_quarantined_files))
 }
 
 
 pub async fn get_user_shared_files(
-    Query(query_params): Query<RequestQueryDto>,
+    Query(query_params): Query<FileListQueryDto>,
     Extension(app_state): Extension<Arc<AppState>>,
     Extension(user): Extension<JWTAuthMiddeware>
 ) -> Result<impl IntoResponse, HttpError> {
@@ -27,12 +28,19 @@ pub async fn get_user_shared_files(
 
     let user_id = uuid::Uuid::parse_str(&user.id.to_string()).unwrap();
 
-    let (shared_files, total_count) = app_state.db_client
-        .get_sent_files(user_id.clone(), page as u32, limit)
+    let (mut shared_files, total_count) = app_state.db_client
+        .get_sent_files(user_id, page as u32, limit, query_params.organization_id)
        .await
        .map_err(|e| HttpError::server_error(e.to_string()))?;
 
-    let filter_send_files = UserSendFileDto::filter_send_user_files(&shared_files);
+    let mut reader = MetadataReader::new(&*app_state.key_store);
+    for file in &mut shared_files {
+        (file.file_name, _) = reader
+            .read(file.key_id, file.encrypted_aes_key.as_deref(), file.encrypted_metadata.as_deref(), &file.file_name, 0)
+            .await?;
+    }
+
+    let filter_send_files = UserSendItemDto::group_send_files(&shared_files);
 
     let response = UserSendFileListResponseDto {
         status: "success".to_string(),
@@ -44,7 +52,7 @@ pub async fn get_user_shared_files(
 }
 
 pub async fn get_receive_shared_files(
-    Query(query_params): Query<RequestQueryDto>,
+    Query(query_params): Query<FileListQueryDto>,
     Extension(app_state): Extension<Arc<AppState>>,
     Extension(user): Extension<JWTAuthMiddeware>
 ) -> Result<impl IntoResponse, HttpError> {
@@ -58,12 +66,19 @@ pub async fn get_receive_shared_files(
 
     let user_id = uuid::Uuid::parse_str(&user.id.to_string()).unwrap();
 
-    let (receive_files, total_count) = app_state.db_client
-        .get_receive_files(user_id.clone(), page as u32, limit)
+    let (mut receive_files, total_count) = app_state.db_client
+        .get_receive_files(user_id, page as u32, limit, query_params.organization_id)
        .await
        .map_err(|e| HttpError::server_error(e.to_string()))?;
 
-    let filter_receive_files = UserReceiveFileDto::filter_receive_user_files(&receive_files);
+    let mut reader = MetadataReader::new(&*app_state.key_store);
+    for file in &mut receive_files {
+        (file.file_name, _) = reader
+            .read(file.key_id, file.encrypted_aes_key.as_deref(), file.encrypted_metadata.as_deref(), &file.file_name, 0)
+            .await?;
+    }
+
+    let filter_receive_files = UserReceiveItemDto::group_receive_files(&receive_files);
 
     let response = UserReceiveFileListResponseDto {
         status: "success".to_string(),
@@ -72,4 +87,38 @@ pub async fn get_receive_shared_files(
     };
 
     Ok(Json(response))
-}
\ No newline at end of file
+}
+
+/// Lists the user's uploads that were rejected because malware was found
+/// in them.
+pub async fn get_quarantined_files(
+    Query(query_params): Query<RequestQueryDto>,
+    Extension(app_state): Extension<Arc<AppState>>,
+    Extension(user): Extension<JWTAuthMiddeware>
+) -> Result<impl IntoResponse, HttpError> {
+    query_params.validate()
+        .map_err(|e| HttpError::bad_request(e.to_string()))?;
+
+    let page = query_params.page.unwrap_or(1);
+    let limit = query_params.limit.unwrap_or(10);
+
+    let (mut files, total_count) = app_state.db_client
+        .get_quarantined_files(Some(user.user.id), page as u32, limit)
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+    let mut reader = MetadataReader::new(&*app_state.key_store);
+    for file in &mut files {
+        (file.file_name, file.file_size) = reader
+            .read(file.key_id, file.encrypted_aes_key.as_deref(), file.encrypted_metadata.as_deref(), &file.file_name, file.file_size)
+            .await?;
+    }
+
+    let response = QuarantinedFileListResponseDto {
+        status: "success".to_string(),
+        files: QuarantinedFileDto::filter_files(&files),
+        results: total_count,
+    };
+
+    Ok(Json(response))
+}
@@ -0,0 +1,433 @@
+use std::sync::Arc;
+
+use axum::{extract::{Path, Query}, http::StatusCode, response::IntoResponse, routing::{delete, get, put}, Extension, Json, Router};
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+use validator::Validate;
+
+use crate::{db::{FolderExt, UserExt}, dtos::{CreateFolderDto, FolderContentsResponseDto, FolderDto, FolderFileDto, FolderResponseDto, FolderShareDto, FolderShareListResponseDto, MoveFileDto, RequestQueryDto, Response, ShareFolderDto, UpdateFolderDto}, error::{ErrorMessage, HttpError}, handler::file::{recipient_key, recipient_public_key, share_status}, middleware::JWTAuthMiddeware, models::{FileContent, Folder, FolderShare, NewFile, ShareableFile}, utils::{encrypt::rewrap_aes_key, file_metadata::MetadataReader, keys::load_private_key, password, quota::check_quota}, AppState};
+
+pub fn folders_handler() -> Router {
+    Router::new()
+        .route("/", get(get_root_contents).post(create_folder))
+        .route("/:folder_id", get(get_folder_contents).put(update_folder).delete(delete_folder))
+        .route("/:folder_id/shares", get(get_folder_shares).post(share_folder))
+        .route("/shares/:folder_share_id", delete(delete_folder_share))
+        .route("/files/:file_id", put(move_file))
+}
+
+pub async fn create_folder(
+    Extension(app_state): Extension<Arc<AppState>>,
+    Extension(user): Extension<JWTAuthMiddeware>,
+    Json(body): Json<CreateFolderDto>,
+) -> Result<impl IntoResponse, HttpError> {
+    body.validate()
+        .map_err(|e| HttpError::bad_request(e.to_string()))?;
+
+    if let Some(parent_id) = body.parent_id {
+        owned_folder(&app_state, user.user.id, parent_id).await?;
+    }
+
+    let folder = app_state.db_client
+        .create_folder(user.user.id, body.parent_id, body.name)
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+    Ok((StatusCode::CREATED, Json(FolderResponseDto {
+        status: "success".to_string(),
+        folder: FolderDto::filter_folder(&folder),
+    })))
+}
+
+pub async fn get_root_contents(
+    Query(query_params): Query<RequestQueryDto>,
+    Extension(app_state): Extension<Arc<AppState>>,
+    Extension(user): Extension<JWTAuthMiddeware>,
+) -> Result<impl IntoResponse, HttpError> {
+    let response = folder_contents(&app_state, user.user.id, None, query_params).await?;
+
+    Ok(Json(response))
+}
+
+pub async fn get_folder_contents(
+    Path(folder_id): Path<Uuid>,
+    Query(query_params): Query<RequestQueryDto>,
+    Extension(app_state): Extension<Arc<AppState>>,
+    Extension(user): Extension<JWTAuthMiddeware>,
+) -> Result<impl IntoResponse, HttpError> {
+    let folder = owned_folder(&app_state, user.user.id, folder_id).await?;
+
+    let response = folder_contents(&app_state, user.user.id, Some(folder), query_params).await?;
+
+    Ok(Json(response))
+}
+
+pub async fn update_folder(
+    Path(folder_id): Path<Uuid>,
+    Extension(app_state): Extension<Arc<AppState>>,
+    Extension(user): Extension<JWTAuthMiddeware>,
+    Json(body): Json<UpdateFolderDto>,
+) -> Result<impl IntoResponse, HttpError> {
+    body.validate()
+        .map_err(|e| HttpError::bad_request(e.to_string()))?;
+
+    owned_folder(&app_state, user.user.id, folder_id).await?;
+
+    if let Some(parent_id) = body.parent_id {
+        owned_folder(&app_state, user.user.id, parent_id).await?;
+
+        let is_descendant = app_state.db_client
+            .is_folder_descendant(folder_id, parent_id)
+            .await
+            .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+        if is_descendant {
+            return Err(HttpError::bad_request("A folder cannot be moved into itself"));
+        }
+    }
+
+    let folder = app_state.db_client
+        .update_folder(user.user.id, folder_id, body.name, body.parent_id)
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?
+        .ok_or_else(|| HttpError::new("Folder not found", StatusCode::NOT_FOUND))?;
+
+    Ok(Json(FolderResponseDto {
+        status: "success".to_string(),
+        folder: FolderDto::filter_folder(&folder),
+    }))
+}
+
+pub async fn delete_folder(
+    Path(folder_id): Path<Uuid>,
+    Extension(app_state): Extension<Arc<AppState>>,
+    Extension(user): Extension<JWTAuthMiddeware>,
+) -> Result<impl IntoResponse, HttpError> {
+    let deleted = app_state.db_client
+        .delete_folder(user.user.id, folder_id)
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+    if !deleted {
+        return Err(HttpError::new("Folder not found", StatusCode::NOT_FOUND));
+    }
+
+    let response = Response {
+        message: "Folder deleted, its files were moved to the root".to_string(),
+        status: "success",
+    };
+
+    Ok(Json(response))
+}
+
+pub async fn move_file(
+    Path(file_id): Path<Uuid>,
+    Extension(app_state): Extension<Arc<AppState>>,
+    Extension(user): Extension<JWTAuthMiddeware>,
+    Json(body): Json<MoveFileDto>,
+) -> Result<impl IntoResponse, HttpError> {
+    if let Some(folder_id) = body.folder_id {
+        owned_folder(&app_state, user.user.id, folder_id).await?;
+    }
+
+    let moved = app_state.db_client
+        .move_file(user.user.id, file_id, body.folder_id)
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+    if !moved {
+        return Err(HttpError::new("File not found", StatusCode::NOT_FOUND));
+    }
+
+    // Moving a file into a shared folder counts as adding it
+    if let Some(folder_id) = body.folder_id {
+        share_added_file(&app_state, user.user.id, file_id, folder_id).await?;
+    }
+
+    let response = Response {
+        message: "File moved successfully".to_string(),
+        status: "success",
+    };
+
+    Ok(Json(response))
+}
+
+pub async fn share_folder(
+    Path(folder_id): Path<Uuid>,
+    Extension(app_state): Extension<Arc<AppState>>,
+    Extension(user): Extension<JWTAuthMiddeware>,
+    Json(body): Json<ShareFolderDto>,
+) -> Result<impl IntoResponse, HttpError> {
+    body.validate()
+        .map_err(|e| HttpError::bad_request(e.to_string()))?;
+
+    let user_id = user.user.id;
+
+    owned_folder(&app_state, user_id, folder_id).await?;
+
+    let recipient = app_state.db_client
+        .get_user(None, None, Some(&body.recipient_email))
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?
+        .ok_or(HttpError::bad_request("Recipient user not found"))?;
+
+    if recipient.id == user_id {
+        return Err(HttpError::bad_request("You cannot share a folder with yourself"));
+    }
+
+    recipient_public_key(&recipient)?;
+
+    if share_status(&app_state, &recipient, user_id).await?.is_none() {
+        return Err(HttpError::forbidden("The recipient is not accepting files from you"));
+    }
+
+    let expiration_date = DateTime::parse_from_rfc3339(&body.expiration_date)
+        .map_err(|e| HttpError::bad_request(e.to_string()))?
+        .with_timezone(&Utc);
+
+    let files = app_state.db_client
+        .get_folder_tree_files(folder_id)
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+    // The recipient gets a copy of every file they don't hold yet
+    let copies_size: i64 = files.iter()
+        .filter(|file| file.recipient_user_id != recipient.id)
+        .map(|file| file.file_size)
+        .sum();
+
+    check_quota(&app_state, user_id, None, copies_size).await?;
+
+    let hash_password = password::hash(&body.password)
+        .await
+        .map_err(HttpError::from)?;
+
+    let folder_share = app_state.db_client
+        .save_folder_share(folder_id, recipient.id, hash_password, expiration_date)
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+    let mut shared_count = 0;
+    for file in &files {
+        if share_file_copy(&app_state, user_id, file, &folder_share).await? {
+            shared_count += 1;
+        }
+    }
+
+    let response = Response {
+        message: format!("Folder shared with {} files, new files will be shared as they are added", shared_count),
+        status: "success",
+    };
+
+    Ok((StatusCode::CREATED, Json(response)))
+}
+
+pub async fn get_folder_shares(
+    Path(folder_id): Path<Uuid>,
+    Extension(app_state): Extension<Arc<AppState>>,
+    Extension(user): Extension<JWTAuthMiddeware>,
+) -> Result<impl IntoResponse, HttpError> {
+    owned_folder(&app_state, user.user.id, folder_id).await?;
+
+    let shares = app_state.db_client
+        .get_folder_shares(folder_id)
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+    let response = FolderShareListResponseDto {
+        status: "success".to_string(),
+        shares: FolderShareDto::filter_shares(&shares),
+    };
+
+    Ok(Json(response))
+}
+
+pub async fn delete_folder_share(
+    Path(folder_share_id): Path<Uuid>,
+    Extension(app_state): Extension<Arc<AppState>>,
+    Extension(user): Extension<JWTAuthMiddeware>,
+) -> Result<impl IntoResponse, HttpError> {
+    let deleted = app_state.db_client
+        .delete_folder_share(user.user.id, folder_share_id)
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+    if !deleted {
+        return Err(HttpError::new("Folder share not found", StatusCode::NOT_FOUND));
+    }
+
+    let response = Response {
+        message: "Folder share revoked successfully".to_string(),
+        status: "success",
+    };
+
+    Ok(Json(response))
+}
+
+/// Gives every active share of `folder_id` (or one of its ancestors) a copy of
+/// a file that was just added to it.
+pub async fn share_added_file(
+    app_state: &AppState,
+    sender_id: Uuid,
+    file_id: Uuid,
+    folder_id: Uuid,
+) -> Result<(), HttpError> {
+    let folder_shares = app_state.db_client
+        .get_active_folder_shares(folder_id)
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+    if folder_shares.is_empty() {
+        return Ok(());
+    }
+
+    let file = match app_state.db_client
+        .get_shareable_file(file_id)
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))? {
+        Some(file) => file,
+        None => return Ok(()),
+    };
+
+    for folder_share in &folder_shares {
+        share_file_copy(app_state, sender_id, &file, folder_share).await?;
+    }
+
+    Ok(())
+}
+
+/// Stores a copy of `file` for the folder share recipient, with the AES key
+/// rewrapped for them. Returns false when the recipient was skipped.
+async fn share_file_copy(
+    app_state: &AppState,
+    sender_id: Uuid,
+    file: &ShareableFile,
+    folder_share: &FolderShare,
+) -> Result<bool, HttpError> {
+    if file.recipient_user_id == folder_share.recipient_user_id {
+        return Ok(false);
+    }
+
+    // Files moved around inside a shared tree, or copies moved back into it,
+    // are already there
+    let source_file_id = file.source_file_id.unwrap_or(file.id);
+
+    let has_copy = app_state.db_client
+        .has_folder_share_copy(folder_share.id, source_file_id)
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+    if has_copy {
+        return Ok(false);
+    }
+
+    let recipient = match app_state.db_client
+        .get_user(Some(folder_share.recipient_user_id), None, None)
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))? {
+        Some(recipient) if !recipient.disabled && recipient.public_key.is_some() => recipient,
+        _ => return Ok(false),
+    };
+
+    let status = match share_status(app_state, &recipient, sender_id).await? {
+        Some(status) => status,
+        None => return Ok(false),
+    };
+
+    // Copies are stored like any other file of the sender
+    let limits = check_quota(app_state, sender_id, None, file.file_size).await?;
+
+    let holder_private_key = load_private_key(&*app_state.key_store, file.key_id.unwrap_or(file.recipient_user_id)).await?;
+    let (key_id, public_key) = recipient_key(app_state, &recipient).await?;
+    let encrypted_aes_key = rewrap_aes_key(
+        &file.encrypted_aes_key,
+        &holder_private_key,
+        &public_key
+    ).await?;
+
+    app_state.db_client
+        .save_encrypted_file(NewFile {
+            user_id: sender_id,
+            file_name: file.file_name.clone(),
+            file_size: file.file_size,
+            recipient_user_id: recipient.id,
+            password: folder_share.password.clone(),
+            expiration_date: folder_share.expiration_date,
+            encrypted_aes_key,
+            // The copy has the same file key, so it can point at the same blob
+            content: match file.blob_id {
+                Some(blob_id) => FileContent::Blob(blob_id),
+                None => FileContent::Inline(file.encrypted_file.clone().unwrap_or_default()),
+            },
+            iv: file.iv.clone(),
+            encrypted_digest: file.encrypted_digest.clone(),
+            encrypted_metadata: file.encrypted_metadata.clone(),
+            signature: file.signature.clone(),
+            signer_public_key: file.signer_public_key.clone(),
+            key_id: Some(key_id),
+            sender_encrypted_aes_key: file.sender_encrypted_aes_key.clone(),
+            sender_key_id: file.sender_key_id,
+            status,
+            folder_share_id: Some(folder_share.id),
+            source_file_id: Some(source_file_id),
+            scan_status: file.scan_status,
+            limits,
+            ..Default::default()
+        })
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?
+        .ok_or(ErrorMessage::QuotaExceeded)?;
+
+    Ok(true)
+}
+
+async fn owned_folder(
+    app_state: &AppState,
+    user_id: Uuid,
+    folder_id: Uuid,
+) -> Result<Folder, HttpError> {
+    app_state.db_client
+        .get_folder(user_id, folder_id)
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?
+        .ok_or_else(|| HttpError::new("Folder not found", StatusCode::NOT_FOUND))
+}
+
+async fn folder_contents(
+    app_state: &AppState,
+    user_id: Uuid,
+    folder: Option<Folder>,
+    query_params: RequestQueryDto,
+) -> Result<FolderContentsResponseDto, HttpError> {
+    query_params.validate()
+        .map_err(|e| HttpError::bad_request(e.to_string()))?;
+
+    let page = query_params.page.unwrap_or(1);
+    let limit = query_params.limit.unwrap_or(10);
+    let folder_id = folder.as_ref().map(|folder| folder.id);
+
+    let folders = app_state.db_client
+        .get_subfolders(user_id, folder_id)
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+    let (mut files, total_count) = app_state.db_client
+        .get_folder_files(user_id, folder_id, page as u32, limit)
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+    let mut reader = MetadataReader::new(&*app_state.key_store);
+    for file in &mut files {
+        (file.file_name, file.file_size) = reader
+            .read(file.key_id, file.encrypted_aes_key.as_deref(), file.encrypted_metadata.as_deref(), &file.file_name, file.file_size)
+            .await?;
+    }
+
+    Ok(FolderContentsResponseDto {
+        status: "success".to_string(),
+        folder: folder.as_ref().map(FolderDto::filter_folder),
+        folders: FolderDto::filter_folders(&folders),
+        files: FolderFileDto::filter_files(&files),
+        results: total_count,
+    })
+}
@@ -1,4 +1,9 @@
 pub mod auth;
 pub mod user;
 pub mod file_query;
-pub mod file;
\ No newline at end of file
+pub mod file;
+pub mod admin;
+pub mod contact;
+pub mod share;
+pub mod organization;pub mod folder;
+pub mod upload;
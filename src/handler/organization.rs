@@ -0,0 +1,343 @@
+use std::sync::Arc;
+
+use axum::{extract::Path, http::StatusCode, response::IntoResponse, routing::{get, post, put}, Extension, Json, Router};
+use uuid::Uuid;
+use validator::Validate;
+
+use crate::{db::{OrganizationExt, UserExt}, dtos::{AddOrganizationMemberDto, CreateOrganizationDto, OrganizationDto, OrganizationListResponseDto, OrganizationMemberDto, OrganizationResponseDto, OrganizationSettingsDto, Response, UpdateOrganizationMemberDto}, error::{ErrorMessage, HttpError}, middleware::JWTAuthMiddeware, models::OrganizationRole, AppState};
+
+pub fn organizations_handler() -> Router {
+    Router::new()
+        .route("/", get(get_organizations).post(create_organization))
+        .route("/invitations", get(get_invitations))
+        .route("/:organization_id", get(get_organization).delete(delete_organization))
+        .route("/:organization_id/settings", put(update_organization_settings))
+        .route("/:organization_id/invitation", put(accept_invitation).delete(decline_invitation))
+        .route("/:organization_id/members", post(add_member))
+        .route("/:organization_id/members/:user_id", put(update_member_role).delete(remove_member))
+}
+
+pub async fn create_organization(
+    Extension(app_state): Extension<Arc<AppState>>,
+    Extension(user): Extension<JWTAuthMiddeware>,
+    Json(body): Json<CreateOrganizationDto>,
+) -> Result<impl IntoResponse, HttpError> {
+    body.validate()
+        .map_err(|e| HttpError::bad_request(e.to_string()))?;
+
+    let organization = app_state.db_client
+        .create_organization(user.user.id, body.name)
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+    let response = organization_response(&app_state, organization.id, OrganizationRole::Owner).await?;
+
+    Ok((StatusCode::CREATED, Json(response)))
+}
+
+pub async fn get_organizations(
+    Extension(app_state): Extension<Arc<AppState>>,
+    Extension(user): Extension<JWTAuthMiddeware>,
+) -> Result<impl IntoResponse, HttpError> {
+    let organizations = app_state.db_client
+        .get_user_organizations(user.user.id)
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+    let response = OrganizationListResponseDto {
+        status: "success".to_string(),
+        organizations: OrganizationDto::filter_organizations(&organizations),
+    };
+
+    Ok(Json(response))
+}
+
+/// Lists the organizations the user was invited to and has not joined yet.
+pub async fn get_invitations(
+    Extension(app_state): Extension<Arc<AppState>>,
+    Extension(user): Extension<JWTAuthMiddeware>,
+) -> Result<impl IntoResponse, HttpError> {
+    let organizations = app_state.db_client
+        .get_organization_invitations(user.user.id)
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+    let response = OrganizationListResponseDto {
+        status: "success".to_string(),
+        organizations: OrganizationDto::filter_organizations(&organizations),
+    };
+
+    Ok(Json(response))
+}
+
+pub async fn accept_invitation(
+    Path(organization_id): Path<Uuid>,
+    Extension(app_state): Extension<Arc<AppState>>,
+    Extension(user): Extension<JWTAuthMiddeware>,
+) -> Result<impl IntoResponse, HttpError> {
+    let accepted = app_state.db_client
+        .accept_organization_invitation(organization_id, user.user.id)
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+    if !accepted {
+        return Err(HttpError::new("Invitation not found", StatusCode::NOT_FOUND));
+    }
+
+    let role = member_role(&app_state, organization_id, user.user.id).await?;
+
+    let response = organization_response(&app_state, organization_id, role).await?;
+
+    Ok(Json(response))
+}
+
+pub async fn decline_invitation(
+    Path(organization_id): Path<Uuid>,
+    Extension(app_state): Extension<Arc<AppState>>,
+    Extension(user): Extension<JWTAuthMiddeware>,
+) -> Result<impl IntoResponse, HttpError> {
+    let membership = app_state.db_client
+        .get_organization_membership(organization_id, user.user.id)
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+    if membership.is_none_or(|membership| membership.accepted) {
+        return Err(HttpError::new("Invitation not found", StatusCode::NOT_FOUND));
+    }
+
+    app_state.db_client
+        .remove_organization_member(organization_id, user.user.id)
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+    let response = Response {
+        message: "Invitation declined".to_string(),
+        status: "success",
+    };
+
+    Ok(Json(response))
+}
+
+pub async fn get_organization(
+    Path(organization_id): Path<Uuid>,
+    Extension(app_state): Extension<Arc<AppState>>,
+    Extension(user): Extension<JWTAuthMiddeware>,
+) -> Result<impl IntoResponse, HttpError> {
+    let role = member_role(&app_state, organization_id, user.user.id).await?;
+
+    let response = organization_response(&app_state, organization_id, role).await?;
+
+    Ok(Json(response))
+}
+
+pub async fn update_organization_settings(
+    Path(organization_id): Path<Uuid>,
+    Extension(app_state): Extension<Arc<AppState>>,
+    Extension(user): Extension<JWTAuthMiddeware>,
+    Json(body): Json<OrganizationSettingsDto>,
+) -> Result<impl IntoResponse, HttpError> {
+    body.validate()
+        .map_err(|e| HttpError::bad_request(e.to_string()))?;
+
+    let role = member_role(&app_state, organization_id, user.user.id).await?;
+    if !role.can_manage() {
+        return Err(HttpError::forbidden(ErrorMessage::PermissionDenied.to_string()));
+    }
+
+    let allowed_recipient_domains = body.allowed_recipient_domains
+        .iter()
+        .map(|domain| domain.trim().trim_start_matches('@').to_lowercase())
+        .filter(|domain| !domain.is_empty())
+        .collect();
+
+    app_state.db_client
+        .update_organization_settings(
+            organization_id,
+            body.name,
+            body.max_expiry_days,
+            allowed_recipient_domains
+        )
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+    let response = organization_response(&app_state, organization_id, role).await?;
+
+    Ok(Json(response))
+}
+
+pub async fn delete_organization(
+    Path(organization_id): Path<Uuid>,
+    Extension(app_state): Extension<Arc<AppState>>,
+    Extension(user): Extension<JWTAuthMiddeware>,
+) -> Result<impl IntoResponse, HttpError> {
+    let role = member_role(&app_state, organization_id, user.user.id).await?;
+    if role != OrganizationRole::Owner {
+        return Err(HttpError::forbidden(ErrorMessage::PermissionDenied.to_string()));
+    }
+
+    app_state.db_client
+        .delete_organization(organization_id)
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+    let response = Response {
+        message: "Organization deleted successfully".to_string(),
+        status: "success",
+    };
+
+    Ok(Json(response))
+}
+
+pub async fn add_member(
+    Path(organization_id): Path<Uuid>,
+    Extension(app_state): Extension<Arc<AppState>>,
+    Extension(user): Extension<JWTAuthMiddeware>,
+    Json(body): Json<AddOrganizationMemberDto>,
+) -> Result<impl IntoResponse, HttpError> {
+    body.validate()
+        .map_err(|e| HttpError::bad_request(e.to_string()))?;
+
+    let role = member_role(&app_state, organization_id, user.user.id).await?;
+    let new_role = body.role.unwrap_or(OrganizationRole::Member);
+
+    // Only owners can hand out ownership
+    if !role.can_manage() || (new_role == OrganizationRole::Owner && role != OrganizationRole::Owner) {
+        return Err(HttpError::forbidden(ErrorMessage::PermissionDenied.to_string()));
+    }
+
+    let member = app_state.db_client
+        .get_user(None, None, Some(&body.email))
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?
+        .ok_or(HttpError::bad_request("User not found"))?;
+
+    // The user is only invited, they join once they accept. Roles of
+    // existing members only change through update_member_role.
+    let added = app_state.db_client
+        .add_organization_member(organization_id, member.id, new_role)
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+    if !added {
+        return Err(HttpError::unique_constraint_violation("User is already a member of or invited to this organization"));
+    }
+
+    let response = organization_response(&app_state, organization_id, role).await?;
+
+    Ok(Json(response))
+}
+
+pub async fn update_member_role(
+    Path((organization_id, member_id)): Path<(Uuid, Uuid)>,
+    Extension(app_state): Extension<Arc<AppState>>,
+    Extension(user): Extension<JWTAuthMiddeware>,
+    Json(body): Json<UpdateOrganizationMemberDto>,
+) -> Result<impl IntoResponse, HttpError> {
+    let role = member_role(&app_state, organization_id, user.user.id).await?;
+
+    // Owners would otherwise be able to leave the organization without one
+    if member_id == user.user.id {
+        return Err(HttpError::bad_request("You cannot change your own role"));
+    }
+
+    let current_role = app_state.db_client
+        .get_organization_membership(organization_id, member_id)
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?
+        .ok_or_else(|| HttpError::new("Member not found", StatusCode::NOT_FOUND))?
+        .role;
+
+    // Only owners can hand out ownership or take it away
+    let touches_owner = current_role == OrganizationRole::Owner || body.role == OrganizationRole::Owner;
+    if !role.can_manage() || (touches_owner && role != OrganizationRole::Owner) {
+        return Err(HttpError::forbidden(ErrorMessage::PermissionDenied.to_string()));
+    }
+
+    app_state.db_client
+        .update_organization_member_role(organization_id, member_id, body.role)
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+    let response = organization_response(&app_state, organization_id, role).await?;
+
+    Ok(Json(response))
+}
+
+pub async fn remove_member(
+    Path((organization_id, member_id)): Path<(Uuid, Uuid)>,
+    Extension(app_state): Extension<Arc<AppState>>,
+    Extension(user): Extension<JWTAuthMiddeware>,
+) -> Result<impl IntoResponse, HttpError> {
+    let role = member_role(&app_state, organization_id, user.user.id).await?;
+    let leaving = member_id == user.user.id;
+
+    if leaving && role == OrganizationRole::Owner {
+        return Err(HttpError::bad_request("Owners cannot leave, delete the organization instead"));
+    }
+
+    if !leaving {
+        // Pending invitations are withdrawn the same way
+        let member_role = app_state.db_client
+            .get_organization_membership(organization_id, member_id)
+            .await
+            .map_err(|e| HttpError::server_error(e.to_string()))?
+            .map(|membership| membership.role);
+
+        let allowed = match member_role {
+            Some(OrganizationRole::Owner) => role == OrganizationRole::Owner,
+            Some(_) => role.can_manage(),
+            None => return Err(HttpError::new("Member not found", StatusCode::NOT_FOUND)),
+        };
+
+        if !allowed {
+            return Err(HttpError::forbidden(ErrorMessage::PermissionDenied.to_string()));
+        }
+    }
+
+    app_state.db_client
+        .remove_organization_member(organization_id, member_id)
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+    let response = Response {
+        message: "Member removed successfully".to_string(),
+        status: "success",
+    };
+
+    Ok(Json(response))
+}
+
+async fn member_role(
+    app_state: &AppState,
+    organization_id: Uuid,
+    user_id: Uuid,
+) -> Result<OrganizationRole, HttpError> {
+    app_state.db_client
+        .get_organization_role(organization_id, user_id)
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?
+        .ok_or_else(|| HttpError::new("Organization not found", StatusCode::NOT_FOUND))
+}
+
+async fn organization_response(
+    app_state: &AppState,
+    organization_id: Uuid,
+    role: OrganizationRole,
+) -> Result<OrganizationResponseDto, HttpError> {
+    let organization = app_state.db_client
+        .get_organization(organization_id)
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?
+        .ok_or_else(|| HttpError::new("Organization not found", StatusCode::NOT_FOUND))?;
+
+    let members = app_state.db_client
+        .get_organization_members(organization_id)
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+    Ok(OrganizationResponseDto {
+        status: "success".to_string(),
+        organization: OrganizationDto::filter_organization(&organization, Some(role)),
+        members: OrganizationMemberDto::filter_members(&members),
+    })
+}
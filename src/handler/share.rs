@@ -0,0 +1,90 @@
+use std::sync::Arc;
+
+use axum::{extract::{Path, Query}, http::StatusCode, response::IntoResponse, routing::{get, put}, Extension, Json, Router};
+use uuid::Uuid;
+use validator::Validate;
+
+use crate::{db::ShareExt, dtos::{PendingShareListResponseDto, RequestQueryDto, Response, UserReceiveFileDto}, error::HttpError, middleware::JWTAuthMiddeware, utils::file_metadata::MetadataReader, AppState};
+
+pub fn shares_handler() -> Router {
+    Router::new()
+        .route("/pending", get(get_pending_shares))
+        .route("/:shared_id/accept", put(accept_share))
+        .route("/:shared_id/decline", put(decline_share))
+}
+
+pub async fn get_pending_shares(
+    Query(query_params): Query<RequestQueryDto>,
+    Extension(app_state): Extension<Arc<AppState>>,
+    Extension(user): Extension<JWTAuthMiddeware>,
+) -> Result<impl IntoResponse, HttpError> {
+    query_params.validate()
+        .map_err(|e| HttpError::bad_request(e.to_string()))?;
+
+    let page = query_params.page.unwrap_or(1);
+    let limit = query_params.limit.unwrap_or(10);
+
+    let (mut pending_shares, total_count) = app_state.db_client
+        .get_pending_shares(user.user.id, page as u32, limit)
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+    let mut reader = MetadataReader::new(&*app_state.key_store);
+    for share in &mut pending_shares {
+        (share.file_name, _) = reader
+            .read(share.key_id, share.encrypted_aes_key.as_deref(), share.encrypted_metadata.as_deref(), &share.file_name, 0)
+            .await?;
+    }
+
+    let response = PendingShareListResponseDto {
+        status: "success".to_string(),
+        files: UserReceiveFileDto::filter_receive_user_files(&pending_shares),
+        results: total_count,
+    };
+
+    Ok(Json(response))
+}
+
+pub async fn accept_share(
+    Path(shared_id): Path<Uuid>,
+    Extension(app_state): Extension<Arc<AppState>>,
+    Extension(user): Extension<JWTAuthMiddeware>,
+) -> Result<impl IntoResponse, HttpError> {
+    let accepted = app_state.db_client
+        .accept_share(user.user.id, shared_id)
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+    if !accepted {
+        return Err(HttpError::new("Pending share not found", StatusCode::NOT_FOUND));
+    }
+
+    let response = Response {
+        message: "Share accepted".to_string(),
+        status: "success",
+    };
+
+    Ok(Json(response))
+}
+
+pub async fn decline_share(
+    Path(shared_id): Path<Uuid>,
+    Extension(app_state): Extension<Arc<AppState>>,
+    Extension(user): Extension<JWTAuthMiddeware>,
+) -> Result<impl IntoResponse, HttpError> {
+    let declined = app_state.db_client
+        .decline_share(user.user.id, shared_id)
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+    if !declined {
+        return Err(HttpError::new("Pending share not found", StatusCode::NOT_FOUND));
+    }
+
+    let response = Response {
+        message: "Share declined and removed".to_string(),
+        status: "success",
+    };
+
+    Ok(Json(response))
+}
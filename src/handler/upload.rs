@@ -0,0 +1,312 @@
+use std::{io::SeekFrom, sync::Arc};
+
+use axum::{body::Body, extract::Path, http::{header, HeaderMap, Response, StatusCode}, response::IntoResponse, routing::{head, post}, Extension, Router};
+use chrono::{DateTime, Duration, Utc};
+use futures_util::StreamExt;
+use tokio::{fs, io::{AsyncSeekExt, AsyncWriteExt}};
+use uuid::Uuid;
+use validator::Validate;
+
+use crate::{db::UploadExt, dtos::FileUploadDtos, error::HttpError, handler::file::share_files, middleware::JWTAuthMiddeware, models::Upload, utils::{password, quota::{check_file_size, check_quota}, upload::{http_date, parse_metadata, upload_path, UPLOADS_DIR}}, AppState};
+
+const OFFSET_CONTENT_TYPE: &str = "application/offset+octet-stream";
+
+pub fn uploads_handler() -> Router {
+    Router::new()
+        .route("/", post(create_upload))
+        .route("/:upload_id", head(get_upload_offset).patch(append_upload).delete(terminate_upload))
+}
+
+/// Creates an upload from `Upload-Length` and the share settings in
+/// `Upload-Metadata` (`filename`, `recipient_email` or `organization_id`,
+/// `folder_id`, `password`, `expiration_date`).
+pub async fn create_upload(
+    Extension(app_state): Extension<Arc<AppState>>,
+    Extension(user): Extension<JWTAuthMiddeware>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, HttpError> {
+    if headers.contains_key("Upload-Defer-Length") {
+        return Err(HttpError::bad_request("Deferred upload length is not supported"));
+    }
+
+    let upload_length = header_i64(&headers, "Upload-Length")?;
+
+    if upload_length == 0 {
+        return Err(HttpError::bad_request("Empty uploads are not supported"));
+    }
+
+    if upload_length > app_state.env.tus_max_size {
+        return Err(HttpError::payload_too_large(
+            format!("Uploads are limited to {} bytes", app_state.env.tus_max_size)
+        ));
+    }
+
+    check_file_size(&app_state, upload_length)?;
+
+    let metadata = match headers.get("Upload-Metadata") {
+        Some(value) => {
+            let value = value.to_str()
+                .map_err(|_| HttpError::bad_request("Invalid Upload-Metadata header"))?;
+
+            parse_metadata(value).map_err(HttpError::bad_request)?
+        }
+        None => Default::default(),
+    };
+
+    let form_data = FileUploadDtos {
+        recipient_email: metadata.get("recipient_email").cloned(),
+        organization_id: metadata_uuid(metadata.get("organization_id"), "Invalid organization id")?,
+        folder_id: metadata_uuid(metadata.get("folder_id"), "Invalid folder id")?,
+        password: metadata.get("password").cloned().unwrap_or_default(),
+        expiration_date: metadata.get("expiration_date").cloned().unwrap_or_default(),
+    };
+
+    form_data.validate()
+        .map_err(|e| HttpError::bad_request(e.to_string()))?;
+
+    if form_data.recipient_email.is_some() == form_data.organization_id.is_some() {
+        return Err(HttpError::bad_request("Provide either recipient_email or organization_id"));
+    }
+
+    // Checked again when the upload completes, by then other files may have used up the quota
+    check_quota(&app_state, user.user.id, form_data.organization_id, upload_length).await?;
+
+    let file_name = metadata.get("filename")
+        .filter(|name| !name.is_empty())
+        .cloned()
+        .unwrap_or_else(|| "unknow_file".to_string());
+
+    let expiration_date = DateTime::parse_from_rfc3339(&form_data.expiration_date)
+        .map_err(|e| HttpError::bad_request(e.to_string()))?
+        .with_timezone(&Utc);
+
+    // Only the hash is kept while the upload is in progress
+    let hash_password = password::hash(&form_data.password)
+        .await
+        .map_err(HttpError::from)?;
+
+    let upload = app_state.db_client
+        .create_upload(
+            user.user.id,
+            upload_length,
+            file_name,
+            form_data.recipient_email,
+            form_data.organization_id,
+            form_data.folder_id,
+            hash_password,
+            expiration_date,
+            Utc::now() + Duration::hours(app_state.env.upload_expiry_hours)
+        )
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+    fs::create_dir_all(UPLOADS_DIR)
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+    fs::File::create(upload_path(upload.id))
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+    Response::builder()
+        .status(StatusCode::CREATED)
+        .header(header::LOCATION, format!("/api/uploads/{}", upload.id))
+        .header("Upload-Expires", http_date(upload.expires_at))
+        .body(Body::empty())
+        .map_err(|e| HttpError::server_error(e.to_string()))
+}
+
+pub async fn get_upload_offset(
+    Path(upload_id): Path<Uuid>,
+    Extension(app_state): Extension<Arc<AppState>>,
+    Extension(user): Extension<JWTAuthMiddeware>,
+) -> Result<impl IntoResponse, HttpError> {
+    let upload = find_upload(&app_state, user.user.id, upload_id).await?;
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .header("Upload-Offset", upload.upload_offset)
+        .header("Upload-Length", upload.upload_length)
+        .header("Upload-Expires", http_date(upload.expires_at))
+        .header(header::CACHE_CONTROL, "no-store")
+        .body(Body::empty())
+        .map_err(|e| HttpError::server_error(e.to_string()))
+}
+
+/// Appends the request body at `Upload-Offset`. The last chunk hands the file
+/// to the regular upload pipeline and removes the partial upload.
+pub async fn append_upload(
+    Path(upload_id): Path<Uuid>,
+    Extension(app_state): Extension<Arc<AppState>>,
+    Extension(user): Extension<JWTAuthMiddeware>,
+    headers: HeaderMap,
+    body: Body,
+) -> Result<impl IntoResponse, HttpError> {
+    let content_type = headers.get(header::CONTENT_TYPE).and_then(|value| value.to_str().ok());
+    if content_type != Some(OFFSET_CONTENT_TYPE) {
+        return Err(HttpError::new(
+            format!("Content-Type must be {}", OFFSET_CONTENT_TYPE),
+            StatusCode::UNSUPPORTED_MEDIA_TYPE
+        ));
+    }
+
+    let offset = header_i64(&headers, "Upload-Offset")?;
+
+    let _guard = app_state.upload_locks
+        .try_lock(upload_id)
+        .ok_or_else(|| HttpError::new("The upload is already being written", StatusCode::CONFLICT))?;
+
+    let upload = find_upload(&app_state, user.user.id, upload_id).await?;
+
+    if offset != upload.upload_offset {
+        return Err(HttpError::new("Upload-Offset does not match the current offset", StatusCode::CONFLICT));
+    }
+
+    let mut file = fs::OpenOptions::new()
+        .write(true)
+        .open(upload_path(upload_id))
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+    // Drop anything an earlier, interrupted request wrote past the recorded offset
+    file.set_len(offset as u64)
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+    file.seek(SeekFrom::End(0))
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+    let mut new_offset = offset;
+    let mut interrupted = false;
+    let mut stream = body.into_data_stream();
+
+    while let Some(chunk) = stream.next().await {
+        let chunk = match chunk {
+            Ok(chunk) => chunk,
+            Err(_) => {
+                interrupted = true;
+                break;
+            }
+        };
+
+        if new_offset + chunk.len() as i64 > upload.upload_length {
+            return Err(HttpError::bad_request("The request body goes past Upload-Length"));
+        }
+
+        file.write_all(&chunk)
+            .await
+            .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+        new_offset += chunk.len() as i64;
+    }
+
+    file.sync_data()
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+    // Whatever arrived is kept, so a dropped connection resumes from here
+    app_state.db_client
+        .set_upload_offset(upload_id, new_offset)
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+    if interrupted {
+        return Err(HttpError::bad_request("The upload was interrupted"));
+    }
+
+    if new_offset == upload.upload_length {
+        complete_upload(&app_state, upload).await?;
+    }
+
+    Response::builder()
+        .status(StatusCode::NO_CONTENT)
+        .header("Upload-Offset", new_offset)
+        .body(Body::empty())
+        .map_err(|e| HttpError::server_error(e.to_string()))
+}
+
+pub async fn terminate_upload(
+    Path(upload_id): Path<Uuid>,
+    Extension(app_state): Extension<Arc<AppState>>,
+    Extension(user): Extension<JWTAuthMiddeware>,
+) -> Result<impl IntoResponse, HttpError> {
+    let _guard = app_state.upload_locks
+        .try_lock(upload_id)
+        .ok_or_else(|| HttpError::new("The upload is already being written", StatusCode::CONFLICT))?;
+
+    let deleted = app_state.db_client
+        .delete_upload(user.user.id, upload_id)
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+    if !deleted {
+        return Err(HttpError::new("Upload not found", StatusCode::NOT_FOUND));
+    }
+
+    remove_upload_file(upload_id).await;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn complete_upload(
+    app_state: &AppState,
+    upload: Upload,
+) -> Result<(), HttpError> {
+    let file_data = fs::read(upload_path(upload.id))
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+    share_files(
+        app_state,
+        upload.user_id,
+        upload.recipient_email,
+        upload.organization_id,
+        upload.folder_id,
+        upload.password,
+        upload.expiration_date,
+        vec![(upload.file_name, None, file_data)]
+    ).await?;
+
+    app_state.db_client
+        .delete_upload(upload.user_id, upload.id)
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+    remove_upload_file(upload.id).await;
+
+    Ok(())
+}
+
+pub async fn remove_upload_file(upload_id: Uuid) {
+    if let Err(err) = fs::remove_file(upload_path(upload_id)).await {
+        eprintln!("Failed to remove upload {}: {}", upload_id, err);
+    }
+}
+
+async fn find_upload(
+    app_state: &AppState,
+    user_id: Uuid,
+    upload_id: Uuid,
+) -> Result<Upload, HttpError> {
+    app_state.db_client
+        .get_upload(user_id, upload_id)
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?
+        .ok_or_else(|| HttpError::new("Upload not found", StatusCode::NOT_FOUND))
+}
+
+fn header_i64(headers: &HeaderMap, name: &str) -> Result<i64, HttpError> {
+    headers.get(name)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<i64>().ok())
+        .filter(|value| *value >= 0)
+        .ok_or_else(|| HttpError::bad_request(format!("A valid {} header is required", name)))
+}
+
+fn metadata_uuid(value: Option<&String>, message: &str) -> Result<Option<Uuid>, HttpError> {
+    value
+        .map(|value| Uuid::parse_str(value))
+        .transpose()
+        .map_err(|_| HttpError::bad_request(message))
+}
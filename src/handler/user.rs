@@ -1,9 +1,10 @@
 use std::sync::Arc;
 
-use axum::{extract::Query, response::IntoResponse, routing::{get, put}, Extension, Json, Router};
+use axum::{extract::{rejection::JsonRejection, Path, Query}, http::StatusCode, response::IntoResponse, routing::{delete, get, post, put}, Extension, Json, Router};
+use uuid::Uuid;
 use validator::Validate;
 
-use crate::{db::UserExt, dtos::{EmailListResponseDto, FilterEmailDto, FilterUserDto, NameUpdateDto, Response, SearchQueryByEmailDTO, UserData, UserPasswordUpdateDto, UserResponseDto}, error::{ErrorMessage, HttpError}, middleware::JWTAuthMiddeware, utils::password, AppState};
+use crate::{db::{BlockExt, KeyExt, UserExt}, dtos::{BlockUserDto, BlockedUserDto, BlockedUserListResponseDto, EmailListResponseDto, FilterEmailDto, FilterUserDto, NameUpdateDto, Response, SearchMode, SearchQueryByEmailDTO, ShareConsentDto, StorageUsageDto, UserData, ExportKeysDto, ExportedKeyDto, ImportKeysDto, KeyExportResponseDto, RotateKeysDto, UserKeyDto, UserKeyListResponseDto, UserPasswordUpdateDto, UserResponseDto}, error::{ErrorMessage, HttpError}, middleware::JWTAuthMiddeware, utils::{keys::{export_private_key, generate_recovery_code, import_private_key, rewrap_rotated_keys, rotate_key}, password, quota::storage_usage}, AppState};
 
 
 pub fn users_handler() -> Router {
@@ -15,19 +16,31 @@ pub fn users_handler() -> Router {
     .route("/name", put(update_user_name))
     .route("/password", put(update_user_password))
     .route("/search-emails", get(search_by_email))
+    .route("/share-consent", put(update_share_consent))
+    .route("/blocked", get(get_blocked_users).post(block_user))
+    .route("/blocked/:user_id", delete(unblock_user))
+    .route("/keys", get(get_keys))
+    .route("/keys/rotate", post(rotate_keys))
+    .route("/keys/export", post(export_keys))
+    .route("/keys/import", post(import_keys))
 }
 
 
 
 pub async fn get_me(
-    Extension(_app_state): Extension<Arc<AppState>>,
+    Extension(app_state): Extension<Arc<AppState>>,
     Extension(user): Extension<JWTAuthMiddeware>,
 ) -> Result<impl IntoResponse, HttpError> {
     let filtered_user = FilterUserDto::filter_user(&user.user);
 
+    let (usage, organizations) = storage_usage(&app_state, user.user.id).await?;
+
     let response_data = UserResponseDto {
         status: "success".to_string(),
-        data: UserData { user: filtered_user },
+        data: UserData {
+            user: filtered_user,
+            storage: Some(StorageUsageDto::filter_usage(&usage, &organizations)),
+        },
     };
 
     Ok(Json(response_data))
@@ -45,7 +58,7 @@ pub async fn update_user_name(
     let user_id = uuid::Uuid::parse_str(&user.id.to_string()).unwrap();
 
     let result = app_state.db_client
-        .update_user_name(user_id.clone(), &body.name)
+        .update_user_name(user_id, &body.name)
         .await
         .map_err(|e| HttpError::server_error(e.to_string()))?;
 
@@ -53,7 +66,7 @@ pub async fn update_user_name(
 
     let response = UserResponseDto {
         status: "success".to_string(),
-        data: UserData { user: filtered_user },
+        data: UserData { user: filtered_user, storage: None },
     };
 
     Ok(Json(response))
@@ -72,7 +85,7 @@ pub async fn update_user_password(
     let user_id = uuid::Uuid::parse_str(&user.id.to_string()).unwrap();
 
     let result = app_state.db_client
-        .get_user(Some(user_id.clone()), None, None)
+        .get_user(Some(user_id), None, None)
         .await
         .map_err(|e| HttpError::server_error(e.to_string()))?;
 
@@ -83,17 +96,19 @@ pub async fn update_user_password(
         &body.old_password,
         &user.password 
     )
-    .map_err(|e| HttpError::server_error(e.to_string()))?;
+    .await
+    .map_err(HttpError::from)?;
 
     if !password_match {
         return Err(HttpError::bad_request("Old password is incorrect".to_string()));
     }
 
     let hashed_password = password::hash(&body.new_password)
-       .map_err(|e| HttpError::server_error(e.to_string()))?;
+       .await
+       .map_err(HttpError::from)?;
 
     app_state.db_client
-        .update_user_password(user_id.clone(), hashed_password)
+        .update_user_password(user_id, hashed_password)
         .await
         .map_err(|e| HttpError::server_error(e.to_string()))?;
 
@@ -105,22 +120,140 @@ pub async fn update_user_password(
     Ok(Json(response))
 }
 
-pub async fn search_by_email(
-    Query(params): Query<SearchQueryByEmailDTO>,
+pub async fn update_share_consent(
     Extension(app_state): Extension<Arc<AppState>>,
     Extension(user): Extension<JWTAuthMiddeware>,
+    Json(body): Json<ShareConsentDto>
 ) -> Result<impl IntoResponse, HttpError> {
-    params.validate()
+    let result = app_state.db_client
+        .update_share_consent(user.user.id, body.require_share_consent)
+        .await
         .map_err(|e| HttpError::server_error(e.to_string()))?;
 
-    let query_pattern = format!("%{}%", params.query);
-    let user_id = uuid::Uuid::parse_str(&user.user.id.to_string()).unwrap();
+    let filtered_user = FilterUserDto::filter_user(&result);
 
-    let users = app_state.db_client
-        .search_by_email(user_id.clone(), query_pattern.clone())
+    let response = UserResponseDto {
+        status: "success".to_string(),
+        data: UserData { user: filtered_user, storage: None },
+    };
+
+    Ok(Json(response))
+}
+
+pub async fn get_blocked_users(
+    Extension(app_state): Extension<Arc<AppState>>,
+    Extension(user): Extension<JWTAuthMiddeware>,
+) -> Result<impl IntoResponse, HttpError> {
+    let blocked = app_state.db_client
+        .get_blocked_users(user.user.id)
         .await
         .map_err(|e| HttpError::server_error(e.to_string()))?;
 
+    let response = BlockedUserListResponseDto {
+        status: "success".to_string(),
+        users: BlockedUserDto::filter_blocked_users(&blocked),
+    };
+
+    Ok(Json(response))
+}
+
+pub async fn block_user(
+    Extension(app_state): Extension<Arc<AppState>>,
+    Extension(user): Extension<JWTAuthMiddeware>,
+    Json(body): Json<BlockUserDto>
+) -> Result<impl IntoResponse, HttpError> {
+    body.validate()
+        .map_err(|e| HttpError::bad_request(e.to_string()))?;
+
+    let blocked_user = app_state.db_client
+        .get_user(None, None, Some(&body.email))
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?
+        .ok_or(HttpError::bad_request("User not found"))?;
+
+    if blocked_user.id == user.user.id {
+        return Err(HttpError::bad_request("You cannot block yourself"));
+    }
+
+    match app_state.db_client.block_user(user.user.id, blocked_user.id).await {
+        Ok(_) => {}
+        Err(sqlx::Error::Database(db_err)) if db_err.is_unique_violation() => {
+            return Err(HttpError::unique_constraint_violation("This user is already blocked"));
+        }
+        Err(e) => return Err(HttpError::server_error(e.to_string())),
+    }
+
+    let response = Response {
+        message: "User blocked successfully".to_string(),
+        status: "success",
+    };
+
+    Ok((StatusCode::CREATED, Json(response)))
+}
+
+pub async fn unblock_user(
+    Path(blocked_user_id): Path<Uuid>,
+    Extension(app_state): Extension<Arc<AppState>>,
+    Extension(user): Extension<JWTAuthMiddeware>,
+) -> Result<impl IntoResponse, HttpError> {
+    let removed = app_state.db_client
+        .unblock_user(user.user.id, blocked_user_id)
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+    if !removed {
+        return Err(HttpError::new("This user is not blocked", StatusCode::NOT_FOUND));
+    }
+
+    let response = Response {
+        message: "User unblocked successfully".to_string(),
+        status: "success",
+    };
+
+    Ok(Json(response))
+}
+
+pub async fn search_by_email(
+    Query(params): Query<SearchQueryByEmailDTO>,
+    Extension(app_state): Extension<Arc<AppState>>,
+    Extension(user): Extension<JWTAuthMiddeware>,
+) -> Result<impl IntoResponse, HttpError> {
+    params.validate()
+        .map_err(|e| HttpError::bad_request(e.to_string()))?;
+
+    let query = params.query.trim();
+    let min_length = app_state.env.search_min_query_length;
+
+    if query.chars().count() < min_length {
+        return Err(HttpError::bad_request(format!("Query must be at least {} characters", min_length)));
+    }
+
+    let user_id = user.user.id;
+
+    let users = match params.mode.unwrap_or_default() {
+        SearchMode::Exact => {
+            // Same visibility rules as prefix mode, a pattern without
+            // wildcards only matches the whole address
+            let users = app_state.db_client
+                .search_by_email(user_id, escape_like(query), app_state.env.search_max_results)
+                .await
+                .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+            users
+                .into_iter()
+                .filter(|found| found.email == query)
+                .collect()
+        }
+        SearchMode::Prefix => {
+            let query_pattern = format!("{}%", escape_like(query));
+
+            app_state.db_client
+                .search_by_email(user_id, query_pattern, app_state.env.search_max_results)
+                .await
+                .map_err(|e| HttpError::server_error(e.to_string()))?
+        }
+    };
+
     let filtered_email = FilterEmailDto::filter_emails(&users);
     let response_data = EmailListResponseDto {
         status: "success".to_string(),
@@ -128,4 +261,158 @@ pub async fn search_by_email(
     };
 
     Ok(Json(response_data))
-}
\ No newline at end of file
+}
+
+fn escape_like(query: &str) -> String {
+    query
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
+}
+
+pub async fn get_keys(
+    Extension(app_state): Extension<Arc<AppState>>,
+    Extension(user): Extension<JWTAuthMiddeware>,
+) -> Result<impl IntoResponse, HttpError> {
+    let response = key_list_response(&app_state, user.user.id).await?;
+
+    Ok(Json(response))
+}
+
+/// Creates a new key pair for the user, optionally of another type. Files
+/// of their active shares are moved to it in the background, the old key
+/// stays usable until then.
+pub async fn rotate_keys(
+    Extension(app_state): Extension<Arc<AppState>>,
+    Extension(user): Extension<JWTAuthMiddeware>,
+    body: Result<Json<RotateKeysDto>, JsonRejection>,
+) -> Result<impl IntoResponse, HttpError> {
+    // The body is optional, an empty request keeps the current key type
+    let body = match body {
+        Ok(Json(body)) => body,
+        Err(JsonRejection::MissingJsonContentType(_)) => RotateKeysDto::default(),
+        Err(rejection) => return Err(HttpError::bad_request(rejection.body_text())),
+    };
+
+    let key_type = match body.key_type {
+        Some(key_type) => key_type,
+        None => app_state.db_client
+            .get_active_key(user.user.id)
+            .await
+            .map_err(|e| HttpError::server_error(e.to_string()))?
+            .map(|key| key.key_type)
+            .unwrap_or(app_state.env.default_key_type),
+    };
+
+    rotate_key(&app_state, user.user.id, key_type).await?;
+
+    let rewrap_state = app_state.clone();
+    tokio::spawn(async move {
+        if let Err(err) = rewrap_rotated_keys(&rewrap_state.db_client, &*rewrap_state.key_store).await {
+            eprintln!("Error re-wrapping rotated keys: {}", err.message);
+        }
+    });
+
+    let response = key_list_response(&app_state, user.user.id).await?;
+
+    Ok((StatusCode::CREATED, Json(response)))
+}
+
+/// Exports every key that still decrypts files, each as an encrypted PKCS#8
+/// PEM. The account password is asked again since this hands out the keys.
+pub async fn export_keys(
+    Extension(app_state): Extension<Arc<AppState>>,
+    Extension(user): Extension<JWTAuthMiddeware>,
+    Json(body): Json<ExportKeysDto>
+) -> Result<impl IntoResponse, HttpError> {
+    body.validate()
+        .map_err(|e| HttpError::bad_request(e.to_string()))?;
+
+    let password_match = password::compare(&body.password, &user.user.password)
+        .await
+        .map_err(HttpError::from)?;
+
+    if !password_match {
+        return Err(HttpError::bad_request("Password is incorrect".to_string()));
+    }
+
+    let recovery_code = match body.passphrase {
+        Some(_) => None,
+        None => Some(generate_recovery_code()),
+    };
+
+    let passphrase = body.passphrase.as_deref()
+        .or(recovery_code.as_deref())
+        .unwrap_or_default();
+
+    let keys = app_state.db_client
+        .get_usable_keys(user.user.id)
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+    let mut exported = Vec::with_capacity(keys.len());
+
+    for key in keys {
+        exported.push(ExportedKeyDto {
+            id: key.id.to_string(),
+            key_type: key.key_type,
+            status: key.status,
+            created_at: key.created_at.unwrap(),
+            private_key: export_private_key(&*app_state.key_store, key.id, passphrase).await?,
+        });
+    }
+
+    let response = KeyExportResponseDto {
+        status: "success".to_string(),
+        recovery_code,
+        keys: exported,
+    };
+
+    Ok(Json(response))
+}
+
+/// Restores exported keys, e.g. on a new deployment that only has the
+/// database. Each key must be one of the caller's non-retired keys.
+pub async fn import_keys(
+    Extension(app_state): Extension<Arc<AppState>>,
+    Extension(user): Extension<JWTAuthMiddeware>,
+    Json(body): Json<ImportKeysDto>
+) -> Result<impl IntoResponse, HttpError> {
+    body.validate()
+        .map_err(|e| HttpError::bad_request(e.to_string()))?;
+
+    let keys = app_state.db_client
+        .get_usable_keys(user.user.id)
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+    for imported in &body.keys {
+        let key = keys.iter()
+            .find(|key| key.id.to_string() == imported.id)
+            .ok_or_else(|| HttpError::bad_request(format!("Key {} is not one of your keys", imported.id)))?;
+
+        import_private_key(&*app_state.key_store, key, &imported.private_key, &body.passphrase).await?;
+    }
+
+    let response = Response {
+        message: format!("{} keys imported successfully", body.keys.len()),
+        status: "success",
+    };
+
+    Ok(Json(response))
+}
+
+async fn key_list_response(
+    app_state: &AppState,
+    user_id: Uuid,
+) -> Result<UserKeyListResponseDto, HttpError> {
+    let keys = app_state.db_client
+        .get_user_keys(user_id)
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+    Ok(UserKeyListResponseDto {
+        status: "success".to_string(),
+        keys: UserKeyDto::filter_keys(&keys),
+    })
+}
@@ -7,35 +7,51 @@ mod utils;
 mod middleware;
 mod handler;
 mod router;
+mod cli;
 
 
-use std::sync::Arc;
+use std::{net::SocketAddr, sync::Arc, time::Duration};
 
-use axum::http::{header::{ACCEPT, AUTHORIZATION, CONTENT_TYPE}, HeaderValue, Method};
+use axum::http::{header::{ACCEPT, ACCEPT_RANGES, AUTHORIZATION, CONTENT_RANGE, CONTENT_TYPE, ETAG, IF_RANGE, LOCATION, RANGE}, HeaderName, HeaderValue, Method};
 use config::Config;
-use db::{DBClient, UserExt};
+use db::{DBClient, LoginAttemptExt, RateLimitExt, UploadExt, UserExt};
 use dotenv::dotenv;
 use router::create_router;
 use sqlx::postgres::PgPoolOptions;
 use tower_http::cors::CorsLayer;
 use tracing_subscriber::filter::LevelFilter;
 use tokio_cron_scheduler::{JobScheduler, Job};
+use handler::upload::remove_upload_file;
+use utils::{crypto_pool, escrow::{escrow_missing_keys, load_escrow_public_key}, key_store::{load_jwt_secret, FilesystemKeyStore, KeyStore, PostgresKeyStore, RemoteKeyStore}, key_pair::PublicKey, key_pool::KeyPool, keys::rewrap_rotated_keys, rate_limit::{MemoryRateLimitStore, PostgresRateLimitStore, RateLimitStore, TokenBucketLimiter}, scanner::{ClamdScanner, MalwareScanner}, upload::UploadLocks};
 
 
 #[derive(Debug, Clone)]
 pub struct AppState {
     pub env: Config,
     pub db_client: DBClient,
+    pub login_limiter: TokenBucketLimiter,
+    pub rate_limit_store: Arc<dyn RateLimitStore>,
+    pub upload_locks: Arc<UploadLocks>,
+    pub escrow_public_key: Option<PublicKey>,
+    pub key_store: Arc<dyn KeyStore>,
+    pub key_pool: Arc<KeyPool>,
+    pub scanner: Option<Arc<dyn MalwareScanner>>,
 }
 
 #[tokio::main]
 async fn main() {
+    if let Some(code) = cli::run(std::env::args().skip(1).collect()) {
+        std::process::exit(code);
+    }
+
     tracing_subscriber::fmt()
         .with_max_level(LevelFilter::DEBUG)
         .init();
 
     dotenv().ok();
-    let config = Config::init();
+    let mut config = Config::init();
+
+    crypto_pool::init(config.crypto_workers, config.crypto_max_queue);
 
     let pool = match PgPoolOptions::new()
         .max_connections(10)
@@ -53,21 +69,138 @@ async fn main() {
 
     let cors = CorsLayer::new()
         .allow_origin("http://localhost:3000".parse::<HeaderValue>().unwrap())
-        .allow_headers([AUTHORIZATION, ACCEPT, CONTENT_TYPE])
+        .allow_headers([
+            AUTHORIZATION,
+            ACCEPT,
+            CONTENT_TYPE,
+            RANGE,
+            IF_RANGE,
+            HeaderName::from_static("tus-resumable"),
+            HeaderName::from_static("upload-length"),
+            HeaderName::from_static("upload-offset"),
+            HeaderName::from_static("upload-metadata"),
+        ])
+        .expose_headers([
+            LOCATION,
+            ACCEPT_RANGES,
+            CONTENT_RANGE,
+            ETAG,
+            HeaderName::from_static("repr-digest"),
+            HeaderName::from_static("digest"),
+            HeaderName::from_static("x-sender-id"),
+            HeaderName::from_static("x-sender-signature"),
+            HeaderName::from_static("x-sender-public-key"),
+            HeaderName::from_static("tus-resumable"),
+            HeaderName::from_static("upload-offset"),
+            HeaderName::from_static("upload-length"),
+            HeaderName::from_static("upload-expires"),
+        ])
         .allow_credentials(true)
-        .allow_methods([Method::GET, Method::POST, Method::PUT]);
+        .allow_methods([Method::GET, Method::POST, Method::PUT, Method::DELETE, Method::PATCH, Method::HEAD]);
 
     let db_client = DBClient::new(pool);
+
+    let rate_limit_store: Arc<dyn RateLimitStore> = match config.rate_limit_store.as_str() {
+        "memory" => Arc::new(MemoryRateLimitStore::new()),
+        "postgres" => Arc::new(PostgresRateLimitStore::new(db_client.clone())),
+        other => panic!("Unknown RATE_LIMIT_STORE: {}", other),
+    };
+
+    let key_store: Arc<dyn KeyStore> = match config.key_store.as_str() {
+        "filesystem" => Arc::new(
+            FilesystemKeyStore::new(&config.key_store_dir)
+                .unwrap_or_else(|e| panic!("KEY_STORE_DIR is invalid: {}", e))
+        ),
+        "postgres" => {
+            let master_key = config.key_store_master_key.as_deref()
+                .expect("KEY_STORE_MASTER_KEY must be set for KEY_STORE=postgres");
+
+            Arc::new(
+                PostgresKeyStore::new(db_client.clone(), master_key)
+                    .unwrap_or_else(|e| panic!("KEY_STORE_MASTER_KEY is invalid: {}", e))
+            )
+        }
+        "kms" => Arc::new(RemoteKeyStore::new(
+            config.kms_url.clone().expect("KMS_URL must be set for KEY_STORE=kms"),
+            config.kms_token.clone().expect("KMS_TOKEN must be set for KEY_STORE=kms"),
+        )),
+        // The remote client against a local stand-in, for development
+        #[cfg(feature = "mocks")]
+        "mock-kms" => {
+            let (url, token) = utils::mock_kms::spawn_mock_kms(&format!("{}/mock_kms", config.key_store_dir))
+                .await
+                .unwrap_or_else(|e| panic!("Mock KMS failed to start: {}", e));
+
+            Arc::new(RemoteKeyStore::new(url, token))
+        }
+        other => panic!("Unknown KEY_STORE: {}", other),
+    };
+
+    if config.jwt_secret.is_empty() {
+        config.jwt_secret = load_jwt_secret(&*key_store)
+            .await
+            .unwrap_or_else(|e| panic!("JWT secret could not be loaded from the key store: {}", e));
+    }
+
+    let escrow_public_key = config.escrow_public_key.as_deref().map(|path| {
+        load_escrow_public_key(path)
+            .unwrap_or_else(|e| panic!("ESCROW_PUBLIC_KEY is invalid: {}", e))
+    });
+
+    let scanner_timeout = Duration::from_secs(config.scanner_timeout_secs);
+
+    let scanner: Option<Arc<dyn MalwareScanner>> = match config.scanner.as_str() {
+        "none" => None,
+        "clamd" => Some(Arc::new(ClamdScanner::new(config.clamd_address.clone(), scanner_timeout))),
+        // The clamd client against a local stand-in, for development
+        #[cfg(feature = "mocks")]
+        "mock-clamd" => {
+            let address = utils::mock_clamd::spawn_mock_clamd()
+                .await
+                .unwrap_or_else(|e| panic!("Mock clamd failed to start: {}", e));
+
+            Some(Arc::new(ClamdScanner::new(address, scanner_timeout)))
+        }
+        other => panic!("Unknown SCANNER: {}", other),
+    };
+
+    let key_pool = Arc::new(KeyPool::new(config.key_pool_size));
+
+    if key_pool.capacity() > 0 {
+        tokio::spawn(key_pool.clone().fill());
+    }
+
     let app_state = AppState {
         env: config.clone(),
         db_client: db_client.clone(),
+        login_limiter: TokenBucketLimiter::new(config.login_ip_burst, config.login_ip_per_minute),
+        rate_limit_store,
+        upload_locks: Arc::new(UploadLocks::new()),
+        escrow_public_key: escrow_public_key.clone(),
+        key_store: key_store.clone(),
+        key_pool,
+        scanner,
     };
 
+    // Keys from before escrow was configured are picked up right away
+    if let Some(escrow_public_key) = escrow_public_key.clone() {
+        let db_client = db_client.clone();
+        let key_store = key_store.clone();
+        tokio::spawn(async move {
+            if let Err(err) = escrow_missing_keys(&db_client, &*key_store, &escrow_public_key).await {
+                eprintln!("Error escrowing private keys: {}", err.message);
+            }
+        });
+    }
+
     let sched = JobScheduler::new().await.unwrap();
+    let login_lockout_minutes = config.login_lockout_minutes;
 
     let job = Job::new_async("0 0 * * * *", {
        move |_, _| {
         let db_client = db_client.clone();
+        let escrow_public_key = escrow_public_key.clone();
+        let key_store = key_store.clone();
         Box::pin(async move {
             println!("Running scheduled task to delete expired files...");
             if let Err(err) = db_client.delete_expired_files().await {
@@ -75,6 +208,37 @@ async fn main() {
             } else {
                 println!("Successfully deleted expired files.");
             }
+
+            if let Err(err) = db_client.delete_stale_rate_limits().await {
+                eprintln!("Error deleting stale rate limits: {:?}", err);
+            }
+
+            if let Err(err) = db_client.delete_stale_login_attempts(login_lockout_minutes).await {
+                eprintln!("Error deleting stale login attempts: {:?}", err);
+            }
+
+            match db_client.delete_expired_uploads().await {
+                Ok(upload_ids) => {
+                    for upload_id in upload_ids {
+                        remove_upload_file(upload_id).await;
+                    }
+                }
+                Err(err) => eprintln!("Error deleting expired uploads: {:?}", err),
+            }
+
+            match rewrap_rotated_keys(&db_client, &*key_store).await {
+                Ok(0) => {}
+                Ok(count) => println!("Re-wrapped {} files to their owners' active keys.", count),
+                Err(err) => eprintln!("Error re-wrapping rotated keys: {}", err.message),
+            }
+
+            if let Some(escrow_public_key) = escrow_public_key {
+                match escrow_missing_keys(&db_client, &*key_store, &escrow_public_key).await {
+                    Ok(0) => {}
+                    Ok(count) => println!("Escrowed {} private keys.", count),
+                    Err(err) => eprintln!("Error escrowing private keys: {}", err.message),
+                }
+            }
         })
        } 
     }).unwrap();
@@ -85,15 +249,15 @@ async fn main() {
         sched.start().await.unwrap();
     });
 
-    let app = create_router(Arc::new(app_state.clone())).layer(cors.clone());
+    let app = create_router(Arc::new(app_state.clone()))
+        .layer(cors.clone())
+        .layer(axum::middleware::from_fn_with_state(config.tus_max_size, middleware::tus_options));
         
-    println!(
-        "{}",
-        format!("🚀 Server is running on http://localhost:{}", config.port)
-    );
+    println!("🚀 Server is running on http://localhost:{}", config.port);
 
     let listener = tokio::net::TcpListener::bind(format!("0.0.0.0:{}", &config.port))
     .await.unwrap();
 
-    axum::serve(listener, app).await.unwrap();
+    axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>())
+        .await.unwrap();
 }
@@ -1,10 +1,15 @@
 use std::sync::Arc;
 
-use axum::{extract::Request, http::header, middleware::Next, response::IntoResponse, Extension};
+use std::net::SocketAddr;
+
+use axum::{extract::{ConnectInfo, OriginalUri, Request, State}, http::{header, HeaderMap, HeaderValue, Method, StatusCode}, middleware::Next, response::{IntoResponse, Response}, Extension};
 use serde::{Deserialize, Serialize};
 use axum_extra::extract::cookie::CookieJar;
 
-use crate::{db::UserExt, error::{ErrorMessage, HttpError}, models::User, utils::token, AppState};
+use crate::{db::UserExt, error::{ErrorMessage, HttpError}, models::{User, UserRole}, utils::{rate_limit::{RateLimitDecision, RateLimitStore}, token, upload::{TUS_EXTENSIONS, TUS_VERSION}}, AppState};
+
+// The only route a user flagged for a password reset may still reach
+const PASSWORD_RESET_PATH: &str = "/api/users/password";
 
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -26,11 +31,9 @@ pub async fn auth(
                     .get(header::AUTHORIZATION)
                     .and_then(|auth_header| auth_header.to_str().ok())
                     .and_then(|auth_value| {
-                        if auth_value.starts_with("Bearer ") {
-                            Some(auth_value[7..].to_owned())
-                        }else {
-                            None
-                        }
+                        auth_value
+                            .strip_prefix("Bearer ")
+                            .map(|token| token.to_owned())
                     })  
             });
     let token = cookies.ok_or_else(|| {
@@ -45,7 +48,7 @@ pub async fn auth(
             }
         };
     
-    let user_id = uuid::Uuid::parse_str(&token_details.to_string()).unwrap();
+    let user_id = uuid::Uuid::parse_str(&token_details.sub).unwrap();
 
     let user = app_state.db_client.get_user(Some(user_id), None, None)
         .await
@@ -55,9 +58,183 @@ pub async fn auth(
         HttpError::unauthorized(ErrorMessage::UserNoLongerExist.to_string())
     })?;
 
+    if user.disabled {
+        return Err(HttpError::forbidden(ErrorMessage::UserDisabled.to_string()));
+    }
+
+    // `iat` is in whole seconds, so a token from the second of the revocation
+    // is revoked too
+    if let Some(valid_after) = user.tokens_valid_after {
+        if (token_details.iat as i64) <= valid_after.timestamp() {
+            return Err(HttpError::unauthorized(ErrorMessage::InvalidToken.to_string()));
+        }
+    }
+
+    if user.password_reset_required && request_path(&req) != PASSWORD_RESET_PATH {
+        return Err(HttpError::forbidden(ErrorMessage::PasswordResetRequired.to_string()));
+    }
+
     req.extensions_mut().insert(JWTAuthMiddeware {
         user: user.clone(),
     });
 
     Ok(next.run(req).await)
-}
\ No newline at end of file
+}
+
+pub async fn admin(
+    Extension(user): Extension<JWTAuthMiddeware>,
+    req: Request,
+    next: Next,
+) -> Result<impl IntoResponse, HttpError> {
+    if user.user.role != UserRole::Admin {
+        return Err(HttpError::forbidden(ErrorMessage::PermissionDenied.to_string()));
+    }
+
+    Ok(next.run(req).await)
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct RateLimitPolicy {
+    pub name: &'static str,
+    pub burst: u32,
+    pub per_minute: u32,
+}
+
+impl RateLimitPolicy {
+    pub fn new(name: &'static str, burst: u32, per_minute: u32) -> Self {
+        RateLimitPolicy { name, burst, per_minute }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RateLimiter {
+    store: Arc<dyn RateLimitStore>,
+    routes: Vec<(Method, &'static str, RateLimitPolicy)>,
+    fallback: Option<RateLimitPolicy>,
+}
+
+impl RateLimiter {
+    pub fn new(store: Arc<dyn RateLimitStore>) -> Self {
+        RateLimiter {
+            store,
+            routes: Vec::new(),
+            fallback: None,
+        }
+    }
+
+    pub fn route(mut self, method: Method, path: &'static str, policy: RateLimitPolicy) -> Self {
+        self.routes.push((method, path, policy));
+        self
+    }
+
+    pub fn fallback(mut self, policy: RateLimitPolicy) -> Self {
+        self.fallback = Some(policy);
+        self
+    }
+
+    fn policy_for(&self, method: &Method, path: &str) -> Option<RateLimitPolicy> {
+        self.routes
+            .iter()
+            .find(|(route_method, route_path, _)| route_method == method && *route_path == path)
+            .map(|(_, _, policy)| *policy)
+            .or(self.fallback)
+    }
+}
+
+/// Applies the matching policy per user when authenticated, per IP otherwise.
+/// Must be layered inside `auth` for requests to be keyed by user.
+pub async fn rate_limit(
+    State(limiter): State<RateLimiter>,
+    req: Request,
+    next: Next,
+) -> Response {
+    let policy = match limiter.policy_for(req.method(), &request_path(&req)) {
+        Some(policy) => policy,
+        None => return next.run(req).await,
+    };
+
+    let subject = match req.extensions().get::<JWTAuthMiddeware>() {
+        Some(auth) => format!("user:{}", auth.user.id),
+        None => match req.extensions().get::<ConnectInfo<SocketAddr>>() {
+            Some(ConnectInfo(addr)) => format!("ip:{}", addr.ip()),
+            None => "ip:unknown".to_string(),
+        },
+    };
+
+    let key = format!("{}:{}", policy.name, subject);
+
+    let decision = match limiter.store.take(&key, policy.burst, policy.per_minute).await {
+        Ok(decision) => decision,
+        Err(err) => {
+            // Fail open: a broken store must not take the API down with it
+            eprintln!("Rate limit store error: {}", err);
+            return next.run(req).await;
+        }
+    };
+
+    let mut response = if decision.allowed {
+        next.run(req).await
+    } else {
+        HttpError::too_many_requests(ErrorMessage::RateLimited.to_string())
+            .with_retry_after(decision.retry_after.as_secs().max(1))
+            .into_response()
+    };
+
+    rate_limit_headers(response.headers_mut(), &decision);
+
+    response
+}
+
+/// Rejects tus requests for another protocol version and tags every response
+/// with the version spoken here.
+pub async fn tus_resumable(req: Request, next: Next) -> Response {
+    let supported = req.headers().get("Tus-Resumable").and_then(|value| value.to_str().ok()) == Some(TUS_VERSION);
+
+    let mut response = if supported {
+        next.run(req).await
+    } else {
+        let mut response = HttpError::new("Unsupported tus version", StatusCode::PRECONDITION_FAILED)
+            .into_response();
+        response.headers_mut().insert("Tus-Version", HeaderValue::from_static(TUS_VERSION));
+        response
+    };
+
+    response.headers_mut().insert("Tus-Resumable", HeaderValue::from_static(TUS_VERSION));
+
+    response
+}
+
+/// Answers tus discovery. The CORS layer replies to every `OPTIONS` request
+/// itself, so this wraps it and adds the tus headers to its response.
+pub async fn tus_options(
+    State(max_size): State<i64>,
+    req: Request,
+    next: Next,
+) -> Response {
+    let discovery = req.method() == Method::OPTIONS && request_path(&req).starts_with("/api/uploads");
+
+    let mut response = next.run(req).await;
+
+    if discovery {
+        let headers = response.headers_mut();
+        headers.insert("Tus-Resumable", HeaderValue::from_static(TUS_VERSION));
+        headers.insert("Tus-Version", HeaderValue::from_static(TUS_VERSION));
+        headers.insert("Tus-Extension", HeaderValue::from_static(TUS_EXTENSIONS));
+        headers.insert("Tus-Max-Size", HeaderValue::from(max_size));
+    }
+
+    response
+}
+
+fn rate_limit_headers(headers: &mut HeaderMap, decision: &RateLimitDecision) {
+    headers.insert("RateLimit-Limit", HeaderValue::from(decision.limit));
+    headers.insert("RateLimit-Remaining", HeaderValue::from(decision.remaining));
+    headers.insert("RateLimit-Reset", HeaderValue::from(decision.reset_after.as_secs()));
+}
+
+fn request_path(req: &Request) -> String {
+    req.extensions()
+        .get::<OriginalUri>()
+        .map(|uri| uri.path().to_owned())
+        .unwrap_or_else(|| req.uri().path().to_owned())
+}
@@ -1,6 +1,107 @@
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, sqlx::Type, PartialEq)]
+#[sqlx(type_name = "user_role", rename_all = "lowercase")]
+pub enum UserRole {
+    Admin,
+    User,
+}
+
+impl UserRole {
+    pub fn to_str(self) -> &'static str {
+        match self {
+            UserRole::Admin => "admin",
+            UserRole::User => "user",
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize, Serialize, Clone, Copy, sqlx::Type, PartialEq)]
+#[sqlx(type_name = "share_status", rename_all = "lowercase")]
+pub enum ShareStatus {
+    Pending,
+    #[default]
+    Accepted,
+}
+
+impl ShareStatus {
+    pub fn to_str(self) -> &'static str {
+        match self {
+            ShareStatus::Pending => "pending",
+            ShareStatus::Accepted => "accepted",
+        }
+    }
+}
+
+/// What the malware scan of a file's plaintext found. Files stored while
+/// scanning was off have none.
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, sqlx::Type, PartialEq)]
+#[sqlx(type_name = "scan_status", rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum ScanStatus {
+    Clean,
+    /// Kept for the sender only and never delivered
+    Quarantined,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, sqlx::Type, PartialEq)]
+#[sqlx(type_name = "key_status", rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum KeyStatus {
+    /// New files are wrapped with this key
+    Active,
+    /// Replaced, but some files are still wrapped with it
+    Rotated,
+    /// Nothing uses it any more and the private key has been deleted
+    Retired,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, sqlx::Type, PartialEq)]
+#[sqlx(type_name = "key_type", rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum KeyType {
+    /// RSA-2048 with PKCS#1 v1.5 key wrapping
+    Rsa,
+    /// X25519 with the age key wrap (HKDF-SHA256 and ChaCha20-Poly1305)
+    X25519,
+}
+
+impl std::str::FromStr for KeyType {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "rsa" => Ok(KeyType::Rsa),
+            "x25519" => Ok(KeyType::X25519),
+            other => Err(format!("Unknown key type: {}", other)),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, sqlx::Type, PartialEq)]
+#[sqlx(type_name = "organization_role", rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum OrganizationRole {
+    Owner,
+    Admin,
+    Member,
+}
+
+impl OrganizationRole {
+    pub fn to_str(self) -> &'static str {
+        match self {
+            OrganizationRole::Owner => "owner",
+            OrganizationRole::Admin => "admin",
+            OrganizationRole::Member => "member",
+        }
+    }
+
+    pub fn can_manage(self) -> bool {
+        matches!(self, OrganizationRole::Owner | OrganizationRole::Admin)
+    }
+}
+
 #[derive(Debug, Clone, Deserialize, Serialize, sqlx::FromRow, sqlx::Type)]
 pub struct User {
     pub id: uuid::Uuid,
@@ -8,6 +109,11 @@ pub struct User {
     pub email: String,
     pub password: String,
     pub public_key: Option<String>,
+    pub role: UserRole,
+    pub disabled: bool,
+    pub password_reset_required: bool,
+    pub tokens_valid_after: Option<DateTime<Utc>>,
+    pub require_share_consent: bool,
     pub created_at: Option<DateTime<Utc>>,
     pub updated_at: Option<DateTime<Utc>>,
 }
@@ -16,11 +122,32 @@ pub struct User {
 pub struct File {
     pub id: uuid::Uuid,
     pub user_id: Option<uuid::Uuid>,
-    pub file_name: String,
+    pub file_name: Option<String>,
     pub file_size: i64,
     pub encrypted_aes_key: Vec<u8>,
     pub encrypted_file: Vec<u8>,
-    pub iv: Vec<u8>,
+    pub blob_id: Option<uuid::Uuid>,
+    pub iv: Option<Vec<u8>>,
+    pub encrypted_digest: Option<Vec<u8>>,
+    pub encrypted_metadata: Option<Vec<u8>>,
+    pub key_id: Option<uuid::Uuid>,
+    pub folder_id: Option<uuid::Uuid>,
+    pub created_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, sqlx::FromRow, sqlx::Type)]
+pub struct FileInfo {
+    pub id: uuid::Uuid,
+    pub user_id: Option<uuid::Uuid>,
+    pub file_name: Option<String>,
+    pub file_size: i64,
+    pub encrypted_aes_key: Vec<u8>,
+    pub iv: Option<Vec<u8>>,
+    pub encrypted_digest: Option<Vec<u8>>,
+    pub encrypted_metadata: Option<Vec<u8>>,
+    pub signature: Option<Vec<u8>>,
+    pub signer_public_key: Option<String>,
+    pub key_id: Option<uuid::Uuid>,
     pub created_at: Option<DateTime<Utc>>,
 }
 
@@ -31,14 +158,94 @@ pub struct SharedLink {
     pub recipient_user_id: Option<uuid::Uuid>,
     pub password: String,
     pub expiration_date: Option<DateTime<Utc>>,
+    pub status: ShareStatus,
+    pub organization_id: Option<uuid::Uuid>,
+    pub folder_share_id: Option<uuid::Uuid>,
+    pub transfer_id: Option<uuid::Uuid>,
+    pub created_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, sqlx::FromRow, sqlx::Type)]
+pub struct Upload {
+    pub id: uuid::Uuid,
+    pub user_id: uuid::Uuid,
+    pub upload_length: i64,
+    pub upload_offset: i64,
+    pub file_name: String,
+    pub recipient_email: Option<String>,
+    pub organization_id: Option<uuid::Uuid>,
+    pub folder_id: Option<uuid::Uuid>,
+    pub password: String,
+    pub expiration_date: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+    pub created_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, sqlx::FromRow, sqlx::Type)]
+pub struct Folder {
+    pub id: uuid::Uuid,
+    pub user_id: uuid::Uuid,
+    pub parent_id: Option<uuid::Uuid>,
+    pub name: String,
+    pub created_at: Option<DateTime<Utc>>,
+    pub updated_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, sqlx::FromRow, sqlx::Type)]
+pub struct FolderShare {
+    pub id: uuid::Uuid,
+    pub folder_id: uuid::Uuid,
+    pub recipient_user_id: uuid::Uuid,
+    pub password: String,
+    pub expiration_date: DateTime<Utc>,
+    pub created_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, sqlx::FromRow, sqlx::Type)]
+pub struct Organization {
+    pub id: uuid::Uuid,
+    pub name: String,
+    pub max_expiry_days: Option<i32>,
+    pub allowed_recipient_domains: Vec<String>,
     pub created_at: Option<DateTime<Utc>>,
+    pub updated_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, sqlx::FromRow, sqlx::Type)]
+pub struct BlockedUser {
+    pub id: uuid::Uuid,
+    pub user_id: uuid::Uuid,
+    pub blocked_user_id: uuid::Uuid,
+    pub created_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, sqlx::FromRow, sqlx::Type)]
+pub struct Contact {
+    pub id: uuid::Uuid,
+    pub user_id: uuid::Uuid,
+    pub contact_user_id: uuid::Uuid,
+    pub nickname: Option<String>,
+    pub created_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, sqlx::FromRow)]
+pub struct LoginAttempt {
+    pub email: String,
+    pub failed_attempts: i32,
+    pub last_failed_at: Option<DateTime<Utc>>,
+    pub locked_until: Option<DateTime<Utc>>,
 }
 
 #[derive(sqlx::FromRow)]
 pub struct SentFileDetails {
     pub file_id: uuid::Uuid,
     pub file_name: String,
+    pub key_id: Option<uuid::Uuid>,
+    pub encrypted_aes_key: Option<Vec<u8>>,
+    pub encrypted_metadata: Option<Vec<u8>>,
     pub recipient_email: String,
+    pub status: ShareStatus,
+    pub transfer_id: Option<uuid::Uuid>,
     pub expiration_date: Option<DateTime<Utc>>,
     pub created_at: Option<DateTime<Utc>>
 }
@@ -47,8 +254,261 @@ pub struct SentFileDetails {
 pub struct ReceiveFileDetails {
     pub file_id: uuid::Uuid,
     pub file_name: String,
+    pub key_id: Option<uuid::Uuid>,
+    pub encrypted_aes_key: Option<Vec<u8>>,
+    pub encrypted_metadata: Option<Vec<u8>>,
     pub sender_email: String,
+    pub transfer_id: Option<uuid::Uuid>,
     pub expiration_date: Option<DateTime<Utc>>,
     pub created_at: Option<DateTime<Utc>>
 }
 
+#[derive(sqlx::FromRow)]
+pub struct ContactDetails {
+    pub id: uuid::Uuid,
+    pub contact_user_id: uuid::Uuid,
+    pub name: String,
+    pub email: String,
+    pub nickname: Option<String>,
+    pub created_at: Option<DateTime<Utc>>
+}
+
+#[derive(sqlx::FromRow)]
+pub struct ContactSuggestion {
+    pub user_id: uuid::Uuid,
+    pub name: String,
+    pub email: String,
+    pub shared_count: i64,
+    pub last_shared_at: Option<DateTime<Utc>>
+}
+
+#[derive(sqlx::FromRow)]
+pub struct BlockedUserDetails {
+    pub blocked_user_id: uuid::Uuid,
+    pub name: String,
+    pub email: String,
+    pub created_at: Option<DateTime<Utc>>
+}
+
+#[derive(sqlx::FromRow)]
+pub struct OrganizationDetails {
+    pub id: uuid::Uuid,
+    pub name: String,
+    pub max_expiry_days: Option<i32>,
+    pub allowed_recipient_domains: Vec<String>,
+    pub role: OrganizationRole,
+    pub created_at: Option<DateTime<Utc>>
+}
+
+#[derive(sqlx::FromRow)]
+pub struct OrganizationMemberDetails {
+    pub user_id: uuid::Uuid,
+    pub name: String,
+    pub email: String,
+    pub role: OrganizationRole,
+    pub accepted: bool,
+    pub created_at: Option<DateTime<Utc>>
+}
+
+/// A user's place in an organization, whether they joined or are still
+/// only invited.
+#[derive(sqlx::FromRow)]
+pub struct OrganizationMembership {
+    pub role: OrganizationRole,
+    pub accepted: bool,
+}
+
+#[derive(sqlx::FromRow)]
+pub struct FolderFileDetails {
+    pub file_id: uuid::Uuid,
+    pub file_name: String,
+    pub file_size: i64,
+    pub key_id: Option<uuid::Uuid>,
+    pub encrypted_aes_key: Option<Vec<u8>>,
+    pub encrypted_metadata: Option<Vec<u8>>,
+    pub recipient_email: Option<String>,
+    pub expiration_date: Option<DateTime<Utc>>,
+    pub created_at: Option<DateTime<Utc>>
+}
+
+#[derive(sqlx::FromRow)]
+pub struct FolderShareDetails {
+    pub id: uuid::Uuid,
+    pub folder_id: uuid::Uuid,
+    pub recipient_email: String,
+    pub expiration_date: DateTime<Utc>,
+    pub created_at: Option<DateTime<Utc>>
+}
+
+/// A stored file together with the recipient whose key can unwrap it.
+#[derive(sqlx::FromRow)]
+pub struct ShareableFile {
+    pub id: uuid::Uuid,
+    pub file_name: Option<String>,
+    pub file_size: i64,
+    pub encrypted_aes_key: Vec<u8>,
+    pub encrypted_file: Option<Vec<u8>>,
+    pub blob_id: Option<uuid::Uuid>,
+    pub iv: Option<Vec<u8>>,
+    pub encrypted_digest: Option<Vec<u8>>,
+    pub encrypted_metadata: Option<Vec<u8>>,
+    pub sender_encrypted_aes_key: Option<Vec<u8>>,
+    pub sender_key_id: Option<uuid::Uuid>,
+    pub signature: Option<Vec<u8>>,
+    pub signer_public_key: Option<String>,
+    pub key_id: Option<uuid::Uuid>,
+    pub scan_status: Option<ScanStatus>,
+    /// Set on copies made for folder shares
+    pub source_file_id: Option<uuid::Uuid>,
+    pub recipient_user_id: uuid::Uuid,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, sqlx::FromRow)]
+pub struct UserKey {
+    pub id: uuid::Uuid,
+    pub user_id: uuid::Uuid,
+    pub public_key: String,
+    pub key_type: KeyType,
+    pub status: KeyStatus,
+    pub created_at: Option<DateTime<Utc>>,
+    pub retired_at: Option<DateTime<Utc>>,
+}
+
+#[derive(sqlx::FromRow)]
+pub struct UserKeyDetails {
+    pub id: uuid::Uuid,
+    pub key_type: KeyType,
+    pub status: KeyStatus,
+    pub file_count: i64,
+    pub created_at: Option<DateTime<Utc>>,
+    pub retired_at: Option<DateTime<Utc>>,
+}
+
+/// A user's private key wrapped for the escrow key, encrypted like a file.
+#[derive(sqlx::FromRow)]
+pub struct KeyEscrow {
+    pub key_id: uuid::Uuid,
+    pub encrypted_key: Vec<u8>,
+    pub encrypted_private_key: Vec<u8>,
+    pub iv: Option<Vec<u8>>,
+}
+
+#[derive(sqlx::FromRow)]
+pub struct KeyInventory {
+    pub id: uuid::Uuid,
+    pub escrowed: bool,
+}
+
+/// Where a new file's ciphertext is stored.
+pub enum FileContent {
+    /// In the file row itself.
+    Inline(Vec<u8>),
+    /// In a new blob that later identical uploads of the sender can share.
+    /// Stored inline instead if a blob with the tag appeared in the meantime.
+    NewBlob { dedup_tag: Vec<u8>, encrypted_file: Vec<u8> },
+    /// In an existing blob.
+    Blob(uuid::Uuid),
+}
+
+impl Default for FileContent {
+    fn default() -> Self {
+        FileContent::Inline(Vec::new())
+    }
+}
+
+/// Bytes of storage the sender and the organization a share is made through
+/// may use in total. `None` is unlimited.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct StorageLimits {
+    pub user: Option<i64>,
+    pub organization: Option<i64>,
+}
+
+/// A new file and the share that delivers it. Optional fields left at their
+/// default are stored as NULL.
+#[derive(Default)]
+pub struct NewFile {
+    pub user_id: uuid::Uuid,
+    pub file_name: Option<String>,
+    pub file_size: i64,
+    pub recipient_user_id: uuid::Uuid,
+    pub password: String,
+    pub expiration_date: DateTime<Utc>,
+    pub encrypted_aes_key: Vec<u8>,
+    pub content: FileContent,
+    pub iv: Option<Vec<u8>>,
+    pub encrypted_digest: Option<Vec<u8>>,
+    pub encrypted_metadata: Option<Vec<u8>>,
+    pub signature: Option<Vec<u8>>,
+    pub signer_public_key: Option<String>,
+    pub key_id: Option<uuid::Uuid>,
+    pub sender_encrypted_aes_key: Option<Vec<u8>>,
+    pub sender_key_id: Option<uuid::Uuid>,
+    pub status: ShareStatus,
+    pub organization_id: Option<uuid::Uuid>,
+    pub folder_id: Option<uuid::Uuid>,
+    pub folder_share_id: Option<uuid::Uuid>,
+    /// Set on folder-share copies, the file they were copied from
+    pub source_file_id: Option<uuid::Uuid>,
+    pub transfer_id: Option<uuid::Uuid>,
+    pub scan_status: Option<ScanStatus>,
+    pub scan_signature: Option<String>,
+    /// Checked against the stored files in the same transaction as the insert
+    pub limits: StorageLimits,
+}
+
+/// A sender's stored blob, with the file key as wrapped for the sender by
+/// one of the files using it.
+#[derive(sqlx::FromRow)]
+pub struct FileBlob {
+    pub id: uuid::Uuid,
+    pub sender_key_id: uuid::Uuid,
+    pub sender_encrypted_aes_key: Vec<u8>,
+    pub encrypted_metadata: Vec<u8>,
+}
+
+/// A file still wrapped with a rotated key, and the key it should move to.
+/// `sender` marks the sender's copy of the file key.
+#[derive(sqlx::FromRow)]
+pub struct RewrapFile {
+    pub id: uuid::Uuid,
+    pub sender: bool,
+    pub key_id: uuid::Uuid,
+    pub encrypted_aes_key: Vec<u8>,
+    pub new_key_id: uuid::Uuid,
+    pub new_public_key: String,
+}
+
+/// Bytes a user has stored, as the sum of `file_size` over the files they
+/// sent, and the quota an admin set for them, if any.
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct StorageUsage {
+    pub used: i64,
+    pub quota_bytes: Option<i64>,
+}
+
+/// Bytes stored by all members of an organization the user belongs to.
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct OrganizationStorageUsage {
+    pub organization_id: uuid::Uuid,
+    pub name: String,
+    pub used: i64,
+    pub quota_bytes: Option<i64>,
+}
+
+/// An upload held back because malware was found in it, with the file key
+/// as wrapped for its sender.
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct QuarantinedFile {
+    pub file_id: uuid::Uuid,
+    pub sender_id: Option<uuid::Uuid>,
+    pub sender_email: Option<String>,
+    pub file_name: String,
+    pub file_size: i64,
+    pub key_id: Option<uuid::Uuid>,
+    pub encrypted_aes_key: Option<Vec<u8>>,
+    pub encrypted_metadata: Option<Vec<u8>>,
+    pub scan_signature: Option<String>,
+    pub expiration_date: Option<DateTime<Utc>>,
+    pub created_at: Option<DateTime<Utc>>,
+}
@@ -1,30 +1,88 @@
 use std::sync::Arc;
 
-use axum::{middleware, Extension, Router};
+use axum::{http::Method, middleware, Extension, Router};
 use tower_http::trace::TraceLayer;
 
-use crate::{handler::{auth::auth_handler, file::file_handle, file_query::get_file_list_handler, user::users_handler}, middleware::auth, AppState};
+use crate::{handler::{admin::admin_handler, auth::auth_handler, contact::contacts_handler, file::file_handle, file_query::get_file_list_handler, folder::folders_handler, organization::organizations_handler, share::shares_handler, upload::uploads_handler, user::users_handler}, middleware::{admin, auth, rate_limit, tus_resumable, RateLimitPolicy, RateLimiter}, AppState};
 
 pub fn create_router(app_state: Arc<AppState>) -> Router {
+    let rate_limiter = RateLimiter::new(app_state.rate_limit_store.clone())
+        .route(Method::POST, "/api/file/upload", RateLimitPolicy::new("upload", 10, 10))
+        .route(Method::POST, "/api/file/retrieve", RateLimitPolicy::new("retrieve", 30, 30))
+        .route(Method::POST, "/api/file/bundle", RateLimitPolicy::new("bundle", 5, 5))
+        .route(Method::POST, "/api/file/export", RateLimitPolicy::new("export", 30, 30))
+        .route(Method::POST, "/api/file/import", RateLimitPolicy::new("import", 10, 10))
+        .route(Method::GET, "/api/users/search-emails", RateLimitPolicy::new("search-emails", 20, 20))
+        .fallback(RateLimitPolicy::new(
+            "default",
+            app_state.env.rate_limit_burst,
+            app_state.env.rate_limit_per_minute
+        ));
+
     let api_route = Router::new()
-        .nest("/auth", auth_handler())
         .nest(
-            "/users", 
+            "/auth",
+            auth_handler()
+                .layer(middleware::from_fn_with_state(rate_limiter.clone(), rate_limit))
+        )
+        .nest(
+            "/users",
             users_handler()
+                .layer(middleware::from_fn_with_state(rate_limiter.clone(), rate_limit))
                 .layer(middleware::from_fn(auth))
         )
         .nest(
             "/file",
-            file_handle()
-            .layer(middleware::from_fn(auth)) 
+            file_handle(&app_state)
+            .layer(middleware::from_fn_with_state(rate_limiter.clone(), rate_limit))
+            .layer(middleware::from_fn(auth))
         )
         .nest(
             "/list",
             get_file_list_handler()
-            .layer(middleware::from_fn(auth)) 
+            .layer(middleware::from_fn_with_state(rate_limiter.clone(), rate_limit))
+            .layer(middleware::from_fn(auth))
+        )
+        .nest(
+            "/contacts",
+            contacts_handler()
+            .layer(middleware::from_fn_with_state(rate_limiter.clone(), rate_limit))
+            .layer(middleware::from_fn(auth))
+        )
+        .nest(
+            "/shares",
+            shares_handler()
+            .layer(middleware::from_fn_with_state(rate_limiter.clone(), rate_limit))
+            .layer(middleware::from_fn(auth))
+        )
+        .nest(
+            "/organizations",
+            organizations_handler()
+            .layer(middleware::from_fn_with_state(rate_limiter.clone(), rate_limit))
+            .layer(middleware::from_fn(auth))
+        )
+        .nest(
+            "/folders",
+            folders_handler()
+            .layer(middleware::from_fn_with_state(rate_limiter.clone(), rate_limit))
+            .layer(middleware::from_fn(auth))
+        )
+        .nest(
+            "/uploads",
+            uploads_handler()
+            .layer(middleware::from_fn_with_state(rate_limiter.clone(), rate_limit))
+            .layer(middleware::from_fn(auth))
+            .layer(middleware::from_fn(tus_resumable))
+        )
+        .nest(
+            "/admin",
+            admin_handler()
+            .layer(middleware::from_fn(admin))
+            .layer(middleware::from_fn_with_state(rate_limiter.clone(), rate_limit))
+            .layer(middleware::from_fn(auth))
         )
         .layer(TraceLayer::new_for_http())
         .layer(Extension(app_state));
 
     Router::new().nest("/api", api_route)
-}
\ No newline at end of file
+}
@@ -0,0 +1,175 @@
+use std::io::Read;
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::HttpError;
+
+/// How a file's content was compressed before it was encrypted, recorded in
+/// its container metadata. `size` is the length of the compressed content at
+/// the start of the payload, ahead of any padding.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "algorithm", rename_all = "lowercase")]
+pub enum Compression {
+    Zstd { size: u64 },
+}
+
+/// Whether uploads are compressed before encryption.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CompressionMode {
+    None,
+    Zstd,
+}
+
+impl std::str::FromStr for CompressionMode {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "none" => Ok(CompressionMode::None),
+            "zstd" => Ok(CompressionMode::Zstd),
+            other => Err(format!("Unknown compression {}", other)),
+        }
+    }
+}
+
+/// MIME types whose content is compressed already. Office documents and
+/// EPUBs are ZIP archives.
+const COMPRESSED_TYPES: &[&str] = &[
+    "application/zip",
+    "application/gzip",
+    "application/x-gzip",
+    "application/x-bzip2",
+    "application/x-xz",
+    "application/x-7z-compressed",
+    "application/vnd.rar",
+    "application/x-rar-compressed",
+    "application/zstd",
+    "application/pdf",
+    "application/epub+zip",
+];
+
+const COMPRESSED_TYPE_PREFIXES: &[&str] = &[
+    "image/",
+    "audio/",
+    "video/",
+    "font/woff",
+    "application/vnd.openxmlformats-officedocument.",
+    "application/vnd.oasis.opendocument.",
+];
+
+/// Formats under a compressed prefix that are stored uncompressed.
+const UNCOMPRESSED_TYPES: &[&str] = &["image/svg+xml", "image/bmp", "audio/wav", "audio/x-wav"];
+
+/// Whether content of this MIME type is worth compressing. Unknown types are
+/// tried, `compress` keeps the original when nothing is saved.
+pub fn is_compressible(content_type: Option<&str>) -> bool {
+    let Some(content_type) = content_type else {
+        return true;
+    };
+
+    let essence = content_type.split(';').next().unwrap_or_default().trim().to_ascii_lowercase();
+
+    if UNCOMPRESSED_TYPES.contains(&essence.as_str()) {
+        return true;
+    }
+
+    !COMPRESSED_TYPES.contains(&essence.as_str())
+        && !COMPRESSED_TYPE_PREFIXES.iter().any(|prefix| essence.starts_with(prefix))
+}
+
+/// Compresses `data` with zstd at `level`. Returns `None` when the result
+/// would not be smaller.
+pub fn compress(data: &[u8], level: i32) -> Result<Option<(Vec<u8>, Compression)>, HttpError> {
+    let compressed = zstd::bulk::compress(data, level)
+        .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+    if compressed.len() >= data.len() {
+        return Ok(None);
+    }
+
+    let compression = Compression::Zstd { size: compressed.len() as u64 };
+
+    Ok(Some((compressed, compression)))
+}
+
+/// Undoes `compress` on a decrypted payload, which may carry padding after
+/// the compressed content. `size` is the original length, as claimed by the
+/// metadata: the output grows as it is decoded and decoding stops one byte
+/// past it, so a frame that expands further costs no more than that.
+pub fn decompress(compression: Compression, payload: &[u8], size: u64) -> Result<Vec<u8>, HttpError> {
+    match compression {
+        Compression::Zstd { size: compressed_size } => {
+            let compressed = payload
+                .get(..compressed_size as usize)
+                .ok_or_else(|| HttpError::server_error("File integrity check failed"))?;
+
+            let mut decompressed = Vec::new();
+
+            zstd::stream::read::Decoder::with_buffer(compressed)
+                .map_err(|e| HttpError::server_error(e.to_string()))?
+                .take(size.saturating_add(1))
+                .read_to_end(&mut decompressed)
+                .map_err(|_| HttpError::server_error("File integrity check failed"))?;
+
+            if decompressed.len() as u64 != size {
+                return Err(HttpError::server_error("File integrity check failed"));
+            }
+
+            Ok(decompressed)
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(length: usize) -> Vec<u8> {
+        b"all work and no play makes jack a dull boy\n".iter().copied().cycle().take(length).collect()
+    }
+
+    #[test]
+    fn round_trips_with_padding_after_the_frame() {
+        let data = text(100_000);
+        let (compressed, compression) = compress(&data, 3).unwrap().unwrap();
+
+        assert_eq!(compression, Compression::Zstd { size: compressed.len() as u64 });
+        assert!(compressed.len() < data.len());
+
+        let mut payload = compressed;
+        payload.resize(payload.len() + 500, 0);
+
+        assert_eq!(decompress(compression, &payload, data.len() as u64).unwrap(), data);
+    }
+
+    #[test]
+    fn keeps_incompressible_data() {
+        let mut data = vec![0u8; 4096];
+        rand::Rng::fill(&mut rand::thread_rng(), &mut data[..]);
+
+        assert!(compress(&data, 3).unwrap().is_none());
+        assert!(compress(b"", 3).unwrap().is_none());
+    }
+
+    #[test]
+    fn rejects_output_longer_than_the_claimed_size() {
+        // A small frame that expands to 64 MiB, claimed to be tiny
+        let data = vec![0u8; 64 * 1024 * 1024];
+        let (compressed, compression) = compress(&data, 3).unwrap().unwrap();
+
+        assert!(decompress(compression, &compressed, 10).is_err());
+        assert!(decompress(compression, &compressed, data.len() as u64 - 1).is_err());
+        assert!(decompress(compression, &compressed, data.len() as u64 + 1).is_err());
+    }
+
+    #[test]
+    fn rejects_truncated_frames() {
+        let data = text(100_000);
+        let (compressed, _) = compress(&data, 3).unwrap().unwrap();
+        let truncated = Compression::Zstd { size: compressed.len() as u64 - 1 };
+        let overlong = Compression::Zstd { size: compressed.len() as u64 + 1 };
+
+        assert!(decompress(truncated, &compressed, data.len() as u64).is_err());
+        assert!(decompress(overlong, &compressed, data.len() as u64).is_err());
+    }
+}
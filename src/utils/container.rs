@@ -0,0 +1,721 @@
+//! The SecureShare encrypted file container (`.ssef`), version 1.
+//!
+//! A container holds everything needed to decrypt a file except a private
+//! key. All integers are big-endian.
+//!
+//! ```text
+//! offset  size  field
+//! 0       4     magic "SSEF"
+//! 4       1     version (1)
+//! 5       4     header length H, the bytes from offset 9 up to the first chunk
+//! 9       1     cipher id (1 = AES-256-GCM in the STREAM construction)
+//! 10      4     chunk size C, plaintext bytes per chunk
+//! 14      8     payload size P, the (compressed) file plus any size padding
+//! 22      16    salt
+//! 38      1     recipient count N, then N recipient stanzas:
+//!                 1   key type (1 = RSA-2048 PKCS#1 v1.5, 2 = X25519 age stanza)
+//!                 16  key id (all zero when the key has none)
+//!                 2   wrapped key length L
+//!                 L   file key wrapped for that key
+//!         4     metadata length M
+//!         M     metadata, JSON sealed with AES-256-GCM under the zero nonce
+//!         32    header MAC, HMAC-SHA256 over the header without the stanzas
+//! 9 + H         chunks: C payload bytes each (the last may be shorter, or
+//!               empty for an empty payload) plus a 16 byte tag
+//! ```
+//!
+//! The metadata holds the file name, MIME type, size and SHA-256 of the file,
+//! and how it was compressed if it was. The payload is the file, or its
+//! zstd frame, followed by zeros when the size is padded, so neither the
+//! header nor the container length gives the exact size away.
+//!
+//! The payload, metadata and MAC keys are derived from the 32 byte file key
+//! with HKDF-SHA256 salted with the salt. Chunk `i` is sealed under the nonce
+//! `i` as 11 bytes || 1 on the last chunk, 0 otherwise, so chunks cannot be
+//! reordered, dropped or truncated. Stanzas are left out of the MAC so a file
+//! can be re-wrapped for another key without touching the rest of it; a
+//! forged stanza only yields a wrong file key, which the MAC rejects.
+
+use aes_gcm::{aead::{Aead, Payload}, Aes256Gcm, KeyInit, Nonce};
+use hkdf::Hkdf;
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use uuid::Uuid;
+
+use crate::{error::HttpError, models::KeyType, utils::{compression::{decompress, Compression}, digest::sha256}};
+
+pub const MAGIC: &[u8; 4] = b"SSEF";
+pub const VERSION: u8 = 1;
+pub const CIPHER_AES_256_GCM_STREAM: u8 = 1;
+pub const DEFAULT_CHUNK_SIZE: u32 = 64 * 1024;
+
+/// Magic, version and header length.
+pub const PREFIX_SIZE: usize = 9;
+pub const TAG_SIZE: usize = 16;
+
+const SALT_SIZE: usize = 16;
+const MAC_SIZE: usize = 32;
+const MAX_CHUNK_SIZE: u32 = 16 * 1024 * 1024;
+
+const PAYLOAD_INFO: &[u8] = b"ssef/v1 payload";
+const METADATA_INFO: &[u8] = b"ssef/v1 metadata";
+const MAC_INFO: &[u8] = b"ssef/v1 header";
+
+/// A copy of the file key wrapped for one recipient key.
+#[derive(Debug, Clone)]
+pub struct Stanza {
+    pub key_type: KeyType,
+    pub key_id: Uuid,
+    pub wrapped_key: Vec<u8>,
+}
+
+/// What is known about the plaintext, readable only with the file key.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Metadata {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content_type: Option<String>,
+    pub size: u64,
+    /// Hex SHA-256 of the plaintext.
+    pub sha256: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub compression: Option<Compression>,
+}
+
+impl Metadata {
+    pub fn new(name: String, content_type: Option<String>, size: usize, digest: &[u8]) -> Self {
+        Metadata {
+            name,
+            content_type,
+            size: size as u64,
+            sha256: hex(digest),
+            compression: None,
+        }
+    }
+}
+
+/// How far a file's stored size is rounded up to hide its exact size.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SizePadding {
+    None,
+    /// Padmé: at most 12% overhead, and only O(log log n) bits of the size are
+    /// left to tell files apart.
+    Padme,
+}
+
+impl SizePadding {
+    pub fn padded_size(self, size: u64) -> u64 {
+        match self {
+            SizePadding::None => size,
+            SizePadding::Padme if size < 2 => size,
+            SizePadding::Padme => {
+                let exponent = 63 - size.leading_zeros() as u64;
+                let exponent_bits = 64 - exponent.leading_zeros() as u64;
+                let mask = (1u64 << (exponent - exponent_bits)) - 1;
+
+                (size + mask) & !mask
+            },
+        }
+    }
+}
+
+impl std::str::FromStr for SizePadding {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "none" => Ok(SizePadding::None),
+            "padme" => Ok(SizePadding::Padme),
+            other => Err(format!("Unknown size padding {}", other)),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Header {
+    pub cipher: u8,
+    pub chunk_size: u32,
+    pub payload_size: u64,
+    pub salt: [u8; SALT_SIZE],
+    pub stanzas: Vec<Stanza>,
+    pub sealed_metadata: Vec<u8>,
+    pub mac: [u8; MAC_SIZE],
+}
+
+fn malformed() -> HttpError {
+    HttpError::bad_request("The file is not a valid SSEF container")
+}
+
+fn key_type_id(key_type: KeyType) -> u8 {
+    match key_type {
+        KeyType::Rsa => 1,
+        KeyType::X25519 => 2,
+    }
+}
+
+fn key_type_from_id(id: u8) -> Result<KeyType, HttpError> {
+    match id {
+        1 => Ok(KeyType::Rsa),
+        2 => Ok(KeyType::X25519),
+        _ => Err(malformed()),
+    }
+}
+
+struct Keys {
+    payload: [u8; 32],
+    metadata: [u8; 32],
+    mac: [u8; 32],
+}
+
+impl Keys {
+    fn derive(file_key: &[u8], salt: &[u8]) -> Result<Self, HttpError> {
+        let hkdf = Hkdf::<Sha256>::new(Some(salt), file_key);
+        let mut keys = Keys { payload: [0; 32], metadata: [0; 32], mac: [0; 32] };
+
+        for (info, key) in [(PAYLOAD_INFO, &mut keys.payload), (METADATA_INFO, &mut keys.metadata), (MAC_INFO, &mut keys.mac)] {
+            hkdf.expand(info, key)
+                .map_err(|e| HttpError::server_error(e.to_string()))?;
+        }
+
+        Ok(keys)
+    }
+}
+
+fn chunk_nonce(index: u64, last: bool) -> [u8; 12] {
+    let mut nonce = [0u8; 12];
+    nonce[3..11].copy_from_slice(&index.to_be_bytes());
+    nonce[11] = last as u8;
+    nonce
+}
+
+fn cipher(key: &[u8; 32]) -> Result<Aes256Gcm, HttpError> {
+    Aes256Gcm::new_from_slice(key)
+        .map_err(|e| HttpError::server_error(e.to_string()))
+}
+
+/// Encrypts `file_data` into a container for the given stanzas, which must
+/// all wrap `file_key`, padding the payload as asked. `file_data` is already
+/// compressed when the metadata says so.
+pub fn seal(
+    file_data: &[u8],
+    metadata: &Metadata,
+    file_key: &[u8],
+    stanzas: Vec<Stanza>,
+    padding: SizePadding,
+) -> Result<Vec<u8>, HttpError> {
+    let mut salt = [0u8; SALT_SIZE];
+    rand::Rng::fill(&mut rand::thread_rng(), &mut salt);
+
+    let keys = Keys::derive(file_key, &salt)?;
+    let sealed_metadata = seal_metadata(&keys, metadata)?;
+
+    let mut header = Header {
+        cipher: CIPHER_AES_256_GCM_STREAM,
+        chunk_size: DEFAULT_CHUNK_SIZE,
+        payload_size: padding.padded_size(file_data.len() as u64),
+        salt,
+        stanzas,
+        sealed_metadata,
+        mac: [0; MAC_SIZE],
+    };
+    header.mac = header.compute_mac(&keys.mac)?;
+
+    let mut container = header.to_bytes()?;
+    let payload_size = header.payload_size as usize;
+    container.reserve(payload_size + (header.chunk_count() as usize) * TAG_SIZE);
+
+    let payload = cipher(&keys.payload)?;
+    let chunk_count = header.chunk_count();
+
+    for index in 0..chunk_count {
+        let start = (index * header.chunk_size as u64) as usize;
+        let end = (start + header.chunk_size as usize).min(payload_size);
+
+        // Only the chunks that reach into the padding are copied
+        let chunk = if end <= file_data.len() {
+            file_data[start..end].into()
+        } else {
+            let mut chunk = file_data.get(start..).unwrap_or_default().to_vec();
+            chunk.resize(end - start, 0);
+            std::borrow::Cow::Owned(chunk)
+        };
+
+        let sealed = payload
+            .encrypt(Nonce::from_slice(&chunk_nonce(index, index + 1 == chunk_count)), chunk.as_ref())
+            .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+        container.extend(sealed);
+    }
+
+    Ok(container)
+}
+
+/// Decrypts (and decompresses) a whole container and checks the plaintext
+/// against the digest in its metadata.
+pub fn open(container: &[u8], file_key: &[u8]) -> Result<(Metadata, Vec<u8>, Vec<u8>), HttpError> {
+    let (header, data_offset) = Header::parse(container)?;
+    let opener = header.opener(file_key)?;
+    let metadata = opener.metadata(&header)?;
+
+    let mut payload = opener.open_chunks(&header, 0, &container[data_offset..])?;
+
+    if payload.len() as u64 != header.payload_size {
+        return Err(HttpError::server_error("File integrity check failed"));
+    }
+
+    let plaintext = match metadata.compression {
+        Some(compression) => decompress(compression, &payload, metadata.size)?,
+        None if metadata.size <= header.payload_size => {
+            payload.truncate(metadata.size as usize);
+            payload
+        },
+        None => return Err(HttpError::server_error("File integrity check failed")),
+    };
+
+    let digest = sha256(&plaintext);
+
+    if hex(&digest) != metadata.sha256 {
+        return Err(HttpError::server_error("File integrity check failed"));
+    }
+
+    Ok((metadata, plaintext, digest))
+}
+
+/// Seals metadata to be stored next to a container, so it can be read
+/// without fetching the header: salt || sealed metadata. The salt is fresh,
+/// so files sharing one container and file key can each have their own.
+pub fn seal_detached_metadata(file_key: &[u8], metadata: &Metadata) -> Result<Vec<u8>, HttpError> {
+    let mut salt = [0u8; SALT_SIZE];
+    rand::Rng::fill(&mut rand::thread_rng(), &mut salt);
+
+    let mut detached = salt.to_vec();
+    detached.extend(seal_metadata(&Keys::derive(file_key, &salt)?, metadata)?);
+
+    Ok(detached)
+}
+
+/// Reverses `seal_detached_metadata`.
+pub fn open_detached_metadata(file_key: &[u8], detached: &[u8]) -> Result<Metadata, HttpError> {
+    if detached.len() <= SALT_SIZE {
+        return Err(HttpError::server_error("Stored metadata is malformed"));
+    }
+
+    let (salt, sealed_metadata) = detached.split_at(SALT_SIZE);
+
+    open_metadata(&Keys::derive(file_key, salt)?, sealed_metadata)
+}
+
+// Every salt gives a new metadata key, so the fixed nonce is never reused
+fn seal_metadata(keys: &Keys, metadata: &Metadata) -> Result<Vec<u8>, HttpError> {
+    let metadata = serde_json::to_vec(metadata)
+        .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+    cipher(&keys.metadata)?
+        .encrypt(Nonce::from_slice(&[0u8; 12]), metadata.as_slice())
+        .map_err(|e| HttpError::server_error(e.to_string()))
+}
+
+fn open_metadata(keys: &Keys, sealed_metadata: &[u8]) -> Result<Metadata, HttpError> {
+    let metadata = cipher(&keys.metadata)?
+        .decrypt(Nonce::from_slice(&[0u8; 12]), sealed_metadata)
+        .map_err(|_| HttpError::server_error("File metadata could not be decrypted"))?;
+
+    serde_json::from_slice(&metadata)
+        .map_err(|e| HttpError::server_error(e.to_string()))
+}
+
+pub fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// The keys of one container, checked against its header MAC.
+pub struct Opener {
+    keys: Keys,
+}
+
+impl Opener {
+    pub fn metadata(&self, header: &Header) -> Result<Metadata, HttpError> {
+        open_metadata(&self.keys, &header.sealed_metadata)
+    }
+
+    /// Decrypts consecutive sealed chunks starting at chunk `first_chunk`.
+    /// The last chunk of `sealed` may be the container's final chunk.
+    pub fn open_chunks(&self, header: &Header, first_chunk: u64, sealed: &[u8]) -> Result<Vec<u8>, HttpError> {
+        let payload = cipher(&self.keys.payload)?;
+        let chunk_count = header.chunk_count();
+        let sealed_chunk_size = header.chunk_size as usize + TAG_SIZE;
+
+        // An empty file still has its final, empty chunk
+        if sealed.is_empty() {
+            return Err(HttpError::server_error("File integrity check failed"));
+        }
+
+        let mut plaintext = Vec::with_capacity(sealed.len());
+
+        for (offset, chunk) in sealed.chunks(sealed_chunk_size).enumerate() {
+            let index = first_chunk + offset as u64;
+
+            if index >= chunk_count {
+                return Err(HttpError::server_error("The file has more data than its header describes"));
+            }
+
+            let opened = payload
+                .decrypt(
+                    Nonce::from_slice(&chunk_nonce(index, index + 1 == chunk_count)),
+                    Payload { msg: chunk, aad: &[] },
+                )
+                .map_err(|_| HttpError::server_error("File integrity check failed"))?;
+
+            plaintext.extend(opened);
+        }
+
+        Ok(plaintext)
+    }
+}
+
+impl Header {
+    /// Reads a header from the start of `bytes`, which may continue with the
+    /// chunks. Returns the header and the offset of the first chunk.
+    pub fn parse(bytes: &[u8]) -> Result<(Header, usize), HttpError> {
+        let header_len = Header::header_len(bytes)?;
+        let end = PREFIX_SIZE.checked_add(header_len).ok_or_else(malformed)?;
+        let mut reader = Reader { bytes: bytes.get(PREFIX_SIZE..end).ok_or_else(malformed)? };
+
+        let cipher = reader.u8()?;
+        if cipher != CIPHER_AES_256_GCM_STREAM {
+            return Err(HttpError::bad_request(format!("Unsupported container cipher {}", cipher)));
+        }
+
+        let chunk_size = reader.u32()?;
+        if chunk_size == 0 || chunk_size > MAX_CHUNK_SIZE {
+            return Err(malformed());
+        }
+
+        let payload_size = reader.u64()?;
+        let salt = reader.array::<SALT_SIZE>()?;
+
+        let stanza_count = reader.u8()?;
+        let mut stanzas = Vec::with_capacity(stanza_count as usize);
+        for _ in 0..stanza_count {
+            let key_type = key_type_from_id(reader.u8()?)?;
+            let key_id = Uuid::from_bytes(reader.array::<16>()?);
+            let length = reader.u16()? as usize;
+            let wrapped_key = reader.take(length)?.to_vec();
+
+            stanzas.push(Stanza { key_type, key_id, wrapped_key });
+        }
+
+        let metadata_len = reader.u32()? as usize;
+        let sealed_metadata = reader.take(metadata_len)?.to_vec();
+        let mac = reader.array::<MAC_SIZE>()?;
+
+        if !reader.bytes.is_empty() {
+            return Err(malformed());
+        }
+
+        let header = Header { cipher, chunk_size, payload_size, salt, stanzas, sealed_metadata, mac };
+
+        Ok((header, end))
+    }
+
+    /// Reads the header length from the fixed prefix, checking the magic and
+    /// version on the way.
+    pub fn header_len(prefix: &[u8]) -> Result<usize, HttpError> {
+        let prefix = prefix.get(..PREFIX_SIZE).ok_or_else(malformed)?;
+
+        if &prefix[..4] != MAGIC {
+            return Err(malformed());
+        }
+
+        if prefix[4] != VERSION {
+            return Err(HttpError::bad_request(format!("Unsupported container version {}", prefix[4])));
+        }
+
+        Ok(u32::from_be_bytes([prefix[5], prefix[6], prefix[7], prefix[8]]) as usize)
+    }
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>, HttpError> {
+        if self.stanzas.len() > u8::MAX as usize {
+            return Err(HttpError::server_error("Too many recipients for one container"));
+        }
+
+        let mut body = Vec::new();
+        body.push(self.cipher);
+        body.extend(self.chunk_size.to_be_bytes());
+        body.extend(self.payload_size.to_be_bytes());
+        body.extend(self.salt);
+
+        body.push(self.stanzas.len() as u8);
+        for stanza in &self.stanzas {
+            let length = u16::try_from(stanza.wrapped_key.len())
+                .map_err(|_| HttpError::server_error("Wrapped key is too long"))?;
+
+            body.push(key_type_id(stanza.key_type));
+            body.extend(stanza.key_id.as_bytes());
+            body.extend(length.to_be_bytes());
+            body.extend(&stanza.wrapped_key);
+        }
+
+        body.extend((self.sealed_metadata.len() as u32).to_be_bytes());
+        body.extend(&self.sealed_metadata);
+        body.extend(self.mac);
+
+        let mut bytes = Vec::with_capacity(PREFIX_SIZE + body.len());
+        bytes.extend(MAGIC);
+        bytes.push(VERSION);
+        bytes.extend((body.len() as u32).to_be_bytes());
+        bytes.extend(body);
+
+        Ok(bytes)
+    }
+
+    pub fn chunk_count(&self) -> u64 {
+        self.payload_size.div_ceil(self.chunk_size as u64).max(1)
+    }
+
+    /// Derives the container keys from `file_key` and checks them against
+    /// the header MAC.
+    pub fn opener(&self, file_key: &[u8]) -> Result<Opener, HttpError> {
+        let keys = Keys::derive(file_key, &self.salt)?;
+
+        let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(&keys.mac)
+            .map_err(|e| HttpError::server_error(e.to_string()))?;
+        mac.update(&self.authenticated_bytes());
+
+        mac.verify_slice(&self.mac)
+            .map_err(|_| HttpError::server_error("The file key does not match this file"))?;
+
+        Ok(Opener { keys })
+    }
+
+    fn compute_mac(&self, mac_key: &[u8]) -> Result<[u8; MAC_SIZE], HttpError> {
+        let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(mac_key)
+            .map_err(|e| HttpError::server_error(e.to_string()))?;
+        mac.update(&self.authenticated_bytes());
+
+        Ok(mac.finalize().into_bytes().into())
+    }
+
+    fn authenticated_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        bytes.extend(MAGIC);
+        bytes.push(VERSION);
+        bytes.push(self.cipher);
+        bytes.extend(self.chunk_size.to_be_bytes());
+        bytes.extend(self.payload_size.to_be_bytes());
+        bytes.extend(self.salt);
+        bytes.extend((self.sealed_metadata.len() as u32).to_be_bytes());
+        bytes.extend(&self.sealed_metadata);
+        bytes
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, length: usize) -> Result<&'a [u8], HttpError> {
+        if self.bytes.len() < length {
+            return Err(malformed());
+        }
+
+        let (taken, rest) = self.bytes.split_at(length);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], HttpError> {
+        let mut array = [0u8; N];
+        array.copy_from_slice(self.take(N)?);
+        Ok(array)
+    }
+
+    fn u8(&mut self) -> Result<u8, HttpError> {
+        Ok(self.array::<1>()?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, HttpError> {
+        Ok(u16::from_be_bytes(self.array()?))
+    }
+
+    fn u32(&mut self) -> Result<u32, HttpError> {
+        Ok(u32::from_be_bytes(self.array()?))
+    }
+
+    fn u64(&mut self) -> Result<u64, HttpError> {
+        Ok(u64::from_be_bytes(self.array()?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FILE_KEY: [u8; 32] = [7; 32];
+
+    fn stanza(wrapped_key: &[u8]) -> Stanza {
+        Stanza { key_type: KeyType::X25519, key_id: Uuid::new_v4(), wrapped_key: wrapped_key.to_vec() }
+    }
+
+    fn seal_file(file_data: &[u8], file_key: &[u8], padding: SizePadding) -> Vec<u8> {
+        let metadata = Metadata::new("report.pdf".to_string(), None, file_data.len(), &sha256(file_data));
+
+        seal(file_data, &metadata, file_key, vec![stanza(b"wrapped")], padding).unwrap()
+    }
+
+    /// Three chunks, the last one short.
+    fn three_chunk_file() -> Vec<u8> {
+        (0..2 * DEFAULT_CHUNK_SIZE as usize + 100).map(|i| i as u8).collect()
+    }
+
+    fn sealed_chunks(container: &[u8]) -> (usize, Vec<Vec<u8>>) {
+        let (_, data_offset) = Header::parse(container).unwrap();
+        let chunks = container[data_offset..]
+            .chunks(DEFAULT_CHUNK_SIZE as usize + TAG_SIZE)
+            .map(|chunk| chunk.to_vec())
+            .collect();
+
+        (data_offset, chunks)
+    }
+
+    #[test]
+    fn header_round_trips() {
+        let container = seal_file(b"hello", &FILE_KEY, SizePadding::None);
+        let (header, data_offset) = Header::parse(&container).unwrap();
+
+        assert_eq!(header.cipher, CIPHER_AES_256_GCM_STREAM);
+        assert_eq!(header.chunk_size, DEFAULT_CHUNK_SIZE);
+        assert_eq!(header.payload_size, 5);
+        assert_eq!(header.stanzas.len(), 1);
+        assert_eq!(header.stanzas[0].wrapped_key, b"wrapped");
+        assert_eq!(Header::header_len(&container).unwrap() + PREFIX_SIZE, data_offset);
+        assert_eq!(header.to_bytes().unwrap(), container[..data_offset]);
+        assert_eq!(container.len(), data_offset + 5 + TAG_SIZE);
+
+        let (metadata, plaintext, digest) = open(&container, &FILE_KEY).unwrap();
+        assert_eq!(metadata.name, "report.pdf");
+        assert_eq!(metadata.size, 5);
+        assert_eq!(plaintext, b"hello");
+        assert_eq!(digest, sha256(b"hello"));
+    }
+
+    #[test]
+    fn rejects_bad_prefixes() {
+        let container = seal_file(b"hello", &FILE_KEY, SizePadding::None);
+
+        assert!(Header::parse(&container[..PREFIX_SIZE - 1]).is_err());
+        assert!(Header::parse(&container[..PREFIX_SIZE + 10]).is_err());
+
+        let mut wrong_magic = container.clone();
+        wrong_magic[0] = b'X';
+        assert!(Header::parse(&wrong_magic).is_err());
+
+        let mut wrong_version = container;
+        wrong_version[4] = VERSION + 1;
+        assert!(Header::parse(&wrong_version).is_err());
+    }
+
+    #[test]
+    fn opens_multi_chunk_and_padded_files() {
+        let file_data = three_chunk_file();
+
+        let container = seal_file(&file_data, &FILE_KEY, SizePadding::None);
+        assert_eq!(sealed_chunks(&container).1.len(), 3);
+        assert_eq!(open(&container, &FILE_KEY).unwrap().1, file_data);
+
+        let container = seal_file(&file_data, &FILE_KEY, SizePadding::Padme);
+        let (header, _) = Header::parse(&container).unwrap();
+        assert_eq!(header.payload_size, SizePadding::Padme.padded_size(file_data.len() as u64));
+        assert_eq!(open(&container, &FILE_KEY).unwrap().1, file_data);
+    }
+
+    #[test]
+    fn rejects_truncated_containers() {
+        let container = seal_file(&three_chunk_file(), &FILE_KEY, SizePadding::None);
+        let (data_offset, chunks) = sealed_chunks(&container);
+
+        // Cut inside the last chunk, on a chunk boundary, and before any chunk
+        assert!(open(&container[..container.len() - 1], &FILE_KEY).is_err());
+        assert!(open(&container[..container.len() - chunks[2].len()], &FILE_KEY).is_err());
+        assert!(open(&container[..data_offset], &FILE_KEY).is_err());
+    }
+
+    #[test]
+    fn rejects_reordered_and_dropped_chunks() {
+        let container = seal_file(&three_chunk_file(), &FILE_KEY, SizePadding::None);
+        let (data_offset, chunks) = sealed_chunks(&container);
+        let header = &container[..data_offset];
+
+        let reordered = [header, &chunks[1], &chunks[0], &chunks[2]].concat();
+        assert!(open(&reordered, &FILE_KEY).is_err());
+
+        let dropped = [header, &chunks[0], &chunks[2]].concat();
+        assert!(open(&dropped, &FILE_KEY).is_err());
+
+        let appended = [&container, chunks[1].as_slice()].concat();
+        assert!(open(&appended, &FILE_KEY).is_err());
+    }
+
+    #[test]
+    fn mac_rejects_swapped_stanzas_and_metadata() {
+        let other_key = [9u8; 32];
+        let container = seal_file(b"hello", &FILE_KEY, SizePadding::None);
+        let other = seal_file(b"other", &other_key, SizePadding::None);
+
+        let (mut header, data_offset) = Header::parse(&container).unwrap();
+        let (other_header, _) = Header::parse(&other).unwrap();
+
+        // A stanza from another file unwraps to that file's key
+        header.stanzas = vec![stanza(&other_key)];
+        let swapped = [header.to_bytes().unwrap(), container[data_offset..].to_vec()].concat();
+        assert!(Header::parse(&swapped).unwrap().0.opener(&other_key).is_err());
+        assert!(open(&swapped, &other_key).is_err());
+
+        // Stanzas are left out of the MAC, so the right key still opens it
+        assert_eq!(open(&swapped, &FILE_KEY).unwrap().1, b"hello");
+
+        header.sealed_metadata = other_header.sealed_metadata;
+        let swapped = [header.to_bytes().unwrap(), container[data_offset..].to_vec()].concat();
+        assert!(open(&swapped, &FILE_KEY).is_err());
+    }
+
+    #[test]
+    fn padme_rounds_up_to_few_distinct_sizes() {
+        assert_eq!(SizePadding::Padme.padded_size(0), 0);
+        assert_eq!(SizePadding::Padme.padded_size(1), 1);
+        assert_eq!(SizePadding::Padme.padded_size(9), 10);
+        assert_eq!(SizePadding::Padme.padded_size(1000), 1024);
+        assert_eq!(SizePadding::Padme.padded_size(1025), 1088);
+        assert_eq!(SizePadding::Padme.padded_size(1_000_000), 1_015_808);
+        assert_eq!(SizePadding::None.padded_size(1_000_000), 1_000_000);
+    }
+
+    #[test]
+    fn padme_overhead_stays_under_twelve_percent() {
+        for size in (1..100_000).chain([u32::MAX as u64, u64::MAX / 2]) {
+            let padded = SizePadding::Padme.padded_size(size);
+
+            assert!(padded >= size);
+            assert!((padded - size) as f64 / size as f64 <= 0.12, "{} pads to {}", size, padded);
+            assert_eq!(SizePadding::Padme.padded_size(padded), padded);
+        }
+    }
+
+    #[test]
+    fn empty_payload_has_one_empty_chunk() {
+        let container = seal_file(b"", &FILE_KEY, SizePadding::Padme);
+        let (header, data_offset) = Header::parse(&container).unwrap();
+
+        assert_eq!(header.payload_size, 0);
+        assert_eq!(header.chunk_count(), 1);
+        assert_eq!(container.len(), data_offset + TAG_SIZE);
+
+        let (metadata, plaintext, _) = open(&container, &FILE_KEY).unwrap();
+        assert_eq!(metadata.size, 0);
+        assert!(plaintext.is_empty());
+
+        assert!(open(&container[..data_offset], &FILE_KEY).is_err());
+    }
+}
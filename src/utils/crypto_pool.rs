@@ -0,0 +1,78 @@
+use std::sync::{atomic::{AtomicUsize, Ordering}, Arc, OnceLock};
+
+use tokio::sync::Semaphore;
+
+use crate::error::{ErrorMessage, HttpError};
+
+const DEFAULT_MAX_QUEUE: usize = 256;
+
+static POOL: OnceLock<CryptoPool> = OnceLock::new();
+
+/// Runs CPU-heavy work (key generation, encryption, password hashing) on
+/// Tokio's blocking threads so it never stalls the async workers. At most
+/// `workers` tasks run at once and at most `max_queue` wait for a turn;
+/// anything beyond that is turned away with a 503.
+#[derive(Debug)]
+struct CryptoPool {
+    permits: Arc<Semaphore>,
+    queued: AtomicUsize,
+    max_queue: usize,
+}
+
+impl CryptoPool {
+    fn new(workers: usize, max_queue: usize) -> Self {
+        CryptoPool {
+            permits: Arc::new(Semaphore::new(workers.max(1))),
+            queued: AtomicUsize::new(0),
+            max_queue,
+        }
+    }
+}
+
+/// Holds a place in the queue, given back even if the caller stops waiting.
+struct QueueSlot<'a>(&'a AtomicUsize);
+
+impl Drop for QueueSlot<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Sets the pool size. Called once at startup, before the first `run`.
+pub fn init(workers: usize, max_queue: usize) {
+    if POOL.set(CryptoPool::new(workers, max_queue)).is_err() {
+        eprintln!("The crypto pool was already initialized");
+    }
+}
+
+pub fn default_workers() -> usize {
+    std::thread::available_parallelism()
+        .map(|workers| workers.get())
+        .unwrap_or(4)
+}
+
+pub async fn run<F, T>(task: F) -> Result<T, HttpError>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    let pool = POOL.get_or_init(|| CryptoPool::new(default_workers(), DEFAULT_MAX_QUEUE));
+
+    let slot = QueueSlot(&pool.queued);
+
+    if pool.queued.fetch_add(1, Ordering::SeqCst) >= pool.max_queue {
+        return Err(HttpError::service_unavailable(ErrorMessage::ServerBusy.to_string()));
+    }
+
+    let permit = pool.permits.clone().acquire_owned().await;
+    drop(slot);
+
+    let permit = permit.map_err(|e| HttpError::server_error(e.to_string()))?;
+
+    tokio::task::spawn_blocking(move || {
+        let _permit = permit;
+        task()
+    })
+    .await
+    .map_err(|e| HttpError::server_error(e.to_string()))
+}
@@ -1,30 +1,102 @@
-use aes::Aes256;
+use aes::{cipher::generic_array::GenericArray, Aes256, BlockDecrypt, NewBlockCipher};
 use block_modes::{block_padding::Pkcs7, BlockMode, Cbc};
-use rsa::{Pkcs1v15Encrypt, RsaPrivateKey};
 
-use crate::error::HttpError;
+use crate::{error::HttpError, utils::{container, crypto_pool, digest::sha256, key_pair::PrivateKey}};
 
+pub const BLOCK_SIZE: usize = 16;
+
+/// Decrypts a stored file and checks the plaintext against its digest before
+/// returning it along with that digest. Files with an IV predate containers
+/// and are plain AES-CBC, checked against the sealed digest when they have
+/// one; without one there is no digest to return. Runs on the crypto pool.
 pub async fn decrypt_file(
     encrypted_aes_key: Vec<u8>,
     encrypted_file_data: Vec<u8>,
-    iv: Vec<u8>,
-    user_private_key: &RsaPrivateKey,
-) -> Result<Vec<u8>, HttpError> {
+    iv: Option<Vec<u8>>,
+    encrypted_digest: Option<Vec<u8>>,
+    user_private_key: &PrivateKey,
+) -> Result<(Vec<u8>, Option<Vec<u8>>), HttpError> {
+    let user_private_key = user_private_key.clone();
+
+    crypto_pool::run(move || {
+        let aes_key = unwrap_aes_key(&encrypted_aes_key, &user_private_key)?;
+
+        let iv = match iv {
+            Some(iv) => iv,
+            None => return container::open(&encrypted_file_data, &aes_key).map(|(_, plaintext, digest)| (plaintext, Some(digest))),
+        };
+
+        let cipher = Cbc::<Aes256, Pkcs7>::new_from_slices(&aes_key, &iv)
+            .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+        let decrypted_data = cipher.decrypt_vec(&encrypted_file_data)
+            .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+        let digest = match encrypted_digest {
+            Some(encrypted_digest) => {
+                let digest = sha256(&decrypted_data);
+
+                if open_digest(&aes_key, &encrypted_digest)? != digest {
+                    return Err(HttpError::server_error("File integrity check failed"));
+                }
+
+                Some(digest)
+            }
+            None => None,
+        };
 
-    let aes_key = user_private_key.decrypt(
-        Pkcs1v15Encrypt, 
-        &encrypted_aes_key
-    ).map_err(|e| HttpError::server_error(e.to_string()))?;
+        Ok((decrypted_data, digest))
+    })
+    .await?
+}
 
-    let iv = iv;
+/// Reverses `seal_digest`.
+pub fn open_digest(aes_key: &[u8], encrypted_digest: &[u8]) -> Result<Vec<u8>, HttpError> {
+    if encrypted_digest.len() <= BLOCK_SIZE {
+        return Err(HttpError::server_error("Stored digest is malformed"));
+    }
 
-    let cipher = Cbc::<Aes256, Pkcs7>::new_from_slices(&aes_key, &iv)
+    let (iv, sealed) = encrypted_digest.split_at(BLOCK_SIZE);
+
+    let cipher = Cbc::<Aes256, Pkcs7>::new_from_slices(aes_key, iv)
         .map_err(|e| HttpError::server_error(e.to_string()))?;
 
-    let mut buffer = encrypted_file_data.clone();
+    cipher.decrypt_vec(sealed)
+        .map_err(|e| HttpError::server_error(e.to_string()))
+}
+
+pub fn unwrap_aes_key(
+    encrypted_aes_key: &[u8],
+    user_private_key: &PrivateKey,
+) -> Result<Vec<u8>, HttpError> {
+    user_private_key.unwrap_key(encrypted_aes_key)
+}
+
+/// Decrypts a run of CBC blocks taken from the middle of a file. Each block
+/// only depends on the ciphertext block before it, which is the IV for the
+/// first block of the file. Padding is left in place.
+pub fn decrypt_blocks(
+    aes_key: &[u8],
+    previous_block: &[u8],
+    blocks: &[u8],
+) -> Result<Vec<u8>, HttpError> {
+    if previous_block.len() != BLOCK_SIZE || !blocks.len().is_multiple_of(BLOCK_SIZE) {
+        return Err(HttpError::server_error("Ciphertext is not aligned to the block size"));
+    }
 
-    let decrypted_data = cipher.decrypt_vec(&mut buffer)
+    let cipher = Aes256::new_from_slice(aes_key)
         .map_err(|e| HttpError::server_error(e.to_string()))?;
 
-    Ok(decrypted_data)
-}
\ No newline at end of file
+    let mut previous = previous_block;
+    let mut decrypted = Vec::with_capacity(blocks.len());
+
+    for block in blocks.chunks(BLOCK_SIZE) {
+        let mut buffer = GenericArray::clone_from_slice(block);
+        cipher.decrypt_block(&mut buffer);
+
+        decrypted.extend(buffer.iter().zip(previous).map(|(byte, mask)| byte ^ mask));
+        previous = block;
+    }
+
+    Ok(decrypted)
+}
@@ -0,0 +1,46 @@
+use hmac::{Hmac, Mac};
+use rand::{rngs::OsRng, RngCore};
+use sha2::Sha256;
+use uuid::Uuid;
+
+use crate::{error::HttpError, utils::key_store::KeyStore};
+
+/// Tags a file so identical uploads from the same sender can share one stored
+/// ciphertext: an HMAC of its digest under the sender's `dedup_key`. Tags of
+/// different senders never match, and without the key a tag says nothing
+/// about the content.
+pub fn dedup_tag(dedup_key: &[u8], digest: &[u8]) -> Result<Vec<u8>, HttpError> {
+    let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(dedup_key)
+        .map_err(|e| HttpError::server_error(e.to_string()))?;
+    mac.update(digest);
+
+    Ok(mac.finalize().into_bytes().to_vec())
+}
+
+/// Loads a sender's deduplication key from the key store, creating it on
+/// their first upload.
+pub async fn dedup_key(key_store: &dyn KeyStore, user_id: Uuid) -> Result<Vec<u8>, HttpError> {
+    let name = dedup_key_name(user_id);
+
+    if let Some(key) = key_store.load(&name).await.map_err(HttpError::server_error)? {
+        return Ok(key);
+    }
+
+    let mut key = [0u8; 32];
+    OsRng.fill_bytes(&mut key);
+
+    key_store.store(&name, &key).await.map_err(HttpError::server_error)?;
+
+    Ok(key.to_vec())
+}
+
+pub async fn delete_dedup_key(key_store: &dyn KeyStore, user_id: Uuid) -> Result<(), HttpError> {
+    key_store
+        .delete(&dedup_key_name(user_id))
+        .await
+        .map_err(HttpError::server_error)
+}
+
+fn dedup_key_name(user_id: Uuid) -> String {
+    format!("dedup_keys/{}.key", user_id)
+}
@@ -0,0 +1,16 @@
+use base64::{engine::general_purpose::STANDARD, Engine};
+use sha2::{Digest, Sha256};
+
+pub fn sha256(data: &[u8]) -> Vec<u8> {
+    Sha256::digest(data).to_vec()
+}
+
+/// `Repr-Digest` value (RFC 9530), a structured field byte sequence.
+pub fn repr_digest_header(digest: &[u8]) -> String {
+    format!("sha-256=:{}:", STANDARD.encode(digest))
+}
+
+/// Legacy `Digest` value (RFC 3230) for clients that predate `Repr-Digest`.
+pub fn digest_header(digest: &[u8]) -> String {
+    format!("SHA-256={}", STANDARD.encode(digest))
+}
@@ -1,36 +1,167 @@
 use aes::Aes256;
 use block_modes::{block_padding::Pkcs7, BlockMode, Cbc};
 use rand::Rng;
-use rsa::{Pkcs1v15Encrypt, RsaPublicKey};
+use uuid::Uuid;
 
-use crate::error::HttpError;
+use crate::{error::HttpError, utils::{compression, container::{self, Metadata, SizePadding, Stanza}, crypto_pool, key_pair::{PrivateKey, PublicKey}}};
 
+/// A file key wrapped for a file's recipient, and for its sender when there
+/// is one, with the file's digest and metadata sealed under it.
+pub struct FileKeys {
+    pub encrypted_aes_key: Vec<u8>,
+    pub encrypted_digest: Vec<u8>,
+    /// Kept next to the container so listings can read names without
+    /// fetching the file.
+    pub encrypted_metadata: Vec<u8>,
+    pub sender_encrypted_aes_key: Option<Vec<u8>>,
+}
+
+pub struct EncryptedFile {
+    pub keys: FileKeys,
+    pub encrypted_file: Vec<u8>,
+}
+
+/// Seals a file into an SSEF container with a stanza for the recipient key
+/// and one for the sender key, if given. `digest` is the SHA-256 of the
+/// plaintext, also sealed on its own with the file key. With a compression
+/// level the file is compressed first if that makes it smaller. Runs on the
+/// crypto pool.
 pub async fn encrypt_file(
     file_data: Vec<u8>,
-    user_public_key: &RsaPublicKey
-) -> Result<(Vec<u8>,Vec<u8>,Vec<u8>), HttpError> {
+    mut metadata: Metadata,
+    digest: &[u8],
+    recipient: (Uuid, &PublicKey),
+    sender: Option<(Uuid, &PublicKey)>,
+    padding: SizePadding,
+    compression_level: Option<i32>,
+) -> Result<EncryptedFile, HttpError> {
+    let digest = digest.to_vec();
+    let (key_id, user_public_key) = (recipient.0, recipient.1.clone());
+    let sender = sender.map(|(key_id, public_key)| (key_id, public_key.clone()));
+
+    crypto_pool::run(move || {
+        let compressed = match compression_level {
+            Some(level) => compression::compress(&file_data, level)?,
+            None => None,
+        };
+
+        let file_data = match compressed {
+            Some((compressed, compression)) => {
+                metadata.compression = Some(compression);
+                compressed
+            },
+            None => file_data,
+        };
+
+        let mut aes_key = [0u8; 32];
+        rand::thread_rng().fill(&mut aes_key);
+
+        let keys = wrap_file_key(
+            &aes_key,
+            &metadata,
+            &digest,
+            &user_public_key,
+            sender.as_ref().map(|(_, public_key)| public_key)
+        )?;
+
+        let mut stanzas = vec![Stanza {
+            key_type: user_public_key.key_type(),
+            key_id,
+            wrapped_key: keys.encrypted_aes_key.clone(),
+        }];
+
+        if let (Some((sender_key_id, sender_public_key)), Some(wrapped_key)) = (&sender, &keys.sender_encrypted_aes_key) {
+            stanzas.push(Stanza {
+                key_type: sender_public_key.key_type(),
+                key_id: *sender_key_id,
+                wrapped_key: wrapped_key.clone(),
+            });
+        }
+
+        let encrypted_file = container::seal(&file_data, &metadata, &aes_key, stanzas, padding)?;
 
-    let mut aes_key = [0u8; 32];
+        Ok(EncryptedFile { keys, encrypted_file })
+    })
+    .await?
+}
+
+/// Wraps the key of an already stored container for a new file with the
+/// same content, so both can share the ciphertext. The key is unwrapped with
+/// the holder's private key; the file gets its own sealed metadata and digest,
+/// keeping the compression recorded in the holder's `holder_metadata`.
+/// Runs on the crypto pool.
+pub async fn reuse_file_key(
+    encrypted_aes_key: &[u8],
+    holder_private_key: &PrivateKey,
+    holder_metadata: &[u8],
+    mut metadata: Metadata,
+    digest: &[u8],
+    recipient_public_key: &PublicKey,
+    sender_public_key: Option<&PublicKey>,
+) -> Result<FileKeys, HttpError> {
+    let encrypted_aes_key = encrypted_aes_key.to_vec();
+    let holder_private_key = holder_private_key.clone();
+    let holder_metadata = holder_metadata.to_vec();
+    let digest = digest.to_vec();
+    let recipient_public_key = recipient_public_key.clone();
+    let sender_public_key = sender_public_key.cloned();
+
+    crypto_pool::run(move || {
+        let aes_key = holder_private_key.unwrap_key(&encrypted_aes_key)?;
+        metadata.compression = container::open_detached_metadata(&aes_key, &holder_metadata)?.compression;
+
+        wrap_file_key(&aes_key, &metadata, &digest, &recipient_public_key, sender_public_key.as_ref())
+    })
+    .await?
+}
+
+fn wrap_file_key(
+    aes_key: &[u8],
+    metadata: &Metadata,
+    digest: &[u8],
+    recipient_public_key: &PublicKey,
+    sender_public_key: Option<&PublicKey>,
+) -> Result<FileKeys, HttpError> {
+    Ok(FileKeys {
+        encrypted_aes_key: recipient_public_key.wrap_key(aes_key)?,
+        encrypted_digest: seal_digest(aes_key, digest)?,
+        encrypted_metadata: container::seal_detached_metadata(aes_key, metadata)?,
+        sender_encrypted_aes_key: sender_public_key
+            .map(|public_key| public_key.wrap_key(aes_key))
+            .transpose()?,
+    })
+}
+
+/// Encrypts a plaintext digest under the file's AES key with its own IV, so
+/// only key holders can read or replace it. The result is IV || ciphertext.
+pub fn seal_digest(aes_key: &[u8], digest: &[u8]) -> Result<Vec<u8>, HttpError> {
     let mut iv = [0u8; 16];
-    rand::thread_rng().fill(&mut aes_key);
     rand::thread_rng().fill(&mut iv);
 
-    let cipher = Cbc::<Aes256, Pkcs7>::new_from_slices(&aes_key, &iv)
+    let cipher = Cbc::<Aes256, Pkcs7>::new_from_slices(aes_key, &iv)
         .map_err(|e| HttpError::server_error(e.to_string()))?;
 
-    let mut buffer = file_data.clone();
-    let encrypted_data = cipher.encrypt_vec(&mut buffer);
-
-    let encrypted_aes_key = user_public_key.encrypt(
-        &mut rand::thread_rng(), 
-        Pkcs1v15Encrypt, 
-        &aes_key
-    )
-    .map_err(|e| HttpError::server_error(e.to_string()))?;
-
-    Ok((
-        encrypted_aes_key,
-        encrypted_data,
-        iv.to_vec(),
-    ))
-}
\ No newline at end of file
+    let mut sealed = iv.to_vec();
+    sealed.extend(cipher.encrypt_vec(digest));
+
+    Ok(sealed)
+}
+
+/// Unwraps a file's AES key with the current holder's private key and wraps it
+/// again for another recipient, leaving the file ciphertext untouched.
+pub async fn rewrap_aes_key(
+    encrypted_aes_key: &[u8],
+    holder_private_key: &PrivateKey,
+    recipient_public_key: &PublicKey
+) -> Result<Vec<u8>, HttpError> {
+    let encrypted_aes_key = encrypted_aes_key.to_vec();
+    let holder_private_key = holder_private_key.clone();
+    let recipient_public_key = recipient_public_key.clone();
+
+    crypto_pool::run(move || {
+        let aes_key = holder_private_key.unwrap_key(&encrypted_aes_key)?;
+
+        recipient_public_key.wrap_key(&aes_key)
+    })
+    .await?
+}
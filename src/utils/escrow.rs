@@ -0,0 +1,129 @@
+use rsa::{pkcs1::DecodeRsaPublicKey, pkcs8::DecodePublicKey, RsaPublicKey};
+use uuid::Uuid;
+
+use crate::{db::{DBClient, KeyExt}, error::HttpError, utils::{container::{Metadata, SizePadding}, decrypt::decrypt_file, digest::sha256, encrypt::encrypt_file, key_pair::{PrivateKey, PublicKey}, key_store::KeyStore, keys::{load_private_key, private_key_exists, restore_private_key}}};
+
+/// Reads the escrow public key from a file: an RSA PEM, PKCS#1 or SPKI, or
+/// an age recipient (`age1...`) on a line of its own.
+pub fn load_escrow_public_key(path: &str) -> Result<PublicKey, String> {
+    let contents = std::fs::read_to_string(path)
+        .map_err(|e| format!("{}: {}", path, e))?;
+
+    if let Some(recipient) = contents.lines().map(str::trim).find(|line| line.starts_with("age1")) {
+        return PublicKey::parse(recipient)
+            .map_err(|e| format!("{}: {}", path, e.message));
+    }
+
+    RsaPublicKey::from_pkcs1_pem(&contents)
+        .or_else(|_| RsaPublicKey::from_public_key_pem(&contents))
+        .map(PublicKey::Rsa)
+        .map_err(|e| format!("{}: {}", path, e))
+}
+
+/// Parses the escrow private key an admin supplies for a restore.
+pub fn parse_escrow_private_key(pem: &str) -> Result<PrivateKey, HttpError> {
+    PrivateKey::from_pem(pem)
+        .map_err(|_| HttpError::bad_request("The escrow private key is not a valid RSA or X25519 key"))
+}
+
+/// Wraps a user's private key for the escrow key, the same way files are
+/// wrapped for recipients.
+pub async fn escrow_key(
+    db_client: &DBClient,
+    key_store: &dyn KeyStore,
+    escrow_public_key: &PublicKey,
+    key_id: Uuid,
+) -> Result<(), HttpError> {
+    let private_key = load_private_key(key_store, key_id).await?
+        .to_pkcs8_der()?;
+
+    let private_key = private_key.as_bytes().to_vec();
+
+    let digest = sha256(&private_key);
+    let metadata = Metadata::new(format!("{}.der", key_id), None, private_key.len(), &digest);
+
+    let escrowed = encrypt_file(
+        private_key,
+        metadata,
+        &digest,
+        (Uuid::nil(), escrow_public_key),
+        None,
+        SizePadding::None,
+        None
+    ).await?;
+
+    db_client
+        .save_key_escrow(key_id, escrowed.keys.encrypted_aes_key, escrowed.encrypted_file, None)
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))
+}
+
+/// Escrows every usable key that has no escrow copy yet, skipping keys whose
+/// private half is missing. Returns how many were escrowed.
+pub async fn escrow_missing_keys(
+    db_client: &DBClient,
+    key_store: &dyn KeyStore,
+    escrow_public_key: &PublicKey,
+) -> Result<usize, HttpError> {
+    let key_ids = db_client
+        .get_unescrowed_keys()
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+    let mut escrowed = 0;
+
+    for key_id in key_ids {
+        if !private_key_exists(key_store, key_id).await? {
+            continue;
+        }
+
+        escrow_key(db_client, key_store, escrow_public_key, key_id).await?;
+        escrowed += 1;
+    }
+
+    Ok(escrowed)
+}
+
+pub struct RestoreSummary {
+    pub restored: usize,
+    pub present: usize,
+}
+
+/// Recreates missing private keys from escrow. Keys that are already in the
+/// key store are left as they are.
+pub async fn restore_from_escrow(
+    db_client: &DBClient,
+    key_store: &dyn KeyStore,
+    escrow_private_key: &PrivateKey,
+) -> Result<RestoreSummary, HttpError> {
+    let escrows = db_client
+        .get_key_escrows()
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+    let mut summary = RestoreSummary { restored: 0, present: 0 };
+
+    for escrow in escrows {
+        if private_key_exists(key_store, escrow.key_id).await? {
+            summary.present += 1;
+            continue;
+        }
+
+        let (private_key, _) = decrypt_file(
+            escrow.encrypted_key,
+            escrow.encrypted_private_key,
+            escrow.iv,
+            None,
+            escrow_private_key
+        )
+        .await
+        .map_err(|_| HttpError::bad_request("The escrow private key does not match the escrowed keys"))?;
+
+        let private_key = PrivateKey::from_pkcs8_der(&private_key)?;
+
+        restore_private_key(key_store, escrow.key_id, &private_key).await?;
+        summary.restored += 1;
+    }
+
+    Ok(summary)
+}
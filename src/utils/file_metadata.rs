@@ -0,0 +1,112 @@
+use std::collections::{hash_map::Entry, HashMap};
+
+use uuid::Uuid;
+
+use crate::{error::HttpError, utils::{container::open_detached_metadata, crypto_pool, decrypt::unwrap_aes_key, key_pair::PrivateKey, key_store::KeyStore, keys::load_private_key}};
+
+/// Shown in listings when a file's sealed metadata cannot be opened.
+pub const UNAVAILABLE_NAME: &str = "(name unavailable)";
+
+/// What a file is called and how big it is, from its sealed metadata or,
+/// for files stored before metadata was sealed, from the plaintext columns.
+pub struct FileMetadata {
+    pub name: String,
+    pub size: i64,
+    pub content_type: Option<String>,
+    /// Whether the payload is compressed, so byte ranges cannot be read
+    /// from it directly.
+    pub compressed: bool,
+}
+
+/// Opens a file's sealed metadata with the key that holds the file key.
+/// Runs on the crypto pool.
+pub async fn open_metadata(
+    private_key: &PrivateKey,
+    encrypted_aes_key: &[u8],
+    encrypted_metadata: Option<&[u8]>,
+    file_name: Option<&str>,
+    file_size: i64,
+) -> Result<FileMetadata, HttpError> {
+    let Some(encrypted_metadata) = encrypted_metadata else {
+        return Ok(FileMetadata {
+            name: file_name.unwrap_or(UNAVAILABLE_NAME).to_string(),
+            size: file_size,
+            content_type: None,
+            compressed: false,
+        });
+    };
+
+    let private_key = private_key.clone();
+    let encrypted_aes_key = encrypted_aes_key.to_vec();
+    let encrypted_metadata = encrypted_metadata.to_vec();
+
+    let metadata = crypto_pool::run(move || {
+        let aes_key = unwrap_aes_key(&encrypted_aes_key, &private_key)?;
+        open_detached_metadata(&aes_key, &encrypted_metadata)
+    })
+    .await??;
+
+    Ok(FileMetadata {
+        name: metadata.name,
+        size: metadata.size as i64,
+        content_type: metadata.content_type,
+        compressed: metadata.compression.is_some(),
+    })
+}
+
+/// Opens the metadata of a page of files, loading each private key once.
+/// A file whose metadata cannot be opened, for example because its key is
+/// gone, is listed as `UNAVAILABLE_NAME` rather than failing the page.
+pub struct MetadataReader<'a> {
+    key_store: &'a dyn KeyStore,
+    private_keys: HashMap<Uuid, Option<PrivateKey>>,
+}
+
+impl<'a> MetadataReader<'a> {
+    pub fn new(key_store: &'a dyn KeyStore) -> Self {
+        MetadataReader {
+            key_store,
+            private_keys: HashMap::new(),
+        }
+    }
+
+    /// Returns the name and size to show for a file.
+    pub async fn read(
+        &mut self,
+        key_id: Option<Uuid>,
+        encrypted_aes_key: Option<&[u8]>,
+        encrypted_metadata: Option<&[u8]>,
+        file_name: &str,
+        file_size: i64,
+    ) -> Result<(String, i64), HttpError> {
+        let unavailable = (UNAVAILABLE_NAME.to_string(), file_size);
+
+        // Quarantine records of senders without a key have no name at all
+        if encrypted_metadata.is_none() {
+            return Ok(if file_name.is_empty() { unavailable } else { (file_name.to_string(), file_size) });
+        }
+
+        let (Some(key_id), Some(encrypted_aes_key)) = (key_id, encrypted_aes_key) else {
+            return Ok(unavailable);
+        };
+
+        let private_key = match self.private_keys.entry(key_id) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => entry.insert(load_private_key(self.key_store, key_id).await.ok()),
+        };
+
+        let Some(private_key) = private_key else {
+            return Ok(unavailable);
+        };
+
+        match open_metadata(private_key, encrypted_aes_key, encrypted_metadata, None, file_size).await {
+            Ok(metadata) => Ok((metadata.name, metadata.size)),
+            // A busy pool fails the request, anything else only this file
+            Err(err) if err.status == axum::http::StatusCode::SERVICE_UNAVAILABLE => Err(err),
+            Err(err) => {
+                eprintln!("Error opening file metadata: {}", err.message);
+                Ok(unavailable)
+            },
+        }
+    }
+}
@@ -0,0 +1,370 @@
+use base64::{engine::general_purpose::STANDARD, Engine};
+use bech32::{FromBase32, ToBase32, Variant};
+use chacha20poly1305::{aead::Aead, ChaCha20Poly1305, Key, KeyInit, Nonce};
+use hkdf::Hkdf;
+use rand::rngs::OsRng;
+use rsa::{pkcs1::{DecodeRsaPrivateKey, DecodeRsaPublicKey, EncodeRsaPrivateKey, EncodeRsaPublicKey, LineEnding}, pkcs8::{der::{asn1::OctetStringRef, Decode, Encode}, AlgorithmIdentifierRef, DecodePrivateKey, EncodePrivateKey, EncryptedPrivateKeyInfo, ObjectIdentifier, PrivateKeyInfo, SecretDocument}, Pkcs1v15Encrypt, RsaPrivateKey, RsaPublicKey};
+use sha2::Sha256;
+use x25519_dalek::{EphemeralSecret, PublicKey as X25519PublicKey, StaticSecret};
+
+use crate::{error::HttpError, models::KeyType};
+
+const X25519_OID: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.3.101.110");
+
+// Bech32 prefixes of age recipients and identities
+const AGE_RECIPIENT_HRP: &str = "age";
+const AGE_IDENTITY_HRP: &str = "age-secret-key-";
+const AGE_X25519_LABEL: &[u8] = b"age-encryption.org/v1/X25519";
+
+const X25519_KEY_SIZE: usize = 32;
+
+/// The public half of a user or escrow key, which file keys are wrapped for.
+#[derive(Debug, Clone, PartialEq)]
+pub enum PublicKey {
+    Rsa(RsaPublicKey),
+    X25519(X25519PublicKey),
+}
+
+impl PublicKey {
+    /// Parses a public key as stored in the database: an age recipient
+    /// (`age1...`) or a base64 PKCS#1 PEM.
+    pub fn parse(encoded: &str) -> Result<Self, HttpError> {
+        if encoded.starts_with("age1") {
+            let bytes = decode_bech32(encoded, AGE_RECIPIENT_HRP)?;
+            return Ok(PublicKey::X25519(X25519PublicKey::from(bytes)));
+        }
+
+        let pem = STANDARD.decode(encoded)
+            .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+        let pem = String::from_utf8(pem)
+            .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+        RsaPublicKey::from_pkcs1_pem(&pem)
+            .map(PublicKey::Rsa)
+            .map_err(|e| HttpError::server_error(e.to_string()))
+    }
+
+    pub fn key_type(&self) -> KeyType {
+        match self {
+            PublicKey::Rsa(_) => KeyType::Rsa,
+            PublicKey::X25519(_) => KeyType::X25519,
+        }
+    }
+
+    pub fn encode(&self) -> Result<String, HttpError> {
+        match self {
+            PublicKey::Rsa(public_key) => {
+                let pem = public_key.to_pkcs1_pem(LineEnding::LF)
+                    .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+                Ok(STANDARD.encode(pem.as_bytes()))
+            }
+            PublicKey::X25519(public_key) => encode_bech32(AGE_RECIPIENT_HRP, public_key.as_bytes()),
+        }
+    }
+
+    /// Wraps a file key so only the matching private key can recover it.
+    ///
+    /// X25519 keys use the age recipient stanza construction: an ephemeral
+    /// share, HKDF-SHA256 over the shared secret salted with both public keys,
+    /// and ChaCha20-Poly1305 with a zero nonce. The result is the ephemeral
+    /// share followed by the sealed key.
+    pub fn wrap_key(&self, file_key: &[u8]) -> Result<Vec<u8>, HttpError> {
+        match self {
+            PublicKey::Rsa(public_key) => public_key
+                .encrypt(&mut rand::thread_rng(), Pkcs1v15Encrypt, file_key)
+                .map_err(|e| HttpError::server_error(e.to_string())),
+            PublicKey::X25519(public_key) => {
+                let ephemeral_secret = EphemeralSecret::random_from_rng(OsRng);
+                let ephemeral_share = X25519PublicKey::from(&ephemeral_secret);
+                let shared_secret = ephemeral_secret.diffie_hellman(public_key);
+
+                if !shared_secret.was_contributory() {
+                    return Err(HttpError::server_error("The recipient key is not a valid X25519 key"));
+                }
+
+                let wrap_key = age_wrap_key(shared_secret.as_bytes(), &ephemeral_share, public_key)?;
+
+                let sealed_key = ChaCha20Poly1305::new(Key::from_slice(&wrap_key))
+                    .encrypt(&Nonce::default(), file_key)
+                    .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+                let mut wrapped = ephemeral_share.as_bytes().to_vec();
+                wrapped.extend(sealed_key);
+                Ok(wrapped)
+            }
+        }
+    }
+}
+
+/// The private half of a user or escrow key.
+#[derive(Clone)]
+pub enum PrivateKey {
+    Rsa(Box<RsaPrivateKey>),
+    X25519(StaticSecret),
+}
+
+impl PrivateKey {
+    pub fn generate(key_type: KeyType) -> Result<Self, HttpError> {
+        match key_type {
+            KeyType::Rsa => RsaPrivateKey::new(&mut OsRng, 2048)
+                .map(|private_key| PrivateKey::Rsa(Box::new(private_key)))
+                .map_err(|e| HttpError::server_error(e.to_string())),
+            KeyType::X25519 => Ok(PrivateKey::X25519(StaticSecret::random_from_rng(OsRng))),
+        }
+    }
+
+    pub fn public_key(&self) -> PublicKey {
+        match self {
+            PrivateKey::Rsa(private_key) => PublicKey::Rsa(RsaPublicKey::from(private_key.as_ref())),
+            PrivateKey::X25519(secret) => PublicKey::X25519(X25519PublicKey::from(secret)),
+        }
+    }
+
+    /// Reverses `PublicKey::wrap_key`.
+    pub fn unwrap_key(&self, wrapped_key: &[u8]) -> Result<Vec<u8>, HttpError> {
+        match self {
+            PrivateKey::Rsa(private_key) => private_key
+                .decrypt(Pkcs1v15Encrypt, wrapped_key)
+                .map_err(|e| HttpError::server_error(e.to_string())),
+            PrivateKey::X25519(secret) => {
+                if wrapped_key.len() <= X25519_KEY_SIZE {
+                    return Err(HttpError::server_error("Wrapped key is malformed"));
+                }
+
+                let (ephemeral_share, sealed_key) = wrapped_key.split_at(X25519_KEY_SIZE);
+                let ephemeral_share = X25519PublicKey::from(<[u8; X25519_KEY_SIZE]>::try_from(ephemeral_share).unwrap());
+                let shared_secret = secret.diffie_hellman(&ephemeral_share);
+
+                // A low-order share would give a shared secret anyone can compute
+                if !shared_secret.was_contributory() {
+                    return Err(HttpError::server_error("Wrapped key is malformed"));
+                }
+
+                let wrap_key = age_wrap_key(shared_secret.as_bytes(), &ephemeral_share, &X25519PublicKey::from(secret))?;
+
+                ChaCha20Poly1305::new(Key::from_slice(&wrap_key))
+                    .decrypt(&Nonce::default(), sealed_key)
+                    .map_err(|_| HttpError::server_error("Wrapped key could not be opened with this key"))
+            }
+        }
+    }
+
+    /// The PEM kept in the key store: PKCS#1 for RSA, like keys from before
+    /// key types existed, and PKCS#8 for X25519.
+    pub fn to_pem(&self) -> Result<String, HttpError> {
+        match self {
+            PrivateKey::Rsa(private_key) => private_key.to_pkcs1_pem(LineEnding::LF)
+                .map(|pem| pem.to_string())
+                .map_err(|e| HttpError::server_error(e.to_string())),
+            PrivateKey::X25519(_) => self.to_pkcs8_der()?
+                .to_pem("PRIVATE KEY", LineEnding::LF)
+                .map(|pem| pem.to_string())
+                .map_err(|e| HttpError::server_error(e.to_string())),
+        }
+    }
+
+    /// Parses a PKCS#1 or PKCS#8 PEM, or an age identity (`AGE-SECRET-KEY-1...`).
+    pub fn from_pem(pem: &str) -> Result<Self, HttpError> {
+        let pem = pem.trim();
+
+        if pem.starts_with("AGE-SECRET-KEY-1") {
+            let bytes = decode_bech32(pem, AGE_IDENTITY_HRP)?;
+            return Ok(PrivateKey::X25519(StaticSecret::from(bytes)));
+        }
+
+        if let Ok(private_key) = RsaPrivateKey::from_pkcs1_pem(pem) {
+            return Ok(PrivateKey::Rsa(Box::new(private_key)));
+        }
+
+        let (_, document) = SecretDocument::from_pem(pem)
+            .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+        Self::from_pkcs8_der(document.as_bytes())
+    }
+
+    pub fn to_pkcs8_der(&self) -> Result<SecretDocument, HttpError> {
+        match self {
+            PrivateKey::Rsa(private_key) => private_key.to_pkcs8_der()
+                .map_err(|e| HttpError::server_error(e.to_string())),
+            PrivateKey::X25519(secret) => {
+                let secret_bytes = secret.to_bytes();
+
+                let inner = OctetStringRef::new(&secret_bytes)
+                    .and_then(|octets| octets.to_der())
+                    .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+                let algorithm = AlgorithmIdentifierRef { oid: X25519_OID, parameters: None };
+
+                SecretDocument::encode_msg(&PrivateKeyInfo::new(algorithm, &inner))
+                    .map_err(|e| HttpError::server_error(e.to_string()))
+            }
+        }
+    }
+
+    pub fn from_pkcs8_der(der: &[u8]) -> Result<Self, HttpError> {
+        let info = PrivateKeyInfo::try_from(der)
+            .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+        if info.algorithm.oid != X25519_OID {
+            return RsaPrivateKey::from_pkcs8_der(der)
+                .map(|private_key| PrivateKey::Rsa(Box::new(private_key)))
+                .map_err(|e| HttpError::server_error(e.to_string()));
+        }
+
+        let secret_bytes = OctetStringRef::from_der(info.private_key)
+            .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+        let secret_bytes = <[u8; X25519_KEY_SIZE]>::try_from(secret_bytes.as_bytes())
+            .map_err(|_| HttpError::server_error("X25519 private key must be 32 bytes"))?;
+
+        Ok(PrivateKey::X25519(StaticSecret::from(secret_bytes)))
+    }
+
+    /// Exports as a passphrase-encrypted PKCS#8 PEM (PBES2 with scrypt and
+    /// AES-256-CBC).
+    pub fn to_encrypted_pem(&self, passphrase: &str) -> Result<String, HttpError> {
+        let document = self.to_pkcs8_der()?;
+
+        let info = PrivateKeyInfo::try_from(document.as_bytes())
+            .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+        info.encrypt(&mut OsRng, passphrase)
+            .and_then(|encrypted| encrypted.to_pem("ENCRYPTED PRIVATE KEY", LineEnding::LF).map_err(Into::into))
+            .map(|pem| pem.to_string())
+            .map_err(|e| HttpError::server_error(e.to_string()))
+    }
+
+    /// Reverses `to_encrypted_pem`. Returns `None` when the PEM can't be
+    /// decrypted, most likely because of a wrong passphrase.
+    pub fn from_encrypted_pem(pem: &str, passphrase: &str) -> Option<Self> {
+        let (_, document) = SecretDocument::from_pem(pem).ok()?;
+
+        let decrypted = EncryptedPrivateKeyInfo::try_from(document.as_bytes())
+            .ok()?
+            .decrypt(passphrase)
+            .ok()?;
+
+        Self::from_pkcs8_der(decrypted.as_bytes()).ok()
+    }
+}
+
+fn age_wrap_key(
+    shared_secret: &[u8],
+    ephemeral_share: &X25519PublicKey,
+    recipient: &X25519PublicKey,
+) -> Result<[u8; 32], HttpError> {
+    let mut salt = ephemeral_share.as_bytes().to_vec();
+    salt.extend_from_slice(recipient.as_bytes());
+
+    let mut wrap_key = [0u8; 32];
+    Hkdf::<Sha256>::new(Some(&salt), shared_secret)
+        .expand(AGE_X25519_LABEL, &mut wrap_key)
+        .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+    Ok(wrap_key)
+}
+
+fn encode_bech32(hrp: &str, bytes: &[u8]) -> Result<String, HttpError> {
+    bech32::encode(hrp, bytes.to_base32(), Variant::Bech32)
+        .map_err(|e| HttpError::server_error(e.to_string()))
+}
+
+fn decode_bech32(encoded: &str, expected_hrp: &str) -> Result<[u8; X25519_KEY_SIZE], HttpError> {
+    let (hrp, data, variant) = bech32::decode(encoded)
+        .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+    if hrp != expected_hrp || variant != Variant::Bech32 {
+        return Err(HttpError::server_error(format!("Expected a key starting with {}1", expected_hrp)));
+    }
+
+    let bytes = Vec::<u8>::from_base32(&data)
+        .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+    <[u8; X25519_KEY_SIZE]>::try_from(bytes.as_slice())
+        .map_err(|_| HttpError::server_error("X25519 keys must be 32 bytes"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key_pairs() -> Vec<PrivateKey> {
+        vec![
+            PrivateKey::generate(KeyType::Rsa).unwrap(),
+            PrivateKey::generate(KeyType::X25519).unwrap(),
+        ]
+    }
+
+    #[test]
+    fn wraps_and_unwraps_file_keys() {
+        let file_key = [42u8; 32];
+
+        for private_key in key_pairs() {
+            let wrapped = private_key.public_key().wrap_key(&file_key).unwrap();
+
+            assert_ne!(wrapped, file_key);
+            assert_eq!(private_key.unwrap_key(&wrapped).unwrap(), file_key);
+        }
+    }
+
+    #[test]
+    fn rejects_keys_wrapped_for_someone_else() {
+        let file_key = [42u8; 32];
+
+        for (private_key, other_key) in key_pairs().into_iter().zip(key_pairs()) {
+            let wrapped = other_key.public_key().wrap_key(&file_key).unwrap();
+
+            assert!(private_key.unwrap_key(&wrapped).is_err());
+        }
+    }
+
+    #[test]
+    fn rejects_malformed_x25519_wraps() {
+        let private_key = PrivateKey::generate(KeyType::X25519).unwrap();
+        let mut wrapped = private_key.public_key().wrap_key(&[42u8; 32]).unwrap();
+
+        assert!(private_key.unwrap_key(&wrapped[..X25519_KEY_SIZE]).is_err());
+
+        // The all-zero point has low order, the shared secret would be zero
+        wrapped[..X25519_KEY_SIZE].fill(0);
+        assert!(private_key.unwrap_key(&wrapped).is_err());
+    }
+
+    #[test]
+    fn public_keys_round_trip() {
+        for private_key in key_pairs() {
+            let public_key = private_key.public_key();
+
+            assert_eq!(PublicKey::parse(&public_key.encode().unwrap()).unwrap(), public_key);
+        }
+    }
+
+    #[test]
+    fn private_keys_round_trip_through_pem_and_pkcs8() {
+        for private_key in key_pairs() {
+            let public_key = private_key.public_key();
+
+            let from_pem = PrivateKey::from_pem(&private_key.to_pem().unwrap()).unwrap();
+            assert_eq!(from_pem.public_key(), public_key);
+
+            let pkcs8_pem = private_key.to_pkcs8_der().unwrap()
+                .to_pem("PRIVATE KEY", LineEnding::LF)
+                .unwrap();
+            assert_eq!(PrivateKey::from_pem(&pkcs8_pem).unwrap().public_key(), public_key);
+        }
+    }
+
+    #[test]
+    fn encrypted_pem_needs_the_passphrase() {
+        for private_key in key_pairs() {
+            let pem = private_key.to_encrypted_pem("correct horse").unwrap();
+
+            let imported = PrivateKey::from_encrypted_pem(&pem, "correct horse").unwrap();
+            assert_eq!(imported.public_key(), private_key.public_key());
+
+            assert!(PrivateKey::from_encrypted_pem(&pem, "wrong horse").is_none());
+        }
+    }
+}
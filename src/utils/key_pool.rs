@@ -0,0 +1,74 @@
+use std::{fmt, sync::Arc, time::Duration};
+
+use tokio::sync::{Mutex, Notify};
+
+use crate::{error::HttpError, models::KeyType, utils::{crypto_pool, key_pair::PrivateKey}};
+
+/// RSA key pairs generated ahead of time, so registration and rotation do
+/// not wait on a 2048-bit key search. X25519 keys are cheap and are always
+/// generated on demand.
+pub struct KeyPool {
+    keys: Mutex<Vec<PrivateKey>>,
+    capacity: usize,
+    refill: Notify,
+}
+
+impl fmt::Debug for KeyPool {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("KeyPool")
+            .field("capacity", &self.capacity)
+            .finish_non_exhaustive()
+    }
+}
+
+impl KeyPool {
+    pub fn new(capacity: usize) -> Self {
+        KeyPool {
+            keys: Mutex::new(Vec::with_capacity(capacity)),
+            capacity,
+            refill: Notify::new(),
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Hands out a pregenerated key when one is ready, otherwise generates
+    /// one on the crypto pool.
+    pub async fn take(&self, key_type: KeyType) -> Result<PrivateKey, HttpError> {
+        if key_type == KeyType::Rsa {
+            let private_key = self.keys.lock().await.pop();
+
+            if let Some(private_key) = private_key {
+                self.refill.notify_one();
+                return Ok(private_key);
+            }
+        }
+
+        crypto_pool::run(move || PrivateKey::generate(key_type)).await?
+    }
+
+    /// Keeps the pool topped up. Keys are generated one at a time on a
+    /// blocking thread of their own rather than the crypto pool, so a refill
+    /// never makes a request wait for a worker. Runs for the life of the server.
+    pub async fn fill(self: Arc<Self>) {
+        loop {
+            while self.keys.lock().await.len() < self.capacity {
+                let generated = tokio::task::spawn_blocking(|| PrivateKey::generate(KeyType::Rsa))
+                    .await
+                    .map_err(|e| HttpError::server_error(e.to_string()));
+
+                match generated {
+                    Ok(Ok(private_key)) => self.keys.lock().await.push(private_key),
+                    Ok(Err(err)) | Err(err) => {
+                        eprintln!("Error pregenerating a key pair: {}", err.message);
+                        tokio::time::sleep(Duration::from_secs(1)).await;
+                    }
+                }
+            }
+
+            self.refill.notified().await;
+        }
+    }
+}
@@ -0,0 +1,446 @@
+use std::{fmt::Debug, fs, io::ErrorKind, path::{Component, Path, PathBuf}};
+
+use aes_gcm::{aead::{Aead, AeadCore, KeyInit, OsRng, Payload}, Aes256Gcm, Key, Nonce};
+use async_trait::async_trait;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use rand::RngCore;
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+
+use crate::db::{DBClient, SecretExt};
+
+const MASTER_KEY_FILE: &str = "master.key";
+const NONCE_SIZE: usize = 12;
+
+/// Where the JWT secret is kept when `JWT_SECRET_KEY` is not set.
+pub const JWT_SECRET_NAME: &str = "jwt_secret";
+
+/// Storage for secrets the server holds on behalf of users, like private
+/// keys. Names are relative paths such as `private_keys/<id>.pem`.
+#[async_trait]
+pub trait KeyStore: Debug + Send + Sync {
+    /// Stores `secret` under `name`, replacing any previous value.
+    async fn store(&self, name: &str, secret: &[u8]) -> Result<(), String>;
+
+    async fn load(&self, name: &str) -> Result<Option<Vec<u8>>, String>;
+
+    /// Removes `name`; removing a missing secret is not an error.
+    async fn delete(&self, name: &str) -> Result<(), String>;
+
+    /// Encrypts data under the store's master key. The result is opaque and
+    /// can only be opened by `unseal` on the same store.
+    async fn seal(&self, plaintext: &[u8]) -> Result<Vec<u8>, String>;
+
+    async fn unseal(&self, sealed: &[u8]) -> Result<Vec<u8>, String>;
+}
+
+/// Secrets as plain files under a directory, the layout used before key
+/// stores existed. Sealing uses a master key kept in the same directory.
+#[derive(Debug, Clone)]
+pub struct FilesystemKeyStore {
+    root: PathBuf,
+    master_key: [u8; 32],
+}
+
+impl FilesystemKeyStore {
+    /// Opens the store at `root`, creating the directory and master key on
+    /// first use.
+    pub fn new(root: impl Into<PathBuf>) -> Result<Self, String> {
+        let root = root.into();
+
+        fs::create_dir_all(&root)
+            .map_err(|e| format!("{}: {}", root.display(), e))?;
+
+        let master_key_path = root.join(MASTER_KEY_FILE);
+
+        let master_key = match fs::read(&master_key_path) {
+            Ok(bytes) => <[u8; 32]>::try_from(bytes.as_slice())
+                .map_err(|_| format!("{}: expected 32 bytes", master_key_path.display()))?,
+            Err(err) if err.kind() == ErrorKind::NotFound => {
+                let mut master_key = [0u8; 32];
+                OsRng.fill_bytes(&mut master_key);
+                write_private_file(&master_key_path, &master_key)
+                    .map_err(|e| format!("{}: {}", master_key_path.display(), e))?;
+                master_key
+            }
+            Err(err) => return Err(format!("{}: {}", master_key_path.display(), err)),
+        };
+
+        Ok(FilesystemKeyStore { root, master_key })
+    }
+
+    fn path(&self, name: &str) -> Result<PathBuf, String> {
+        check_name(name)?;
+
+        if name == MASTER_KEY_FILE {
+            return Err(format!("Invalid secret name: {}", name));
+        }
+
+        Ok(self.root.join(name))
+    }
+}
+
+#[async_trait]
+impl KeyStore for FilesystemKeyStore {
+    async fn store(&self, name: &str, secret: &[u8]) -> Result<(), String> {
+        let path = self.path(name)?;
+
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent)
+                .await
+                .map_err(|e| e.to_string())?;
+        }
+
+        let secret = secret.to_vec();
+        tokio::task::spawn_blocking(move || write_private_file(&path, &secret))
+            .await
+            .map_err(|e| e.to_string())?
+            .map_err(|e| e.to_string())
+    }
+
+    async fn load(&self, name: &str) -> Result<Option<Vec<u8>>, String> {
+        match tokio::fs::read(self.path(name)?).await {
+            Ok(secret) => Ok(Some(secret)),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err.to_string()),
+        }
+    }
+
+    async fn delete(&self, name: &str) -> Result<(), String> {
+        match tokio::fs::remove_file(self.path(name)?).await {
+            Ok(()) => Ok(()),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(()),
+            Err(err) => Err(err.to_string()),
+        }
+    }
+
+    async fn seal(&self, plaintext: &[u8]) -> Result<Vec<u8>, String> {
+        seal_with(&self.master_key, plaintext, b"")
+    }
+
+    async fn unseal(&self, sealed: &[u8]) -> Result<Vec<u8>, String> {
+        unseal_with(&self.master_key, sealed, b"")
+    }
+}
+
+/// Secrets sealed under a master key and kept in the `secrets` table, so
+/// every instance sees the same keys without sharing a disk.
+#[derive(Debug, Clone)]
+pub struct PostgresKeyStore {
+    db_client: DBClient,
+    master_key: [u8; 32],
+}
+
+impl PostgresKeyStore {
+    /// `master_key` is 32 bytes, base64 encoded.
+    pub fn new(db_client: DBClient, master_key: &str) -> Result<Self, String> {
+        let master_key = STANDARD.decode(master_key.trim())
+            .ok()
+            .and_then(|bytes| <[u8; 32]>::try_from(bytes.as_slice()).ok())
+            .ok_or("expected 32 bytes, base64 encoded")?;
+
+        Ok(PostgresKeyStore { db_client, master_key })
+    }
+}
+
+#[async_trait]
+impl KeyStore for PostgresKeyStore {
+    async fn store(&self, name: &str, secret: &[u8]) -> Result<(), String> {
+        check_name(name)?;
+
+        // Bound to its name, so rows cannot be swapped between secrets
+        let sealed = seal_with(&self.master_key, secret, name.as_bytes())?;
+
+        self.db_client
+            .save_secret(name, sealed)
+            .await
+            .map_err(|e| e.to_string())
+    }
+
+    async fn load(&self, name: &str) -> Result<Option<Vec<u8>>, String> {
+        let sealed = self.db_client
+            .get_secret(name)
+            .await
+            .map_err(|e| e.to_string())?;
+
+        match sealed {
+            Some(sealed) => unseal_with(&self.master_key, &sealed, name.as_bytes()).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    async fn delete(&self, name: &str) -> Result<(), String> {
+        self.db_client
+            .delete_secret(name)
+            .await
+            .map_err(|e| e.to_string())
+    }
+
+    async fn seal(&self, plaintext: &[u8]) -> Result<Vec<u8>, String> {
+        seal_with(&self.master_key, plaintext, b"")
+    }
+
+    async fn unseal(&self, sealed: &[u8]) -> Result<Vec<u8>, String> {
+        unseal_with(&self.master_key, sealed, b"")
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct SecretBody {
+    pub value: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct SealBody {
+    pub plaintext: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct UnsealBody {
+    pub ciphertext: String,
+}
+
+/// Client for a KMS-style key service. Secrets and master keys never leave
+/// the service unencrypted except through `load` and `unseal`.
+///
+/// The service speaks JSON with base64 values, authenticated by a bearer token:
+/// `GET`/`PUT`/`DELETE /v1/secrets/{name}` (`{"value"}`),
+/// `POST /v1/seal` (`{"plaintext"}` to `{"ciphertext"}`) and
+/// `POST /v1/unseal` (the reverse).
+#[derive(Debug, Clone)]
+pub struct RemoteKeyStore {
+    client: reqwest::Client,
+    base_url: String,
+    token: String,
+}
+
+impl RemoteKeyStore {
+    pub fn new(base_url: impl Into<String>, token: impl Into<String>) -> Self {
+        RemoteKeyStore {
+            client: reqwest::Client::new(),
+            base_url: base_url.into().trim_end_matches('/').to_string(),
+            token: token.into(),
+        }
+    }
+
+    fn secret_url(&self, name: &str) -> Result<String, String> {
+        check_name(name)?;
+        Ok(format!("{}/v1/secrets/{}", self.base_url, name))
+    }
+}
+
+#[async_trait]
+impl KeyStore for RemoteKeyStore {
+    async fn store(&self, name: &str, secret: &[u8]) -> Result<(), String> {
+        self.client
+            .put(self.secret_url(name)?)
+            .bearer_auth(&self.token)
+            .json(&SecretBody { value: STANDARD.encode(secret) })
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|e| e.to_string())?;
+
+        Ok(())
+    }
+
+    async fn load(&self, name: &str) -> Result<Option<Vec<u8>>, String> {
+        let response = self.client
+            .get(self.secret_url(name)?)
+            .bearer_auth(&self.token)
+            .send()
+            .await
+            .map_err(|e| e.to_string())?;
+
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+
+        let body: SecretBody = response
+            .error_for_status()
+            .map_err(|e| e.to_string())?
+            .json()
+            .await
+            .map_err(|e| e.to_string())?;
+
+        STANDARD.decode(body.value)
+            .map(Some)
+            .map_err(|e| e.to_string())
+    }
+
+    async fn delete(&self, name: &str) -> Result<(), String> {
+        let response = self.client
+            .delete(self.secret_url(name)?)
+            .bearer_auth(&self.token)
+            .send()
+            .await
+            .map_err(|e| e.to_string())?;
+
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(());
+        }
+
+        response.error_for_status().map_err(|e| e.to_string())?;
+        Ok(())
+    }
+
+    async fn seal(&self, plaintext: &[u8]) -> Result<Vec<u8>, String> {
+        let body: UnsealBody = self.client
+            .post(format!("{}/v1/seal", self.base_url))
+            .bearer_auth(&self.token)
+            .json(&SealBody { plaintext: STANDARD.encode(plaintext) })
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|e| e.to_string())?
+            .json()
+            .await
+            .map_err(|e| e.to_string())?;
+
+        STANDARD.decode(body.ciphertext).map_err(|e| e.to_string())
+    }
+
+    async fn unseal(&self, sealed: &[u8]) -> Result<Vec<u8>, String> {
+        let body: SealBody = self.client
+            .post(format!("{}/v1/unseal", self.base_url))
+            .bearer_auth(&self.token)
+            .json(&UnsealBody { ciphertext: STANDARD.encode(sealed) })
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|e| e.to_string())?
+            .json()
+            .await
+            .map_err(|e| e.to_string())?;
+
+        STANDARD.decode(body.plaintext).map_err(|e| e.to_string())
+    }
+}
+
+/// Returns the JWT secret kept in the store, generating one on first start.
+pub async fn load_jwt_secret(key_store: &dyn KeyStore) -> Result<String, String> {
+    if let Some(secret) = key_store.load(JWT_SECRET_NAME).await? {
+        return String::from_utf8(secret).map_err(|e| e.to_string());
+    }
+
+    let mut secret = [0u8; 32];
+    OsRng.fill_bytes(&mut secret);
+    let secret: String = secret.iter().map(|byte| format!("{:02x}", byte)).collect();
+
+    key_store.store(JWT_SECRET_NAME, secret.as_bytes()).await?;
+
+    Ok(secret)
+}
+
+/// Nonce followed by the AES-256-GCM ciphertext and tag. `aad` must be
+/// given again to unseal.
+fn seal_with(master_key: &[u8; 32], plaintext: &[u8], aad: &[u8]) -> Result<Vec<u8>, String> {
+    let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(master_key));
+    let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+
+    let ciphertext = cipher.encrypt(&nonce, Payload { msg: plaintext, aad })
+        .map_err(|e| e.to_string())?;
+
+    let mut sealed = nonce.to_vec();
+    sealed.extend_from_slice(&ciphertext);
+    Ok(sealed)
+}
+
+fn unseal_with(master_key: &[u8; 32], sealed: &[u8], aad: &[u8]) -> Result<Vec<u8>, String> {
+    if sealed.len() < NONCE_SIZE {
+        return Err("Sealed data is too short".to_string());
+    }
+
+    let (nonce, ciphertext) = sealed.split_at(NONCE_SIZE);
+    let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(master_key));
+
+    cipher.decrypt(Nonce::from_slice(nonce), Payload { msg: ciphertext, aad })
+        .map_err(|_| "Sealed data could not be opened with this master key".to_string())
+}
+
+/// Names are relative paths without `..`, so no backend can be pointed
+/// outside its own namespace.
+pub fn check_name(name: &str) -> Result<(), String> {
+    let valid = !name.is_empty()
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_' | '/'))
+        && Path::new(name).components().all(|component| matches!(component, Component::Normal(_)));
+
+    if valid {
+        Ok(())
+    } else {
+        Err(format!("Invalid secret name: {}", name))
+    }
+}
+
+/// Secrets are readable by the server's user only.
+fn write_private_file(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+
+    std::io::Write::write_all(&mut options.open(path)?, contents)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::mock_kms::spawn_mock_kms;
+
+    async fn remote_store() -> (RemoteKeyStore, String, PathBuf) {
+        let root = std::env::temp_dir().join(format!("mock_kms_{}", uuid::Uuid::new_v4()));
+        let (url, token) = spawn_mock_kms(root.to_str().unwrap()).await.unwrap();
+
+        (RemoteKeyStore::new(url.clone(), token), url, root)
+    }
+
+    #[test]
+    fn sealed_secrets_are_bound_to_their_name() {
+        let master_key = [7u8; 32];
+        let sealed = seal_with(&master_key, b"secret", b"private_keys/a.pem").unwrap();
+
+        assert_eq!(unseal_with(&master_key, &sealed, b"private_keys/a.pem").unwrap(), b"secret");
+        assert!(unseal_with(&master_key, &sealed, b"private_keys/b.pem").is_err());
+        assert!(unseal_with(&master_key, &sealed, b"").is_err());
+        assert!(unseal_with(&[8u8; 32], &sealed, b"private_keys/a.pem").is_err());
+    }
+
+    #[tokio::test]
+    async fn remote_store_round_trips_secrets() {
+        let (store, _, root) = remote_store().await;
+
+        assert_eq!(store.load("private_keys/a.pem").await.unwrap(), None);
+
+        store.store("private_keys/a.pem", b"secret").await.unwrap();
+        assert_eq!(store.load("private_keys/a.pem").await.unwrap(), Some(b"secret".to_vec()));
+
+        store.delete("private_keys/a.pem").await.unwrap();
+        store.delete("private_keys/a.pem").await.unwrap();
+        assert_eq!(store.load("private_keys/a.pem").await.unwrap(), None);
+
+        let sealed = store.seal(b"plaintext").await.unwrap();
+        assert_ne!(sealed, b"plaintext");
+        assert_eq!(store.unseal(&sealed).await.unwrap(), b"plaintext");
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[tokio::test]
+    async fn remote_store_needs_the_token_and_valid_names() {
+        let (store, url, root) = remote_store().await;
+        let intruder = RemoteKeyStore::new(url, "wrong");
+
+        store.store("jwt_secret", b"secret").await.unwrap();
+        assert!(intruder.load("jwt_secret").await.is_err());
+        assert!(intruder.store("jwt_secret", b"forged").await.is_err());
+        assert_eq!(store.load("jwt_secret").await.unwrap(), Some(b"secret".to_vec()));
+
+        assert!(store.store("../outside", b"secret").await.is_err());
+
+        fs::remove_dir_all(root).unwrap();
+    }
+}
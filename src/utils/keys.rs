@@ -1,54 +1,218 @@
-use std::{fs::{self, File}, io::Write, sync::Arc};
+use std::{collections::{hash_map::Entry, HashMap}, sync::Arc};
 
 use axum::{http::StatusCode, response::IntoResponse};
-use rand::rngs::OsRng;
-use rsa::{pkcs1::{EncodeRsaPrivateKey, EncodeRsaPublicKey}, RsaPrivateKey, RsaPublicKey};
-use base64::{engine::general_purpose::STANDARD, Engine};
-
-use crate::{db::UserExt, error::HttpError, models::User, AppState};
+use rand::{rngs::OsRng, Rng};
+use uuid::Uuid;
 
+use crate::{db::{DBClient, KeyExt}, error::HttpError, models::{KeyType, User, UserKey}, utils::{encrypt::rewrap_aes_key, escrow::escrow_key, key_pair::{PrivateKey, PublicKey}, crypto_pool, key_store::KeyStore}, AppState};
 
+/// Files re-wrapped per query while moving shares off rotated keys.
+const REWRAP_BATCH_SIZE: i64 = 100;
 
 pub async fn generate_key(
     app_state: Arc<AppState>,
     user: User,
+    key_type: KeyType,
 ) -> Result<impl IntoResponse, HttpError> {
 
-    let mut rng = OsRng;
+    // The first key pair shares the user's id, like keys created before rotation
+    create_key_pair(&app_state, user.id, user.id, key_type).await?;
 
-    let private_key = RsaPrivateKey::new(&mut rng, 2048)
-    .map_err(|e| {
-        HttpError::server_error(e.to_string())
-    })?;
+    Ok((StatusCode::OK, "true"))
 
-    let public_key = RsaPublicKey::from(&private_key);
+}
 
-    let private_key_pem = private_key.to_pkcs1_pem(rsa::pkcs1::LineEnding::LF)
-    .map_err(|e| HttpError::server_error(e.to_string()))?;
+/// Replaces the user's active key pair. Older keys stay available for
+/// decryption until `rewrap_rotated_keys` has moved their files over.
+pub async fn rotate_key(
+    app_state: &AppState,
+    user_id: Uuid,
+    key_type: KeyType,
+) -> Result<UserKey, HttpError> {
+    create_key_pair(app_state, user_id, Uuid::new_v4(), key_type).await
+}
 
-    let public_key_prm = public_key.to_pkcs1_pem(rsa::pkcs1::LineEnding::LF)
-        .map_err(|e| HttpError::server_error(e.to_string()))?;
+async fn create_key_pair(
+    app_state: &AppState,
+    user_id: Uuid,
+    key_id: Uuid,
+    key_type: KeyType,
+) -> Result<UserKey, HttpError> {
 
-    let public_key_b64 = STANDARD.encode(public_key_prm.as_bytes());
+    let private_key = app_state.key_pool.take(key_type).await?;
 
-    let user_id = uuid::Uuid::parse_str(&user.id.to_string()).unwrap();
+    let public_key = private_key.public_key().encode()?;
 
-    app_state.db_client
-    .save_user_key(user_id.clone(), public_key_b64.clone())
-    .await
-    .map_err(|e| HttpError::server_error(e.to_string()))?;
+    // Written before the key is published so it can never be active without its private half
+    store_private_key(&*app_state.key_store, key_id, &private_key).await?;
 
-    let private_keys_dir = "assets/private_keys";
-    fs::create_dir_all(&private_keys_dir)
+    let key = app_state.db_client
+        .create_user_key(user_id, key_id, public_key, key_type)
+        .await
         .map_err(|e| HttpError::server_error(e.to_string()))?;
 
-    let pem_file_path = format!("{}/{}.pem",private_keys_dir,user_id.clone());
-    let mut file = File::create(&pem_file_path)
+    // A failure here is retried by the scheduled escrow run
+    if let Some(escrow_public_key) = &app_state.escrow_public_key {
+        if let Err(err) = escrow_key(&app_state.db_client, &*app_state.key_store, escrow_public_key, key_id).await {
+            eprintln!("Error escrowing key {}: {}", key_id, err.message);
+        }
+    }
+
+    Ok(key)
+}
+
+async fn store_private_key(key_store: &dyn KeyStore, key_id: Uuid, private_key: &PrivateKey) -> Result<(), HttpError> {
+    let private_key_pem = private_key.to_pem()?;
+
+    key_store
+        .store(&private_key_name(key_id), private_key_pem.as_bytes())
+        .await
+        .map_err(HttpError::server_error)
+}
+
+/// Writes a recovered private key back where `load_private_key` looks for it.
+pub async fn restore_private_key(key_store: &dyn KeyStore, key_id: Uuid, private_key: &PrivateKey) -> Result<(), HttpError> {
+    store_private_key(key_store, key_id, private_key).await
+}
+
+pub async fn delete_private_key(key_store: &dyn KeyStore, key_id: Uuid) -> Result<(), HttpError> {
+    key_store
+        .delete(&private_key_name(key_id))
+        .await
+        .map_err(HttpError::server_error)
+}
+
+pub async fn private_key_exists(key_store: &dyn KeyStore, key_id: Uuid) -> Result<bool, HttpError> {
+    key_store
+        .load(&private_key_name(key_id))
+        .await
+        .map(|private_key| private_key.is_some())
+        .map_err(HttpError::server_error)
+}
+
+/// Exports a private key as a passphrase-encrypted PKCS#8 PEM
+/// (PBES2 with scrypt and AES-256-CBC).
+pub async fn export_private_key(key_store: &dyn KeyStore, key_id: Uuid, passphrase: &str) -> Result<String, HttpError> {
+    let private_key = load_private_key(key_store, key_id).await?;
+    let passphrase = passphrase.to_string();
+
+    crypto_pool::run(move || private_key.to_encrypted_pem(&passphrase)).await?
+}
+
+/// Decrypts an exported key and stores it, after checking that it is the
+/// private half of `key`.
+pub async fn import_private_key(key_store: &dyn KeyStore, key: &UserKey, private_key_pem: &str, passphrase: &str) -> Result<(), HttpError> {
+    let private_key_pem = private_key_pem.to_string();
+    let passphrase = passphrase.to_string();
+
+    let private_key = crypto_pool::run(move || PrivateKey::from_encrypted_pem(&private_key_pem, &passphrase))
+        .await?
+        .ok_or_else(|| HttpError::bad_request(format!("Key {} could not be decrypted, check the passphrase", key.id)))?;
+
+    if private_key.public_key() != parse_public_key(&key.public_key)? {
+        return Err(HttpError::bad_request(format!("Key {} does not match this account", key.id)));
+    }
+
+    restore_private_key(key_store, key.id, &private_key).await
+}
+
+/// A random code to protect an export when the user has no passphrase of
+/// their own, e.g. `K7QX-M2PD-...`, about 120 bits.
+pub fn generate_recovery_code() -> String {
+    const ALPHABET: &[u8] = b"ABCDEFGHJKLMNPQRSTUVWXYZ23456789";
+
+    let mut rng = OsRng;
+    (0..6)
+        .map(|_| {
+            (0..4)
+                .map(|_| ALPHABET[rng.gen_range(0..ALPHABET.len())] as char)
+                .collect::<String>()
+        })
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+pub async fn load_private_key(key_store: &dyn KeyStore, key_id: Uuid) -> Result<PrivateKey, HttpError> {
+    let private_key = key_store
+        .load(&private_key_name(key_id))
+        .await
+        .map_err(HttpError::server_error)?
+        .ok_or_else(|| HttpError::server_error(format!("Private key {} is missing", key_id)))?;
+
+    let private_key = String::from_utf8(private_key)
+        .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+    PrivateKey::from_pem(&private_key)
+}
+
+/// Parses a public key as stored in the database, an age recipient for
+/// X25519 keys or a base64 PKCS#1 PEM for RSA.
+pub fn parse_public_key(public_key: &str) -> Result<PublicKey, HttpError> {
+    PublicKey::parse(public_key)
+}
+
+/// Moves files of still-active shares, and the sender's copies of file keys,
+/// from rotated keys to their owner's active key, then retires rotated keys nothing uses any more and deletes
+/// their private halves. Returns how many files were re-wrapped.
+pub async fn rewrap_rotated_keys(db_client: &DBClient, key_store: &dyn KeyStore) -> Result<usize, HttpError> {
+    let mut private_keys: HashMap<Uuid, PrivateKey> = HashMap::new();
+    let mut rewrapped = 0;
+
+    loop {
+        let files = db_client
+            .get_files_to_rewrap(REWRAP_BATCH_SIZE)
+            .await
+            .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+        if files.is_empty() {
+            break;
+        }
+
+        let mut progressed = false;
+
+        for file in files {
+            let private_key = match private_keys.entry(file.key_id) {
+                Entry::Occupied(entry) => entry.into_mut(),
+                Entry::Vacant(entry) => entry.insert(load_private_key(key_store, file.key_id).await?),
+            };
+
+            let encrypted_aes_key = rewrap_aes_key(
+                &file.encrypted_aes_key,
+                private_key,
+                &parse_public_key(&file.new_public_key)?
+            ).await?;
+
+            let updated = db_client
+                .update_file_key(file.id, file.sender, file.key_id, file.new_key_id, encrypted_aes_key)
+                .await
+                .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+            if updated {
+                rewrapped += 1;
+                progressed = true;
+            }
+        }
+
+        // Another run got to the whole batch first
+        if !progressed {
+            break;
+        }
+    }
+
+    let retired = db_client
+        .retire_unused_keys()
+        .await
         .map_err(|e| HttpError::server_error(e.to_string()))?;
 
-    file.write_all(private_key_pem.as_bytes())
-    .map_err(|e| HttpError::server_error(e.to_string()))?;
+    for key_id in retired {
+        if let Err(err) = key_store.delete(&private_key_name(key_id)).await {
+            eprintln!("Error removing retired key {}: {}", key_id, err);
+        }
+    }
 
-    Ok((StatusCode::OK, "true"))
+    Ok(rewrapped)
+}
 
-}
\ No newline at end of file
+fn private_key_name(key_id: Uuid) -> String {
+    format!("private_keys/{}.pem", key_id)
+}
@@ -0,0 +1,73 @@
+use tokio::{io::{AsyncReadExt, AsyncWriteExt}, net::{TcpListener, TcpStream}};
+
+/// The EICAR anti-virus test file, which every scanner reports.
+pub const EICAR: &[u8] = br"X5O!P%@AP[4\PZX54(P^)7CC)7}$EICAR-STANDARD-ANTIVIRUS-TEST-FILE!$H+H*";
+
+/// Largest stream accepted, like clamd's default `StreamMaxLength`.
+const MAX_STREAM_LENGTH: usize = 25 * 1024 * 1024;
+
+/// Starts an in-process stand-in for clamd on a random local port. It
+/// answers INSTREAM and reports content containing the EICAR test file as
+/// `Eicar-Test-Signature`. Returns its `host:port`.
+pub async fn spawn_mock_clamd() -> Result<String, String> {
+    let listener = TcpListener::bind("127.0.0.1:0")
+        .await
+        .map_err(|e| e.to_string())?;
+
+    let address = listener.local_addr().map_err(|e| e.to_string())?;
+
+    tokio::spawn(async move {
+        loop {
+            match listener.accept().await {
+                Ok((stream, _)) => {
+                    tokio::spawn(async move {
+                        if let Err(err) = handle_connection(stream).await {
+                            eprintln!("Mock clamd connection failed: {}", err);
+                        }
+                    });
+                },
+                Err(err) => {
+                    eprintln!("Mock clamd stopped: {}", err);
+                    break;
+                },
+            }
+        }
+    });
+
+    Ok(address.to_string())
+}
+
+async fn handle_connection(mut stream: TcpStream) -> std::io::Result<()> {
+    let mut command = [0u8; 10];
+    stream.read_exact(&mut command).await?;
+
+    if &command != b"zINSTREAM\0" {
+        return stream.write_all(b"UNKNOWN COMMAND\0").await;
+    }
+
+    let mut data = Vec::new();
+
+    loop {
+        let length = stream.read_u32().await? as usize;
+
+        if length == 0 {
+            break;
+        }
+
+        if data.len() + length > MAX_STREAM_LENGTH {
+            return stream.write_all(b"INSTREAM size limit exceeded. ERROR\0").await;
+        }
+
+        let start = data.len();
+        data.resize(start + length, 0);
+        stream.read_exact(&mut data[start..]).await?;
+    }
+
+    let reply: &[u8] = if data.windows(EICAR.len()).any(|window| window == EICAR) {
+        b"stream: Eicar-Test-Signature FOUND\0"
+    } else {
+        b"stream: OK\0"
+    };
+
+    stream.write_all(reply).await
+}
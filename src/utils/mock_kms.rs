@@ -0,0 +1,140 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{Path, State},
+    http::{HeaderMap, StatusCode},
+    routing::{get, post},
+    Json, Router,
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use rand::{distributions::Alphanumeric, rngs::OsRng, Rng};
+
+use crate::utils::key_store::{FilesystemKeyStore, KeyStore, SealBody, SecretBody, UnsealBody};
+
+#[derive(Debug)]
+struct MockKms {
+    store: FilesystemKeyStore,
+    token: String,
+}
+
+/// Starts an in-process stand-in for the remote key service on a random
+/// local port, backed by a filesystem store at `root`. Returns its base URL
+/// and the bearer token it expects.
+pub async fn spawn_mock_kms(root: &str) -> Result<(String, String), String> {
+    let token: String = OsRng
+        .sample_iter(&Alphanumeric)
+        .take(32)
+        .map(char::from)
+        .collect();
+
+    let state = Arc::new(MockKms {
+        store: FilesystemKeyStore::new(root)?,
+        token: token.clone(),
+    });
+
+    let app = Router::new()
+        .route("/v1/secrets/*name", get(load_secret).put(store_secret).delete(delete_secret))
+        .route("/v1/seal", post(seal))
+        .route("/v1/unseal", post(unseal))
+        .with_state(state);
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+        .await
+        .map_err(|e| e.to_string())?;
+
+    let address = listener.local_addr().map_err(|e| e.to_string())?;
+
+    tokio::spawn(async move {
+        if let Err(err) = axum::serve(listener, app).await {
+            eprintln!("Mock KMS stopped: {:?}", err);
+        }
+    });
+
+    Ok((format!("http://{}", address), token))
+}
+
+type MockKmsError = (StatusCode, String);
+
+fn authorize(state: &MockKms, headers: &HeaderMap) -> Result<(), MockKmsError> {
+    let authorized = headers
+        .get("authorization")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .is_some_and(|token| token == state.token);
+
+    if authorized {
+        Ok(())
+    } else {
+        Err((StatusCode::UNAUTHORIZED, "Invalid token".to_string()))
+    }
+}
+
+fn failure(message: String) -> MockKmsError {
+    (StatusCode::BAD_REQUEST, message)
+}
+
+fn decode(value: &str) -> Result<Vec<u8>, MockKmsError> {
+    STANDARD.decode(value).map_err(|e| failure(e.to_string()))
+}
+
+async fn load_secret(
+    State(state): State<Arc<MockKms>>,
+    Path(name): Path<String>,
+    headers: HeaderMap,
+) -> Result<Json<SecretBody>, MockKmsError> {
+    authorize(&state, &headers)?;
+
+    match state.store.load(&name).await.map_err(failure)? {
+        Some(secret) => Ok(Json(SecretBody { value: STANDARD.encode(secret) })),
+        None => Err((StatusCode::NOT_FOUND, format!("{} not found", name))),
+    }
+}
+
+async fn store_secret(
+    State(state): State<Arc<MockKms>>,
+    Path(name): Path<String>,
+    headers: HeaderMap,
+    Json(body): Json<SecretBody>,
+) -> Result<StatusCode, MockKmsError> {
+    authorize(&state, &headers)?;
+
+    state.store.store(&name, &decode(&body.value)?).await.map_err(failure)?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn delete_secret(
+    State(state): State<Arc<MockKms>>,
+    Path(name): Path<String>,
+    headers: HeaderMap,
+) -> Result<StatusCode, MockKmsError> {
+    authorize(&state, &headers)?;
+
+    state.store.delete(&name).await.map_err(failure)?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn seal(
+    State(state): State<Arc<MockKms>>,
+    headers: HeaderMap,
+    Json(body): Json<SealBody>,
+) -> Result<Json<UnsealBody>, MockKmsError> {
+    authorize(&state, &headers)?;
+
+    let sealed = state.store.seal(&decode(&body.plaintext)?).await.map_err(failure)?;
+
+    Ok(Json(UnsealBody { ciphertext: STANDARD.encode(sealed) }))
+}
+
+async fn unseal(
+    State(state): State<Arc<MockKms>>,
+    headers: HeaderMap,
+    Json(body): Json<UnsealBody>,
+) -> Result<Json<SealBody>, MockKmsError> {
+    authorize(&state, &headers)?;
+
+    let plaintext = state.store.unseal(&decode(&body.ciphertext)?).await.map_err(failure)?;
+
+    Ok(Json(SealBody { plaintext: STANDARD.encode(plaintext) }))
+}
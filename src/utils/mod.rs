@@ -2,4 +2,25 @@ pub mod password;
 pub mod token;
 pub mod keys;
 pub mod encrypt;
-pub mod decrypt;
\ No newline at end of file
+pub mod decrypt;
+pub mod rate_limit;
+pub mod zip;
+pub mod upload;
+pub mod range;
+pub mod digest;
+pub mod signing;
+pub mod escrow;
+pub mod key_store;
+#[cfg(any(test, feature = "mocks"))]
+pub mod mock_kms;
+pub mod key_pair;
+pub mod crypto_pool;
+pub mod key_pool;
+pub mod container;
+pub mod file_metadata;
+pub mod dedup;
+pub mod compression;
+pub mod quota;
+pub mod scanner;
+#[cfg(any(test, feature = "mocks"))]
+pub mod mock_clamd;
@@ -9,11 +9,15 @@ use argon2::{
     Argon2
 };
 
-use crate::error::ErrorMessage;
+use std::sync::OnceLock;
+
+use crate::{error::ErrorMessage, utils::crypto_pool};
 
 const MAX_PASSWORD_LENGTH: usize = 64;
 
-pub fn hash(password: impl Into<String>) -> Result<String, ErrorMessage> {
+static DUMMY_HASH: OnceLock<String> = OnceLock::new();
+
+pub async fn hash(password: impl Into<String>) -> Result<String, ErrorMessage> {
     let password = password.into();
 
     if password.is_empty() {
@@ -24,16 +28,17 @@ pub fn hash(password: impl Into<String>) -> Result<String, ErrorMessage> {
         return Err(ErrorMessage::ExceededMaxPasswordLength(MAX_PASSWORD_LENGTH));
     }
 
-    let salt = SaltString::generate(&mut OsRng);
-    let hash_password = Argon2::default()
-        .hash_password(password.as_bytes(), &salt)
-        .map_err(|_| ErrorMessage::HashingError)?
-        .to_string();
-
-    Ok(hash_password)
+    on_pool(move || {
+        let salt = SaltString::generate(&mut OsRng);
+        Argon2::default()
+            .hash_password(password.as_bytes(), &salt)
+            .map(|hash| hash.to_string())
+            .map_err(|_| ErrorMessage::HashingError)
+    })
+    .await
 }
 
-pub fn compare(password: &str, hashed_password: &str) -> Result<bool, ErrorMessage> {
+pub async fn compare(password: &str, hashed_password: &str) -> Result<bool, ErrorMessage> {
     if password.is_empty() {
         return Err(ErrorMessage::EmptyPassword);
     }
@@ -42,12 +47,43 @@ pub fn compare(password: &str, hashed_password: &str) -> Result<bool, ErrorMessa
         return Err(ErrorMessage::ExceededMaxPasswordLength(MAX_PASSWORD_LENGTH));
     }
 
-    let parsed_hash = PasswordHash::new(hashed_password)
-    .map_err(|_| ErrorMessage::InvalidHashFormat)?;
+    let password = password.to_string();
+    let hashed_password = hashed_password.to_string();
+
+    on_pool(move || {
+        let parsed_hash = PasswordHash::new(&hashed_password)
+            .map_err(|_| ErrorMessage::InvalidHashFormat)?;
+
+        Ok(Argon2::default()
+            .verify_password(password.as_bytes(), &parsed_hash)
+            .is_ok())
+    })
+    .await
+}
+
+/// Runs a full hash verification against a throwaway hash, so a lookup for an
+/// unknown account takes as long as a wrong password for a real one.
+pub async fn compare_dummy(password: &str) -> Result<bool, ErrorMessage> {
+    let dummy_hash = match DUMMY_HASH.get() {
+        Some(hash) => hash,
+        None => {
+            let hash = hash("dummy-password").await?;
+            DUMMY_HASH.get_or_init(|| hash)
+        }
+    };
 
-    let password_matched = Argon2::default()
-        .verify_password(password.as_bytes(), &parsed_hash)
-        .map_or(false, |_| true);
+    compare(password, dummy_hash).await?;
 
-    Ok(password_matched)
-}
\ No newline at end of file
+    Ok(false)
+}
+
+/// Argon2 runs on the crypto pool; a full queue surfaces as `ServerBusy`.
+async fn on_pool<T, F>(task: F) -> Result<T, ErrorMessage>
+where
+    F: FnOnce() -> Result<T, ErrorMessage> + Send + 'static,
+    T: Send + 'static,
+{
+    crypto_pool::run(task)
+        .await
+        .map_err(|_| ErrorMessage::ServerBusy)?
+}
@@ -0,0 +1,95 @@
+use uuid::Uuid;
+
+use crate::{db::QuotaExt, error::{ErrorMessage, HttpError}, models::StorageLimits, AppState};
+
+/// Bytes stored against a quota. `limit` is `None` when there is none.
+pub struct Usage {
+    pub used: i64,
+    pub limit: Option<i64>,
+}
+
+impl Usage {
+    /// A quota set by an admin wins over the default; either is unlimited at 0.
+    fn new(used: i64, quota_bytes: Option<i64>, default_quota: i64) -> Self {
+        let limit = Some(quota_bytes.unwrap_or(default_quota)).filter(|limit| *limit > 0);
+
+        Usage { used, limit }
+    }
+
+    fn allows(&self, additional: i64) -> bool {
+        self.limit.is_none_or(|limit| self.used.saturating_add(additional) <= limit)
+    }
+}
+
+pub struct OrganizationUsage {
+    pub id: Uuid,
+    pub name: String,
+    pub usage: Usage,
+}
+
+/// What a user has stored, counted from the `file_size` of the files they
+/// sent, and what was shared through each of their organizations.
+pub async fn storage_usage(
+    app_state: &AppState,
+    user_id: Uuid,
+) -> Result<(Usage, Vec<OrganizationUsage>), HttpError> {
+    let user_usage = app_state.db_client
+        .get_storage_usage(user_id)
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+    let organization_usage = app_state.db_client
+        .get_organization_storage_usage(user_id)
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+    let usage = Usage::new(user_usage.used, user_usage.quota_bytes, app_state.env.user_storage_quota);
+
+    let organizations = organization_usage
+        .into_iter()
+        .map(|organization| OrganizationUsage {
+            id: organization.organization_id,
+            name: organization.name,
+            usage: Usage::new(organization.used, organization.quota_bytes, app_state.env.organization_storage_quota),
+        })
+        .collect();
+
+    Ok((usage, organizations))
+}
+
+/// Rejects a single file over `MAX_FILE_SIZE`.
+pub fn check_file_size(app_state: &AppState, size: i64) -> Result<(), HttpError> {
+    if size > app_state.env.max_file_size {
+        return Err(ErrorMessage::FileTooLarge(app_state.env.max_file_size).into());
+    }
+
+    Ok(())
+}
+
+/// Rejects storing `additional` bytes more for a user when that would take
+/// them over quota, or the organization the share is made through. Returns
+/// the limits to store the files under, which are checked again then.
+pub async fn check_quota(
+    app_state: &AppState,
+    user_id: Uuid,
+    organization_id: Option<Uuid>,
+    additional: i64,
+) -> Result<StorageLimits, HttpError> {
+    let (usage, organizations) = storage_usage(app_state, user_id).await?;
+
+    if !usage.allows(additional) {
+        return Err(ErrorMessage::QuotaExceeded.into());
+    }
+
+    let organization = organizations.into_iter()
+        .find(|organization| Some(organization.id) == organization_id);
+
+    if let Some(organization) = organization.as_ref().filter(|organization| !organization.usage.allows(additional)) {
+        return Err(ErrorMessage::OrganizationQuotaExceeded(organization.name.clone()).into());
+    }
+
+    Ok(StorageLimits {
+        user: usage.limit,
+        organization: organization.and_then(|organization| organization.usage.limit),
+    })
+}
@@ -0,0 +1,84 @@
+/// How to answer a request, based on its `Range` header and the file size.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ByteRange {
+    Full,
+    /// Inclusive bounds, always inside the file
+    Partial { start: u64, end: u64 },
+    Unsatisfiable,
+}
+
+/// Parses a single `bytes=` range. Other units, several ranges and malformed
+/// values fall back to the full body, which the spec allows.
+pub fn parse_range(header: Option<&str>, size: u64) -> ByteRange {
+    let spec = match header.and_then(|value| value.trim().strip_prefix("bytes=")) {
+        Some(spec) if !spec.contains(',') => spec.trim(),
+        _ => return ByteRange::Full,
+    };
+
+    let (first, last) = match spec.split_once('-') {
+        Some(bounds) => bounds,
+        None => return ByteRange::Full,
+    };
+
+    let (start, end) = match (first.parse::<u64>().ok(), last.parse::<u64>().ok()) {
+        // bytes=a-b
+        (Some(start), Some(end)) if start <= end => (start, end.min(size.saturating_sub(1))),
+        // bytes=a-
+        (Some(start), None) if last.is_empty() => (start, size.saturating_sub(1)),
+        // bytes=-n, the last n bytes
+        (None, Some(suffix)) if first.is_empty() => {
+            if suffix == 0 {
+                return ByteRange::Unsatisfiable;
+            }
+            (size.saturating_sub(suffix), size.saturating_sub(1))
+        }
+        _ => return ByteRange::Full,
+    };
+
+    if start >= size {
+        return ByteRange::Unsatisfiable;
+    }
+
+    ByteRange::Partial { start, end }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_bounded_ranges() {
+        assert_eq!(parse_range(Some("bytes=0-99"), 1000), ByteRange::Partial { start: 0, end: 99 });
+        assert_eq!(parse_range(Some(" bytes= 10-10 "), 1000), ByteRange::Partial { start: 10, end: 10 });
+        // The end is clamped to the last byte
+        assert_eq!(parse_range(Some("bytes=900-5000"), 1000), ByteRange::Partial { start: 900, end: 999 });
+    }
+
+    #[test]
+    fn parses_open_and_suffix_ranges() {
+        assert_eq!(parse_range(Some("bytes=500-"), 1000), ByteRange::Partial { start: 500, end: 999 });
+        assert_eq!(parse_range(Some("bytes=-100"), 1000), ByteRange::Partial { start: 900, end: 999 });
+        // A suffix longer than the file is the whole file
+        assert_eq!(parse_range(Some("bytes=-5000"), 1000), ByteRange::Partial { start: 0, end: 999 });
+    }
+
+    #[test]
+    fn rejects_ranges_outside_the_file() {
+        assert_eq!(parse_range(Some("bytes=1000-"), 1000), ByteRange::Unsatisfiable);
+        assert_eq!(parse_range(Some("bytes=1000-1001"), 1000), ByteRange::Unsatisfiable);
+        assert_eq!(parse_range(Some("bytes=-0"), 1000), ByteRange::Unsatisfiable);
+        assert_eq!(parse_range(Some("bytes=0-"), 0), ByteRange::Unsatisfiable);
+        assert_eq!(parse_range(Some("bytes=-10"), 0), ByteRange::Unsatisfiable);
+    }
+
+    #[test]
+    fn falls_back_to_the_full_body() {
+        assert_eq!(parse_range(None, 1000), ByteRange::Full);
+        assert_eq!(parse_range(Some("items=0-1"), 1000), ByteRange::Full);
+        assert_eq!(parse_range(Some("bytes=0-1,5-6"), 1000), ByteRange::Full);
+        assert_eq!(parse_range(Some("bytes=5"), 1000), ByteRange::Full);
+        assert_eq!(parse_range(Some("bytes=9-3"), 1000), ByteRange::Full);
+        assert_eq!(parse_range(Some("bytes=a-b"), 1000), ByteRange::Full);
+        assert_eq!(parse_range(Some("bytes=-"), 1000), ByteRange::Full);
+    }
+}
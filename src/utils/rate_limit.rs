@@ -0,0 +1,193 @@
+use std::{collections::HashMap, fmt::Debug, sync::{Arc, Mutex}, time::{Duration, Instant}};
+
+use async_trait::async_trait;
+
+use crate::db::{DBClient, RateLimitExt};
+
+// Full buckets are dropped at most this often, so no request pays for a
+// sweep of the whole map
+const SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, Copy)]
+pub struct RateLimitDecision {
+    pub allowed: bool,
+    pub limit: u32,
+    pub remaining: u32,
+    pub retry_after: Duration,
+    pub reset_after: Duration,
+}
+
+impl RateLimitDecision {
+    fn new(allowed: bool, tokens: f64, capacity: f64, refill_per_second: f64) -> Self {
+        let retry_after = if allowed {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64((1.0 - tokens) / refill_per_second)
+        };
+
+        RateLimitDecision {
+            allowed,
+            limit: capacity as u32,
+            remaining: tokens.max(0.0) as u32,
+            retry_after,
+            reset_after: Duration::from_secs_f64((capacity - tokens).max(0.0) / refill_per_second),
+        }
+    }
+}
+
+#[async_trait]
+pub trait RateLimitStore: Debug + Send + Sync {
+    /// Takes one token from the bucket stored under `key`, creating it full.
+    async fn take(
+        &self,
+        key: &str,
+        capacity: u32,
+        refill_per_minute: u32,
+    ) -> Result<RateLimitDecision, String>;
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Bucket {
+    tokens: f64,
+    capacity: f64,
+    refill_per_second: f64,
+    updated_at: Instant,
+}
+
+impl Bucket {
+    fn refilled(&self, now: Instant) -> f64 {
+        let elapsed = now.duration_since(self.updated_at).as_secs_f64();
+        (self.tokens + elapsed * self.refill_per_second).min(self.capacity)
+    }
+}
+
+#[derive(Debug)]
+struct Buckets {
+    buckets: HashMap<String, Bucket>,
+    swept_at: Instant,
+}
+
+/// Process-local buckets, only suitable for a single instance.
+#[derive(Debug, Clone)]
+pub struct MemoryRateLimitStore {
+    buckets: Arc<Mutex<Buckets>>,
+}
+
+impl Default for MemoryRateLimitStore {
+    fn default() -> Self {
+        MemoryRateLimitStore {
+            buckets: Arc::new(Mutex::new(Buckets {
+                buckets: HashMap::new(),
+                swept_at: Instant::now(),
+            })),
+        }
+    }
+}
+
+impl MemoryRateLimitStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn take_sync(&self, key: &str, capacity: u32, refill_per_minute: u32) -> RateLimitDecision {
+        let capacity = capacity.max(1) as f64;
+        let refill_per_second = refill_per_minute.max(1) as f64 / 60.0;
+        let now = Instant::now();
+        let mut state = self.buckets.lock().unwrap();
+
+        if now.duration_since(state.swept_at) >= SWEEP_INTERVAL {
+            state.buckets.retain(|_, bucket| bucket.refilled(now) < bucket.capacity);
+            state.swept_at = now;
+        }
+
+        let bucket = state.buckets.entry(key.to_string()).or_insert(Bucket {
+            tokens: capacity,
+            capacity,
+            refill_per_second,
+            updated_at: now,
+        });
+
+        bucket.tokens = bucket.refilled(now);
+        bucket.updated_at = now;
+
+        let allowed = bucket.tokens >= 1.0;
+        if allowed {
+            bucket.tokens -= 1.0;
+        }
+
+        RateLimitDecision::new(allowed, bucket.tokens, capacity, refill_per_second)
+    }
+}
+
+#[async_trait]
+impl RateLimitStore for MemoryRateLimitStore {
+    async fn take(
+        &self,
+        key: &str,
+        capacity: u32,
+        refill_per_minute: u32,
+    ) -> Result<RateLimitDecision, String> {
+        Ok(self.take_sync(key, capacity, refill_per_minute))
+    }
+}
+
+/// Buckets kept in Postgres so every instance shares the same limits.
+#[derive(Debug, Clone)]
+pub struct PostgresRateLimitStore {
+    db_client: DBClient,
+}
+
+impl PostgresRateLimitStore {
+    pub fn new(db_client: DBClient) -> Self {
+        PostgresRateLimitStore { db_client }
+    }
+}
+
+#[async_trait]
+impl RateLimitStore for PostgresRateLimitStore {
+    async fn take(
+        &self,
+        key: &str,
+        capacity: u32,
+        refill_per_minute: u32,
+    ) -> Result<RateLimitDecision, String> {
+        let capacity = capacity.max(1) as f64;
+        let refill_per_second = refill_per_minute.max(1) as f64 / 60.0;
+
+        let (allowed, tokens) = self.db_client
+            .take_rate_limit_token(key, capacity, refill_per_second)
+            .await
+            .map_err(|e| e.to_string())?;
+
+        Ok(RateLimitDecision::new(allowed, tokens, capacity, refill_per_second))
+    }
+}
+
+/// Fixed-size in-memory limiter used where a single policy applies, like login.
+#[derive(Debug, Clone)]
+pub struct TokenBucketLimiter {
+    capacity: u32,
+    refill_per_minute: u32,
+    store: MemoryRateLimitStore,
+}
+
+impl TokenBucketLimiter {
+    pub fn new(capacity: u32, refill_per_minute: u32) -> Self {
+        TokenBucketLimiter {
+            capacity,
+            refill_per_minute,
+            store: MemoryRateLimitStore::new(),
+        }
+    }
+
+    /// Takes one token for `key`, or returns how long until one is available.
+    pub fn check(&self, key: &str) -> Result<(), Duration> {
+        let decision = self.store.take_sync(key, self.capacity, self.refill_per_minute);
+
+        if decision.allowed {
+            Ok(())
+        } else {
+            Err(decision.retry_after)
+        }
+    }
+}
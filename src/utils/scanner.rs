@@ -0,0 +1,211 @@
+use std::{fmt::Debug, time::Duration};
+
+use async_trait::async_trait;
+use tokio::{io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt}, net::{TcpStream, UnixStream}};
+
+use crate::{error::{ErrorMessage, HttpError}, AppState};
+
+/// Bytes sent to clamd per INSTREAM chunk.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ScanVerdict {
+    Clean,
+    /// Malware was found; the name of what was found.
+    Infected(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ScanError {
+    /// The file is over the scanner's size limit, clamd's `StreamMaxLength`.
+    TooLarge,
+    /// The scanner could not be reached, timed out or failed.
+    Unavailable(String),
+}
+
+/// Inspects file content for malware. Uploads are scanned as plaintext,
+/// before they are encrypted.
+#[async_trait]
+pub trait MalwareScanner: Debug + Send + Sync {
+    async fn scan(&self, data: &[u8]) -> Result<ScanVerdict, ScanError>;
+}
+
+/// ClamAV's daemon, over its Unix socket (an absolute path) or TCP
+/// (`host:port`), using the INSTREAM command.
+#[derive(Debug, Clone)]
+pub struct ClamdScanner {
+    address: String,
+    timeout: Duration,
+}
+
+impl ClamdScanner {
+    pub fn new(address: impl Into<String>, timeout: Duration) -> Self {
+        ClamdScanner {
+            address: address.into(),
+            timeout,
+        }
+    }
+
+    async fn instream(&self, data: &[u8]) -> Result<ScanVerdict, ScanError> {
+        let connect_error = |e: std::io::Error| ScanError::Unavailable(format!("{}: {}", self.address, e));
+
+        if self.address.starts_with('/') {
+            let stream = UnixStream::connect(&self.address)
+                .await
+                .map_err(connect_error)?;
+
+            instream(stream, data).await
+        } else {
+            let stream = TcpStream::connect(&self.address)
+                .await
+                .map_err(connect_error)?;
+
+            instream(stream, data).await
+        }
+    }
+}
+
+#[async_trait]
+impl MalwareScanner for ClamdScanner {
+    async fn scan(&self, data: &[u8]) -> Result<ScanVerdict, ScanError> {
+        tokio::time::timeout(self.timeout, self.instream(data))
+            .await
+            .map_err(|_| ScanError::Unavailable(format!("{}: scan timed out", self.address)))?
+    }
+}
+
+/// Streams `data` as length-prefixed chunks ended by an empty one, then reads
+/// the single reply clamd sends before closing the connection. clamd replies
+/// early and stops reading once the stream is over its size limit, so a
+/// failed write still looks for that reply.
+async fn instream<S: AsyncRead + AsyncWrite + Unpin>(mut stream: S, data: &[u8]) -> Result<ScanVerdict, ScanError> {
+    let io_error = |e: std::io::Error| ScanError::Unavailable(format!("clamd: {}", e));
+
+    let sent = async {
+        stream.write_all(b"zINSTREAM\0").await?;
+
+        for chunk in data.chunks(CHUNK_SIZE) {
+            stream.write_all(&(chunk.len() as u32).to_be_bytes()).await?;
+            stream.write_all(chunk).await?;
+        }
+
+        stream.write_all(&0u32.to_be_bytes()).await
+    }
+    .await;
+
+    let mut reply = Vec::new();
+    let received = stream.read_to_end(&mut reply).await;
+
+    match (sent, received) {
+        (Err(e), _) if reply.is_empty() => Err(io_error(e)),
+        (_, Err(e)) if reply.is_empty() => Err(io_error(e)),
+        _ => parse_reply(&reply),
+    }
+}
+
+/// Replies look like `stream: OK`, `stream: <name> FOUND` or `<reason> ERROR`.
+fn parse_reply(reply: &[u8]) -> Result<ScanVerdict, ScanError> {
+    let reply = String::from_utf8_lossy(reply);
+    let reply = reply.trim_end_matches(['\0', '\n']);
+    let result = reply.strip_prefix("stream: ").unwrap_or(reply);
+
+    if result == "OK" {
+        return Ok(ScanVerdict::Clean);
+    }
+
+    if result == "INSTREAM size limit exceeded. ERROR" {
+        return Err(ScanError::TooLarge);
+    }
+
+    match result.strip_suffix(" FOUND") {
+        Some(name) => Ok(ScanVerdict::Infected(name.to_string())),
+        None => Err(ScanError::Unavailable(format!("clamd: {}", reply))),
+    }
+}
+
+/// Scans a file with the configured scanner. Returns `None` when scanning is
+/// off. Uploads are refused while the scanner cannot be reached, and files
+/// over its size limit are refused outright.
+pub async fn scan_file(app_state: &AppState, data: &[u8]) -> Result<Option<ScanVerdict>, HttpError> {
+    let Some(scanner) = &app_state.scanner else {
+        return Ok(None);
+    };
+
+    match scanner.scan(data).await {
+        Ok(verdict) => Ok(Some(verdict)),
+        Err(ScanError::TooLarge) => Err(ErrorMessage::TooLargeToScan.into()),
+        Err(ScanError::Unavailable(err)) => {
+            eprintln!("Malware scan failed: {}", err);
+            Err(ErrorMessage::ScannerUnavailable.into())
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::mock_clamd::{spawn_mock_clamd, EICAR};
+
+    async fn mock_scanner() -> ClamdScanner {
+        ClamdScanner::new(spawn_mock_clamd().await.unwrap(), Duration::from_secs(30))
+    }
+
+    #[test]
+    fn parses_replies() {
+        assert_eq!(parse_reply(b"stream: OK\0"), Ok(ScanVerdict::Clean));
+        assert_eq!(parse_reply(b"stream: OK\n"), Ok(ScanVerdict::Clean));
+        assert_eq!(
+            parse_reply(b"stream: Win.Test.EICAR_HDB-1 FOUND\0"),
+            Ok(ScanVerdict::Infected("Win.Test.EICAR_HDB-1".to_string()))
+        );
+        assert_eq!(parse_reply(b"INSTREAM size limit exceeded. ERROR\0"), Err(ScanError::TooLarge));
+        assert_eq!(
+            parse_reply(b"stream: Can't allocate memory ERROR\0"),
+            Err(ScanError::Unavailable("clamd: stream: Can't allocate memory ERROR".to_string()))
+        );
+        assert!(matches!(parse_reply(b""), Err(ScanError::Unavailable(_))));
+    }
+
+    #[tokio::test]
+    async fn mock_reports_clean_files() {
+        let scanner = mock_scanner().await;
+
+        assert_eq!(scanner.scan(b"hello").await, Ok(ScanVerdict::Clean));
+        assert_eq!(scanner.scan(b"").await, Ok(ScanVerdict::Clean));
+    }
+
+    #[tokio::test]
+    async fn mock_reports_eicar_across_chunks() {
+        let scanner = mock_scanner().await;
+
+        // The signature straddles the first chunk boundary
+        let mut data = vec![b'a'; CHUNK_SIZE - 10];
+        data.extend(EICAR);
+
+        assert_eq!(scanner.scan(&data).await, Ok(ScanVerdict::Infected("Eicar-Test-Signature".to_string())));
+    }
+
+    #[tokio::test]
+    async fn mock_rejects_streams_over_its_limit() {
+        let scanner = mock_scanner().await;
+
+        assert_eq!(scanner.scan(&vec![0u8; 26 * 1024 * 1024]).await, Err(ScanError::TooLarge));
+    }
+
+    #[tokio::test]
+    async fn reports_error_replies() {
+        let (client, mut server) = tokio::io::duplex(CHUNK_SIZE);
+
+        tokio::spawn(async move {
+            // The command, one chunk of "hello" and the empty one that ends the stream
+            let mut request = [0u8; 10 + 4 + 5 + 4];
+            server.read_exact(&mut request).await.unwrap();
+            server.write_all(b"stream: Can't allocate memory ERROR\0").await.unwrap();
+        });
+
+        assert_eq!(
+            instream(client, b"hello").await,
+            Err(ScanError::Unavailable("clamd: stream: Can't allocate memory ERROR".to_string()))
+        );
+    }
+}
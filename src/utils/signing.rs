@@ -0,0 +1,104 @@
+use base64::{engine::general_purpose::STANDARD, Engine};
+use ed25519_dalek::{pkcs8::{spki::der::pem::LineEnding, DecodePrivateKey, EncodePrivateKey}, Signature, Signer, SigningKey, VerifyingKey};
+use rand::rngs::OsRng;
+use uuid::Uuid;
+
+use crate::{db::UserExt, error::HttpError, utils::key_store::KeyStore, AppState};
+
+/// Creates an Ed25519 key pair for a user. The private key is kept in the
+/// key store next to their RSA key and the public key is published in
+/// `signing_keys`.
+pub async fn generate_signing_key(
+    app_state: &AppState,
+    user_id: Uuid,
+) -> Result<SigningKey, HttpError> {
+    let signing_key = SigningKey::generate(&mut OsRng);
+
+    let private_key_pem = signing_key.to_pkcs8_pem(LineEnding::LF)
+        .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+    app_state.key_store
+        .store(&signing_key_name(user_id), private_key_pem.as_bytes())
+        .await
+        .map_err(HttpError::server_error)?;
+
+    app_state.db_client
+        .save_signing_key(user_id, encode_public_key(&signing_key.verifying_key()))
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+    Ok(signing_key)
+}
+
+/// Loads a user's signing key, creating one for accounts registered before
+/// signatures existed.
+pub async fn signing_key(
+    app_state: &AppState,
+    user_id: Uuid,
+) -> Result<SigningKey, HttpError> {
+    let has_key = app_state.db_client
+        .get_signing_key(user_id)
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?
+        .is_some();
+
+    let private_key_pem = app_state.key_store
+        .load(&signing_key_name(user_id))
+        .await
+        .map_err(HttpError::server_error)?;
+
+    let private_key_pem = match private_key_pem {
+        Some(private_key_pem) if has_key => String::from_utf8(private_key_pem)
+            .map_err(|e| HttpError::server_error(e.to_string()))?,
+        _ => return generate_signing_key(app_state, user_id).await,
+    };
+
+    SigningKey::from_pkcs8_pem(&private_key_pem)
+        .map_err(|e| HttpError::server_error(e.to_string()))
+}
+
+pub async fn delete_signing_key(key_store: &dyn KeyStore, user_id: Uuid) -> Result<(), HttpError> {
+    key_store
+        .delete(&signing_key_name(user_id))
+        .await
+        .map_err(HttpError::server_error)
+}
+
+pub fn encode_public_key(verifying_key: &VerifyingKey) -> String {
+    STANDARD.encode(verifying_key.as_bytes())
+}
+
+/// The bytes a sender signs for one file. Recipients can rebuild it from the
+/// retrieve response and the SHA-256 of what they downloaded.
+pub fn signed_message(sender_id: Uuid, file_name: &str, file_size: i64, digest: &[u8]) -> Vec<u8> {
+    let digest_hex: String = digest.iter().map(|byte| format!("{:02x}", byte)).collect();
+
+    format!(
+        "secureshare-signature-v1\n{}\n{}\n{}\n{}",
+        sender_id, file_name, file_size, digest_hex
+    ).into_bytes()
+}
+
+pub fn sign(signing_key: &SigningKey, message: &[u8]) -> Vec<u8> {
+    signing_key.sign(message).to_bytes().to_vec()
+}
+
+/// Checks `signature` over `message` against a base64 Ed25519 public key.
+/// Malformed keys or signatures count as invalid.
+pub fn verify(public_key: &str, message: &[u8], signature: &[u8]) -> bool {
+    let verifying_key = STANDARD.decode(public_key)
+        .ok()
+        .and_then(|bytes| <[u8; 32]>::try_from(bytes.as_slice()).ok())
+        .and_then(|bytes| VerifyingKey::from_bytes(&bytes).ok());
+
+    let signature = Signature::from_slice(signature).ok();
+
+    match (verifying_key, signature) {
+        (Some(verifying_key), Some(signature)) => verifying_key.verify_strict(message, &signature).is_ok(),
+        _ => false,
+    }
+}
+
+fn signing_key_name(user_id: Uuid) -> String {
+    format!("signing_keys/{}.pem", user_id)
+}
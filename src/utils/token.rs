@@ -39,7 +39,7 @@ pub fn create_token (
 pub fn decode_token<T: Into<String>>(
     token: T,
     secret: &[u8],
-) -> Result<String, HttpError> {
+) -> Result<TokenClaims, HttpError> {
     let decode = decode::<TokenClaims>(
         &token.into(), 
         &DecodingKey::from_secret(secret), 
@@ -47,7 +47,7 @@ pub fn decode_token<T: Into<String>>(
     );
 
     match decode {
-        Ok(token) => Ok(token.claims.sub),
+        Ok(token) => Ok(token.claims),
         Err(_) => Err(HttpError::unauthorized(ErrorMessage::InvalidToken.to_string()))
     }
 }
\ No newline at end of file
@@ -0,0 +1,86 @@
+use std::{collections::{HashMap, HashSet}, path::PathBuf, sync::{Arc, Mutex}};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+
+pub const TUS_VERSION: &str = "1.0.0";
+pub const TUS_EXTENSIONS: &str = "creation,expiration,termination";
+pub const UPLOADS_DIR: &str = "assets/uploads";
+
+pub fn upload_path(upload_id: Uuid) -> PathBuf {
+    let mut path = PathBuf::from(UPLOADS_DIR);
+    path.push(format!("{}.part", upload_id));
+    path
+}
+
+/// Parses an `Upload-Metadata` header: comma separated `key base64value`
+/// pairs, where the value may be left out.
+pub fn parse_metadata(header: &str) -> Result<HashMap<String, String>, String> {
+    let mut metadata = HashMap::new();
+
+    for pair in header.split(',').map(str::trim).filter(|pair| !pair.is_empty()) {
+        let mut parts = pair.splitn(2, ' ');
+        let key = parts.next().unwrap_or_default();
+
+        let value = match parts.next() {
+            Some(encoded) => {
+                let bytes = STANDARD.decode(encoded.trim())
+                    .map_err(|_| format!("Metadata value for {} is not valid base64", key))?;
+
+                String::from_utf8(bytes)
+                    .map_err(|_| format!("Metadata value for {} is not valid UTF-8", key))?
+            }
+            None => String::new(),
+        };
+
+        if metadata.insert(key.to_string(), value).is_some() {
+            return Err(format!("Metadata key {} is repeated", key));
+        }
+    }
+
+    Ok(metadata)
+}
+
+/// Formats a timestamp as an HTTP date, as used by `Upload-Expires`.
+pub fn http_date(time: DateTime<Utc>) -> String {
+    time.format("%a, %d %b %Y %H:%M:%S GMT").to_string()
+}
+
+/// Uploads currently being written by a request. Uploads are stored on local
+/// disk, so an in-process lock is enough to keep two PATCH requests from
+/// appending to the same upload.
+#[derive(Debug, Default)]
+pub struct UploadLocks {
+    locked: Mutex<HashSet<Uuid>>,
+}
+
+impl UploadLocks {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn try_lock(self: &Arc<Self>, upload_id: Uuid) -> Option<UploadGuard> {
+        let mut locked = self.locked.lock().unwrap();
+
+        if !locked.insert(upload_id) {
+            return None;
+        }
+
+        Some(UploadGuard {
+            locks: self.clone(),
+            upload_id,
+        })
+    }
+}
+
+pub struct UploadGuard {
+    locks: Arc<UploadLocks>,
+    upload_id: Uuid,
+}
+
+impl Drop for UploadGuard {
+    fn drop(&mut self) {
+        self.locks.locked.lock().unwrap().remove(&self.upload_id);
+    }
+}
@@ -0,0 +1,222 @@
+use chrono::{DateTime, Datelike, Timelike, Utc};
+
+const LOCAL_HEADER_SIGNATURE: u32 = 0x04034b50;
+const CENTRAL_HEADER_SIGNATURE: u32 = 0x02014b50;
+const END_OF_CENTRAL_DIRECTORY_SIGNATURE: u32 = 0x06054b50;
+const VERSION: u16 = 20;
+// Names are always written as UTF-8
+const UTF8_FLAG: u16 = 0x0800;
+
+struct CentralEntry {
+    name: String,
+    name_length: u16,
+    crc: u32,
+    size: u32,
+    dos_time: u16,
+    dos_date: u16,
+    offset: u32,
+}
+
+/// Builds an uncompressed ZIP archive (without ZIP64, so up to 4 GiB) one
+/// entry at a time, so each entry can be sent as soon as it is written. Only
+/// the central directory is kept until `finish`.
+pub struct ZipWriter {
+    offset: u64,
+    entries: Vec<CentralEntry>,
+}
+
+impl ZipWriter {
+    pub fn new() -> Self {
+        ZipWriter {
+            offset: 0,
+            entries: Vec::new(),
+        }
+    }
+
+    /// Returns the local header followed by the entry's data.
+    pub fn entry(&mut self, name: &str, data: &[u8], modified: DateTime<Utc>) -> Result<Vec<u8>, String> {
+        let name_length = u16::try_from(name.len()).map_err(|_| "File name is too long for a ZIP archive".to_string())?;
+        let size = u32::try_from(data.len()).map_err(|_| "File is too large for a ZIP archive".to_string())?;
+        let offset = u32::try_from(self.offset).map_err(|_| "Archive is too large".to_string())?;
+        let (dos_time, dos_date) = dos_date_time(modified);
+        let crc = crc32fast::hash(data);
+
+        let mut chunk = Vec::with_capacity(30 + name.len() + data.len());
+        put_u32(&mut chunk, LOCAL_HEADER_SIGNATURE);
+        put_u16(&mut chunk, VERSION);
+        put_u16(&mut chunk, UTF8_FLAG);
+        put_u16(&mut chunk, 0); // stored
+        put_u16(&mut chunk, dos_time);
+        put_u16(&mut chunk, dos_date);
+        put_u32(&mut chunk, crc);
+        put_u32(&mut chunk, size);
+        put_u32(&mut chunk, size);
+        put_u16(&mut chunk, name_length);
+        put_u16(&mut chunk, 0);
+        chunk.extend_from_slice(name.as_bytes());
+        chunk.extend_from_slice(data);
+
+        self.offset += chunk.len() as u64;
+        self.entries.push(CentralEntry {
+            name: name.to_string(),
+            name_length,
+            crc,
+            size,
+            dos_time,
+            dos_date,
+            offset,
+        });
+
+        Ok(chunk)
+    }
+
+    /// Returns the central directory and end record that close the archive.
+    pub fn finish(self) -> Result<Vec<u8>, String> {
+        let directory_offset = u32::try_from(self.offset).map_err(|_| "Archive is too large".to_string())?;
+        let entry_count = u16::try_from(self.entries.len()).map_err(|_| "Too many files for a ZIP archive".to_string())?;
+        let mut chunk = Vec::new();
+
+        for entry in &self.entries {
+            put_u32(&mut chunk, CENTRAL_HEADER_SIGNATURE);
+            put_u16(&mut chunk, VERSION);
+            put_u16(&mut chunk, VERSION);
+            put_u16(&mut chunk, UTF8_FLAG);
+            put_u16(&mut chunk, 0);
+            put_u16(&mut chunk, entry.dos_time);
+            put_u16(&mut chunk, entry.dos_date);
+            put_u32(&mut chunk, entry.crc);
+            put_u32(&mut chunk, entry.size);
+            put_u32(&mut chunk, entry.size);
+            put_u16(&mut chunk, entry.name_length);
+            put_u16(&mut chunk, 0); // extra field length
+            put_u16(&mut chunk, 0); // comment length
+            put_u16(&mut chunk, 0); // disk number
+            put_u16(&mut chunk, 0); // internal attributes
+            put_u32(&mut chunk, 0); // external attributes
+            put_u32(&mut chunk, entry.offset);
+            chunk.extend_from_slice(entry.name.as_bytes());
+        }
+
+        let directory_size = u32::try_from(chunk.len()).map_err(|_| "Archive is too large".to_string())?;
+
+        put_u32(&mut chunk, END_OF_CENTRAL_DIRECTORY_SIGNATURE);
+        put_u16(&mut chunk, 0);
+        put_u16(&mut chunk, 0);
+        put_u16(&mut chunk, entry_count);
+        put_u16(&mut chunk, entry_count);
+        put_u32(&mut chunk, directory_size);
+        put_u32(&mut chunk, directory_offset);
+        put_u16(&mut chunk, 0);
+
+        Ok(chunk)
+    }
+}
+
+impl Default for ZipWriter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn dos_date_time(time: DateTime<Utc>) -> (u16, u16) {
+    // DOS timestamps start in 1980 and have two second precision
+    if time.year() < 1980 {
+        return (0, (1 << 5) | 1);
+    }
+
+    let dos_time = ((time.hour() << 11) | (time.minute() << 5) | (time.second() / 2)) as u16;
+    let dos_date = (((time.year() - 1980) as u32) << 9 | (time.month() << 5) | time.day()) as u16;
+
+    (dos_time, dos_date)
+}
+
+fn put_u16(buffer: &mut Vec<u8>, value: u16) {
+    buffer.extend_from_slice(&value.to_le_bytes());
+}
+
+fn put_u32(buffer: &mut Vec<u8>, value: u32) {
+    buffer.extend_from_slice(&value.to_le_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    fn u16_at(buffer: &[u8], offset: usize) -> u16 {
+        u16::from_le_bytes(buffer[offset..offset + 2].try_into().unwrap())
+    }
+
+    fn u32_at(buffer: &[u8], offset: usize) -> u32 {
+        u32::from_le_bytes(buffer[offset..offset + 4].try_into().unwrap())
+    }
+
+    #[test]
+    fn writes_entries_and_central_directory() {
+        let modified = Utc.with_ymd_and_hms(2024, 3, 15, 10, 30, 20).unwrap();
+        let mut writer = ZipWriter::new();
+
+        let first = writer.entry("a.txt", b"hello", modified).unwrap();
+        let second = writer.entry("dir/é.bin", &[0u8; 3], modified).unwrap();
+        let end = writer.finish().unwrap();
+
+        assert_eq!(u32_at(&first, 0), LOCAL_HEADER_SIGNATURE);
+        assert_eq!(u32_at(&first, 14), crc32fast::hash(b"hello"));
+        assert_eq!(u32_at(&first, 18), 5);
+        assert_eq!(u16_at(&first, 26), 5);
+        assert_eq!(&first[30..35], b"a.txt");
+        assert_eq!(&first[35..], b"hello");
+
+        let name = "dir/é.bin".as_bytes();
+        assert_eq!(u16_at(&second, 26) as usize, name.len());
+        assert_eq!(&second[30..30 + name.len()], name);
+
+        let archive = [first.clone(), second, end].concat();
+        let end_record = &archive[archive.len() - 22..];
+        assert_eq!(u32_at(end_record, 0), END_OF_CENTRAL_DIRECTORY_SIGNATURE);
+        assert_eq!(u16_at(end_record, 10), 2);
+
+        let directory_offset = u32_at(end_record, 16) as usize;
+        let directory_size = u32_at(end_record, 12) as usize;
+        assert_eq!(directory_offset + directory_size, archive.len() - 22);
+
+        let directory = &archive[directory_offset..];
+        assert_eq!(u32_at(directory, 0), CENTRAL_HEADER_SIGNATURE);
+        assert_eq!(u32_at(directory, 42), 0);
+
+        let second_header = 46 + 5;
+        assert_eq!(u32_at(directory, second_header), CENTRAL_HEADER_SIGNATURE);
+        assert_eq!(u32_at(directory, second_header + 42) as usize, first.len());
+    }
+
+    #[test]
+    fn encodes_dos_timestamps() {
+        let modified = Utc.with_ymd_and_hms(2024, 3, 15, 10, 30, 21).unwrap();
+        let (dos_time, dos_date) = dos_date_time(modified);
+
+        assert_eq!(dos_time, (10 << 11) | (30 << 5) | 10);
+        assert_eq!(dos_date, (44 << 9) | (3 << 5) | 15);
+
+        let before_dos = Utc.with_ymd_and_hms(1970, 1, 1, 0, 0, 0).unwrap();
+        assert_eq!(dos_date_time(before_dos), (0, (1 << 5) | 1));
+    }
+
+    #[test]
+    fn rejects_names_longer_than_the_length_field() {
+        let mut writer = ZipWriter::new();
+        let name = "a".repeat(u16::MAX as usize + 1);
+
+        assert!(writer.entry(&name, b"", Utc::now()).is_err());
+        assert!(writer.entry(&name[1..], b"", Utc::now()).is_ok());
+    }
+
+    #[test]
+    fn empty_archive_is_just_the_end_record() {
+        let end = ZipWriter::new().finish().unwrap();
+
+        assert_eq!(end.len(), 22);
+        assert_eq!(u32_at(&end, 0), END_OF_CENTRAL_DIRECTORY_SIGNATURE);
+        assert_eq!(u16_at(&end, 10), 0);
+    }
+}